@@ -11,5 +11,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .file_descriptor_set_path(out_dir.join("timecard_descriptor.bin"))
         .compile_protos(&["proto/timecard.proto"], &["proto"])?;
 
+    // Migrations are embedded by sqlx::migrate!
+    println!("cargo:rerun-if-changed=migrations");
+
     Ok(())
 }
@@ -0,0 +1,81 @@
+-- Tables shared with the Python side
+-- Existing deployments already have them, so every statement is IF NOT EXISTS;
+-- on a fresh database this is the schema the queries in src/services expect
+
+-- ドライバー（社員番号 = id）
+CREATE TABLE IF NOT EXISTS drivers (
+    id INT NOT NULL PRIMARY KEY,
+    name VARCHAR(255) NOT NULL
+);
+
+-- ICカード・免許証の読み取りログ
+CREATE TABLE IF NOT EXISTS ic_log (
+    id VARCHAR(64) NOT NULL,
+    type VARCHAR(32) NOT NULL,
+    detail VARCHAR(255) NULL,
+    date DATETIME NOT NULL,
+    iid VARCHAR(64) NULL,
+    machine_ip VARCHAR(64) NOT NULL,
+    INDEX idx_ic_log_date (date),
+    INDEX idx_ic_log_id (id)
+);
+
+-- ICカードとドライバーの紐付け（deleted = 1 は解除済み）
+CREATE TABLE IF NOT EXISTS ic_id (
+    ic_id VARCHAR(64) NOT NULL,
+    emp_id INT NOT NULL,
+    date DATETIME NOT NULL,
+    deleted TINYINT NOT NULL DEFAULT 0,
+    INDEX idx_ic_id_ic (ic_id, date)
+);
+
+-- 未登録ICカード（registered_id は登録予約中のドライバー）
+CREATE TABLE IF NOT EXISTS ic_non_reged (
+    id VARCHAR(64) NOT NULL PRIMARY KEY,
+    registered_id INT NULL,
+    datetime DATETIME NOT NULL,
+    deleted TINYINT NULL DEFAULT 0
+);
+
+-- 体温測定データ（tmp / amb / dist はカンマ区切り、id = 0 は未照合）
+CREATE TABLE IF NOT EXISTS tmp_data (
+    machine_ip VARCHAR(64) NOT NULL,
+    tmp TEXT NOT NULL,
+    amb TEXT NOT NULL,
+    dist TEXT NOT NULL,
+    date DATETIME NOT NULL,
+    id INT NOT NULL DEFAULT 0,
+    INDEX idx_tmp_data_date (date)
+);
+
+-- カメラ画像
+CREATE TABLE IF NOT EXISTS pic_data (
+    date DATETIME NOT NULL,
+    cam INT NOT NULL,
+    pic LONGBLOB NULL,
+    detail VARCHAR(255) NULL,
+    machine_ip VARCHAR(64) NOT NULL,
+    INDEX idx_pic_data_date (date)
+);
+
+-- 指紋認証ログ
+CREATE TABLE IF NOT EXISTS finger_log (
+    date DATETIME NOT NULL,
+    machine_ip VARCHAR(64) NOT NULL,
+    id INT NOT NULL,
+    message VARCHAR(255) NOT NULL,
+    INDEX idx_finger_log_date (date)
+);
+
+-- Web Push 用 VAPID キー
+CREATE TABLE IF NOT EXISTS vapidkey (
+    publicKey TEXT NOT NULL,
+    privateKey TEXT NOT NULL,
+    uuid VARCHAR(36) NOT NULL PRIMARY KEY
+);
+
+-- 疎通確認用（列名 datettime は既存テーブルに合わせる）
+CREATE TABLE IF NOT EXISTS test (
+    id INT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    datettime INT NOT NULL
+);
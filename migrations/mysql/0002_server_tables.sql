@@ -0,0 +1,148 @@
+-- Tables owned by this server
+-- Every statement is IF NOT EXISTS so databases created by the former
+-- startup ensure_tables step are adopted unchanged
+
+-- 端末コマンドの送信・実行履歴
+CREATE TABLE IF NOT EXISTS terminal_commands (
+    id VARCHAR(36) NOT NULL PRIMARY KEY,
+    machine_ip VARCHAR(64) NOT NULL,
+    socket_id VARCHAR(64) NULL,
+    command VARCHAR(64) NOT NULL,
+    args TEXT NOT NULL,
+    status VARCHAR(16) NOT NULL,
+    result TEXT NULL,
+    requested_by VARCHAR(128) NULL,
+    created_at DATETIME NOT NULL,
+    completed_at DATETIME NULL,
+    INDEX idx_terminal_commands_machine (machine_ip, created_at)
+);
+
+-- 外部送信用 Outbox（destination + stream 単位で順序保証）
+CREATE TABLE IF NOT EXISTS event_outbox (
+    id BIGINT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    destination VARCHAR(64) NOT NULL,
+    stream VARCHAR(128) NOT NULL,
+    payload LONGTEXT NOT NULL,
+    status VARCHAR(16) NOT NULL,
+    attempts INT NOT NULL DEFAULT 0,
+    next_attempt_at DATETIME NOT NULL,
+    last_error TEXT NULL,
+    created_at DATETIME NOT NULL,
+    delivered_at DATETIME NULL,
+    INDEX idx_event_outbox_status (status, destination, stream, id)
+);
+
+-- 端末レジストリ（接続が途絶えても残る端末情報）
+CREATE TABLE IF NOT EXISTS terminals (
+    machine_ip VARCHAR(64) NOT NULL PRIMARY KEY,
+    display_name VARCHAR(128) NULL,
+    location VARCHAR(128) NULL,
+    camera_count INT NOT NULL DEFAULT 0,
+    expected_online_from TIME NULL,
+    expected_online_to TIME NULL,
+    first_seen_at DATETIME NULL,
+    last_seen_at DATETIME NULL,
+    created_at DATETIME NOT NULL,
+    updated_at DATETIME NOT NULL
+);
+
+-- 端末テレメトリの時系列スナップショット
+CREATE TABLE IF NOT EXISTS terminal_telemetry (
+    id BIGINT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    machine_ip VARCHAR(64) NOT NULL,
+    reported_at DATETIME NOT NULL,
+    software_version VARCHAR(64) NOT NULL,
+    uptime_secs BIGINT NOT NULL,
+    disk_total_bytes BIGINT NULL,
+    disk_free_bytes BIGINT NULL,
+    cameras TEXT NOT NULL,
+    sensor_errors TEXT NOT NULL,
+    alerts TEXT NOT NULL,
+    INDEX idx_terminal_telemetry_machine (machine_ip, reported_at)
+);
+
+-- 端末設定（scope = "*" はサイト共通、それ以外は machine_ip 単位の上書き）
+CREATE TABLE IF NOT EXISTS terminal_config (
+    scope VARCHAR(64) NOT NULL,
+    config_key VARCHAR(128) NOT NULL,
+    value TEXT NOT NULL,
+    revision BIGINT NOT NULL,
+    updated_by VARCHAR(128) NULL,
+    updated_at DATETIME NOT NULL,
+    PRIMARY KEY (scope, config_key)
+);
+
+-- 端末設定の変更履歴（id がリビジョン、value = NULL は削除）
+CREATE TABLE IF NOT EXISTS terminal_config_history (
+    id BIGINT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    scope VARCHAR(64) NOT NULL,
+    config_key VARCHAR(128) NOT NULL,
+    value TEXT NULL,
+    updated_by VARCHAR(128) NULL,
+    created_at DATETIME NOT NULL
+);
+
+-- 端末ごとの設定適用状況
+CREATE TABLE IF NOT EXISTS terminal_config_applied (
+    machine_ip VARCHAR(64) NOT NULL PRIMARY KEY,
+    revision BIGINT NOT NULL,
+    success BOOLEAN NOT NULL,
+    message TEXT NULL,
+    applied_at DATETIME NOT NULL
+);
+
+-- クライアントの切断・隔離操作の監査ログ
+CREATE TABLE IF NOT EXISTS client_admin_actions (
+    id BIGINT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    action VARCHAR(16) NOT NULL,
+    socket_id VARCHAR(64) NULL,
+    ip_address VARCHAR(64) NULL,
+    affected INT NOT NULL,
+    reason TEXT NULL,
+    requested_by VARCHAR(128) NULL,
+    created_at DATETIME NOT NULL
+);
+
+-- 受信済み打刻の client_id（再送された打刻の重複排除）
+CREATE TABLE IF NOT EXISTS ingest_receipts (
+    machine_ip VARCHAR(64) NOT NULL,
+    client_id VARCHAR(64) NOT NULL,
+    status VARCHAR(32) NOT NULL,
+    punch_date DATETIME NOT NULL,
+    backfilled BOOLEAN NOT NULL,
+    received_at DATETIME NOT NULL,
+    PRIMARY KEY (machine_ip, client_id)
+);
+
+-- 端末ごとの直近の時刻ずれ（端末時刻 - サーバー時刻）
+CREATE TABLE IF NOT EXISTS terminal_clock_skew (
+    machine_ip VARCHAR(64) NOT NULL PRIMARY KEY,
+    skew_secs BIGINT NOT NULL,
+    measured_at DATETIME NOT NULL
+);
+
+-- 端末ソフトウェアのリリース（成果物を含む）
+CREATE TABLE IF NOT EXISTS software_releases (
+    id BIGINT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    version VARCHAR(64) NOT NULL,
+    channel VARCHAR(32) NOT NULL,
+    filename VARCHAR(255) NOT NULL,
+    sha256 CHAR(64) NOT NULL,
+    size_bytes BIGINT NOT NULL,
+    artifact LONGBLOB NOT NULL,
+    rollout_percent INT NOT NULL,
+    notes TEXT NULL,
+    created_by VARCHAR(128) NULL,
+    created_at DATETIME NOT NULL,
+    UNIQUE KEY uq_software_releases_version (channel, version)
+);
+
+-- リリースの端末ごとの配信状況
+CREATE TABLE IF NOT EXISTS release_rollout (
+    release_id BIGINT NOT NULL,
+    machine_ip VARCHAR(64) NOT NULL,
+    state VARCHAR(16) NOT NULL,
+    message TEXT NULL,
+    updated_at DATETIME NOT NULL,
+    PRIMARY KEY (release_id, machine_ip)
+);
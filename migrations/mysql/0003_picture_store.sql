@@ -0,0 +1,45 @@
+-- External picture storage (PICTURE_STORE)
+-- Pictures written to a filesystem or S3-compatible store keep their metadata here with
+-- pic_key pointing at the stored object and pic left NULL. id lets
+-- `timecard-backend migrate-pictures` move existing blobs out row by row
+--
+-- Offline migration: adding id rebuilds pic_data, which holds every picture blob and is
+-- locked for writes while it runs. Apply it with `timecard-backend migrate` during a
+-- maintenance window, not through AUTO_MIGRATE on a production server.
+-- Each column is added only when missing, so tables the Python side already gave an id
+-- or a primary key are left as they are (id is then added as a unique key).
+
+SET @pic_data_has_id = (
+    SELECT COUNT(*) FROM information_schema.COLUMNS
+    WHERE TABLE_SCHEMA = DATABASE() AND TABLE_NAME = 'pic_data' AND COLUMN_NAME = 'id'
+);
+SET @pic_data_has_pk = (
+    SELECT COUNT(*) FROM information_schema.TABLE_CONSTRAINTS
+    WHERE TABLE_SCHEMA = DATABASE() AND TABLE_NAME = 'pic_data'
+        AND CONSTRAINT_TYPE = 'PRIMARY KEY'
+);
+SET @pic_data_add_id = IF(
+    @pic_data_has_id > 0,
+    'DO 0',
+    IF(
+        @pic_data_has_pk > 0,
+        'ALTER TABLE pic_data ADD COLUMN id BIGINT NOT NULL AUTO_INCREMENT UNIQUE FIRST',
+        'ALTER TABLE pic_data ADD COLUMN id BIGINT NOT NULL AUTO_INCREMENT PRIMARY KEY FIRST'
+    )
+);
+PREPARE pic_data_add_id FROM @pic_data_add_id;
+EXECUTE pic_data_add_id;
+DEALLOCATE PREPARE pic_data_add_id;
+
+SET @pic_data_add_key = IF(
+    (
+        SELECT COUNT(*) FROM information_schema.COLUMNS
+        WHERE TABLE_SCHEMA = DATABASE() AND TABLE_NAME = 'pic_data'
+            AND COLUMN_NAME = 'pic_key'
+    ) > 0,
+    'DO 0',
+    'ALTER TABLE pic_data ADD COLUMN pic_key VARCHAR(512) NULL AFTER pic'
+);
+PREPARE pic_data_add_key FROM @pic_data_add_key;
+EXECUTE pic_data_add_key;
+DEALLOCATE PREPARE pic_data_add_key;
@@ -0,0 +1,56 @@
+-- Current IC card assignments
+-- ic_card_current holds the latest non-deleted ic_id row of each card so IC log queries
+-- can join it instead of grouping the whole ic_id history. The triggers keep it in step
+-- with every write to ic_id (this server and the Python client alike) in the same
+-- transaction; `timecard-backend rebuild-card-assignments` recomputes it from ic_id
+
+CREATE TABLE IF NOT EXISTS ic_card_current (
+    ic_id VARCHAR(64) NOT NULL PRIMARY KEY,
+    emp_id INT NOT NULL,
+    date DATETIME NOT NULL,
+    INDEX idx_ic_card_current_emp (emp_id)
+);
+
+-- 同じ日時の紐付けが複数ある場合は先に見つかった1件
+INSERT IGNORE INTO ic_card_current (ic_id, emp_id, date)
+    SELECT i1.ic_id, i1.emp_id, i1.date
+    FROM ic_id i1
+    INNER JOIN (
+        SELECT ic_id, MAX(date) as max_date
+        FROM ic_id
+        WHERE deleted = 0 AND ic_id != ''
+        GROUP BY ic_id
+    ) i2 ON i1.ic_id = i2.ic_id AND i1.date = i2.max_date
+    WHERE i1.deleted = 0;
+
+CREATE TRIGGER ic_card_current_after_insert AFTER INSERT ON ic_id FOR EACH ROW
+BEGIN
+    DELETE FROM ic_card_current WHERE ic_id = NEW.ic_id;
+    INSERT INTO ic_card_current (ic_id, emp_id, date)
+        SELECT ic_id, emp_id, date FROM ic_id
+        WHERE ic_id = NEW.ic_id AND ic_id != '' AND deleted = 0
+        ORDER BY date DESC LIMIT 1;
+END;
+
+-- 解除（deleted = 1）や付け替えでは1つ前の紐付けに戻る
+CREATE TRIGGER ic_card_current_after_update AFTER UPDATE ON ic_id FOR EACH ROW
+BEGIN
+    DELETE FROM ic_card_current WHERE ic_id IN (OLD.ic_id, NEW.ic_id);
+    INSERT INTO ic_card_current (ic_id, emp_id, date)
+        SELECT ic_id, emp_id, date FROM ic_id
+        WHERE ic_id = OLD.ic_id AND ic_id != '' AND deleted = 0
+        ORDER BY date DESC LIMIT 1;
+    INSERT IGNORE INTO ic_card_current (ic_id, emp_id, date)
+        SELECT ic_id, emp_id, date FROM ic_id
+        WHERE ic_id = NEW.ic_id AND ic_id != '' AND deleted = 0
+        ORDER BY date DESC LIMIT 1;
+END;
+
+CREATE TRIGGER ic_card_current_after_delete AFTER DELETE ON ic_id FOR EACH ROW
+BEGIN
+    DELETE FROM ic_card_current WHERE ic_id = OLD.ic_id;
+    INSERT INTO ic_card_current (ic_id, emp_id, date)
+        SELECT ic_id, emp_id, date FROM ic_id
+        WHERE ic_id = OLD.ic_id AND ic_id != '' AND deleted = 0
+        ORDER BY date DESC LIMIT 1;
+END;
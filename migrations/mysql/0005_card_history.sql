@@ -0,0 +1,56 @@
+-- IC card assignment history
+-- ic_id marks a removed assignment with deleted = 1 (or deletes the row) without recording
+-- when, so the triggers keep one row per assignment in ic_card_history and close it at the
+-- time of removal. IC logs are attributed to the assignment valid at the log's time.
+-- revoked_at_utc is taken from the database clock in UTC and converted to the business
+-- timezone by the server. Assignments already removed before this migration have no known
+-- removal time and are left out of the history
+
+CREATE TABLE IF NOT EXISTS ic_card_history (
+    id BIGINT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    ic_id VARCHAR(64) NOT NULL,
+    emp_id INT NOT NULL,
+    assigned_at DATETIME NOT NULL,
+    revoked_at_utc DATETIME NULL,
+    INDEX idx_ic_card_history_ic (ic_id, assigned_at)
+);
+
+INSERT INTO ic_card_history (ic_id, emp_id, assigned_at)
+    SELECT ic_id, emp_id, date FROM ic_id
+    WHERE deleted = 0 AND ic_id != ''
+    ORDER BY date;
+
+CREATE TRIGGER ic_card_history_after_insert AFTER INSERT ON ic_id FOR EACH ROW
+BEGIN
+    IF NEW.deleted = 0 AND NEW.ic_id != '' THEN
+        INSERT INTO ic_card_history (ic_id, emp_id, assigned_at)
+            VALUES (NEW.ic_id, NEW.emp_id, NEW.date);
+    END IF;
+END;
+
+-- 解除（deleted = 1）や紐付けの書き換えで元の紐付けを閉じ、書き換え後を新しい紐付けとして記録
+CREATE TRIGGER ic_card_history_after_update AFTER UPDATE ON ic_id FOR EACH ROW
+BEGIN
+    DECLARE changed BOOLEAN DEFAULT
+        NOT (OLD.ic_id <=> NEW.ic_id AND OLD.emp_id <=> NEW.emp_id AND OLD.date <=> NEW.date);
+    IF OLD.deleted = 0 AND (NEW.deleted != 0 OR changed) THEN
+        UPDATE ic_card_history SET revoked_at_utc = UTC_TIMESTAMP()
+            WHERE ic_id = OLD.ic_id AND emp_id = OLD.emp_id AND assigned_at = OLD.date
+              AND revoked_at_utc IS NULL
+            ORDER BY id LIMIT 1;
+    END IF;
+    IF NEW.deleted = 0 AND NEW.ic_id != '' AND (OLD.deleted != 0 OR changed) THEN
+        INSERT INTO ic_card_history (ic_id, emp_id, assigned_at)
+            VALUES (NEW.ic_id, NEW.emp_id, NEW.date);
+    END IF;
+END;
+
+CREATE TRIGGER ic_card_history_after_delete AFTER DELETE ON ic_id FOR EACH ROW
+BEGIN
+    IF OLD.deleted = 0 THEN
+        UPDATE ic_card_history SET revoked_at_utc = UTC_TIMESTAMP()
+            WHERE ic_id = OLD.ic_id AND emp_id = OLD.emp_id AND assigned_at = OLD.date
+              AND revoked_at_utc IS NULL
+            ORDER BY id LIMIT 1;
+    END IF;
+END;
@@ -0,0 +1,142 @@
+-- IC card history in business time, with ic_card_current derived from it
+-- revoked_at replaces revoked_at_utc and is business-timezone wall-clock time like
+-- ic_id.date and ic_log.date: the triggers shift the database's UTC clock by
+-- business_clock.utc_offset_minutes, which the server keeps in step with
+-- BUSINESS_TIMEZONE. Assignments removed before 0005 are added to the history, closed at
+-- the card's next assignment or at the time of this migration if it was never reassigned.
+-- ic_card_current now follows ic_card_history instead of ic_id, so it always holds the
+-- open assignment that IC logs resolve to. holder_since is the time from which that
+-- assignment is the holder for every later log (a newer assignment that was removed again
+-- still holds the card until its removal); IC log queries join ic_card_current and only
+-- look up the history for logs older than that. `timecard-backend
+-- rebuild-card-assignments` recomputes ic_card_current from ic_card_history
+
+-- 業務タイムゾーンの現在の UTC オフセット（起動時にサーバーが更新、初期値は Asia/Tokyo）
+CREATE TABLE IF NOT EXISTS business_clock (
+    id INT NOT NULL PRIMARY KEY,
+    utc_offset_minutes INT NOT NULL
+);
+INSERT IGNORE INTO business_clock (id, utc_offset_minutes) VALUES (1, 540);
+
+DROP TRIGGER IF EXISTS ic_card_current_after_insert;
+DROP TRIGGER IF EXISTS ic_card_current_after_update;
+DROP TRIGGER IF EXISTS ic_card_current_after_delete;
+DROP TRIGGER IF EXISTS ic_card_history_after_insert;
+DROP TRIGGER IF EXISTS ic_card_history_after_update;
+DROP TRIGGER IF EXISTS ic_card_history_after_delete;
+
+ALTER TABLE ic_card_history ADD COLUMN revoked_at DATETIME NULL AFTER assigned_at;
+UPDATE ic_card_history
+    SET revoked_at = DATE_ADD(revoked_at_utc, INTERVAL
+        (SELECT utc_offset_minutes FROM business_clock WHERE id = 1) MINUTE)
+    WHERE revoked_at_utc IS NOT NULL;
+ALTER TABLE ic_card_history DROP COLUMN revoked_at_utc;
+
+INSERT INTO ic_card_history (ic_id, emp_id, assigned_at, revoked_at)
+    SELECT i.ic_id, i.emp_id, i.date, COALESCE(
+            (SELECT MIN(n.date) FROM ic_id n WHERE n.ic_id = i.ic_id AND n.date > i.date),
+            DATE_ADD(UTC_TIMESTAMP(), INTERVAL
+                (SELECT utc_offset_minutes FROM business_clock WHERE id = 1) MINUTE)
+        )
+    FROM ic_id i
+    WHERE i.ic_id != '' AND i.deleted != 0
+      AND NOT EXISTS (
+          SELECT 1 FROM ic_card_history h
+          WHERE h.ic_id = i.ic_id AND h.emp_id = i.emp_id AND h.assigned_at = i.date
+      )
+    ORDER BY i.date;
+
+CREATE TRIGGER ic_card_history_after_insert AFTER INSERT ON ic_id FOR EACH ROW
+BEGIN
+    IF NEW.deleted = 0 AND NEW.ic_id != '' THEN
+        INSERT INTO ic_card_history (ic_id, emp_id, assigned_at)
+            VALUES (NEW.ic_id, NEW.emp_id, NEW.date);
+    END IF;
+END;
+
+-- 解除（deleted = 1）や紐付けの書き換えで元の紐付けを閉じ、書き換え後を新しい紐付けとして記録
+CREATE TRIGGER ic_card_history_after_update AFTER UPDATE ON ic_id FOR EACH ROW
+BEGIN
+    DECLARE changed BOOLEAN DEFAULT
+        NOT (OLD.ic_id <=> NEW.ic_id AND OLD.emp_id <=> NEW.emp_id AND OLD.date <=> NEW.date);
+    IF OLD.deleted = 0 AND (NEW.deleted != 0 OR changed) THEN
+        UPDATE ic_card_history
+            SET revoked_at = DATE_ADD(UTC_TIMESTAMP(), INTERVAL
+                (SELECT utc_offset_minutes FROM business_clock WHERE id = 1) MINUTE)
+            WHERE ic_id = OLD.ic_id AND emp_id = OLD.emp_id AND assigned_at = OLD.date
+              AND revoked_at IS NULL
+            ORDER BY id LIMIT 1;
+    END IF;
+    IF NEW.deleted = 0 AND NEW.ic_id != '' AND (OLD.deleted != 0 OR changed) THEN
+        INSERT INTO ic_card_history (ic_id, emp_id, assigned_at)
+            VALUES (NEW.ic_id, NEW.emp_id, NEW.date);
+    END IF;
+END;
+
+CREATE TRIGGER ic_card_history_after_delete AFTER DELETE ON ic_id FOR EACH ROW
+BEGIN
+    IF OLD.deleted = 0 THEN
+        UPDATE ic_card_history
+            SET revoked_at = DATE_ADD(UTC_TIMESTAMP(), INTERVAL
+                (SELECT utc_offset_minutes FROM business_clock WHERE id = 1) MINUTE)
+            WHERE ic_id = OLD.ic_id AND emp_id = OLD.emp_id AND assigned_at = OLD.date
+              AND revoked_at IS NULL
+            ORDER BY id LIMIT 1;
+    END IF;
+END;
+
+ALTER TABLE ic_card_current ADD COLUMN holder_since DATETIME NULL;
+
+-- カードごとに解除されていない最新の紐付け（同じ日時なら後に登録された方）
+DELETE FROM ic_card_current;
+INSERT INTO ic_card_current (ic_id, emp_id, date, holder_since)
+    SELECT h.ic_id, h.emp_id, h.assigned_at, COALESCE(
+            (SELECT MAX(l.revoked_at) FROM ic_card_history l
+             WHERE l.ic_id = h.ic_id AND l.revoked_at IS NOT NULL
+               AND (l.assigned_at > h.assigned_at
+                    OR (l.assigned_at = h.assigned_at AND l.id > h.id))),
+            h.assigned_at
+        )
+    FROM ic_card_history h
+    WHERE h.revoked_at IS NULL
+      AND NOT EXISTS (
+          SELECT 1 FROM ic_card_history h2
+          WHERE h2.ic_id = h.ic_id AND h2.revoked_at IS NULL
+            AND (h2.assigned_at > h.assigned_at
+                 OR (h2.assigned_at = h.assigned_at AND h2.id > h.id))
+      );
+
+-- 紐付けの追加・解除と同じトランザクションでカードの現在の保持者を再計算
+CREATE TRIGGER ic_card_current_after_history_insert AFTER INSERT ON ic_card_history
+FOR EACH ROW
+BEGIN
+    DELETE FROM ic_card_current WHERE ic_id = NEW.ic_id;
+    INSERT INTO ic_card_current (ic_id, emp_id, date, holder_since)
+        SELECT h.ic_id, h.emp_id, h.assigned_at, COALESCE(
+                (SELECT MAX(l.revoked_at) FROM ic_card_history l
+                 WHERE l.ic_id = h.ic_id AND l.revoked_at IS NOT NULL
+                   AND (l.assigned_at > h.assigned_at
+                        OR (l.assigned_at = h.assigned_at AND l.id > h.id))),
+                h.assigned_at
+            )
+        FROM ic_card_history h
+        WHERE h.ic_id = NEW.ic_id AND h.revoked_at IS NULL
+        ORDER BY h.assigned_at DESC, h.id DESC LIMIT 1;
+END;
+
+CREATE TRIGGER ic_card_current_after_history_update AFTER UPDATE ON ic_card_history
+FOR EACH ROW
+BEGIN
+    DELETE FROM ic_card_current WHERE ic_id = NEW.ic_id;
+    INSERT INTO ic_card_current (ic_id, emp_id, date, holder_since)
+        SELECT h.ic_id, h.emp_id, h.assigned_at, COALESCE(
+                (SELECT MAX(l.revoked_at) FROM ic_card_history l
+                 WHERE l.ic_id = h.ic_id AND l.revoked_at IS NOT NULL
+                   AND (l.assigned_at > h.assigned_at
+                        OR (l.assigned_at = h.assigned_at AND l.id > h.id))),
+                h.assigned_at
+            )
+        FROM ic_card_history h
+        WHERE h.ic_id = NEW.ic_id AND h.revoked_at IS NULL
+        ORDER BY h.assigned_at DESC, h.id DESC LIMIT 1;
+END;
@@ -0,0 +1,8 @@
+-- Release artifacts outside the database
+-- Artifacts are written to the picture store (or RELEASE_DIR when pictures stay in the
+-- database) and software_releases.artifact_key points at the object. Releases uploaded
+-- before this migration keep their artifact inline and are still served from it
+
+ALTER TABLE software_releases
+    MODIFY artifact LONGBLOB NULL,
+    ADD COLUMN artifact_key VARCHAR(512) NULL AFTER artifact;
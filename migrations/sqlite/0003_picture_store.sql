@@ -0,0 +1,18 @@
+-- External picture storage (PICTURE_STORE)
+-- Same change as migrations/mysql/0003_picture_store.sql. SQLite cannot add a primary
+-- key to an existing table, so pic_data is rebuilt
+
+CREATE TABLE pic_data_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    date DATETIME NOT NULL,
+    cam INT NOT NULL,
+    pic BLOB NULL,
+    pic_key VARCHAR(512) NULL,
+    detail VARCHAR(255) NULL,
+    machine_ip VARCHAR(64) NOT NULL
+);
+INSERT INTO pic_data_new (date, cam, pic, detail, machine_ip)
+    SELECT date, cam, pic, detail, machine_ip FROM pic_data ORDER BY date;
+DROP TABLE pic_data;
+ALTER TABLE pic_data_new RENAME TO pic_data;
+CREATE INDEX IF NOT EXISTS idx_pic_data_date ON pic_data (date);
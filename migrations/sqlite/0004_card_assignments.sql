@@ -0,0 +1,53 @@
+-- Current IC card assignments
+-- Same table and triggers as migrations/mysql/0004_card_assignments.sql
+
+CREATE TABLE IF NOT EXISTS ic_card_current (
+    ic_id VARCHAR(64) NOT NULL PRIMARY KEY,
+    emp_id INT NOT NULL,
+    date DATETIME NOT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_ic_card_current_emp ON ic_card_current (emp_id);
+
+-- 同じ日時の紐付けが複数ある場合は先に見つかった1件
+INSERT OR IGNORE INTO ic_card_current (ic_id, emp_id, date)
+    SELECT i1.ic_id, i1.emp_id, i1.date
+    FROM ic_id i1
+    INNER JOIN (
+        SELECT ic_id, MAX(date) as max_date
+        FROM ic_id
+        WHERE deleted = 0 AND ic_id != ''
+        GROUP BY ic_id
+    ) i2 ON i1.ic_id = i2.ic_id AND i1.date = i2.max_date
+    WHERE i1.deleted = 0;
+
+CREATE TRIGGER IF NOT EXISTS ic_card_current_after_insert AFTER INSERT ON ic_id
+BEGIN
+    DELETE FROM ic_card_current WHERE ic_id = NEW.ic_id;
+    INSERT INTO ic_card_current (ic_id, emp_id, date)
+        SELECT ic_id, emp_id, date FROM ic_id
+        WHERE ic_id = NEW.ic_id AND ic_id != '' AND deleted = 0
+        ORDER BY date DESC LIMIT 1;
+END;
+
+-- 解除（deleted = 1）や付け替えでは1つ前の紐付けに戻る
+CREATE TRIGGER IF NOT EXISTS ic_card_current_after_update AFTER UPDATE ON ic_id
+BEGIN
+    DELETE FROM ic_card_current WHERE ic_id IN (OLD.ic_id, NEW.ic_id);
+    INSERT INTO ic_card_current (ic_id, emp_id, date)
+        SELECT ic_id, emp_id, date FROM ic_id
+        WHERE ic_id = OLD.ic_id AND ic_id != '' AND deleted = 0
+        ORDER BY date DESC LIMIT 1;
+    INSERT OR IGNORE INTO ic_card_current (ic_id, emp_id, date)
+        SELECT ic_id, emp_id, date FROM ic_id
+        WHERE ic_id = NEW.ic_id AND ic_id != '' AND deleted = 0
+        ORDER BY date DESC LIMIT 1;
+END;
+
+CREATE TRIGGER IF NOT EXISTS ic_card_current_after_delete AFTER DELETE ON ic_id
+BEGIN
+    DELETE FROM ic_card_current WHERE ic_id = OLD.ic_id;
+    INSERT INTO ic_card_current (ic_id, emp_id, date)
+        SELECT ic_id, emp_id, date FROM ic_id
+        WHERE ic_id = OLD.ic_id AND ic_id != '' AND deleted = 0
+        ORDER BY date DESC LIMIT 1;
+END;
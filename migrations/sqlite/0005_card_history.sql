@@ -0,0 +1,53 @@
+-- IC card assignment history
+-- Same table and triggers as migrations/mysql/0005_card_history.sql
+
+CREATE TABLE IF NOT EXISTS ic_card_history (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    ic_id VARCHAR(64) NOT NULL,
+    emp_id INT NOT NULL,
+    assigned_at DATETIME NOT NULL,
+    revoked_at_utc DATETIME NULL
+);
+CREATE INDEX IF NOT EXISTS idx_ic_card_history_ic ON ic_card_history (ic_id, assigned_at);
+
+INSERT INTO ic_card_history (ic_id, emp_id, assigned_at)
+    SELECT ic_id, emp_id, date FROM ic_id
+    WHERE deleted = 0 AND ic_id != ''
+    ORDER BY date;
+
+CREATE TRIGGER IF NOT EXISTS ic_card_history_after_insert AFTER INSERT ON ic_id
+WHEN NEW.deleted = 0 AND NEW.ic_id != ''
+BEGIN
+    INSERT INTO ic_card_history (ic_id, emp_id, assigned_at)
+        VALUES (NEW.ic_id, NEW.emp_id, NEW.date);
+END;
+
+-- 解除（deleted = 1）や紐付けの書き換えで元の紐付けを閉じ、書き換え後を新しい紐付けとして記録
+CREATE TRIGGER IF NOT EXISTS ic_card_history_after_update AFTER UPDATE ON ic_id
+WHEN OLD.deleted != NEW.deleted
+  OR OLD.ic_id IS NOT NEW.ic_id OR OLD.emp_id IS NOT NEW.emp_id OR OLD.date IS NOT NEW.date
+BEGIN
+    UPDATE ic_card_history SET revoked_at_utc = datetime('now')
+        WHERE id = (
+            SELECT id FROM ic_card_history
+            WHERE ic_id = OLD.ic_id AND emp_id = OLD.emp_id AND assigned_at = OLD.date
+              AND revoked_at_utc IS NULL
+            ORDER BY id LIMIT 1
+        )
+        AND OLD.deleted = 0;
+    INSERT INTO ic_card_history (ic_id, emp_id, assigned_at)
+        SELECT NEW.ic_id, NEW.emp_id, NEW.date
+        WHERE NEW.deleted = 0 AND NEW.ic_id != '';
+END;
+
+CREATE TRIGGER IF NOT EXISTS ic_card_history_after_delete AFTER DELETE ON ic_id
+WHEN OLD.deleted = 0
+BEGIN
+    UPDATE ic_card_history SET revoked_at_utc = datetime('now')
+        WHERE id = (
+            SELECT id FROM ic_card_history
+            WHERE ic_id = OLD.ic_id AND emp_id = OLD.emp_id AND assigned_at = OLD.date
+              AND revoked_at_utc IS NULL
+            ORDER BY id LIMIT 1
+        );
+END;
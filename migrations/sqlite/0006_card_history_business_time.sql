@@ -0,0 +1,134 @@
+-- IC card history in business time, with ic_card_current derived from it
+-- Same change as migrations/mysql/0006_card_history_business_time.sql
+
+-- 業務タイムゾーンの現在の UTC オフセット（起動時にサーバーが更新、初期値は Asia/Tokyo）
+CREATE TABLE IF NOT EXISTS business_clock (
+    id INT NOT NULL PRIMARY KEY,
+    utc_offset_minutes INT NOT NULL
+);
+INSERT OR IGNORE INTO business_clock (id, utc_offset_minutes) VALUES (1, 540);
+
+DROP TRIGGER IF EXISTS ic_card_current_after_insert;
+DROP TRIGGER IF EXISTS ic_card_current_after_update;
+DROP TRIGGER IF EXISTS ic_card_current_after_delete;
+DROP TRIGGER IF EXISTS ic_card_history_after_insert;
+DROP TRIGGER IF EXISTS ic_card_history_after_update;
+DROP TRIGGER IF EXISTS ic_card_history_after_delete;
+
+ALTER TABLE ic_card_history ADD COLUMN revoked_at DATETIME NULL;
+UPDATE ic_card_history
+    SET revoked_at = datetime(revoked_at_utc,
+        (SELECT utc_offset_minutes FROM business_clock WHERE id = 1) || ' minutes')
+    WHERE revoked_at_utc IS NOT NULL;
+ALTER TABLE ic_card_history DROP COLUMN revoked_at_utc;
+
+INSERT INTO ic_card_history (ic_id, emp_id, assigned_at, revoked_at)
+    SELECT i.ic_id, i.emp_id, i.date, COALESCE(
+            (SELECT MIN(n.date) FROM ic_id n WHERE n.ic_id = i.ic_id AND n.date > i.date),
+            datetime('now',
+                (SELECT utc_offset_minutes FROM business_clock WHERE id = 1) || ' minutes')
+        )
+    FROM ic_id i
+    WHERE i.ic_id != '' AND i.deleted != 0
+      AND NOT EXISTS (
+          SELECT 1 FROM ic_card_history h
+          WHERE h.ic_id = i.ic_id AND h.emp_id = i.emp_id AND h.assigned_at = i.date
+      )
+    ORDER BY i.date;
+
+CREATE TRIGGER IF NOT EXISTS ic_card_history_after_insert AFTER INSERT ON ic_id
+WHEN NEW.deleted = 0 AND NEW.ic_id != ''
+BEGIN
+    INSERT INTO ic_card_history (ic_id, emp_id, assigned_at)
+        VALUES (NEW.ic_id, NEW.emp_id, NEW.date);
+END;
+
+-- 解除（deleted = 1）や紐付けの書き換えで元の紐付けを閉じ、書き換え後を新しい紐付けとして記録
+CREATE TRIGGER IF NOT EXISTS ic_card_history_after_update AFTER UPDATE ON ic_id
+WHEN OLD.deleted != NEW.deleted
+  OR OLD.ic_id IS NOT NEW.ic_id OR OLD.emp_id IS NOT NEW.emp_id OR OLD.date IS NOT NEW.date
+BEGIN
+    UPDATE ic_card_history
+        SET revoked_at = datetime('now',
+            (SELECT utc_offset_minutes FROM business_clock WHERE id = 1) || ' minutes')
+        WHERE id = (
+            SELECT id FROM ic_card_history
+            WHERE ic_id = OLD.ic_id AND emp_id = OLD.emp_id AND assigned_at = OLD.date
+              AND revoked_at IS NULL
+            ORDER BY id LIMIT 1
+        )
+        AND OLD.deleted = 0;
+    INSERT INTO ic_card_history (ic_id, emp_id, assigned_at)
+        SELECT NEW.ic_id, NEW.emp_id, NEW.date
+        WHERE NEW.deleted = 0 AND NEW.ic_id != '';
+END;
+
+CREATE TRIGGER IF NOT EXISTS ic_card_history_after_delete AFTER DELETE ON ic_id
+WHEN OLD.deleted = 0
+BEGIN
+    UPDATE ic_card_history
+        SET revoked_at = datetime('now',
+            (SELECT utc_offset_minutes FROM business_clock WHERE id = 1) || ' minutes')
+        WHERE id = (
+            SELECT id FROM ic_card_history
+            WHERE ic_id = OLD.ic_id AND emp_id = OLD.emp_id AND assigned_at = OLD.date
+              AND revoked_at IS NULL
+            ORDER BY id LIMIT 1
+        );
+END;
+
+ALTER TABLE ic_card_current ADD COLUMN holder_since DATETIME NULL;
+
+-- カードごとに解除されていない最新の紐付け（同じ日時なら後に登録された方）
+DELETE FROM ic_card_current;
+INSERT INTO ic_card_current (ic_id, emp_id, date, holder_since)
+    SELECT h.ic_id, h.emp_id, h.assigned_at, COALESCE(
+            (SELECT MAX(l.revoked_at) FROM ic_card_history l
+             WHERE l.ic_id = h.ic_id AND l.revoked_at IS NOT NULL
+               AND (l.assigned_at > h.assigned_at
+                    OR (l.assigned_at = h.assigned_at AND l.id > h.id))),
+            h.assigned_at
+        )
+    FROM ic_card_history h
+    WHERE h.revoked_at IS NULL
+      AND NOT EXISTS (
+          SELECT 1 FROM ic_card_history h2
+          WHERE h2.ic_id = h.ic_id AND h2.revoked_at IS NULL
+            AND (h2.assigned_at > h.assigned_at
+                 OR (h2.assigned_at = h.assigned_at AND h2.id > h.id))
+      );
+
+-- 紐付けの追加・解除と同じトランザクションでカードの現在の保持者を再計算
+CREATE TRIGGER IF NOT EXISTS ic_card_current_after_history_insert
+AFTER INSERT ON ic_card_history
+BEGIN
+    DELETE FROM ic_card_current WHERE ic_id = NEW.ic_id;
+    INSERT INTO ic_card_current (ic_id, emp_id, date, holder_since)
+        SELECT h.ic_id, h.emp_id, h.assigned_at, COALESCE(
+                (SELECT MAX(l.revoked_at) FROM ic_card_history l
+                 WHERE l.ic_id = h.ic_id AND l.revoked_at IS NOT NULL
+                   AND (l.assigned_at > h.assigned_at
+                        OR (l.assigned_at = h.assigned_at AND l.id > h.id))),
+                h.assigned_at
+            )
+        FROM ic_card_history h
+        WHERE h.ic_id = NEW.ic_id AND h.revoked_at IS NULL
+        ORDER BY h.assigned_at DESC, h.id DESC LIMIT 1;
+END;
+
+CREATE TRIGGER IF NOT EXISTS ic_card_current_after_history_update
+AFTER UPDATE ON ic_card_history
+BEGIN
+    DELETE FROM ic_card_current WHERE ic_id = NEW.ic_id;
+    INSERT INTO ic_card_current (ic_id, emp_id, date, holder_since)
+        SELECT h.ic_id, h.emp_id, h.assigned_at, COALESCE(
+                (SELECT MAX(l.revoked_at) FROM ic_card_history l
+                 WHERE l.ic_id = h.ic_id AND l.revoked_at IS NOT NULL
+                   AND (l.assigned_at > h.assigned_at
+                        OR (l.assigned_at = h.assigned_at AND l.id > h.id))),
+                h.assigned_at
+            )
+        FROM ic_card_history h
+        WHERE h.ic_id = NEW.ic_id AND h.revoked_at IS NULL
+        ORDER BY h.assigned_at DESC, h.id DESC LIMIT 1;
+END;
@@ -0,0 +1,27 @@
+-- Release artifacts outside the database
+-- Same change as migrations/mysql/0007_release_artifacts.sql. SQLite cannot relax NOT NULL
+-- on an existing column, so software_releases is rebuilt
+
+CREATE TABLE software_releases_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    version VARCHAR(64) NOT NULL,
+    channel VARCHAR(32) NOT NULL,
+    filename VARCHAR(255) NOT NULL,
+    sha256 CHAR(64) NOT NULL,
+    size_bytes BIGINT NOT NULL,
+    artifact BLOB NULL,
+    artifact_key VARCHAR(512) NULL,
+    rollout_percent INT NOT NULL,
+    notes TEXT NULL,
+    created_by VARCHAR(128) NULL,
+    created_at DATETIME NOT NULL,
+    UNIQUE (channel, version)
+);
+INSERT INTO software_releases_new
+    (id, version, channel, filename, sha256, size_bytes, artifact, rollout_percent, notes,
+     created_by, created_at)
+    SELECT id, version, channel, filename, sha256, size_bytes, artifact, rollout_percent, notes,
+        created_by, created_at
+    FROM software_releases;
+DROP TABLE software_releases;
+ALTER TABLE software_releases_new RENAME TO software_releases;
@@ -0,0 +1,216 @@
+// Administrative actions on connected Socket.IO clients (disconnect / quarantine)
+// Every action is recorded in client_admin_actions
+
+use crate::client_state::ClientState;
+use crate::clock;
+use crate::db::Database;
+use crate::with_pool;
+use chrono::NaiveDateTime;
+use socketioxide::{socket::Sid, SocketIo};
+use sqlx::Row;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+/// 操作対象（ソケットID または IP）
+#[derive(Debug, Clone)]
+pub enum ClientTarget {
+    Socket(String),
+    Ip(String),
+}
+
+/// 管理操作の種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdminAction {
+    Disconnect,
+    Quarantine,
+    Release,
+}
+
+impl AdminAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Disconnect => "disconnect",
+            Self::Quarantine => "quarantine",
+            Self::Release => "release",
+        }
+    }
+}
+
+/// client_admin_actions の1行
+#[derive(Debug, Clone)]
+pub struct AdminActionRecord {
+    pub id: i64,
+    pub action: String,
+    pub socket_id: Option<String>,
+    pub ip_address: Option<String>,
+    pub affected: i32,
+    pub reason: Option<String>,
+    pub requested_by: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+/// 切断・隔離の実行と監査記録
+#[derive(Clone)]
+pub struct ClientAdmin {
+    db: Database,
+    clients: ClientState,
+    socketio: Option<Arc<SocketIo>>,
+}
+
+impl ClientAdmin {
+    pub fn new(db: Database, clients: ClientState, socketio: Option<Arc<SocketIo>>) -> Self {
+        Self {
+            db,
+            clients,
+            socketio,
+        }
+    }
+
+    /// 対象に該当する接続中のソケットID
+    fn socket_ids(&self, target: &ClientTarget) -> Vec<String> {
+        match target {
+            ClientTarget::Socket(socket_id) => self
+                .clients
+                .get_all_clients()
+                .into_iter()
+                .filter(|c| &c.socket_id == socket_id)
+                .map(|c| c.socket_id)
+                .collect(),
+            ClientTarget::Ip(ip) => self
+                .clients
+                .find_by_ip(ip)
+                .into_iter()
+                .map(|c| c.socket_id)
+                .collect(),
+        }
+    }
+
+    /// 対象のソケットを切断し、切断数を返す
+    pub async fn disconnect(
+        &self,
+        target: &ClientTarget,
+        reason: Option<&str>,
+        requested_by: Option<&str>,
+    ) -> Result<i32, sqlx::Error> {
+        let mut affected = 0;
+        if let Some(ref io) = self.socketio {
+            for socket_id in self.socket_ids(target) {
+                let Some(socket) = socket_id
+                    .parse::<Sid>()
+                    .ok()
+                    .and_then(|sid| io.get_socket(sid))
+                else {
+                    continue;
+                };
+                match socket.disconnect() {
+                    Ok(()) => affected += 1,
+                    Err(e) => warn!("Failed to disconnect {}: {}", socket_id, e),
+                }
+            }
+        }
+
+        info!("Admin disconnect {:?}: {} sockets", target, affected);
+        record(
+            &self.db,
+            AdminAction::Disconnect,
+            target,
+            affected,
+            reason,
+            requested_by,
+        )
+        .await?;
+        Ok(affected)
+    }
+
+    /// 対象を隔離（または解除）し、該当する接続中のソケット数を返す
+    /// IP 指定の場合は今後同じ IP で接続したソケットも隔離される
+    pub async fn quarantine(
+        &self,
+        target: &ClientTarget,
+        release: bool,
+        reason: Option<&str>,
+        requested_by: Option<&str>,
+    ) -> Result<i32, sqlx::Error> {
+        let affected = self.socket_ids(target).len() as i32;
+        match target {
+            ClientTarget::Socket(socket_id) => {
+                self.clients.set_socket_quarantine(socket_id, !release)
+            }
+            ClientTarget::Ip(ip) => self.clients.set_ip_quarantine(ip, !release),
+        }
+
+        let action = if release {
+            AdminAction::Release
+        } else {
+            AdminAction::Quarantine
+        };
+        warn!(
+            "Admin {} {:?}: {} connected sockets",
+            action.as_str(),
+            target,
+            affected
+        );
+        record(&self.db, action, target, affected, reason, requested_by).await?;
+        Ok(affected)
+    }
+}
+
+async fn record(
+    db: &Database,
+    action: AdminAction,
+    target: &ClientTarget,
+    affected: i32,
+    reason: Option<&str>,
+    requested_by: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    let (socket_id, ip_address) = match target {
+        ClientTarget::Socket(socket_id) => (Some(socket_id.as_str()), None),
+        ClientTarget::Ip(ip) => (None, Some(ip.as_str())),
+    };
+    with_pool!(db, pool => {
+        sqlx::query(
+            "INSERT INTO client_admin_actions
+                (action, socket_id, ip_address, affected, reason, requested_by, created_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(action.as_str())
+        .bind(socket_id)
+        .bind(ip_address)
+        .bind(affected)
+        .bind(reason)
+        .bind(requested_by)
+        .bind(clock::now())
+        .execute(pool)
+        .await?;
+    });
+    Ok(())
+}
+
+/// 管理操作の履歴を新しい順に取得
+pub async fn history(db: &Database, limit: i32) -> Result<Vec<AdminActionRecord>, sqlx::Error> {
+    let records = with_pool!(db, pool => {
+        sqlx::query(
+            "SELECT id, action, socket_id, ip_address, affected, reason, requested_by, created_at
+             FROM client_admin_actions
+             ORDER BY id DESC
+             LIMIT ?",
+        )
+        .bind(limit)
+        .fetch_all(pool)
+        .await?
+        .iter()
+        .map(|row| AdminActionRecord {
+            id: row.get("id"),
+            action: row.get("action"),
+            socket_id: row.get("socket_id"),
+            ip_address: row.get("ip_address"),
+            affected: row.get("affected"),
+            reason: row.get("reason"),
+            requested_by: row.get("requested_by"),
+            created_at: row.get("created_at"),
+        })
+        .collect()
+    });
+
+    Ok(records)
+}
@@ -1,10 +1,31 @@
 // Client state management for tracking connected Socket.IO clients
 // Uses DashMap for thread-safe concurrent access
+// Liveness is derived from last_activity: idle clients are marked stale, then evicted
 
-use chrono::{DateTime, Utc};
-use dashmap::DashMap;
+use chrono::{DateTime, Duration, Utc};
+use dashmap::{DashMap, DashSet};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
+use tokio::sync::broadcast;
+
+/// Liveness of a connected client
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClientHealth {
+    /// Activity (message or heartbeat) within the stale threshold
+    Healthy,
+    /// No activity for longer than the stale threshold
+    Stale,
+}
+
+impl ClientHealth {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Healthy => "healthy",
+            Self::Stale => "stale",
+        }
+    }
+}
 
 /// Information about a connected client
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -13,54 +34,213 @@ pub struct ClientInfo {
     pub ip_address: String,
     pub connected_at: DateTime<Utc>,
     pub last_activity: DateTime<Utc>,
+    pub health: ClientHealth,
+    /// Latest terminal clock minus server clock, in seconds
+    pub clock_skew_secs: Option<i64>,
+}
+
+impl ClientInfo {
+    /// Whether the terminal has identified itself with an IP yet
+    pub fn has_ip(&self) -> bool {
+        self.ip_address != "unknown" && !self.ip_address.is_empty()
+    }
+}
+
+/// Kind of change to the client set
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientEventKind {
+    Connected,
+    Disconnected,
+    IpChanged,
+    /// Healthy -> Stale
+    Stale,
+    /// Stale -> Healthy
+    Recovered,
+    /// Removed by the sweeper after staying stale
+    Evicted,
+}
+
+/// Change notification emitted by ClientState
+#[derive(Debug, Clone)]
+pub struct ClientEvent {
+    pub kind: ClientEventKind,
+    pub client: ClientInfo,
+    /// Previous IP for IpChanged
+    pub previous_ip: Option<String>,
+}
+
+impl ClientEventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Connected => "connected",
+            Self::Disconnected => "disconnected",
+            Self::IpChanged => "ip_changed",
+            Self::Stale => "stale",
+            Self::Recovered => "recovered",
+            Self::Evicted => "evicted",
+        }
+    }
+}
+
+impl ClientEvent {
+    /// Online/offline transition for a terminal with a known IP, if this event is one
+    pub fn liveness(&self) -> Option<bool> {
+        if !self.client.has_ip() {
+            return None;
+        }
+        match self.kind {
+            ClientEventKind::IpChanged | ClientEventKind::Recovered => Some(true),
+            ClientEventKind::Stale => Some(false),
+            // A stale client already reported offline
+            ClientEventKind::Disconnected | ClientEventKind::Evicted
+                if self.client.health == ClientHealth::Healthy =>
+            {
+                Some(false)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Idle thresholds used by the sweeper
+#[derive(Debug, Clone, Copy)]
+pub struct LivenessSettings {
+    pub stale_after: Duration,
+    pub evict_after: Duration,
+    pub sweep_interval: std::time::Duration,
 }
 
 /// Thread-safe state for tracking connected clients
 #[derive(Clone)]
 pub struct ClientState {
     clients: Arc<DashMap<String, ClientInfo>>,
+    events: broadcast::Sender<ClientEvent>,
+    /// Quarantined socket ids and IPs (messages are dropped but the socket stays connected)
+    quarantined_sockets: Arc<DashSet<String>>,
+    quarantined_ips: Arc<DashSet<String>>,
 }
 
 impl ClientState {
     /// Create a new ClientState
     pub fn new() -> Self {
+        let (events, _) = broadcast::channel(256);
         Self {
             clients: Arc::new(DashMap::new()),
+            events,
+            quarantined_sockets: Arc::new(DashSet::new()),
+            quarantined_ips: Arc::new(DashSet::new()),
         }
     }
 
+    /// Subscribe to client change events
+    pub fn subscribe(&self) -> broadcast::Receiver<ClientEvent> {
+        self.events.subscribe()
+    }
+
+    fn emit(&self, kind: ClientEventKind, client: ClientInfo) {
+        self.send(ClientEvent {
+            kind,
+            client,
+            previous_ip: None,
+        });
+    }
+
+    fn send(&self, event: ClientEvent) {
+        // No subscribers is not an error
+        let _ = self.events.send(event);
+    }
+
     /// Add a new client to the state
     pub fn add_client(&self, socket_id: String, ip_address: String) {
         let now = Utc::now();
-        self.clients.insert(
-            socket_id.clone(),
-            ClientInfo {
-                socket_id,
-                ip_address,
-                connected_at: now,
-                last_activity: now,
-            },
-        );
+        let client = ClientInfo {
+            socket_id: socket_id.clone(),
+            ip_address,
+            connected_at: now,
+            last_activity: now,
+            health: ClientHealth::Healthy,
+            clock_skew_secs: None,
+        };
+        self.clients.insert(socket_id, client.clone());
+        self.emit(ClientEventKind::Connected, client);
     }
 
     /// Remove a client from the state
     pub fn remove_client(&self, socket_id: &str) -> Option<ClientInfo> {
-        self.clients.remove(socket_id).map(|(_, v)| v)
+        self.quarantined_sockets.remove(socket_id);
+        let client = self.clients.remove(socket_id).map(|(_, v)| v)?;
+        self.emit(ClientEventKind::Disconnected, client.clone());
+        Some(client)
+    }
+
+    /// Mark activity; returns true if the client recovered from stale
+    fn touch(client: &mut ClientInfo) -> bool {
+        client.last_activity = Utc::now();
+        if client.health == ClientHealth::Stale {
+            client.health = ClientHealth::Healthy;
+            true
+        } else {
+            false
+        }
     }
 
     /// Update the last activity time for a client
-    pub fn update_activity(&self, socket_id: &str) {
-        if let Some(mut client) = self.clients.get_mut(socket_id) {
-            client.last_activity = Utc::now();
+    fn update_activity(&self, socket_id: &str) {
+        let recovered = self
+            .clients
+            .get_mut(socket_id)
+            .and_then(|mut client| Self::touch(&mut client).then(|| client.clone()));
+        if let Some(client) = recovered {
+            self.emit(ClientEventKind::Recovered, client);
+        }
+    }
+
+    /// Record activity for a socket unless it is quarantined
+    /// Quarantined sockets get no liveness credit, so they go stale and are evicted
+    /// Returns false when the caller should drop the message
+    pub fn accept_activity(&self, socket_id: &str) -> bool {
+        if self.is_quarantined(socket_id) {
+            return false;
         }
+        self.update_activity(socket_id);
+        true
     }
 
     /// Update the IP address for a client
     pub fn update_ip(&self, socket_id: &str, ip_address: String) {
+        let mut events = Vec::new();
         if let Some(mut client) = self.clients.get_mut(socket_id) {
-            client.ip_address = ip_address;
-            client.last_activity = Utc::now();
+            let previous_ip = std::mem::replace(&mut client.ip_address, ip_address);
+            if Self::touch(&mut client) {
+                events.push(ClientEvent {
+                    kind: ClientEventKind::Recovered,
+                    client: client.clone(),
+                    previous_ip: None,
+                });
+            }
+            if previous_ip != client.ip_address {
+                events.push(ClientEvent {
+                    kind: ClientEventKind::IpChanged,
+                    client: client.clone(),
+                    previous_ip: Some(previous_ip),
+                });
+            }
         }
+        for event in events {
+            self.send(event);
+        }
+    }
+
+    /// Record the latest clock skew measured for a client
+    pub fn update_clock_skew(&self, socket_id: &str, skew_secs: i64) -> Option<ClientInfo> {
+        let mut client = self.clients.get_mut(socket_id)?;
+        client.clock_skew_secs = Some(skew_secs);
+        Some(client.clone())
+    }
+
+    /// Get a connected client by socket id
+    pub fn get_client(&self, socket_id: &str) -> Option<ClientInfo> {
+        self.clients.get(socket_id).map(|client| client.clone())
     }
 
     /// Get all connected clients
@@ -71,10 +251,98 @@ impl ClientState {
             .collect()
     }
 
+    /// Get connected clients reporting the given IP, most recently active first
+    pub fn find_by_ip(&self, ip_address: &str) -> Vec<ClientInfo> {
+        let mut clients: Vec<ClientInfo> = self
+            .clients
+            .iter()
+            .filter(|entry| entry.value().ip_address == ip_address)
+            .map(|entry| entry.value().clone())
+            .collect();
+        clients.sort_by_key(|c| std::cmp::Reverse(c.last_activity));
+        clients
+    }
+
     /// Get the number of connected clients
+    #[allow(dead_code)]
     pub fn get_client_count(&self) -> usize {
         self.clients.len()
     }
+
+    /// Quarantine (or release) a single socket
+    pub fn set_socket_quarantine(&self, socket_id: &str, quarantined: bool) {
+        if quarantined {
+            self.quarantined_sockets.insert(socket_id.to_string());
+        } else {
+            self.quarantined_sockets.remove(socket_id);
+        }
+    }
+
+    /// Quarantine (or release) every socket reporting the given IP, including future ones
+    pub fn set_ip_quarantine(&self, ip_address: &str, quarantined: bool) {
+        if quarantined {
+            self.quarantined_ips.insert(ip_address.to_string());
+        } else {
+            self.quarantined_ips.remove(ip_address);
+            for client in self.find_by_ip(ip_address) {
+                self.quarantined_sockets.remove(&client.socket_id);
+            }
+        }
+    }
+
+    /// Whether messages from this socket should be dropped
+    pub fn is_quarantined(&self, socket_id: &str) -> bool {
+        if self.quarantined_sockets.contains(socket_id) {
+            return true;
+        }
+        self.clients
+            .get(socket_id)
+            .is_some_and(|client| self.quarantined_ips.contains(&client.ip_address))
+    }
+
+    /// Mark idle clients stale and evict those idle past the eviction threshold
+    /// Returns the evicted clients so the caller can close their sockets
+    pub fn sweep(&self, settings: &LivenessSettings) -> Vec<ClientInfo> {
+        let now = Utc::now();
+        let mut stale = Vec::new();
+        let mut evicted = Vec::new();
+
+        for mut entry in self.clients.iter_mut() {
+            let idle = now - entry.last_activity;
+            if idle >= settings.evict_after {
+                evicted.push(entry.socket_id.clone());
+            } else if idle >= settings.stale_after && entry.health == ClientHealth::Healthy {
+                entry.health = ClientHealth::Stale;
+                stale.push(entry.clone());
+            }
+        }
+
+        for client in stale {
+            tracing::warn!(
+                "Client stale: {} ({}), last activity {}",
+                client.socket_id,
+                client.ip_address,
+                client.last_activity
+            );
+            self.emit(ClientEventKind::Stale, client);
+        }
+
+        evicted
+            .into_iter()
+            .filter_map(|socket_id| {
+                self.quarantined_sockets.remove(&socket_id);
+                self.clients.remove(&socket_id).map(|(_, v)| v)
+            })
+            .inspect(|client| {
+                tracing::warn!(
+                    "Client evicted: {} ({})",
+                    client.socket_id,
+                    client.ip_address
+                );
+                self.emit(ClientEventKind::Evicted, client.clone());
+            })
+            .collect()
+    }
 }
 
 impl Default for ClientState {
@@ -82,3 +350,213 @@ impl Default for ClientState {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> LivenessSettings {
+        LivenessSettings {
+            stale_after: Duration::seconds(90),
+            evict_after: Duration::seconds(600),
+            sweep_interval: std::time::Duration::from_secs(15),
+        }
+    }
+
+    #[test]
+    fn eviction_clears_socket_quarantine() {
+        let clients = ClientState::new();
+        clients.add_client("sock-1".to_string(), "10.0.0.5".to_string());
+        clients.set_socket_quarantine("sock-1", true);
+        assert!(clients.is_quarantined("sock-1"));
+
+        clients.clients.get_mut("sock-1").unwrap().last_activity =
+            Utc::now() - Duration::seconds(601);
+        let evicted = clients.sweep(&settings());
+
+        assert_eq!(evicted.len(), 1);
+        assert!(clients.quarantined_sockets.is_empty());
+        assert!(!clients.is_quarantined("sock-1"));
+    }
+
+    #[test]
+    fn quarantined_sockets_get_no_liveness_credit() {
+        let clients = ClientState::new();
+        clients.add_client("sock-1".to_string(), "10.0.0.5".to_string());
+        clients.add_client("sock-2".to_string(), "10.0.0.6".to_string());
+        clients.set_socket_quarantine("sock-1", true);
+        clients.set_ip_quarantine("10.0.0.6", true);
+        let idle_since = Utc::now() - Duration::seconds(601);
+        for socket_id in ["sock-1", "sock-2"] {
+            clients.clients.get_mut(socket_id).unwrap().last_activity = idle_since;
+        }
+
+        // Messages, punches and telemetry from quarantined sockets are dropped
+        assert!(!clients.accept_activity("sock-1"));
+        assert!(!clients.accept_activity("sock-2"));
+        assert_eq!(
+            clients.get_client("sock-1").unwrap().last_activity,
+            idle_since
+        );
+
+        let evicted = clients.sweep(&settings());
+        assert_eq!(evicted.len(), 2);
+    }
+
+    #[test]
+    fn accepted_activity_keeps_the_client_alive() {
+        let clients = ClientState::new();
+        clients.add_client("sock-1".to_string(), "10.0.0.5".to_string());
+        clients.clients.get_mut("sock-1").unwrap().last_activity =
+            Utc::now() - Duration::seconds(601);
+
+        assert!(clients.accept_activity("sock-1"));
+        assert!(clients.sweep(&settings()).is_empty());
+    }
+
+    #[test]
+    fn ip_quarantine_outlives_eviction() {
+        let clients = ClientState::new();
+        clients.add_client("sock-1".to_string(), "10.0.0.5".to_string());
+        clients.set_ip_quarantine("10.0.0.5", true);
+
+        clients.clients.get_mut("sock-1").unwrap().last_activity =
+            Utc::now() - Duration::seconds(601);
+        clients.sweep(&settings());
+
+        // A terminal reconnecting from the same IP stays quarantined
+        clients.add_client("sock-2".to_string(), "10.0.0.5".to_string());
+        assert!(clients.is_quarantined("sock-2"));
+    }
+
+    fn idle(clients: &ClientState, socket_id: &str, secs: i64) {
+        clients.clients.get_mut(socket_id).unwrap().last_activity =
+            Utc::now() - Duration::seconds(secs);
+    }
+
+    fn drain(events: &mut broadcast::Receiver<ClientEvent>) -> Vec<ClientEvent> {
+        std::iter::from_fn(|| events.try_recv().ok()).collect()
+    }
+
+    #[test]
+    fn sweep_marks_idle_clients_stale_once() {
+        let clients = ClientState::new();
+        clients.add_client("sock-1".to_string(), "10.0.0.5".to_string());
+        clients.add_client("sock-2".to_string(), "10.0.0.6".to_string());
+        let mut events = clients.subscribe();
+        idle(&clients, "sock-1", 91);
+
+        assert!(clients.sweep(&settings()).is_empty());
+        let emitted = drain(&mut events);
+        assert_eq!(emitted.len(), 1);
+        assert_eq!(emitted[0].kind, ClientEventKind::Stale);
+        assert_eq!(emitted[0].client.socket_id, "sock-1");
+        assert_eq!(emitted[0].liveness(), Some(false));
+        assert_eq!(
+            clients.get_client("sock-1").unwrap().health,
+            ClientHealth::Stale
+        );
+        assert_eq!(
+            clients.get_client("sock-2").unwrap().health,
+            ClientHealth::Healthy
+        );
+
+        // Still stale on the next sweep, but not reported again
+        clients.sweep(&settings());
+        assert!(drain(&mut events).is_empty());
+    }
+
+    #[test]
+    fn activity_recovers_a_stale_client() {
+        let clients = ClientState::new();
+        clients.add_client("sock-1".to_string(), "10.0.0.5".to_string());
+        idle(&clients, "sock-1", 91);
+        clients.sweep(&settings());
+        let mut events = clients.subscribe();
+
+        assert!(clients.accept_activity("sock-1"));
+        let emitted = drain(&mut events);
+        assert_eq!(emitted.len(), 1);
+        assert_eq!(emitted[0].kind, ClientEventKind::Recovered);
+        assert_eq!(emitted[0].liveness(), Some(true));
+        assert_eq!(
+            clients.get_client("sock-1").unwrap().health,
+            ClientHealth::Healthy
+        );
+
+        // Activity from a healthy client emits nothing
+        assert!(clients.accept_activity("sock-1"));
+        assert!(drain(&mut events).is_empty());
+    }
+
+    #[test]
+    fn ip_update_recovers_a_stale_client() {
+        let clients = ClientState::new();
+        clients.add_client("sock-1".to_string(), "10.0.0.5".to_string());
+        idle(&clients, "sock-1", 91);
+        clients.sweep(&settings());
+        let mut events = clients.subscribe();
+
+        clients.update_ip("sock-1", "10.0.0.5".to_string());
+        let emitted = drain(&mut events);
+        assert_eq!(emitted.len(), 1);
+        assert_eq!(emitted[0].kind, ClientEventKind::Recovered);
+
+        idle(&clients, "sock-1", 91);
+        clients.sweep(&settings());
+        drain(&mut events);
+        clients.update_ip("sock-1", "10.0.0.7".to_string());
+        let kinds: Vec<_> = drain(&mut events).iter().map(|e| e.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![ClientEventKind::Recovered, ClientEventKind::IpChanged]
+        );
+    }
+
+    #[test]
+    fn sweep_evicts_clients_idle_past_evict_after() {
+        let clients = ClientState::new();
+        clients.add_client("sock-1".to_string(), "10.0.0.5".to_string());
+        clients.add_client("sock-2".to_string(), "10.0.0.6".to_string());
+        idle(&clients, "sock-1", 601);
+        idle(&clients, "sock-2", 599);
+        let mut events = clients.subscribe();
+
+        let evicted = clients.sweep(&settings());
+        assert_eq!(evicted.len(), 1);
+        assert_eq!(evicted[0].socket_id, "sock-1");
+        assert!(clients.get_client("sock-1").is_none());
+        assert!(clients.get_client("sock-2").is_some());
+
+        let kinds: Vec<_> = drain(&mut events)
+            .iter()
+            .map(|e| (e.kind, e.client.socket_id.clone()))
+            .collect();
+        assert!(kinds.contains(&(ClientEventKind::Evicted, "sock-1".to_string())));
+        assert!(kinds.contains(&(ClientEventKind::Stale, "sock-2".to_string())));
+    }
+
+    #[test]
+    fn evicting_a_stale_client_does_not_report_offline_twice() {
+        let clients = ClientState::new();
+        clients.add_client("sock-1".to_string(), "10.0.0.5".to_string());
+        clients.add_client("sock-2".to_string(), "10.0.0.6".to_string());
+        idle(&clients, "sock-1", 91);
+        clients.sweep(&settings());
+        let mut events = clients.subscribe();
+
+        // sock-1 went stale first; sock-2 jumps straight from healthy to evicted
+        idle(&clients, "sock-1", 601);
+        idle(&clients, "sock-2", 601);
+        assert_eq!(clients.sweep(&settings()).len(), 2);
+
+        let offline: Vec<_> = drain(&mut events)
+            .iter()
+            .filter(|e| e.kind == ClientEventKind::Evicted)
+            .map(|e| (e.client.socket_id.clone(), e.liveness()))
+            .collect();
+        assert_eq!(offline.len(), 2);
+        assert!(offline.contains(&("sock-1".to_string(), None)));
+        assert!(offline.contains(&("sock-2".to_string(), Some(false))));
+    }
+}
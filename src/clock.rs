@@ -0,0 +1,80 @@
+// Business timezone
+// Punch tables store naive wall-clock times in the business timezone (BUSINESS_TIMEZONE,
+// default Asia/Tokyo) because terminals and the legacy clients write them that way.
+// Server-side "now" is taken in that timezone instead of the host's, and responses resolve
+// the stored wall-clock times to an explicit offset or a protobuf Timestamp
+
+use chrono::{
+    DateTime, Duration, FixedOffset, LocalResult, NaiveDateTime, Offset, SecondsFormat, TimeZone,
+    Utc,
+};
+use chrono_tz::Tz;
+use std::sync::OnceLock;
+
+/// BUSINESS_TIMEZONE 未設定時のタイムゾーン（従来の固定 +9 時間）
+pub const DEFAULT_TIMEZONE: Tz = chrono_tz::Asia::Tokyo;
+
+static BUSINESS_TIMEZONE: OnceLock<Tz> = OnceLock::new();
+
+/// 業務タイムゾーンを設定（起動時に1回）
+pub fn init(tz: Tz) {
+    let _ = BUSINESS_TIMEZONE.set(tz);
+}
+
+/// 業務タイムゾーン
+pub fn timezone() -> Tz {
+    BUSINESS_TIMEZONE.get().copied().unwrap_or(DEFAULT_TIMEZONE)
+}
+
+/// 業務タイムゾーンの現在時刻（DB に保存する壁時計時刻）
+pub fn now() -> NaiveDateTime {
+    from_utc(Utc::now())
+}
+
+/// 絶対時刻を業務タイムゾーンの壁時計時刻に変換
+pub fn from_utc(time: DateTime<Utc>) -> NaiveDateTime {
+    time.with_timezone(&timezone()).naive_local()
+}
+
+/// 壁時計時刻を業務タイムゾーンのオフセット付き時刻に解決
+///
+/// 夏時間の切り替えで重複する時刻は早い方、存在しない時刻は切り替え前のオフセットで解釈する
+pub fn resolve(naive: NaiveDateTime) -> DateTime<FixedOffset> {
+    let tz = timezone();
+    match tz.from_local_datetime(&naive) {
+        LocalResult::Single(time) => time.fixed_offset(),
+        LocalResult::Ambiguous(earliest, _) => earliest.fixed_offset(),
+        LocalResult::None => {
+            let offset = tz
+                .from_local_datetime(&(naive - Duration::days(1)))
+                .earliest()
+                .map(|before| before.offset().fix())
+                .unwrap_or_else(|| tz.offset_from_utc_datetime(&naive).fix());
+            offset
+                .from_local_datetime(&naive)
+                .single()
+                .unwrap_or_else(|| naive.and_utc().fixed_offset())
+        }
+    }
+}
+
+/// RFC 3339 形式（オフセット付き）
+pub fn to_rfc3339(naive: NaiveDateTime) -> String {
+    resolve(naive).to_rfc3339_opts(SecondsFormat::Secs, false)
+}
+
+/// google.protobuf.Timestamp に変換
+pub fn to_timestamp(naive: NaiveDateTime) -> prost_types::Timestamp {
+    let time = resolve(naive);
+    prost_types::Timestamp {
+        seconds: time.timestamp(),
+        nanos: time.timestamp_subsec_nanos() as i32,
+    }
+}
+
+/// オフセット付きの入力（RFC 3339）を業務タイムゾーンの壁時計時刻に変換
+pub fn parse_rfc3339(value: &str) -> Option<NaiveDateTime> {
+    DateTime::parse_from_rfc3339(value)
+        .ok()
+        .map(|time| from_utc(time.with_timezone(&Utc)))
+}
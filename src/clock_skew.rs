@@ -0,0 +1,263 @@
+// Terminal clock skew detection
+// Punch timestamps come from terminal clocks, so every time a terminal reports its own
+// time (connect auth, heartbeat, message, fresh punch without a client_id) it is
+// compared with the server clock.
+// The latest skew is kept per connection in ClientState and per terminal in
+// terminal_clock_skew, and an alert is published when it crosses the threshold
+
+use crate::client_state::ClientState;
+use crate::clock;
+use crate::db::Database;
+use crate::ingest::parse_punch_date;
+use crate::sinks::{EventBus, OutboundEvent};
+use crate::telemetry::TERMINAL_ALERT_STATUS;
+use crate::with_pool;
+use chrono::NaiveDateTime;
+use dashmap::DashSet;
+use serde_json::json;
+use sqlx::Row;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::{error, info, warn};
+
+/// terminal_clock_skew の1行
+#[derive(Debug, Clone)]
+pub struct ClockSkew {
+    pub machine_ip: String,
+    pub skew_secs: i64,
+    pub measured_at: NaiveDateTime,
+}
+
+/// 端末が報告した時刻をパース（RFC 3339 またはPythonクライアントの日時形式）
+pub fn parse_terminal_time(value: &str) -> Option<NaiveDateTime> {
+    clock::parse_rfc3339(value).or_else(|| parse_punch_date(value).ok())
+}
+
+/// 時刻ずれの記録とアラート通知
+#[derive(Clone)]
+pub struct ClockSkewMonitor {
+    db: Database,
+    clients: ClientState,
+    events: EventBus,
+    alert_after_secs: i64,
+    /// しきい値を超えている端末（同じアラートの繰り返し通知を抑止）
+    alerting: Arc<DashSet<String>>,
+}
+
+impl ClockSkewMonitor {
+    pub fn new(
+        db: Database,
+        clients: ClientState,
+        events: EventBus,
+        alert_after_secs: i64,
+    ) -> Self {
+        Self {
+            db,
+            clients,
+            events,
+            alert_after_secs,
+            alerting: Arc::new(DashSet::new()),
+        }
+    }
+
+    /// ソケットから受信した端末時刻を記録（IP が判明していれば端末単位でも記録）
+    pub async fn observe_socket(&self, socket_id: &str, terminal_time: NaiveDateTime) {
+        let skew_secs = (terminal_time - clock::now()).num_seconds();
+        let Some(client) = self.clients.update_clock_skew(socket_id, skew_secs) else {
+            return;
+        };
+        if client.has_ip() {
+            self.record(&client.ip_address, skew_secs).await;
+        }
+    }
+
+    /// gRPC など接続を持たない経路で受信した端末時刻を記録
+    pub async fn observe_terminal(&self, machine_ip: &str, terminal_time: NaiveDateTime) {
+        let skew_secs = (terminal_time - clock::now()).num_seconds();
+        self.record(machine_ip, skew_secs).await;
+    }
+
+    async fn record(&self, machine_ip: &str, skew_secs: i64) {
+        let measured_at = clock::now();
+        let sql = format!(
+            "INSERT INTO terminal_clock_skew (machine_ip, skew_secs, measured_at)
+             VALUES (?, ?, ?)
+             {}",
+            self.db
+                .dialect()
+                .upsert("machine_ip", &["skew_secs", "measured_at"])
+        );
+        let result = with_pool!(self.db, pool => {
+            sqlx::query(&sql)
+                .bind(machine_ip)
+                .bind(skew_secs)
+                .bind(measured_at)
+                .execute(pool)
+                .await
+                .map(|_| ())
+        });
+        if let Err(e) = result {
+            error!("Failed to record clock skew for {}: {}", machine_ip, e);
+        }
+
+        if skew_secs.abs() <= self.alert_after_secs {
+            if self.alerting.remove(machine_ip).is_some() {
+                info!("Clock skew of {} recovered: {}s", machine_ip, skew_secs);
+            }
+            return;
+        }
+        if !self.alerting.insert(machine_ip.to_string()) {
+            return;
+        }
+
+        let alert = format!(
+            "clock skew {}s exceeds {}s",
+            skew_secs, self.alert_after_secs
+        );
+        warn!("Terminal alert from {}: {}", machine_ip, alert);
+        let data = json!({
+            "ip": machine_ip,
+            "status": TERMINAL_ALERT_STATUS,
+            "message": alert,
+            "data": {
+                "time": measured_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+                "alerts": [alert],
+                "clock_skew_secs": skew_secs,
+            }
+        });
+        self.events.publish(OutboundEvent::from_message(data)).await;
+    }
+}
+
+/// 端末ごとの直近の時刻ずれ
+pub async fn latest_by_ip(db: &Database) -> Result<HashMap<String, ClockSkew>, sqlx::Error> {
+    let skews: Vec<ClockSkew> = with_pool!(db, pool => {
+        sqlx::query("SELECT machine_ip, skew_secs, measured_at FROM terminal_clock_skew")
+            .fetch_all(pool)
+            .await?
+            .iter()
+            .map(|row| ClockSkew {
+                machine_ip: row.get("machine_ip"),
+                skew_secs: row.get("skew_secs"),
+                measured_at: row.get("measured_at"),
+            })
+            .collect()
+    });
+
+    Ok(skews
+        .into_iter()
+        .map(|skew| (skew.machine_ip.clone(), skew))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sinks::{EventSink, SinkError, StatusFilter};
+    use chrono::Duration;
+    use std::sync::Mutex;
+
+    /// 配信されたイベントを保持するシンク
+    #[derive(Default)]
+    struct RecordingSink {
+        events: Mutex<Vec<OutboundEvent>>,
+    }
+
+    #[tonic::async_trait]
+    impl EventSink for RecordingSink {
+        fn name(&self) -> &str {
+            "recording"
+        }
+
+        async fn deliver(&self, event: &OutboundEvent) -> Result<(), SinkError> {
+            self.events.lock().unwrap().push(event.clone());
+            Ok(())
+        }
+    }
+
+    async fn monitor() -> (ClockSkewMonitor, ClientState, Arc<RecordingSink>, Database) {
+        let db = Database::memory().await;
+        let clients = ClientState::new();
+        let events = EventBus::new();
+        let sink = Arc::new(RecordingSink::default());
+        events.register(sink.clone(), StatusFilter::default());
+        let monitor = ClockSkewMonitor::new(db.clone(), clients.clone(), events, 60);
+        (monitor, clients, sink, db)
+    }
+
+    fn alerts(sink: &RecordingSink) -> usize {
+        sink.events
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|event| event.status == TERMINAL_ALERT_STATUS)
+            .count()
+    }
+
+    #[tokio::test]
+    async fn alerts_only_above_the_threshold() {
+        let (monitor, _, sink, db) = monitor().await;
+
+        monitor
+            .observe_terminal("10.0.0.5", clock::now() + Duration::seconds(30))
+            .await;
+        assert_eq!(alerts(&sink), 0);
+
+        monitor
+            .observe_terminal("10.0.0.5", clock::now() - Duration::seconds(300))
+            .await;
+        assert_eq!(alerts(&sink), 1);
+        let event = sink.events.lock().unwrap()[0].clone();
+        assert_eq!(event.machine_ip.as_deref(), Some("10.0.0.5"));
+        assert!(event.data["data"]["clock_skew_secs"].as_i64().unwrap() <= -299);
+
+        let latest = latest_by_ip(&db).await.unwrap();
+        assert_eq!(latest.len(), 1);
+        assert!(latest["10.0.0.5"].skew_secs <= -299);
+    }
+
+    #[tokio::test]
+    async fn repeated_skew_alerts_once_until_recovered() {
+        let (monitor, _, sink, _) = monitor().await;
+        let ahead = || clock::now() + Duration::seconds(600);
+
+        monitor.observe_terminal("10.0.0.5", ahead()).await;
+        monitor.observe_terminal("10.0.0.5", ahead()).await;
+        assert_eq!(alerts(&sink), 1);
+
+        // 別端末のアラートは独立
+        monitor.observe_terminal("10.0.0.6", ahead()).await;
+        assert_eq!(alerts(&sink), 2);
+
+        monitor.observe_terminal("10.0.0.5", clock::now()).await;
+        monitor.observe_terminal("10.0.0.5", ahead()).await;
+        assert_eq!(alerts(&sink), 3);
+    }
+
+    #[tokio::test]
+    async fn socket_skew_is_recorded_per_terminal_once_ip_is_known() {
+        let (monitor, clients, sink, db) = monitor().await;
+        clients.add_client("sock-1".to_string(), "unknown".to_string());
+
+        monitor
+            .observe_socket("sock-1", clock::now() + Duration::seconds(600))
+            .await;
+        assert!(
+            clients
+                .get_client("sock-1")
+                .unwrap()
+                .clock_skew_secs
+                .unwrap()
+                >= 599
+        );
+        assert!(latest_by_ip(&db).await.unwrap().is_empty());
+        assert_eq!(alerts(&sink), 0);
+
+        clients.update_ip("sock-1", "10.0.0.5".to_string());
+        monitor
+            .observe_socket("sock-1", clock::now() + Duration::seconds(600))
+            .await;
+        assert!(latest_by_ip(&db).await.unwrap()["10.0.0.5"].skew_secs >= 599);
+        assert_eq!(alerts(&sink), 1);
+    }
+}
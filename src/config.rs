@@ -1,11 +1,26 @@
+use crate::clock;
+use crate::db::replica::ReplicaSettings;
+use crate::db::{DatabaseOptions, MySqlTarget, Target};
+use crate::picture_store::{PictureStoreSettings, S3Settings};
+use crate::retention::{RetainedTable, RetentionPolicy, RetentionSettings};
+use chrono_tz::Tz;
+use sqlx::mysql::MySqlSslMode;
 use std::env;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
 
 #[derive(Debug, Clone)]
 pub struct Config {
-    pub database_url: String,
+    pub database: DatabaseOptions,
+    // Optional read replica for read-only history queries
+    pub replica: Option<ReplicaSettings>,
     pub grpc_port: u16,
     pub http_port: Option<u16>,
+    #[allow(dead_code)]
     pub log_level: String,
+    #[allow(dead_code)]
     pub socketio_url: Option<String>,
     // Socket.IO server settings
     pub socketio_server_port: Option<u16>,
@@ -13,48 +28,337 @@ pub struct Config {
     pub tls_key_path: Option<String>,
     // Cloudflare Worker broadcast URL
     pub cf_broadcast_url: Option<String>,
+    pub cf_broadcast_secret: Option<String>,
+    // Outbox delivery settings
+    pub outbox_max_attempts: i32,
+    pub outbox_base_backoff_secs: u64,
+    pub outbox_max_backoff_secs: u64,
+    pub outbox_poll_interval_ms: u64,
+    // Terminal command ack timeout (seconds)
+    pub command_ack_timeout_secs: u64,
+    // Client liveness (Socket.IO heartbeat and idle thresholds, seconds)
+    pub client_heartbeat_interval_secs: u64,
+    pub client_heartbeat_timeout_secs: u64,
+    pub client_stale_after_secs: i64,
+    pub client_evict_after_secs: i64,
+    // Telemetry alert thresholds
+    pub telemetry_min_disk_free_percent: f64,
+    pub telemetry_max_camera_image_age_secs: i64,
+    // Terminal clock skew alert threshold (seconds)
+    pub clock_skew_alert_secs: i64,
+    // Outbound event sinks (JSON array, see sinks::SinkConfig)
+    pub event_sinks: Option<String>,
+    // Apply pending schema migrations at startup (default off: startup fails while any are pending)
+    pub auto_migrate: bool,
+    // IANA timezone of the stored wall-clock times and default query ranges
+    pub business_timezone: Tz,
+    // Where camera pictures are written (database, filesystem or s3)
+    pub picture_store: PictureStoreSettings,
+    // Where release artifacts are written while pictures stay in the database
+    pub release_dir: PathBuf,
+    // Per-table retention periods and archive settings
+    pub retention: RetentionSettings,
+    // How often the driver/card directory cache is reconciled with the database (seconds)
+    pub directory_reconcile_secs: u64,
+}
+
+/// 環境変数の値が不正
+#[derive(Debug)]
+pub struct InvalidSetting {
+    pub name: &'static str,
+    pub reason: String,
+}
+
+impl fmt::Display for InvalidSetting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid setting {}: {}", self.name, self.reason)
+    }
+}
+
+impl std::error::Error for InvalidSetting {}
+
+fn invalid(name: &'static str, reason: impl Into<String>) -> InvalidSetting {
+    InvalidSetting {
+        name,
+        reason: reason.into(),
+    }
+}
+
+/// 空でない環境変数の値
+fn non_empty(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|v| !v.trim().is_empty())
+}
+
+/// 環境変数をパース（未設定は None、パースできない値はエラー）
+fn parse_env<T: FromStr>(name: &'static str) -> Result<Option<T>, InvalidSetting>
+where
+    T::Err: fmt::Display,
+{
+    non_empty(name)
+        .map(|v| {
+            v.trim()
+                .parse()
+                .map_err(|e| invalid(name, format!("'{}' ({})", v, e)))
+        })
+        .transpose()
+}
+
+/// 秒数の環境変数（0 は無期限）
+fn optional_secs(name: &'static str, default: u64) -> Result<Option<Duration>, InvalidSetting> {
+    let secs = parse_env(name)?.unwrap_or(default);
+    Ok((secs > 0).then(|| Duration::from_secs(secs)))
+}
+
+/// DATABASE_URL または RDB_* からデータベース接続設定を組み立てる
+fn database_from_env() -> Result<DatabaseOptions, InvalidSetting> {
+    // DATABASE_URL が指定された場合はそのまま使用（sqlite:timecard.db など）
+    let target = match non_empty("DATABASE_URL") {
+        Some(url) => Target::Url(url),
+        None => Target::MySql(MySqlTarget {
+            host: non_empty("RDB_HOST").unwrap_or_else(|| "localhost".to_string()),
+            port: parse_env("RDB_PORT")?.unwrap_or(3306),
+            user: non_empty("RDB_USER").unwrap_or_else(|| "root".to_string()),
+            password: env::var("RDB_PASSWORD").unwrap_or_default(),
+            database: non_empty("RDB_NAME").unwrap_or_else(|| "db".to_string()),
+            socket: non_empty("RDB_SOCKET"),
+        }),
+    };
+
+    let ssl_mode: Option<MySqlSslMode> = parse_env("RDB_SSL_MODE")?;
+    let ssl_ca = non_empty("RDB_SSL_CA");
+    if let Some(ref ca) = ssl_ca {
+        if !std::path::Path::new(ca).is_file() {
+            return Err(invalid(
+                "RDB_SSL_CA",
+                format!("'{}' is not a readable file", ca),
+            ));
+        }
+    }
+
+    let options = DatabaseOptions {
+        target,
+        ssl_mode,
+        ssl_ca,
+        max_connections: parse_env("RDB_MAX_CONNECTIONS")?.unwrap_or(25),
+        min_connections: parse_env("RDB_MIN_CONNECTIONS")?.unwrap_or(5),
+        acquire_timeout: Duration::from_secs(parse_env("RDB_ACQUIRE_TIMEOUT_SECS")?.unwrap_or(30)),
+        idle_timeout: optional_secs("RDB_IDLE_TIMEOUT_SECS", 300)?,
+        max_lifetime: optional_secs("RDB_MAX_LIFETIME_SECS", 1800)?,
+        statement_cache_capacity: parse_env("RDB_STATEMENT_CACHE_CAPACITY")?.unwrap_or(100),
+    };
+
+    if options.max_connections == 0 {
+        return Err(invalid("RDB_MAX_CONNECTIONS", "must be at least 1"));
+    }
+    if options.min_connections > options.max_connections {
+        return Err(invalid(
+            "RDB_MIN_CONNECTIONS",
+            format!(
+                "{} exceeds RDB_MAX_CONNECTIONS ({})",
+                options.min_connections, options.max_connections
+            ),
+        ));
+    }
+    if options.acquire_timeout.is_zero() {
+        return Err(invalid("RDB_ACQUIRE_TIMEOUT_SECS", "must be at least 1"));
+    }
+    if options.is_sqlite() && (options.ssl_mode.is_some() || options.ssl_ca.is_some()) {
+        return Err(invalid(
+            "RDB_SSL_MODE",
+            "TLS settings do not apply to SQLite",
+        ));
+    }
+
+    // URL の形式はここで確認し、接続時ではなく起動時にエラーにする
+    let parsed = if options.is_sqlite() {
+        options.sqlite().map(|_| ())
+    } else {
+        options.mysql().map(|_| ())
+    };
+    parsed.map_err(|e| invalid("DATABASE_URL", e.to_string()))?;
+
+    Ok(options)
+}
+
+/// DATABASE_REPLICA_URL から読み取りレプリカの設定を組み立てる（プール・TLS 設定はプライマリと共通）
+fn replica_from_env(primary: &DatabaseOptions) -> Result<Option<ReplicaSettings>, InvalidSetting> {
+    let Some(url) = non_empty("DATABASE_REPLICA_URL") else {
+        return Ok(None);
+    };
+    let database = DatabaseOptions {
+        target: Target::Url(url),
+        ..primary.clone()
+    };
+    if database.is_sqlite() != primary.is_sqlite() {
+        return Err(invalid(
+            "DATABASE_REPLICA_URL",
+            "must use the same database type as the primary",
+        ));
+    }
+    let parsed = if database.is_sqlite() {
+        database.sqlite().map(|_| ())
+    } else {
+        database.mysql().map(|_| ())
+    };
+    parsed.map_err(|e| invalid("DATABASE_REPLICA_URL", e.to_string()))?;
+
+    let check_interval_secs: u64 = parse_env("REPLICA_CHECK_INTERVAL_SECS")?.unwrap_or(10);
+    if check_interval_secs == 0 {
+        return Err(invalid("REPLICA_CHECK_INTERVAL_SECS", "must be at least 1"));
+    }
+
+    Ok(Some(ReplicaSettings {
+        database,
+        max_lag: Duration::from_secs(parse_env("REPLICA_MAX_LAG_SECS")?.unwrap_or(30)),
+        check_interval: Duration::from_secs(check_interval_secs),
+    }))
+}
+
+/// 必須の環境変数
+fn required(name: &'static str) -> Result<String, InvalidSetting> {
+    non_empty(name).ok_or_else(|| invalid(name, "must be set"))
+}
+
+/// PICTURE_STORE と関連する設定から画像の保存先を組み立てる
+fn picture_store_from_env() -> Result<PictureStoreSettings, InvalidSetting> {
+    let kind = non_empty("PICTURE_STORE").unwrap_or_else(|| "database".to_string());
+    match kind.trim().to_ascii_lowercase().as_str() {
+        "database" => Ok(PictureStoreSettings::Database),
+        "filesystem" => Ok(PictureStoreSettings::Filesystem(
+            non_empty("PICTURE_DIR")
+                .unwrap_or_else(|| "pictures".to_string())
+                .into(),
+        )),
+        "s3" => {
+            let endpoint = required("S3_ENDPOINT")?;
+            if !endpoint.starts_with("http://") && !endpoint.starts_with("https://") {
+                return Err(invalid(
+                    "S3_ENDPOINT",
+                    format!("'{}' must start with http:// or https://", endpoint),
+                ));
+            }
+            Ok(PictureStoreSettings::S3(S3Settings {
+                endpoint,
+                bucket: required("S3_BUCKET")?,
+                region: non_empty("S3_REGION").unwrap_or_else(|| "us-east-1".to_string()),
+                access_key: required("S3_ACCESS_KEY_ID")?,
+                secret_key: required("S3_SECRET_ACCESS_KEY")?,
+                prefix: non_empty("S3_PREFIX").unwrap_or_default(),
+            }))
+        }
+        other => Err(invalid(
+            "PICTURE_STORE",
+            format!("'{}' (expected database, filesystem or s3)", other),
+        )),
+    }
+}
+
+/// RETENTION_* から保持期間とアーカイブ設定を組み立てる
+fn retention_from_env() -> Result<RetentionSettings, InvalidSetting> {
+    let mut policies = Vec::new();
+    for table in RetainedTable::ALL {
+        if let Some(keep) = parse_env(table.env_name())? {
+            policies.push(RetentionPolicy { table, keep });
+        }
+    }
+
+    let interval_secs: u64 = parse_env("RETENTION_INTERVAL_SECS")?.unwrap_or(24 * 60 * 60);
+    if interval_secs == 0 {
+        return Err(invalid("RETENTION_INTERVAL_SECS", "must be at least 1"));
+    }
+    let batch_size: i64 = parse_env("RETENTION_BATCH_SIZE")?.unwrap_or(1000);
+    if batch_size < 1 {
+        return Err(invalid("RETENTION_BATCH_SIZE", "must be at least 1"));
+    }
+
+    Ok(RetentionSettings {
+        policies,
+        archive_dir: non_empty("RETENTION_ARCHIVE_DIR")
+            .unwrap_or_else(|| "archive".to_string())
+            .into(),
+        interval: Duration::from_secs(interval_secs),
+        batch_size,
+    })
 }
 
 impl Config {
-    pub fn from_env() -> Result<Self, env::VarError> {
+    pub fn from_env() -> Result<Self, InvalidSetting> {
         // Load .env file if exists
         dotenvy::dotenv().ok();
 
-        let db_host = env::var("RDB_HOST").unwrap_or_else(|_| "localhost".to_string());
-        let db_user = env::var("RDB_USER").unwrap_or_else(|_| "root".to_string());
-        let db_password = env::var("RDB_PASSWORD").unwrap_or_else(|_| "".to_string());
-        let db_name = env::var("RDB_NAME").unwrap_or_else(|_| "db".to_string());
-
-        let database_url = format!(
-            "mysql://{}:{}@{}:3306/{}",
-            db_user, db_password, db_host, db_name
-        );
+        let database = database_from_env()?;
+        let replica = replica_from_env(&database)?;
+        let picture_store = picture_store_from_env()?;
+        let release_dir = non_empty("RELEASE_DIR")
+            .unwrap_or_else(|| "releases".to_string())
+            .into();
+        let retention = retention_from_env()?;
+        let directory_reconcile_secs: u64 = parse_env("DIRECTORY_RECONCILE_SECS")?.unwrap_or(300);
+        if directory_reconcile_secs == 0 {
+            return Err(invalid("DIRECTORY_RECONCILE_SECS", "must be at least 1"));
+        }
 
-        let grpc_port = env::var("GRPC_PORT")
-            .unwrap_or_else(|_| "50051".to_string())
-            .parse()
-            .unwrap_or(50051);
-
-        let http_port = env::var("HTTP_PORT")
-            .ok()
-            .and_then(|p| p.parse().ok());
+        let grpc_port = parse_env("GRPC_PORT")?.unwrap_or(50051);
+        let http_port = parse_env("HTTP_PORT")?;
 
         let log_level = env::var("LOG_LEVEL").unwrap_or_else(|_| "info".to_string());
 
         let socketio_url = env::var("SOCKETIO_URL").ok();
 
         // Socket.IO server settings
-        let socketio_server_port = env::var("SOCKETIO_SERVER_PORT")
-            .ok()
-            .and_then(|p| p.parse().ok());
+        let socketio_server_port = parse_env("SOCKETIO_SERVER_PORT")?;
 
         let tls_cert_path = env::var("TLS_CERT_PATH").ok();
         let tls_key_path = env::var("TLS_KEY_PATH").ok();
 
         let cf_broadcast_url = env::var("CF_BROADCAST_URL").ok();
+        let cf_broadcast_secret = env::var("CF_BROADCAST_SECRET").ok();
+
+        let outbox_max_attempts: i32 = parse_env("OUTBOX_MAX_ATTEMPTS")?.unwrap_or(10);
+        if outbox_max_attempts < 1 {
+            return Err(invalid("OUTBOX_MAX_ATTEMPTS", "must be at least 1"));
+        }
+        let outbox_base_backoff_secs = parse_env("OUTBOX_BASE_BACKOFF_SECS")?.unwrap_or(2);
+        let outbox_max_backoff_secs = parse_env("OUTBOX_MAX_BACKOFF_SECS")?.unwrap_or(600);
+        let outbox_poll_interval_ms: u64 = parse_env("OUTBOX_POLL_INTERVAL_MS")?.unwrap_or(1000);
+        if outbox_poll_interval_ms == 0 {
+            return Err(invalid("OUTBOX_POLL_INTERVAL_MS", "must be at least 1"));
+        }
+
+        let command_ack_timeout_secs = parse_env("COMMAND_ACK_TIMEOUT_SECS")?.unwrap_or(10);
+
+        let client_heartbeat_interval_secs: u64 =
+            parse_env("CLIENT_HEARTBEAT_INTERVAL_SECS")?.unwrap_or(25);
+        if client_heartbeat_interval_secs == 0 {
+            return Err(invalid(
+                "CLIENT_HEARTBEAT_INTERVAL_SECS",
+                "must be at least 1",
+            ));
+        }
+        let client_heartbeat_timeout_secs =
+            parse_env("CLIENT_HEARTBEAT_TIMEOUT_SECS")?.unwrap_or(20);
+        let client_stale_after_secs = parse_env("CLIENT_STALE_AFTER_SECS")?.unwrap_or(90);
+        let client_evict_after_secs = parse_env("CLIENT_EVICT_AFTER_SECS")?.unwrap_or(600);
+
+        let telemetry_min_disk_free_percent =
+            parse_env("TELEMETRY_MIN_DISK_FREE_PERCENT")?.unwrap_or(10.0);
+        let telemetry_max_camera_image_age_secs =
+            parse_env("TELEMETRY_MAX_CAMERA_IMAGE_AGE_SECS")?.unwrap_or(3600);
+
+        let clock_skew_alert_secs = parse_env("CLOCK_SKEW_ALERT_SECS")?.unwrap_or(120);
+
+        // 本番では `timecard-backend migrate` で明示的に適用する
+        let auto_migrate = parse_env("AUTO_MIGRATE")?.unwrap_or(false);
+
+        let business_timezone = parse_env("BUSINESS_TIMEZONE")?.unwrap_or(clock::DEFAULT_TIMEZONE);
+
+        let event_sinks = env::var("EVENT_SINKS")
+            .ok()
+            .filter(|v| !v.trim().is_empty());
 
         Ok(Config {
-            database_url,
+            database,
+            replica,
             grpc_port,
             http_port,
             log_level,
@@ -63,6 +367,26 @@ impl Config {
             tls_cert_path,
             tls_key_path,
             cf_broadcast_url,
+            cf_broadcast_secret,
+            outbox_max_attempts,
+            outbox_base_backoff_secs,
+            outbox_max_backoff_secs,
+            outbox_poll_interval_ms,
+            command_ack_timeout_secs,
+            client_heartbeat_interval_secs,
+            client_heartbeat_timeout_secs,
+            client_stale_after_secs,
+            client_evict_after_secs,
+            telemetry_min_disk_free_percent,
+            telemetry_max_camera_image_age_secs,
+            clock_skew_alert_secs,
+            event_sinks,
+            auto_migrate,
+            business_timezone,
+            picture_store,
+            release_dir,
+            retention,
+            directory_reconcile_secs,
         })
     }
 }
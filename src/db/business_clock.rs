@@ -0,0 +1,50 @@
+// UTC offset of the business timezone for database triggers
+// The ic_card_history triggers stamp revoked_at from the database clock, which only knows
+// UTC. The server records the current offset of BUSINESS_TIMEZONE in business_clock at
+// startup and re-checks it hourly so daylight-saving changes reach the triggers
+
+use super::Database;
+use crate::clock;
+use chrono::{Offset, Utc};
+use std::time::Duration;
+
+/// オフセットの再確認間隔
+const CHECK_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// 業務タイムゾーンの現在の UTC オフセット（分）
+pub fn current_offset_minutes() -> i32 {
+    let now = Utc::now().with_timezone(&clock::timezone());
+    now.offset().fix().local_minus_utc() / 60
+}
+
+/// 現在のオフセットを business_clock に記録
+pub async fn record(db: &Database) -> Result<i32, sqlx::Error> {
+    let offset = current_offset_minutes();
+    crate::with_pool!(db, pool => {
+        sqlx::query("UPDATE business_clock SET utc_offset_minutes = ? WHERE id = 1")
+            .bind(offset)
+            .execute(pool)
+            .await?;
+    });
+    Ok(offset)
+}
+
+/// オフセットの変化（夏時間の切り替え）を定期的に反映
+pub fn spawn_updater(db: Database) {
+    tokio::spawn(async move {
+        let mut recorded = current_offset_minutes();
+        loop {
+            tokio::time::sleep(CHECK_INTERVAL).await;
+            if current_offset_minutes() == recorded {
+                continue;
+            }
+            match record(&db).await {
+                Ok(offset) => {
+                    tracing::info!("Business clock offset changed to {} minutes", offset);
+                    recorded = offset;
+                }
+                Err(e) => tracing::warn!("Failed to update business clock offset: {}", e),
+            }
+        }
+    });
+}
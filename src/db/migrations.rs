@@ -0,0 +1,234 @@
+// Versioned schema migrations embedded in the binary (./migrations/<dialect>)
+// Startup refuses to run against a database that has migrations this binary does not
+// know (a newer server has migrated it) or whose applied scripts were edited afterwards.
+// Pending migrations must be applied with `timecard-backend migrate`, and startup fails
+// until they are; AUTO_MIGRATE=true applies them at startup instead (local development)
+
+use super::{Database, Dialect};
+use sqlx::migrate::{Migrate, MigrateError, Migrator};
+use std::fmt;
+
+static MYSQL_MIGRATOR: Migrator = sqlx::migrate!("./migrations/mysql");
+static SQLITE_MIGRATOR: Migrator = sqlx::migrate!("./migrations/sqlite");
+
+fn migrator(dialect: Dialect) -> &'static Migrator {
+    match dialect {
+        Dialect::MySql => &MYSQL_MIGRATOR,
+        Dialect::Sqlite => &SQLITE_MIGRATOR,
+    }
+}
+
+#[derive(Debug)]
+pub enum SchemaError {
+    /// バイナリが知らないバージョンが適用済み（新しいサーバーで移行済み）
+    UnknownVersion(i64),
+    /// 適用済みのマイグレーションが変更されている
+    Modified(i64),
+    /// 途中で失敗したマイグレーションがある
+    Dirty(i64),
+    /// 未適用のマイグレーションがある（AUTO_MIGRATE 無効時）
+    Pending(Vec<i64>),
+    Migrate(MigrateError),
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SchemaError::UnknownVersion(v) => write!(
+                f,
+                "Database schema version {} is unknown to this build (latest known: {})",
+                v,
+                latest_version()
+            ),
+            SchemaError::Modified(v) => {
+                write!(f, "Migration {} was modified after it was applied", v)
+            }
+            SchemaError::Dirty(v) => write!(
+                f,
+                "Migration {} failed partway; fix the database and remove the row from _sqlx_migrations",
+                v
+            ),
+            SchemaError::Pending(versions) => write!(
+                f,
+                "Pending migrations {:?}; run `timecard-backend migrate` or set AUTO_MIGRATE=true",
+                versions
+            ),
+            SchemaError::Migrate(e) => write!(f, "Migration error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for SchemaError {}
+
+impl From<MigrateError> for SchemaError {
+    fn from(e: MigrateError) -> Self {
+        SchemaError::Migrate(e)
+    }
+}
+
+/// データベースのスキーマ状態
+#[derive(Debug)]
+pub struct SchemaStatus {
+    /// 適用済みの最新バージョン（空のデータベースは None）
+    pub current: Option<i64>,
+    /// 未適用のバージョン
+    pub pending: Vec<i64>,
+}
+
+/// バイナリに埋め込まれた最新バージョン（両方言で共通）
+pub fn latest_version() -> i64 {
+    MYSQL_MIGRATOR
+        .iter()
+        .filter(|m| m.migration_type.is_up_migration())
+        .map(|m| m.version)
+        .max()
+        .unwrap_or(0)
+}
+
+/// スキーマを検査（不明なバージョン・変更済みのマイグレーションはエラー）
+pub async fn check(db: &Database) -> Result<SchemaStatus, SchemaError> {
+    let migrator = migrator(db.dialect());
+    let (dirty, applied) = crate::with_pool!(db, pool => {
+        let mut conn = pool.acquire().await.map_err(MigrateError::from)?;
+        conn.ensure_migrations_table().await?;
+        (conn.dirty_version().await?, conn.list_applied_migrations().await?)
+    });
+    if let Some(version) = dirty {
+        return Err(SchemaError::Dirty(version));
+    }
+
+    for migration in &applied {
+        let known = migrator
+            .iter()
+            .find(|m| m.migration_type.is_up_migration() && m.version == migration.version);
+        match known {
+            None => return Err(SchemaError::UnknownVersion(migration.version)),
+            Some(m) if m.checksum != migration.checksum => {
+                return Err(SchemaError::Modified(migration.version))
+            }
+            Some(_) => {}
+        }
+    }
+
+    let pending = migrator
+        .iter()
+        .filter(|m| m.migration_type.is_up_migration())
+        .map(|m| m.version)
+        .filter(|v| !applied.iter().any(|a| a.version == *v))
+        .collect();
+
+    Ok(SchemaStatus {
+        current: applied.iter().map(|m| m.version).max(),
+        pending,
+    })
+}
+
+/// 未適用のマイグレーションを適用し、適用したバージョンを返す
+pub async fn run(db: &Database) -> Result<Vec<i64>, SchemaError> {
+    let status = check(db).await?;
+    if status.pending.is_empty() {
+        return Ok(Vec::new());
+    }
+    let migrator = migrator(db.dialect());
+    crate::with_pool!(db, pool => migrator.run(pool).await?);
+    Ok(status.pending)
+}
+
+/// 起動時のスキーマ確認（auto_migrate が有効なら未適用分を適用）
+pub async fn ensure_schema(db: &Database, auto_migrate: bool) -> Result<(), SchemaError> {
+    let status = check(db).await?;
+    if status.pending.is_empty() {
+        tracing::info!("Database schema is at version {}", latest_version());
+        return Ok(());
+    }
+    if !auto_migrate {
+        return Err(SchemaError::Pending(status.pending));
+    }
+    tracing::info!(
+        "Migrating database schema from version {} to {}",
+        status.current.unwrap_or(0),
+        latest_version()
+    );
+    let applied = run(db).await?;
+    tracing::info!(
+        "Applied migrations {:?}; database schema is at version {}",
+        applied,
+        latest_version()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock;
+    use crate::with_pool;
+    use chrono::{Duration, NaiveDateTime, Timelike};
+
+    /// version までのマイグレーションを適用（それ以降のデータ移行を確認するため）
+    async fn run_to(db: &Database, version: i64) {
+        with_pool!(db, pool => {
+            let mut conn = pool.acquire().await.unwrap();
+            conn.ensure_migrations_table().await.unwrap();
+            for migration in migrator(db.dialect()).iter().filter(|m| m.version <= version) {
+                conn.apply(migration).await.unwrap();
+            }
+        });
+    }
+
+    async fn execute(db: &Database, sql: &str) {
+        with_pool!(db, pool => {
+            sqlx::query(sql).execute(pool).await.unwrap();
+        });
+    }
+
+    #[tokio::test]
+    async fn card_history_moves_to_business_time_and_feeds_ic_card_current() {
+        let db = Database::empty_memory().await;
+        run_to(&db, 5).await;
+
+        let base = clock::now().with_nanosecond(0).unwrap();
+        let insert = |ic_id: &str, emp_id: i32, date: NaiveDateTime, deleted: i32| {
+            format!(
+                "INSERT INTO ic_id (ic_id, emp_id, date, deleted) VALUES ('{}', {}, '{}', {})",
+                ic_id,
+                emp_id,
+                date.format("%Y-%m-%d %H:%M:%S"),
+                deleted
+            )
+        };
+        // 0005 より前に解除された紐付け（履歴にない）
+        execute(&db, &insert("OLD", 9, base - Duration::days(3), 1)).await;
+        execute(&db, &insert("CARD", 1, base - Duration::hours(10), 0)).await;
+        execute(&db, &insert("CARD", 2, base - Duration::hours(5), 0)).await;
+        // 0005 の適用後に解除（revoked_at_utc に UTC で記録される）
+        execute(&db, "UPDATE ic_id SET deleted = 1 WHERE emp_id = 2").await;
+
+        run(&db).await.unwrap();
+
+        let history: Vec<(String, i32, Option<NaiveDateTime>)> = with_pool!(db, pool => {
+            sqlx::query_as("SELECT ic_id, emp_id, revoked_at FROM ic_card_history ORDER BY assigned_at")
+                .fetch_all(pool)
+                .await
+                .unwrap()
+        });
+        assert_eq!(history.len(), 3);
+        assert_eq!((history[0].0.as_str(), history[0].1), ("OLD", 9));
+        assert_eq!(history[1], ("CARD".to_string(), 1, None));
+        for (_, _, revoked_at) in [&history[0], &history[2]] {
+            let revoked_at = revoked_at.expect("revoked");
+            assert!((revoked_at - base).num_minutes().abs() <= 1);
+        }
+
+        // 削除された新しい紐付けが閉じた時刻から前の紐付けが現在の保持者
+        let current: Vec<(String, i32, NaiveDateTime)> = with_pool!(db, pool => {
+            sqlx::query_as("SELECT ic_id, emp_id, holder_since FROM ic_card_current")
+                .fetch_all(pool)
+                .await
+                .unwrap()
+        });
+        assert_eq!(current.len(), 1);
+        assert_eq!((current[0].0.as_str(), current[0].1), ("CARD", 1));
+        assert_eq!(Some(current[0].2), history[2].2);
+    }
+}
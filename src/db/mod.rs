@@ -1,3 +1,8 @@
+pub mod business_clock;
+pub mod migrations;
+mod options;
 mod pool;
+pub mod replica;
 
-pub use pool::Database;
+pub use options::{DatabaseOptions, MySqlTarget, Target};
+pub use pool::{Database, Dialect, InsertedId, Pool};
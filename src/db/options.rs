@@ -0,0 +1,133 @@
+// Database connection settings
+// Built by Config::from_env from DATABASE_URL or the individual RDB_* variables. Connection
+// parameters are handed to sqlx as typed options instead of a formatted URL, so passwords
+// need no escaping and TLS / Unix socket settings can be applied on top of either form
+
+use sqlx::mysql::{MySqlConnectOptions, MySqlSslMode};
+use sqlx::sqlite::SqliteConnectOptions;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// 接続先
+#[derive(Debug, Clone)]
+pub enum Target {
+    /// DATABASE_URL（mysql:// または sqlite:）
+    Url(String),
+    /// RDB_* の個別設定による MySQL
+    MySql(MySqlTarget),
+}
+
+/// 個別設定の MySQL 接続先
+#[derive(Clone)]
+pub struct MySqlTarget {
+    pub host: String,
+    pub port: u16,
+    pub user: String,
+    pub password: String,
+    pub database: String,
+    /// 指定時は host/port の代わりに Unix ソケットで接続
+    pub socket: Option<String>,
+}
+
+impl fmt::Debug for MySqlTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MySqlTarget")
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("user", &self.user)
+            .field("database", &self.database)
+            .field("socket", &self.socket)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DatabaseOptions {
+    pub target: Target,
+    /// TLS モード（未指定時は URL の指定または sqlx の既定 preferred）
+    pub ssl_mode: Option<MySqlSslMode>,
+    /// サーバー証明書を検証する CA 証明書（PEM）
+    pub ssl_ca: Option<String>,
+    pub max_connections: u32,
+    pub min_connections: u32,
+    pub acquire_timeout: Duration,
+    /// None の場合は無期限
+    pub idle_timeout: Option<Duration>,
+    pub max_lifetime: Option<Duration>,
+    /// 接続ごとのプリペアドステートメントのキャッシュ数
+    pub statement_cache_capacity: usize,
+}
+
+impl DatabaseOptions {
+    pub fn is_sqlite(&self) -> bool {
+        matches!(self.target, Target::Url(ref url) if url.starts_with("sqlite:"))
+    }
+
+    /// SQLite のインメモリDB（接続ごとに別のDBになる）
+    pub fn is_sqlite_memory(&self) -> bool {
+        matches!(self.target, Target::Url(ref url)
+            if url.starts_with("sqlite:") && (url.contains(":memory:") || url.contains("mode=memory")))
+    }
+
+    pub fn mysql(&self) -> Result<MySqlConnectOptions, sqlx::Error> {
+        let mut options = match self.target {
+            Target::Url(ref url) => MySqlConnectOptions::from_str(url)?,
+            Target::MySql(ref target) => {
+                let options = MySqlConnectOptions::new()
+                    .host(&target.host)
+                    .port(target.port)
+                    .username(&target.user)
+                    .password(&target.password)
+                    .database(&target.database);
+                match target.socket {
+                    Some(ref socket) => options.socket(socket),
+                    None => options,
+                }
+            }
+        };
+
+        if let Some(mode) = self.ssl_mode {
+            options = options.ssl_mode(mode);
+        }
+        if let Some(ref ca) = self.ssl_ca {
+            options = options.ssl_ca(ca);
+        }
+        Ok(options.statement_cache_capacity(self.statement_cache_capacity))
+    }
+
+    pub fn sqlite(&self) -> Result<SqliteConnectOptions, sqlx::Error> {
+        let Target::Url(ref url) = self.target else {
+            return Err(sqlx::Error::Configuration(
+                "SQLite requires a sqlite: DATABASE_URL".into(),
+            ));
+        };
+        Ok(SqliteConnectOptions::from_str(url)?
+            .create_if_missing(true)
+            .busy_timeout(Duration::from_secs(30))
+            .statement_cache_capacity(self.statement_cache_capacity))
+    }
+
+    /// ログ出力用の接続先（パスワードを含めない）
+    pub fn describe(&self) -> String {
+        match self.target {
+            Target::Url(ref url) if self.is_sqlite() => url.clone(),
+            Target::Url(_) => match self.mysql() {
+                Ok(options) => format!(
+                    "mysql://{}:{}/{}",
+                    options.get_host(),
+                    options.get_port(),
+                    options.get_database().unwrap_or_default()
+                ),
+                Err(_) => "mysql (invalid DATABASE_URL)".to_string(),
+            },
+            Target::MySql(ref target) => match target.socket {
+                Some(ref socket) => format!("mysql://{}/{}", socket, target.database),
+                None => format!(
+                    "mysql://{}:{}/{}",
+                    target.host, target.port, target.database
+                ),
+            },
+        }
+    }
+}
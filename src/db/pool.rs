@@ -1,25 +1,177 @@
-use sqlx::mysql::{MySqlPool, MySqlPoolOptions};
-use std::time::Duration;
+use super::DatabaseOptions;
+use sqlx::mysql::{MySqlPool, MySqlPoolOptions, MySqlQueryResult};
+use sqlx::sqlite::{SqlitePool, SqlitePoolOptions, SqliteQueryResult};
+
+/// 接続先データベースの種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dialect {
+    MySql,
+    /// ローカル開発・CI 用
+    Sqlite,
+}
+
+#[derive(Clone)]
+pub enum Pool {
+    MySql(MySqlPool),
+    Sqlite(SqlitePool),
+}
 
 #[derive(Clone)]
 pub struct Database {
-    pub pool: MySqlPool,
+    pool: Pool,
 }
 
 impl Database {
-    pub async fn connect(database_url: &str) -> Result<Self, sqlx::Error> {
-        let pool = MySqlPoolOptions::new()
-            .max_connections(25)
-            .min_connections(5)
-            .acquire_timeout(Duration::from_secs(30))
-            .idle_timeout(Duration::from_secs(300))
-            .connect(database_url)
-            .await?;
+    /// `sqlite:` の DATABASE_URL は SQLite、それ以外は MySQL に接続
+    pub async fn connect(options: &DatabaseOptions) -> Result<Self, sqlx::Error> {
+        let pool = if options.is_sqlite() {
+            Pool::Sqlite(
+                sqlite_pool_options(options)
+                    .connect_with(options.sqlite()?)
+                    .await?,
+            )
+        } else {
+            Pool::MySql(
+                mysql_pool_options(options)
+                    .connect_with(options.mysql()?)
+                    .await?,
+            )
+        };
+        Ok(Database { pool })
+    }
 
+    /// 接続せずにプールを作成（最初のクエリで接続。停止中の読み取りレプリカ用）
+    pub fn connect_lazy(options: &DatabaseOptions) -> Result<Self, sqlx::Error> {
+        let pool = if options.is_sqlite() {
+            Pool::Sqlite(sqlite_pool_options(options).connect_lazy_with(options.sqlite()?))
+        } else {
+            Pool::MySql(mysql_pool_options(options).connect_lazy_with(options.mysql()?))
+        };
         Ok(Database { pool })
     }
 
-    pub fn pool(&self) -> &MySqlPool {
+    /// テスト用: マイグレーション済みの SQLite インメモリDB
+    #[cfg(test)]
+    pub async fn memory() -> Self {
+        let db = Self::empty_memory().await;
+        super::migrations::run(&db).await.expect("migrations");
+        db
+    }
+
+    /// テスト用: マイグレーション前の SQLite インメモリDB
+    #[cfg(test)]
+    pub async fn empty_memory() -> Self {
+        let options = DatabaseOptions {
+            target: super::Target::Url("sqlite::memory:".to_string()),
+            ssl_mode: None,
+            ssl_ca: None,
+            max_connections: 1,
+            min_connections: 1,
+            acquire_timeout: std::time::Duration::from_secs(5),
+            idle_timeout: None,
+            max_lifetime: None,
+            statement_cache_capacity: 100,
+        };
+        Self::connect(&options).await.expect("in-memory database")
+    }
+
+    pub fn pool(&self) -> &Pool {
         &self.pool
     }
+
+    pub fn dialect(&self) -> Dialect {
+        match self.pool {
+            Pool::MySql(_) => Dialect::MySql,
+            Pool::Sqlite(_) => Dialect::Sqlite,
+        }
+    }
+}
+
+fn mysql_pool_options(options: &DatabaseOptions) -> MySqlPoolOptions {
+    MySqlPoolOptions::new()
+        .max_connections(options.max_connections)
+        .min_connections(options.min_connections)
+        .acquire_timeout(options.acquire_timeout)
+        .idle_timeout(options.idle_timeout)
+        .max_lifetime(options.max_lifetime)
+}
+
+fn sqlite_pool_options(options: &DatabaseOptions) -> SqlitePoolOptions {
+    // インメモリDBは接続ごとに別のDBになるため1接続を使い続ける
+    if options.is_sqlite_memory() {
+        return SqlitePoolOptions::new()
+            .max_connections(1)
+            .min_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None);
+    }
+    SqlitePoolOptions::new()
+        .max_connections(options.max_connections)
+        .min_connections(options.min_connections)
+        .acquire_timeout(options.acquire_timeout)
+        .idle_timeout(options.idle_timeout)
+        .max_lifetime(options.max_lifetime)
+}
+
+impl Dialect {
+    /// 重複キーの行を無視する INSERT
+    pub fn insert_ignore(self) -> &'static str {
+        match self {
+            Dialect::MySql => "INSERT IGNORE",
+            Dialect::Sqlite => "INSERT OR IGNORE",
+        }
+    }
+
+    /// 重複キー（key）の行を columns の挿入値で更新する句
+    pub fn upsert(self, key: &str, columns: &[&str]) -> String {
+        match self {
+            Dialect::MySql => format!(
+                "ON DUPLICATE KEY UPDATE {}",
+                columns
+                    .iter()
+                    .map(|c| format!("{c} = VALUES({c})"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Dialect::Sqlite => format!(
+                "ON CONFLICT ({}) DO UPDATE SET {}",
+                key,
+                columns
+                    .iter()
+                    .map(|c| format!("{c} = excluded.{c}"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+}
+
+/// INSERT で採番された ID（MySQL の LAST_INSERT_ID / SQLite の rowid）
+pub trait InsertedId {
+    fn inserted_id(&self) -> i64;
+}
+
+impl InsertedId for MySqlQueryResult {
+    fn inserted_id(&self) -> i64 {
+        self.last_insert_id() as i64
+    }
+}
+
+impl InsertedId for SqliteQueryResult {
+    fn inserted_id(&self) -> i64 {
+        self.last_insert_rowid()
+    }
+}
+
+/// 接続先のプールごとに同じ処理を展開する
+/// `with_pool!(db, pool => expr)` の expr は MySqlPool と SqlitePool の両方でコンパイルされるため、
+/// 行はブロック内でモデルに変換してから返す。方言で異なる SQL は Database::dialect で選ぶ
+#[macro_export]
+macro_rules! with_pool {
+    ($db:expr, $pool:ident => $body:expr) => {
+        match $db.pool() {
+            $crate::db::Pool::MySql($pool) => $body,
+            $crate::db::Pool::Sqlite($pool) => $body,
+        }
+    };
 }
@@ -0,0 +1,322 @@
+// Read replica health
+// When DATABASE_REPLICA_URL is set, read-only history RPCs are served from the replica.
+// ReplicaMonitor pings it and reads the replication lag periodically; reads fall back
+// to the primary while the replica is unreachable, not replicating, lagging beyond
+// REPLICA_MAX_LAG_SECS, not reporting its lag at all or has not been checked recently.
+// Clients only see a fixed reason code (FallbackReason); the details are logged
+
+use super::{Database, DatabaseOptions, Pool};
+use chrono::{DateTime, Utc};
+use sqlx::Row;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// 読み取りレプリカの設定
+#[derive(Debug, Clone)]
+pub struct ReplicaSettings {
+    pub database: DatabaseOptions,
+    /// これ以上遅延している場合はプライマリから読む
+    pub max_lag: Duration,
+    pub check_interval: Duration,
+}
+
+/// 読み取り先
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadSource {
+    Primary,
+    Replica,
+}
+
+impl ReadSource {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ReadSource::Primary => "primary",
+            ReadSource::Replica => "replica",
+        }
+    }
+}
+
+impl fmt::Display for ReadSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// レプリカを使わなかった理由（x-read-fallback に返すコード）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FallbackReason {
+    /// 起動後まだ確認していない
+    NotChecked,
+    /// 確認が止まっている
+    CheckStale,
+    /// 接続・確認クエリが失敗した
+    CheckFailed,
+    /// 遅延が REPLICA_MAX_LAG_SECS を超えている
+    LagExceeded,
+    /// レプリケーションが停止している
+    ReplicationStopped,
+    /// 遅延を確認できない接続先
+    LagUnknown,
+    /// レプリカへのクエリが失敗した
+    QueryFailed,
+}
+
+impl FallbackReason {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FallbackReason::NotChecked => "not_checked",
+            FallbackReason::CheckStale => "check_stale",
+            FallbackReason::CheckFailed => "check_failed",
+            FallbackReason::LagExceeded => "lag_exceeded",
+            FallbackReason::ReplicationStopped => "replication_stopped",
+            FallbackReason::LagUnknown => "lag_unknown",
+            FallbackReason::QueryFailed => "query_failed",
+        }
+    }
+}
+
+impl fmt::Display for FallbackReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Default)]
+struct Health {
+    checked_at: Option<DateTime<Utc>>,
+    /// 使用できない理由（None は使用可能）
+    problem: Option<FallbackReason>,
+    /// 遅延秒数（レプリケーション状態を返さない接続先は None）
+    lag_secs: Option<i64>,
+}
+
+struct Inner {
+    db: Database,
+    settings: ReplicaSettings,
+    health: RwLock<Health>,
+}
+
+#[derive(Clone)]
+pub struct ReplicaMonitor {
+    inner: Arc<Inner>,
+}
+
+impl ReplicaMonitor {
+    pub fn new(db: Database, settings: ReplicaSettings) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                db,
+                settings,
+                health: RwLock::new(Health::default()),
+            }),
+        }
+    }
+
+    pub fn database(&self) -> &Database {
+        &self.inner.db
+    }
+
+    /// 定期的に状態を確認（初回は即時）
+    pub fn spawn(&self) {
+        let monitor = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(monitor.inner.settings.check_interval);
+            loop {
+                interval.tick().await;
+                monitor.check().await;
+            }
+        });
+    }
+
+    /// 接続とレプリケーション遅延を確認して状態を更新
+    pub async fn check(&self) {
+        let max_lag = self.inner.settings.max_lag.as_secs() as i64;
+        match replication_lag(&self.inner.db).await {
+            Ok(Lag::Behind(secs)) if secs > max_lag => self.update(
+                Some((
+                    FallbackReason::LagExceeded,
+                    format!("replica lag {}s exceeds {}s", secs, max_lag),
+                )),
+                Some(secs),
+            ),
+            Ok(Lag::Behind(secs)) => self.update(None, Some(secs)),
+            // 遅延がわからない接続先は古いデータを返す可能性があるため使わない
+            Ok(Lag::Unknown) => self.update(
+                Some((
+                    FallbackReason::LagUnknown,
+                    "replica does not report replication status".to_string(),
+                )),
+                None,
+            ),
+            Ok(Lag::Stopped) => self.update(
+                Some((
+                    FallbackReason::ReplicationStopped,
+                    "replication is not running".to_string(),
+                )),
+                None,
+            ),
+            Err(e) => self.update(
+                Some((
+                    FallbackReason::CheckFailed,
+                    format!("replica check failed: {}", e),
+                )),
+                None,
+            ),
+        }
+    }
+
+    /// レプリカへのクエリが失敗した（次の確認まで使用しない）
+    pub fn mark_failed(&self, e: &sqlx::Error) {
+        let lag_secs = self.read().lag_secs;
+        self.update(
+            Some((
+                FallbackReason::QueryFailed,
+                format!("replica query failed: {}", e),
+            )),
+            lag_secs,
+        );
+    }
+
+    /// 状態を更新（problem は理由と詳細。詳細はログにだけ出す）
+    fn update(&self, problem: Option<(FallbackReason, String)>, lag_secs: Option<i64>) {
+        let mut health = self.inner.health.write().unwrap_or_else(|e| e.into_inner());
+        // 初回と使用可否・理由が変わったときだけログに出す
+        let previous = health.checked_at.map(|_| health.problem);
+        match (previous, &problem) {
+            (Some(None), None) => {}
+            (Some(Some(was)), Some((reason, _))) if was == *reason => {}
+            (_, None) => info!("Read replica is available"),
+            (_, Some((_, detail))) => warn!("Read replica unavailable: {}", detail),
+        }
+        health.checked_at = Some(Utc::now());
+        health.problem = problem.map(|(reason, _)| reason);
+        health.lag_secs = lag_secs;
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, Health> {
+        self.inner.health.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// レプリカを使用できない理由（使用可能なら None）
+    pub fn unavailable_reason(&self) -> Option<FallbackReason> {
+        let health = self.read();
+        let Some(checked_at) = health.checked_at else {
+            return Some(FallbackReason::NotChecked);
+        };
+        if let Some(problem) = health.problem {
+            return Some(problem);
+        }
+        // 確認が止まっている間は状態を信用しない
+        let stale_after = 3 * self.inner.settings.check_interval.as_secs() as i64;
+        if (Utc::now() - checked_at).num_seconds() > stale_after {
+            return Some(FallbackReason::CheckStale);
+        }
+        None
+    }
+}
+
+enum Lag {
+    Behind(i64),
+    /// レプリケーションが停止している
+    Stopped,
+    /// レプリケーション状態を返さない接続先（SQLite、状態を持たない読み取りエンドポイント）
+    Unknown,
+}
+
+async fn replication_lag(db: &Database) -> Result<Lag, sqlx::Error> {
+    let pool = match db.pool() {
+        Pool::MySql(pool) => pool,
+        Pool::Sqlite(pool) => {
+            sqlx::query("SELECT 1").execute(pool).await?;
+            return Ok(Lag::Unknown);
+        }
+    };
+
+    // MySQL 8.0.22 以降は SHOW REPLICA STATUS、それより前は SHOW SLAVE STATUS
+    let (row, column) = match sqlx::query("SHOW REPLICA STATUS")
+        .fetch_optional(pool)
+        .await
+    {
+        Ok(row) => (row, "Seconds_Behind_Source"),
+        Err(_) => (
+            sqlx::query("SHOW SLAVE STATUS")
+                .fetch_optional(pool)
+                .await?,
+            "Seconds_Behind_Master",
+        ),
+    };
+    let Some(row) = row else {
+        return Ok(Lag::Unknown);
+    };
+
+    let lag = row
+        .try_get::<Option<i64>, _>(column)
+        .or_else(|_| {
+            row.try_get::<Option<u64>, _>(column)
+                .map(|lag| lag.map(|lag| lag as i64))
+        })
+        .or_else(|_| {
+            row.try_get::<Option<String>, _>(column)
+                .map(|lag| lag.and_then(|lag| lag.parse().ok()))
+        })?;
+    Ok(match lag {
+        Some(secs) => Lag::Behind(secs),
+        None => Lag::Stopped,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Target;
+
+    fn monitor(db: Database) -> ReplicaMonitor {
+        let settings = ReplicaSettings {
+            database: DatabaseOptions {
+                target: Target::Url("sqlite::memory:".to_string()),
+                ssl_mode: None,
+                ssl_ca: None,
+                max_connections: 1,
+                min_connections: 1,
+                acquire_timeout: Duration::from_secs(5),
+                idle_timeout: None,
+                max_lifetime: None,
+                statement_cache_capacity: 100,
+            },
+            max_lag: Duration::from_secs(30),
+            check_interval: Duration::from_secs(10),
+        };
+        ReplicaMonitor::new(db, settings)
+    }
+
+    #[tokio::test]
+    async fn replica_without_replication_status_is_not_used() {
+        let monitor = monitor(Database::memory().await);
+        assert_eq!(
+            monitor.unavailable_reason(),
+            Some(FallbackReason::NotChecked)
+        );
+
+        // SQLite はレプリケーション状態を返さない
+        monitor.check().await;
+        assert_eq!(
+            monitor.unavailable_reason(),
+            Some(FallbackReason::LagUnknown)
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_query_reports_a_fixed_reason() {
+        let monitor = monitor(Database::memory().await);
+        monitor.mark_failed(&sqlx::Error::Protocol(
+            "Access denied for user 'reader'@'10.0.0.5'".to_string(),
+        ));
+        assert_eq!(
+            monitor.unavailable_reason(),
+            Some(FallbackReason::QueryFailed)
+        );
+        assert_eq!(FallbackReason::QueryFailed.to_string(), "query_failed");
+    }
+}
@@ -0,0 +1,247 @@
+// In-memory driver and card directory
+// Driver names and the current card assignments (ic_card_current) are kept in memory so
+// punches, events and IC log responses resolve names without a query per lookup.
+// The directory is loaded at startup, refreshed after a driver reload or a card change
+// requested through this server, and reconciled with the database periodically to pick
+// up changes the Python client writes directly
+
+use crate::repository::{CardRepository, DriverRepository};
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tracing::{info, warn};
+
+/// カード変更の通知から再読み込みまでの待ち時間（Pythonクライアントの反映待ち）
+const INVALIDATION_DELAY: Duration = Duration::from_secs(2);
+
+#[derive(Default)]
+struct Snapshot {
+    drivers: HashMap<i32, String>,
+    /// ICカードID → 現在の保持者（ドライバーID）
+    cards: HashMap<String, i32>,
+    loaded_at: Option<DateTime<Utc>>,
+    /// 再読み込み待ちの変更通知
+    invalidated_at: Option<DateTime<Utc>>,
+    refreshes: u64,
+    /// 直前の再読み込みで変化した件数
+    last_changes: usize,
+    last_error: Option<String>,
+}
+
+/// キャッシュの統計
+#[derive(Debug, Clone)]
+pub struct DirectoryStats {
+    pub drivers: usize,
+    pub cards: usize,
+    pub driver_hits: u64,
+    pub driver_misses: u64,
+    pub card_hits: u64,
+    pub card_misses: u64,
+    pub refreshes: u64,
+    pub loaded_at: Option<DateTime<Utc>>,
+    /// 最終読み込みからの経過秒数
+    pub age_secs: Option<i64>,
+    /// 変更通知後の再読み込み待ち、または照合間隔の2倍以上更新されていない
+    pub stale: bool,
+    pub last_changes: usize,
+    pub last_error: Option<String>,
+}
+
+struct Inner {
+    drivers: Arc<dyn DriverRepository>,
+    cards: Arc<dyn CardRepository>,
+    reconcile_interval: Duration,
+    snapshot: RwLock<Snapshot>,
+    invalidated: Notify,
+    driver_hits: AtomicU64,
+    driver_misses: AtomicU64,
+    card_hits: AtomicU64,
+    card_misses: AtomicU64,
+}
+
+#[derive(Clone)]
+pub struct DriverDirectory {
+    inner: Arc<Inner>,
+}
+
+impl DriverDirectory {
+    pub fn new(
+        drivers: Arc<dyn DriverRepository>,
+        cards: Arc<dyn CardRepository>,
+        reconcile_interval: Duration,
+    ) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                drivers,
+                cards,
+                reconcile_interval,
+                snapshot: RwLock::new(Snapshot::default()),
+                invalidated: Notify::new(),
+                driver_hits: AtomicU64::new(0),
+                driver_misses: AtomicU64::new(0),
+                card_hits: AtomicU64::new(0),
+                card_misses: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, Snapshot> {
+        self.inner
+            .snapshot
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, Snapshot> {
+        self.inner
+            .snapshot
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// DB から全件を読み込み直す（変化した件数を返す）
+    pub async fn refresh(&self) -> Result<usize, sqlx::Error> {
+        let loaded = async {
+            let drivers = self.inner.drivers.list().await?;
+            let cards = self.inner.cards.current_assignments().await?;
+            Ok::<_, sqlx::Error>((drivers, cards))
+        }
+        .await;
+
+        let mut snapshot = self.write();
+        let (drivers, cards) = match loaded {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                snapshot.last_error = Some(e.to_string());
+                return Err(e);
+            }
+        };
+        let drivers: HashMap<i32, String> = drivers.into_iter().map(|d| (d.id, d.name)).collect();
+        let cards: HashMap<String, i32> = cards.into_iter().map(|c| (c.ic_id, c.emp_id)).collect();
+
+        let changes =
+            changed_entries(&snapshot.drivers, &drivers) + changed_entries(&snapshot.cards, &cards);
+        snapshot.drivers = drivers;
+        snapshot.cards = cards;
+        snapshot.loaded_at = Some(Utc::now());
+        snapshot.invalidated_at = None;
+        snapshot.refreshes += 1;
+        snapshot.last_changes = changes;
+        snapshot.last_error = None;
+        Ok(changes)
+    }
+
+    /// カードの紐付けが変わる操作の後に呼ぶ（少し待ってから再読み込み）
+    pub fn invalidate(&self) {
+        self.write().invalidated_at.get_or_insert_with(Utc::now);
+        self.inner.invalidated.notify_one();
+    }
+
+    /// 変更通知と定期照合による再読み込みを開始
+    pub fn spawn_reconciler(&self) {
+        let directory = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let periodic = tokio::select! {
+                    _ = directory.inner.invalidated.notified() => {
+                        tokio::time::sleep(INVALIDATION_DELAY).await;
+                        false
+                    }
+                    _ = tokio::time::sleep(directory.inner.reconcile_interval) => true,
+                };
+                match directory.refresh().await {
+                    // 定期照合で差分が見つかった = 他から変更された
+                    Ok(changes) if periodic && changes > 0 => {
+                        info!("Driver directory reconciled: {} entries changed", changes)
+                    }
+                    Ok(_) => {}
+                    Err(e) => warn!("Failed to refresh driver directory: {}", e),
+                }
+            }
+        });
+    }
+
+    /// ドライバー名（キャッシュにない場合は DB を参照して追加）
+    pub async fn driver_name(&self, driver_id: i32) -> Result<Option<String>, sqlx::Error> {
+        if let Some(name) = self.read().drivers.get(&driver_id) {
+            self.inner.driver_hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(Some(name.clone()));
+        }
+        self.inner.driver_misses.fetch_add(1, Ordering::Relaxed);
+
+        let driver = self.inner.drivers.get(driver_id).await?;
+        if let Some(ref driver) = driver {
+            self.write().drivers.insert(driver.id, driver.name.clone());
+        }
+        Ok(driver.map(|d| d.name))
+    }
+
+    /// ICカードの現在の保持者
+    pub fn card_holder(&self, ic_id: &str) -> Option<i32> {
+        let holder = self.read().cards.get(ic_id).copied();
+        let counter = match holder {
+            Some(_) => &self.inner.card_hits,
+            None => &self.inner.card_misses,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        holder
+    }
+
+    /// ICログのドライバー名
+    ///
+    /// ICカードはログの時刻に紐付いていた保持者（holder_id、リポジトリが ic_card_history から解決）、
+    /// 紐付けがなければ免許証の iid で解決
+    pub async fn ic_log_driver_name(
+        &self,
+        holder_id: Option<i32>,
+        iid: Option<&str>,
+    ) -> Option<String> {
+        let driver_id = holder_id.or_else(|| iid.and_then(|iid| iid.trim().parse().ok()))?;
+        match self.driver_name(driver_id).await {
+            Ok(name) => name,
+            Err(e) => {
+                warn!("Failed to look up driver {}: {}", driver_id, e);
+                None
+            }
+        }
+    }
+
+    pub fn stats(&self) -> DirectoryStats {
+        let snapshot = self.read();
+        let now = Utc::now();
+        let age_secs = snapshot.loaded_at.map(|at| (now - at).num_seconds());
+        let stale_after = 2 * self.inner.reconcile_interval.as_secs() as i64;
+        DirectoryStats {
+            drivers: snapshot.drivers.len(),
+            cards: snapshot.cards.len(),
+            driver_hits: self.inner.driver_hits.load(Ordering::Relaxed),
+            driver_misses: self.inner.driver_misses.load(Ordering::Relaxed),
+            card_hits: self.inner.card_hits.load(Ordering::Relaxed),
+            card_misses: self.inner.card_misses.load(Ordering::Relaxed),
+            refreshes: snapshot.refreshes,
+            loaded_at: snapshot.loaded_at,
+            age_secs,
+            stale: snapshot.invalidated_at.is_some()
+                || age_secs.is_none_or(|age| age > stale_after),
+            last_changes: snapshot.last_changes,
+            last_error: snapshot.last_error.clone(),
+        }
+    }
+}
+
+/// 追加・変更・削除された件数
+fn changed_entries<K, V>(old: &HashMap<K, V>, new: &HashMap<K, V>) -> usize
+where
+    K: std::hash::Hash + Eq,
+    V: PartialEq,
+{
+    let changed = new
+        .iter()
+        .filter(|(key, value)| old.get(key) != Some(value))
+        .count();
+    let removed = old.keys().filter(|key| !new.contains_key(key)).count();
+    changed + removed
+}
@@ -1,17 +1,24 @@
 // HTTP REST API endpoints
 
 use axum::{
-    extract::State,
-    http::StatusCode,
+    body::Body,
+    extract::{FromRef, Path, Query, State},
+    http::{header, StatusCode},
+    response::Response,
     routing::get,
     Json, Router,
 };
-use chrono::{Duration, Local, NaiveDateTime};
-use serde::Serialize;
+use chrono::{Duration, NaiveDateTime};
+use serde::{Deserialize, Serialize};
 use sqlx::Row;
+use std::sync::Arc;
 use tower_http::cors::{Any, CorsLayer};
 
+use crate::clock;
 use crate::db::Database;
+use crate::picture_store::ObjectStore;
+use crate::releases;
+use crate::with_pool;
 
 /// CakePHP互換のレスポンス形式
 #[derive(Debug, Serialize)]
@@ -19,6 +26,8 @@ pub struct IcLogResponse {
     pub id: Option<String>,
     pub datetime: String,
     pub machine_ip: String,
+    /// datetime を業務タイムゾーンのオフセット付きで表した RFC 3339 形式
+    pub timestamp: String,
 }
 
 #[derive(Debug, Serialize)]
@@ -26,10 +35,31 @@ pub struct FingerLogResponse {
     pub id: i32,
     pub datetime: String,
     pub machine_ip: String,
+    /// datetime を業務タイムゾーンのオフセット付きで表した RFC 3339 形式
+    pub timestamp: String,
+}
+
+/// リリースのダウンロードパラメータ（登録済み端末の ip 指定時は配信状況に記録）
+#[derive(Debug, Deserialize)]
+pub struct DownloadParams {
+    pub ip: Option<String>,
+}
+
+/// ルーターの状態（データベースとリリース成果物の保存先）
+#[derive(Clone)]
+pub struct ApiState {
+    pub db: Database,
+    pub release_artifacts: Arc<dyn ObjectStore>,
+}
+
+impl FromRef<ApiState> for Database {
+    fn from_ref(state: &ApiState) -> Self {
+        state.db.clone()
+    }
 }
 
 /// データベース付きルーターを作成
-pub fn create_router_with_db(db: Database) -> Router {
+pub fn create_router_with_db(db: Database, release_artifacts: Arc<dyn ObjectStore>) -> Router {
     let cors = CorsLayer::new()
         .allow_origin(Any)
         .allow_methods(Any)
@@ -39,16 +69,24 @@ pub fn create_router_with_db(db: Database) -> Router {
         .route("/health", get(health_check))
         .route("/api/ic_log", get(get_ic_log))
         .route("/api/finger_log", get(get_finger_log))
-        .with_state(db)
+        .route("/api/releases/{id}/download", get(download_release))
+        .with_state(ApiState {
+            db,
+            release_artifacts,
+        })
         .layer(cors)
 }
 
 /// APIルートのみを作成（Socket.IOルーターにマージ用）
-pub fn create_api_routes(db: Database) -> Router<()> {
+pub fn create_api_routes(db: Database, release_artifacts: Arc<dyn ObjectStore>) -> Router<()> {
     Router::new()
         .route("/api/ic_log", get(get_ic_log))
         .route("/api/finger_log", get(get_finger_log))
-        .with_state(db)
+        .route("/api/releases/{id}/download", get(download_release))
+        .with_state(ApiState {
+            db,
+            release_artifacts,
+        })
 }
 
 async fn health_check() -> &'static str {
@@ -60,38 +98,41 @@ async fn health_check() -> &'static str {
 async fn get_ic_log(
     State(db): State<Database>,
 ) -> Result<Json<Vec<IcLogResponse>>, (StatusCode, String)> {
-    let two_days_ago = Local::now() - Duration::days(2);
-    let start_date = two_days_ago.format("%Y-%m-%d %H:%M:%S").to_string();
-
-    let rows = sqlx::query(
-        "SELECT date, iid, machine_ip FROM ic_log WHERE date >= ? ORDER BY date ASC",
-    )
-    .bind(&start_date)
-    .fetch_all(db.pool())
-    .await
-    .map_err(|e| {
-        tracing::error!("Database error in get_ic_log: {}", e);
-        (StatusCode::INTERNAL_SERVER_ERROR, format!("Database error: {}", e))
-    })?;
-
-    let mut logs: Vec<IcLogResponse> = Vec::new();
-    for row in rows {
-        let date: NaiveDateTime = match row.try_get("date") {
-            Ok(d) => d,
-            Err(e) => {
-                tracing::warn!("Failed to get date from ic_log row: {}", e);
-                continue;
-            }
-        };
-        let id: Option<String> = row.try_get("iid").ok();
-        let machine_ip: String = row.try_get("machine_ip").unwrap_or_default();
-
-        logs.push(IcLogResponse {
-            id,
-            datetime: date.format("%Y-%m-%d %H:%M:%S").to_string(),
-            machine_ip,
-        });
-    }
+    let start_date = clock::now() - Duration::days(2);
+
+    let logs: Vec<IcLogResponse> = with_pool!(db, pool => {
+        let rows = sqlx::query(
+            "SELECT date, iid, machine_ip FROM ic_log WHERE date >= ? ORDER BY date ASC",
+        )
+        .bind(start_date)
+        .fetch_all(pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("Database error in get_ic_log: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, format!("Database error: {}", e))
+        })?;
+
+        let mut logs: Vec<IcLogResponse> = Vec::new();
+        for row in rows {
+            let date: NaiveDateTime = match row.try_get("date") {
+                Ok(d) => d,
+                Err(e) => {
+                    tracing::warn!("Failed to get date from ic_log row: {}", e);
+                    continue;
+                }
+            };
+            let id: Option<String> = row.try_get("iid").ok();
+            let machine_ip: String = row.try_get("machine_ip").unwrap_or_default();
+
+            logs.push(IcLogResponse {
+                id,
+                datetime: date.format("%Y-%m-%d %H:%M:%S").to_string(),
+                machine_ip,
+                timestamp: clock::to_rfc3339(date),
+            });
+        }
+        logs
+    });
 
     Ok(Json(logs))
 }
@@ -101,44 +142,95 @@ async fn get_ic_log(
 async fn get_finger_log(
     State(db): State<Database>,
 ) -> Result<Json<Vec<FingerLogResponse>>, (StatusCode, String)> {
-    let two_days_ago = Local::now() - Duration::days(2);
-    let start_date = two_days_ago.format("%Y-%m-%d %H:%M:%S").to_string();
-
-    let rows = sqlx::query(
-        "SELECT id, date, machine_ip FROM finger_log WHERE date >= ? ORDER BY date ASC",
-    )
-    .bind(&start_date)
-    .fetch_all(db.pool())
-    .await
-    .map_err(|e| {
-        tracing::error!("Database error in get_finger_log: {}", e);
-        (StatusCode::INTERNAL_SERVER_ERROR, format!("Database error: {}", e))
-    })?;
-
-    let mut logs: Vec<FingerLogResponse> = Vec::new();
-    for row in rows {
-        let date: NaiveDateTime = match row.try_get("date") {
-            Ok(d) => d,
-            Err(e) => {
-                tracing::warn!("Failed to get date from finger_log row: {}", e);
-                continue;
-            }
-        };
-        let id: i32 = match row.try_get("id") {
-            Ok(i) => i,
-            Err(e) => {
-                tracing::warn!("Failed to get id from finger_log row: {}", e);
-                continue;
-            }
-        };
-        let machine_ip: String = row.try_get("machine_ip").unwrap_or_default();
-
-        logs.push(FingerLogResponse {
-            id,
-            datetime: date.format("%Y-%m-%d %H:%M:%S").to_string(),
-            machine_ip,
-        });
-    }
+    let start_date = clock::now() - Duration::days(2);
+
+    let logs: Vec<FingerLogResponse> = with_pool!(db, pool => {
+        let rows = sqlx::query(
+            "SELECT id, date, machine_ip FROM finger_log WHERE date >= ? ORDER BY date ASC",
+        )
+        .bind(start_date)
+        .fetch_all(pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("Database error in get_finger_log: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, format!("Database error: {}", e))
+        })?;
+
+        let mut logs: Vec<FingerLogResponse> = Vec::new();
+        for row in rows {
+            let date: NaiveDateTime = match row.try_get("date") {
+                Ok(d) => d,
+                Err(e) => {
+                    tracing::warn!("Failed to get date from finger_log row: {}", e);
+                    continue;
+                }
+            };
+            let id: i32 = match row.try_get("id") {
+                Ok(i) => i,
+                Err(e) => {
+                    tracing::warn!("Failed to get id from finger_log row: {}", e);
+                    continue;
+                }
+            };
+            let machine_ip: String = row.try_get("machine_ip").unwrap_or_default();
+
+            logs.push(FingerLogResponse {
+                id,
+                datetime: date.format("%Y-%m-%d %H:%M:%S").to_string(),
+                machine_ip,
+                timestamp: clock::to_rfc3339(date),
+            });
+        }
+        logs
+    });
 
     Ok(Json(logs))
 }
+
+/// /api/releases/{id}/download - 端末ソフトウェアの配布（成果物はストアからストリーミング）
+async fn download_release(
+    State(state): State<ApiState>,
+    Path(id): Path<i64>,
+    Query(params): Query<DownloadParams>,
+) -> Result<Response, (StatusCode, String)> {
+    let (release, artifact) =
+        releases::open_artifact(&state.db, state.release_artifacts.as_ref(), id)
+            .await
+            .map_err(|e| {
+                tracing::error!("Failed to open artifact of release {}: {}", id, e);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("Failed to open artifact of release {}", id),
+                )
+            })?
+            .ok_or_else(|| (StatusCode::NOT_FOUND, format!("Release {} not found", id)))?;
+
+    // 未登録の IP は記録しない（誰でも配信状況を書き換えられないように）
+    if let Some(ip) = params.ip.filter(|ip| !ip.is_empty()) {
+        match releases::record_download(&state.db, release.id, &ip).await {
+            Ok(true) => {}
+            Ok(false) => tracing::warn!(
+                "Download of release {} reported for unknown terminal {}",
+                id,
+                ip
+            ),
+            Err(e) => tracing::warn!(
+                "Failed to record download of release {} by {}: {}",
+                id,
+                ip,
+                e
+            ),
+        }
+    }
+
+    Response::builder()
+        .header(header::CONTENT_TYPE, "application/octet-stream")
+        .header(header::CONTENT_LENGTH, release.size_bytes)
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", release.filename),
+        )
+        .header("X-Checksum-Sha256", release.sha256)
+        .body(Body::from_stream(artifact))
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
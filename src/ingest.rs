@@ -0,0 +1,527 @@
+// Punch ingestion
+// Validates punches sent by terminals and writes tmp_data / pic_data / ic_log rows
+// in a single transaction, replacing the direct MySQL writes of the Python client.
+// Punches carrying a client-generated id are recorded in ingest_receipts so replays
+// from terminals that were offline are stored only once
+
+use crate::clock;
+use crate::db::Database;
+use crate::directory::DriverDirectory;
+use crate::picture_store::{self, PictureStore, PictureStoreError, StoredPicture};
+use crate::with_pool;
+use base64::Engine;
+use chrono::NaiveDateTime;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::fmt;
+
+/// 1枚あたりの画像サイズ上限
+pub const MAX_PHOTO_BYTES: usize = 8 * 1024 * 1024;
+
+/// 1打刻あたりの画像枚数上限
+pub const MAX_PHOTOS: usize = 4;
+
+/// 1回のバッチ登録で受け付ける打刻数の上限
+pub const MAX_BATCH_PUNCHES: usize = 500;
+
+/// client_id の最大長
+const MAX_CLIENT_ID_LEN: usize = 64;
+
+/// Pythonクライアントと同じ日時フォーマット
+const DATE_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// 打刻ステータス（Pythonクライアントの status 文字列と一致）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PunchStatus {
+    TmpInserted,
+    TmpInsertedWoPic,
+    TmpInsertedByIc,
+    TmpInsertedByFing,
+    InsertIcLog,
+}
+
+impl PunchStatus {
+    pub fn parse(status: &str) -> Option<Self> {
+        match status {
+            "tmp inserted" => Some(Self::TmpInserted),
+            "tmp inserted wo pic" => Some(Self::TmpInsertedWoPic),
+            "tmp inserted by ic" => Some(Self::TmpInsertedByIc),
+            "tmp inserted by fing" => Some(Self::TmpInsertedByFing),
+            "insert ic_log" => Some(Self::InsertIcLog),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::TmpInserted => "tmp inserted",
+            Self::TmpInsertedWoPic => "tmp inserted wo pic",
+            Self::TmpInsertedByIc => "tmp inserted by ic",
+            Self::TmpInsertedByFing => "tmp inserted by fing",
+            Self::InsertIcLog => "insert ic_log",
+        }
+    }
+}
+
+/// 温度・環境・距離の計測値（カンマ区切り文字列）
+#[derive(Debug, Clone)]
+pub struct Readings {
+    pub tmp: String,
+    pub amb: String,
+    pub dist: String,
+}
+
+/// ICカード読み取り（ic_log 1行分）
+#[derive(Debug, Clone)]
+pub struct IcRead {
+    pub id: String,
+    pub log_type: String,
+    pub detail: Option<String>,
+    pub iid: Option<String>,
+}
+
+/// 撮影画像
+#[derive(Debug, Clone)]
+pub struct Photo {
+    pub cam: i32,
+    /// pic_data.detail（未指定の場合は打刻ステータス）
+    pub detail: Option<String>,
+    pub data: Vec<u8>,
+}
+
+/// 端末から送信される1回分の打刻
+#[derive(Debug, Clone)]
+pub struct Punch {
+    pub machine_ip: String,
+    pub status: PunchStatus,
+    pub date: NaiveDateTime,
+    pub driver_id: Option<i32>,
+    pub readings: Option<Readings>,
+    pub ic: Option<IcRead>,
+    pub photos: Vec<Photo>,
+    pub message: Option<String>,
+    /// 端末が生成する一意なID（再送時の重複排除に使用）
+    pub client_id: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum IngestError {
+    Invalid(String),
+    Database(sqlx::Error),
+    /// 画像ストアへの保存に失敗
+    Storage(PictureStoreError),
+}
+
+impl fmt::Display for IngestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IngestError::Invalid(msg) => write!(f, "Invalid punch: {}", msg),
+            IngestError::Database(e) => write!(f, "Database error: {}", e),
+            IngestError::Storage(e) => write!(f, "Picture store error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for IngestError {}
+
+impl From<sqlx::Error> for IngestError {
+    fn from(e: sqlx::Error) -> Self {
+        IngestError::Database(e)
+    }
+}
+
+impl From<PictureStoreError> for IngestError {
+    fn from(e: PictureStoreError) -> Self {
+        IngestError::Storage(e)
+    }
+}
+
+fn invalid(msg: impl Into<String>) -> IngestError {
+    IngestError::Invalid(msg.into())
+}
+
+/// 日時文字列をパース（"YYYY-MM-DD HH:MM:SS" / "YYYY-MM-DDTHH:MM:SS" は業務タイムゾーンの時刻、
+/// オフセット付きの RFC 3339 は業務タイムゾーンに変換）
+pub fn parse_punch_date(value: &str) -> Result<NaiveDateTime, IngestError> {
+    if let Some(date) = clock::parse_rfc3339(value) {
+        return Ok(date);
+    }
+    NaiveDateTime::parse_from_str(value, DATE_FORMAT)
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S"))
+        .map_err(|_| invalid(format!("invalid date '{}'", value)))
+}
+
+/// base64文字列をデコード
+pub fn decode_photo(value: &str) -> Result<Vec<u8>, IngestError> {
+    base64::engine::general_purpose::STANDARD
+        .decode(value.trim())
+        .map_err(|e| invalid(format!("invalid base64 photo: {}", e)))
+}
+
+/// 登録しなかった打刻の画像を画像ストアから削除（失敗はログのみ）
+async fn discard_pictures(pictures: &dyn PictureStore, stored: &[StoredPicture]) {
+    for picture in stored {
+        if let StoredPicture::External(key) = picture {
+            if let Err(e) = pictures.delete(key).await {
+                tracing::warn!("Failed to delete unused picture {}: {}", key, e);
+            }
+        }
+    }
+}
+
+impl Punch {
+    /// 打刻内容を検証
+    pub fn validate(&self) -> Result<(), IngestError> {
+        if self.machine_ip.trim().is_empty() || self.machine_ip == "unknown" {
+            return Err(invalid("machine_ip is required"));
+        }
+
+        match self.status {
+            PunchStatus::TmpInserted | PunchStatus::TmpInsertedWoPic => {
+                if self.readings.is_none() {
+                    return Err(invalid(format!(
+                        "readings are required for '{}'",
+                        self.status.as_str()
+                    )));
+                }
+            }
+            PunchStatus::TmpInsertedByIc | PunchStatus::TmpInsertedByFing => {
+                if self.driver_id.is_none() {
+                    return Err(invalid(format!(
+                        "driver_id is required for '{}'",
+                        self.status.as_str()
+                    )));
+                }
+            }
+            PunchStatus::InsertIcLog => {
+                if self.ic.is_none() {
+                    return Err(invalid("ic is required for 'insert ic_log'"));
+                }
+            }
+        }
+
+        if self.status == PunchStatus::TmpInsertedWoPic && !self.photos.is_empty() {
+            return Err(invalid("photos are not allowed for 'tmp inserted wo pic'"));
+        }
+
+        if let Some(driver_id) = self.driver_id {
+            if driver_id <= 0 {
+                return Err(invalid(format!("invalid driver_id {}", driver_id)));
+            }
+        }
+
+        if let Some(ref ic) = self.ic {
+            if ic.id.trim().is_empty() {
+                return Err(invalid("ic.id is required"));
+            }
+        }
+
+        if let Some(ref client_id) = self.client_id {
+            if client_id.trim().is_empty() || client_id.len() > MAX_CLIENT_ID_LEN {
+                return Err(invalid(format!("invalid client_id '{}'", client_id)));
+            }
+        }
+
+        if self.photos.len() > MAX_PHOTOS {
+            return Err(invalid(format!(
+                "too many photos ({} > {})",
+                self.photos.len(),
+                MAX_PHOTOS
+            )));
+        }
+
+        for photo in &self.photos {
+            if photo.cam < 0 {
+                return Err(invalid(format!("invalid cam {}", photo.cam)));
+            }
+            if photo.data.is_empty() {
+                return Err(invalid(format!("photo for cam {} is empty", photo.cam)));
+            }
+            if photo.data.len() > MAX_PHOTO_BYTES {
+                return Err(invalid(format!(
+                    "photo for cam {} exceeds {} bytes",
+                    photo.cam, MAX_PHOTO_BYTES
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// 関連する行を1トランザクションで書き込み
+    ///
+    /// client_id が受信済みの場合は何も書き込まずに false を返す。
+    /// 画像は先に画像ストアへ保存し、書き込まなかった場合は削除する
+    pub async fn store(
+        &self,
+        db: &Database,
+        pictures: &dyn PictureStore,
+        backfilled: bool,
+    ) -> Result<bool, IngestError> {
+        let mut stored = Vec::with_capacity(self.photos.len());
+        for photo in &self.photos {
+            let key = picture_store::picture_key(&self.machine_ip, self.date, photo.cam);
+            match pictures.put(&key, photo.data.clone()).await {
+                Ok(picture) => stored.push(picture),
+                Err(e) => {
+                    discard_pictures(pictures, &stored).await;
+                    return Err(e.into());
+                }
+            }
+        }
+
+        let result = self.write_rows(db, &stored, backfilled).await;
+        if !matches!(result, Ok(true)) {
+            discard_pictures(pictures, &stored).await;
+        }
+        result
+    }
+
+    async fn write_rows(
+        &self,
+        db: &Database,
+        pictures: &[StoredPicture],
+        backfilled: bool,
+    ) -> Result<bool, IngestError> {
+        let receipt_sql = format!(
+            "{} INTO ingest_receipts
+                (machine_ip, client_id, status, punch_date, backfilled, received_at)
+             VALUES (?, ?, ?, ?, ?, ?)",
+            db.dialect().insert_ignore()
+        );
+
+        with_pool!(db, pool => {
+            let mut tx = pool.begin().await?;
+
+            if let Some(ref client_id) = self.client_id {
+                let inserted = sqlx::query(&receipt_sql)
+                    .bind(&self.machine_ip)
+                    .bind(client_id)
+                    .bind(self.status.as_str())
+                    .bind(self.date)
+                    .bind(backfilled)
+                    .bind(clock::now())
+                    .execute(&mut *tx)
+                    .await?
+                    .rows_affected();
+                if inserted == 0 {
+                    return Ok(false);
+                }
+            }
+
+            // 計測値は id=0 の行、ドライバー紐付けは id=driver_id の行
+            if let Some(ref readings) = self.readings {
+                sqlx::query(
+                    "INSERT INTO tmp_data (machine_ip, tmp, amb, dist, date, id)
+                     VALUES (?, ?, ?, ?, ?, 0)",
+                )
+                .bind(&self.machine_ip)
+                .bind(&readings.tmp)
+                .bind(&readings.amb)
+                .bind(&readings.dist)
+                .bind(self.date)
+                .execute(&mut *tx)
+                .await?;
+            }
+
+            if let Some(driver_id) = self.driver_id {
+                let (tmp, amb, dist) = self
+                    .readings
+                    .as_ref()
+                    .map(|r| (r.tmp.as_str(), r.amb.as_str(), r.dist.as_str()))
+                    .unwrap_or(("", "", ""));
+                sqlx::query(
+                    "INSERT INTO tmp_data (machine_ip, tmp, amb, dist, date, id)
+                     VALUES (?, ?, ?, ?, ?, ?)",
+                )
+                .bind(&self.machine_ip)
+                .bind(tmp)
+                .bind(amb)
+                .bind(dist)
+                .bind(self.date)
+                .bind(driver_id)
+                .execute(&mut *tx)
+                .await?;
+            }
+
+            for (photo, picture) in self.photos.iter().zip(pictures) {
+                let (pic, pic_key) = picture.columns();
+                sqlx::query(
+                    "INSERT INTO pic_data (date, cam, pic, pic_key, detail, machine_ip)
+                     VALUES (?, ?, ?, ?, ?, ?)",
+                )
+                .bind(self.date)
+                .bind(photo.cam)
+                .bind(pic)
+                .bind(pic_key)
+                .bind(photo.detail.as_deref().unwrap_or(self.status.as_str()))
+                .bind(&self.machine_ip)
+                .execute(&mut *tx)
+                .await?;
+            }
+
+            if let Some(ref ic) = self.ic {
+                sqlx::query(
+                    "INSERT INTO ic_log (id, type, detail, date, iid, machine_ip)
+                     VALUES (?, ?, ?, ?, ?, ?)",
+                )
+                .bind(&ic.id)
+                .bind(&ic.log_type)
+                .bind(&ic.detail)
+                .bind(self.date)
+                .bind(&ic.iid)
+                .bind(&self.machine_ip)
+                .execute(&mut *tx)
+                .await?;
+            }
+
+            tx.commit().await?;
+        });
+        Ok(true)
+    }
+
+    /// hello イベント用のペイロードを生成（Pythonクライアントの送信形式と同じ）
+    pub async fn hello_payload(&self, directory: &DriverDirectory) -> Value {
+        let mut data = Map::new();
+        data.insert(
+            "time".to_string(),
+            json!(self.date.format(DATE_FORMAT).to_string()),
+        );
+
+        // ドライバー未指定のICカード打刻は現在の保持者
+        let driver_id = self.driver_id.or_else(|| {
+            self.ic
+                .as_ref()
+                .and_then(|ic| directory.card_holder(&ic.id))
+        });
+        if let Some(driver_id) = driver_id {
+            data.insert("id".to_string(), json!(driver_id));
+            match directory.driver_name(driver_id).await {
+                Ok(Some(name)) => {
+                    data.insert("name".to_string(), json!(name));
+                }
+                Ok(None) => tracing::warn!("Driver not found for id {}", driver_id),
+                Err(e) => tracing::error!("Failed to fetch driver name: {}", e),
+            }
+        }
+
+        if let Some(ref readings) = self.readings {
+            data.insert("tmp".to_string(), json!(readings.tmp));
+        }
+
+        if let Some(ref ic) = self.ic {
+            data.insert("ic".to_string(), json!(ic.id));
+        }
+
+        for photo in &self.photos {
+            let key = match photo.cam {
+                1 => "pic_data_1",
+                2 => "pic_data_2",
+                _ => "pic_data",
+            };
+            data.insert(
+                key.to_string(),
+                json!(base64::engine::general_purpose::STANDARD.encode(&photo.data)),
+            );
+        }
+
+        json!({
+            "ip": self.machine_ip,
+            "status": self.status.as_str(),
+            "message": self.message.clone().unwrap_or_default(),
+            "data": Value::Object(data),
+        })
+    }
+}
+
+/// Socket.IO "punch" イベントのペイロード
+///
+/// 画像は base64 文字列 (`data`) または バイナリ添付のインデックス (`bin`) で指定する
+#[derive(Debug, Clone, Deserialize)]
+pub struct PunchPayload {
+    pub ip: String,
+    pub status: String,
+    pub time: String,
+    pub id: Option<i32>,
+    pub message: Option<String>,
+    pub tmp: Option<String>,
+    pub amb: Option<String>,
+    pub dist: Option<String>,
+    pub ic: Option<IcPayload>,
+    #[serde(default)]
+    pub pics: Vec<PhotoPayload>,
+    pub client_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct IcPayload {
+    pub id: String,
+    #[serde(rename = "type", default)]
+    pub log_type: String,
+    pub detail: Option<String>,
+    pub iid: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PhotoPayload {
+    pub cam: i32,
+    pub detail: Option<String>,
+    pub data: Option<String>,
+    pub bin: Option<usize>,
+}
+
+impl PunchPayload {
+    /// バイナリ添付と合わせて Punch に変換
+    pub fn into_punch(self, attachments: &[Vec<u8>]) -> Result<Punch, IngestError> {
+        let status = PunchStatus::parse(&self.status)
+            .ok_or_else(|| invalid(format!("unknown status '{}'", self.status)))?;
+        let date = parse_punch_date(&self.time)?;
+
+        let readings = self.tmp.map(|tmp| Readings {
+            tmp,
+            amb: self.amb.unwrap_or_default(),
+            dist: self.dist.unwrap_or_default(),
+        });
+
+        let ic = self.ic.map(|ic| IcRead {
+            id: ic.id,
+            log_type: ic.log_type,
+            detail: ic.detail,
+            iid: ic.iid,
+        });
+
+        let photos = self
+            .pics
+            .into_iter()
+            .map(|pic| {
+                let data = match (pic.data, pic.bin) {
+                    (Some(encoded), _) => decode_photo(&encoded)?,
+                    (None, Some(index)) => attachments
+                        .get(index)
+                        .cloned()
+                        .ok_or_else(|| invalid(format!("binary attachment {} not found", index)))?,
+                    (None, None) => {
+                        return Err(invalid(format!("photo for cam {} has no data", pic.cam)))
+                    }
+                };
+                Ok(Photo {
+                    cam: pic.cam,
+                    detail: pic.detail,
+                    data,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Punch {
+            machine_ip: self.ip,
+            status,
+            date,
+            driver_id: self.id,
+            readings,
+            ic,
+            photos,
+            message: self.message,
+            client_id: self.client_id,
+        })
+    }
+}
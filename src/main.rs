@@ -1,21 +1,43 @@
+mod client_admin;
 mod client_state;
+mod clock;
+mod clock_skew;
 mod config;
 mod db;
+mod directory;
 mod http_api;
+mod ingest;
 mod models;
+mod outbox;
+mod picture_store;
+mod protocol;
+mod releases;
+mod repository;
+mod retention;
 mod services;
+mod sinks;
 mod socketio_server;
+mod telemetry;
+mod terminal_command;
+mod terminal_config;
+mod terminal_registry;
 
 use std::sync::Arc;
 
-use client_state::ClientState;
+use client_state::{ClientState, LivenessSettings};
 use config::Config;
 use db::Database;
+use outbox::{Outbox, OutboxSettings};
 use services::{
     ClientServiceImpl, DriverServiceImpl, FingerLogServiceImpl, ICLogServiceImpl,
-    ICNonRegServiceImpl, NotificationServiceImpl, PicDataServiceImpl, TestServiceImpl,
-    TmpDataServiceImpl, VapidKeyServiceImpl, VersionServiceImpl,
+    ICNonRegServiceImpl, IngestServiceImpl, NotificationServiceImpl, OutboxServiceImpl,
+    PicDataServiceImpl, ReleaseServiceImpl, RetentionServiceImpl, TerminalCommandServiceImpl,
+    TerminalConfigServiceImpl, TestServiceImpl, TmpDataServiceImpl, VapidKeyServiceImpl,
+    VersionServiceImpl,
 };
+use sinks::{EventBus, SocketIoSink, SinkKind};
+use terminal_command::CommandDispatcher;
+use terminal_config::ConfigPusher;
 use tokio::sync::broadcast;
 use tonic::transport::Server;
 use tonic_reflection::server::Builder as ReflectionBuilder;
@@ -37,11 +59,14 @@ pub mod proto {
 use proto::timecard::{
     client_service_server::ClientServiceServer, driver_service_server::DriverServiceServer,
     finger_log_service_server::FingerLogServiceServer, ic_log_service_server::IcLogServiceServer,
-    ic_non_reg_service_server::IcNonRegServiceServer,
+    ic_non_reg_service_server::IcNonRegServiceServer, ingest_service_server::IngestServiceServer,
     notification_service_server::NotificationServiceServer,
-    pic_data_service_server::PicDataServiceServer, test_service_server::TestServiceServer,
-    tmp_data_service_server::TmpDataServiceServer, vapid_key_service_server::VapidKeyServiceServer,
-    version_service_server::VersionServiceServer,
+    outbox_service_server::OutboxServiceServer, pic_data_service_server::PicDataServiceServer,
+    release_service_server::ReleaseServiceServer, retention_service_server::RetentionServiceServer,
+    terminal_command_service_server::TerminalCommandServiceServer,
+    terminal_config_service_server::TerminalConfigServiceServer,
+    test_service_server::TestServiceServer, tmp_data_service_server::TmpDataServiceServer,
+    vapid_key_service_server::VapidKeyServiceServer, version_service_server::VersionServiceServer,
 };
 
 /// 古いログファイルを削除（7日以上前）
@@ -106,49 +131,314 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .init();
 
     // 設定読み込み
-    let config = Config::from_env()?;
+    let config = Config::from_env().map_err(|e| e.to_string())?;
     info!("Starting gRPC server on port {}", config.grpc_port);
 
+    clock::init(config.business_timezone);
+    info!("Business timezone: {}", config.business_timezone);
+
     // データベース接続
-    info!("Connecting to database...");
-    let database = Database::connect(&config.database_url).await?;
+    info!("Connecting to database {}...", config.database.describe());
+    let database = Database::connect(&config.database).await.map_err(|e| {
+        format!(
+            "Failed to connect to database {}: {}",
+            config.database.describe(),
+            e
+        )
+    })?;
     info!("Database connected successfully");
 
+    // 読み取りレプリカ（停止中でも起動できるよう接続は最初の確認時）
+    let replica = match config.replica {
+        Some(ref settings) => {
+            let replica_db = Database::connect_lazy(&settings.database)
+                .map_err(|e| format!("Invalid replica settings: {}", e))?;
+            info!(
+                "Read replica: {} (max lag {}s)",
+                settings.database.describe(),
+                settings.max_lag.as_secs()
+            );
+            Some(db::replica::ReplicaMonitor::new(
+                replica_db,
+                settings.clone(),
+            ))
+        }
+        None => None,
+    };
+
+    // マイグレーションモード: 未適用のマイグレーションを適用して終了
+    if std::env::args().nth(1).as_deref() == Some("migrate") {
+        let applied = db::migrations::run(&database).await?;
+        info!(
+            "Applied migrations {:?}; database schema is at version {}",
+            applied,
+            db::migrations::latest_version()
+        );
+        return Ok(());
+    }
+
+    // スキーマバージョン確認（AUTO_MIGRATE=false なら未適用分があれば起動しない）
+    db::migrations::ensure_schema(&database, config.auto_migrate).await?;
+    let repositories = repository::Repositories::sql(database.clone());
+    let replica_repositories = replica
+        .as_ref()
+        .map(|monitor| repository::Repositories::sql(monitor.database().clone()));
+    let reads = repository::ReadRepositories::new(
+        &repositories,
+        replica_repositories.as_ref().zip(replica.as_ref()),
+    );
+
+    // 紐付け再構築モード: 紐付け履歴から ic_card_current を作り直して終了
+    if std::env::args().nth(1).as_deref() == Some("rebuild-card-assignments") {
+        let rebuilt = repositories.cards.rebuild_current_assignments().await?;
+        info!("Rebuilt ic_card_current with {} card assignments", rebuilt);
+        return Ok(());
+    }
+
+    // ICカード紐付け履歴のトリガーが使う業務タイムゾーンのオフセット
+    let offset = db::business_clock::record(&database)
+        .await
+        .map_err(|e| format!("Failed to record business clock offset: {}", e))?;
+    info!("Business clock offset: {} minutes", offset);
+    db::business_clock::spawn_updater(database.clone());
+
+    // 画像の保存先（PICTURE_STORE）
+    let pictures = picture_store::open(&config.picture_store)
+        .await
+        .map_err(|e| format!("Failed to open picture store: {}", e))?;
+    info!("Picture store: {}", config.picture_store.describe());
+
+    // 画像移行モード: pic_data.pic に残っている画像を外部ストアへ移して終了
+    if std::env::args().nth(1).as_deref() == Some("migrate-pictures") {
+        if let picture_store::PictureStoreSettings::Database = config.picture_store {
+            return Err("migrate-pictures requires PICTURE_STORE=filesystem or s3".into());
+        }
+        let migrated = picture_store::migrate_inline_pictures(&database, pictures.as_ref()).await?;
+        info!(
+            "Moved {} pictures to the {} store",
+            migrated,
+            pictures.name()
+        );
+        return Ok(());
+    }
+
+    // データ保持ジョブ（RETENTION_* で保持期間を設定したテーブルのみ）
+    let retention_job =
+        retention::RetentionJob::new(database.clone(), pictures.clone(), config.retention.clone());
+
+    // 保持モード: 期限切れの行をアーカイブして削除（--dry-run は件数のみ表示）して終了
+    if std::env::args().nth(1).as_deref() == Some("retention") {
+        if config.retention.policies.is_empty() {
+            return Err("retention requires at least one RETENTION_<TABLE> period".into());
+        }
+        let dry_run = std::env::args().skip(2).any(|arg| arg == "--dry-run");
+        let reports = if dry_run {
+            retention_job.report().await?
+        } else {
+            retention_job.run().await?
+        };
+        for report in &reports {
+            info!("{}", retention::describe(report));
+            if !dry_run {
+                info!(
+                    "{}: archived and deleted {} rows into {} files",
+                    report.table.as_str(),
+                    report.archived_rows,
+                    report.archive_files.len()
+                );
+            }
+        }
+        return Ok(());
+    }
+    if !config.retention.policies.is_empty() {
+        retention_job.spawn();
+        info!(
+            "Retention job enabled (archive: {})",
+            config.retention.archive_dir.display()
+        );
+    }
+
     // クライアント接続状態管理
     let client_state = ClientState::new();
     info!("Client state initialized");
 
+    // 端末レジストリに接続状態を記録（初回のIP報告で自動登録）
+    terminal_registry::spawn_presence_recorder(database.clone(), &client_state);
+
+    // 外部配信シンク設定（EVENT_SINKS 未設定時は Socket.IO + CF Worker + gRPC ストリーム）
+    let sink_configs = sinks::parse_sink_configs(
+        config.event_sinks.as_deref(),
+        config.cf_broadcast_url.as_deref(),
+    )?;
+    let destinations = sinks::outbox_destinations(
+        &sink_configs,
+        config.cf_broadcast_url.as_deref(),
+        config.cf_broadcast_secret.as_deref(),
+    )?;
+
+    // HTTP 系シンク送信用 Outbox（CF Worker / Webhook シンク設定時のみ）
+    let outbox = if destinations.is_empty() {
+        None
+    } else {
+        let settings = OutboxSettings {
+            max_attempts: config.outbox_max_attempts,
+            base_backoff: std::time::Duration::from_secs(config.outbox_base_backoff_secs),
+            max_backoff: std::time::Duration::from_secs(config.outbox_max_backoff_secs),
+            poll_interval: std::time::Duration::from_millis(config.outbox_poll_interval_ms),
+            request_timeout: std::time::Duration::from_secs(5),
+            batch_size: 100,
+        };
+        let names: Vec<String> = destinations.keys().cloned().collect();
+        let outbox = Outbox::new(database.clone(), settings, destinations);
+        outbox.spawn_worker();
+        info!("Outbox worker enabled for {}", names.join(", "));
+        Some(outbox)
+    };
+
     // イベントブロードキャスト用チャンネル
     let (broadcaster, _) = broadcast::channel(1024);
     let broadcaster = Arc::new(broadcaster);
 
+    let events = EventBus::new();
+    sinks::register_sinks(&events, &sink_configs, outbox.as_ref(), &broadcaster);
+
+    // 端末テレメトリの保存・アラート評価
+    let telemetry_recorder = telemetry::TelemetryRecorder::new(
+        database.clone(),
+        telemetry::TelemetryThresholds {
+            min_disk_free_percent: config.telemetry_min_disk_free_percent,
+            max_camera_image_age_secs: config.telemetry_max_camera_image_age_secs,
+        },
+        events.clone(),
+    );
+    let clock_skew_monitor = clock_skew::ClockSkewMonitor::new(
+        database.clone(),
+        client_state.clone(),
+        events.clone(),
+        config.clock_skew_alert_secs,
+    );
+
+    // ドライバー名・ICカード紐付けのキャッシュ（起動時に読み込み、定期的にDBと照合）
+    let driver_directory = directory::DriverDirectory::new(
+        repositories.drivers.clone(),
+        repositories.cards.clone(),
+        std::time::Duration::from_secs(config.directory_reconcile_secs),
+    );
+    let loaded = driver_directory.refresh().await?;
+    driver_directory.spawn_reconciler();
+    if let Some(ref monitor) = replica {
+        monitor.spawn();
+    }
+    info!("Driver directory loaded ({} entries)", loaded);
 
     // Socket.IO サーバー初期化（設定されている場合）
     let socketio_io = if config.socketio_server_port.is_some() {
         let (socketio_layer, io) = socketio_server::setup_socketio(
             database.clone(),
             client_state.clone(),
-            config.cf_broadcast_url.clone(),
+            events.clone(),
+            telemetry_recorder.clone(),
+            clock_skew_monitor.clone(),
+            pictures.clone(),
+            driver_directory.clone(),
+            socketio_server::HeartbeatSettings {
+                ping_interval: std::time::Duration::from_secs(
+                    config.client_heartbeat_interval_secs,
+                ),
+                ping_timeout: std::time::Duration::from_secs(config.client_heartbeat_timeout_secs),
+            },
         );
-        Some((socketio_layer, Arc::new(io)))
+        let io = Arc::new(io);
+        for sink_config in &sink_configs {
+            if let SinkKind::Socketio = sink_config.kind {
+                events.register(
+                    Arc::new(SocketIoSink::new(sink_config.sink_name(), io.clone())),
+                    sink_config.filter(),
+                );
+            }
+        }
+
+        // 無通信クライアントの stale 判定・切断
+        socketio_server::spawn_client_sweeper(
+            io.clone(),
+            client_state.clone(),
+            LivenessSettings {
+                stale_after: chrono::Duration::seconds(config.client_stale_after_secs),
+                evict_after: chrono::Duration::seconds(config.client_evict_after_secs),
+                sweep_interval: std::time::Duration::from_secs(15),
+            },
+        );
+        Some((socketio_layer, io))
     } else {
         None
     };
+    sinks::spawn_liveness_publisher(&client_state, events.clone());
 
     // gRPC サービス初期化
-    let client_service = ClientServiceImpl::new(client_state.clone());
-    let driver_service = DriverServiceImpl::new(database.clone());
-    let ic_log_service = ICLogServiceImpl::new(database.clone());
-    let pic_data_service = PicDataServiceImpl::new(database.clone());
-    let tmp_data_service = TmpDataServiceImpl::new(database.clone());
-    let finger_log_service = FingerLogServiceImpl::new(database.clone());
+    let client_admin = client_admin::ClientAdmin::new(
+        database.clone(),
+        client_state.clone(),
+        socketio_io.as_ref().map(|(_, io)| io.clone()),
+    );
+    let client_service =
+        ClientServiceImpl::new(client_state.clone(), database.clone(), client_admin);
+    let driver_service =
+        DriverServiceImpl::new(repositories.drivers.clone(), driver_directory.clone());
+    let ic_log_service = ICLogServiceImpl::new(reads.ic_logs.clone(), driver_directory.clone());
+    let pic_data_service = PicDataServiceImpl::new(reads.pictures.clone(), pictures.clone());
+    let tmp_data_service = TmpDataServiceImpl::new(reads.readings.clone());
+    let finger_log_service = FingerLogServiceImpl::new(reads.readings.clone());
     let ic_non_reg_service = if let Some((_, ref io)) = socketio_io {
-        ICNonRegServiceImpl::with_socketio(database.clone(), io.clone())
+        ICNonRegServiceImpl::with_socketio(
+            repositories.cards.clone(),
+            driver_directory.clone(),
+            io.clone(),
+        )
     } else {
-        ICNonRegServiceImpl::new(database.clone())
+        ICNonRegServiceImpl::new(repositories.cards.clone(), driver_directory.clone())
     };
+    let ingest_service = IngestServiceImpl::new(
+        database.clone(),
+        events.clone(),
+        telemetry_recorder.clone(),
+        clock_skew_monitor,
+        pictures.clone(),
+        driver_directory.clone(),
+    );
     let vapid_key_service = VapidKeyServiceImpl::new(database.clone());
-    let notification_service = NotificationServiceImpl::new(database.clone(), broadcaster.clone());
+    let notification_service = NotificationServiceImpl::new(driver_directory, broadcaster.clone());
+    let command_dispatcher = CommandDispatcher::new(
+        database.clone(),
+        client_state.clone(),
+        socketio_io.as_ref().map(|(_, io)| io.clone()),
+        std::time::Duration::from_secs(config.command_ack_timeout_secs),
+    );
+    let terminal_command_service =
+        TerminalCommandServiceImpl::new(database.clone(), command_dispatcher);
+    // 端末設定の配信（IP 報告時と設定変更時）
+    let config_pusher = ConfigPusher::new(
+        database.clone(),
+        client_state.clone(),
+        socketio_io.as_ref().map(|(_, io)| io.clone()),
+        std::time::Duration::from_secs(config.command_ack_timeout_secs),
+    );
+    config_pusher.spawn_on_connect();
+    let terminal_config_service = TerminalConfigServiceImpl::new(database.clone(), config_pusher);
+    // ソフトウェア更新の案内（IP 報告時とリリース変更時）
+    let update_notifier = releases::UpdateNotifier::new(
+        database.clone(),
+        client_state.clone(),
+        socketio_io.as_ref().map(|(_, io)| io.clone()),
+    );
+    update_notifier.spawn_on_connect();
+    // リリース成果物の保存先
+    let release_artifacts = releases::open_store(&config.picture_store, config.release_dir.clone())
+        .await
+        .map_err(|e| format!("Failed to open release artifact store: {}", e))?;
+    let release_service =
+        ReleaseServiceImpl::new(database.clone(), release_artifacts.clone(), update_notifier);
+    let outbox_service = OutboxServiceImpl::new(outbox.clone());
+    let retention_service = RetentionServiceImpl::new(retention_job);
     let test_service = TestServiceImpl::new(database.clone());
     let version_service = VersionServiceImpl::new();
 
@@ -173,7 +463,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     info!("HTTP API server listening on {}", http_addr);
 
     // HTTP API サーバー (health check + CakePHP互換API)
-    let http_router = http_api::create_router_with_db(database.clone());
+    let http_router = http_api::create_router_with_db(database.clone(), release_artifacts.clone());
     let http_listener = tokio::net::TcpListener::bind(&http_addr).await?;
 
     // gRPC-Web対応サーバー
@@ -189,8 +479,20 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .add_service(TmpDataServiceServer::new(tmp_data_service))
         .add_service(FingerLogServiceServer::new(finger_log_service))
         .add_service(IcNonRegServiceServer::new(ic_non_reg_service))
+        .add_service(
+            // 画像を含むため受信サイズ上限を引き上げ
+            IngestServiceServer::new(ingest_service).max_decoding_message_size(32 * 1024 * 1024),
+        )
         .add_service(VapidKeyServiceServer::new(vapid_key_service))
         .add_service(NotificationServiceServer::new(notification_service))
+        .add_service(OutboxServiceServer::new(outbox_service))
+        .add_service(RetentionServiceServer::new(retention_service))
+        .add_service(
+            // 成果物を含むため受信サイズ上限を引き上げ
+            ReleaseServiceServer::new(release_service).max_decoding_message_size(256 * 1024 * 1024),
+        )
+        .add_service(TerminalCommandServiceServer::new(terminal_command_service))
+        .add_service(TerminalConfigServiceServer::new(terminal_config_service))
         .add_service(TestServiceServer::new(test_service))
         .add_service(VersionServiceServer::new(version_service))
         .serve(grpc_addr);
@@ -206,7 +508,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             .allow_methods(Any);
 
         // API routes for Socket.IO server (same port)
-        let api_routes = http_api::create_api_routes(database.clone());
+        let api_routes = http_api::create_api_routes(database.clone(), release_artifacts.clone());
 
         let socketio_router = axum::Router::new()
             .route("/health", axum::routing::get(|| async { "OK" }))
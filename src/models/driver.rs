@@ -1,3 +1,4 @@
+use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 
@@ -6,3 +7,22 @@ pub struct Driver {
     pub id: i32,
     pub name: String,
 }
+
+/// ICカードの現在の紐付け（解除されていない最新の紐付け）
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct CardAssignment {
+    pub ic_id: String,
+    pub emp_id: i32,
+}
+
+/// ICカードの紐付け期間（ic_card_history の1行）
+///
+/// assigned_at は ic_id.date、revoked_at は解除（deleted = 1 または行削除）した時刻で、
+/// どちらも業務タイムゾーンの壁時計時刻
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct CardAssignmentPeriod {
+    pub ic_id: String,
+    pub emp_id: i32,
+    pub assigned_at: NaiveDateTime,
+    pub revoked_at: Option<NaiveDateTime>,
+}
@@ -13,8 +13,9 @@ pub struct IcLog {
     pub machine_ip: String,
 }
 
+/// ICログ + ログの時刻にICカードを保持していたドライバー
 #[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
-pub struct IcLogWithDriver {
+pub struct IcLogWithHolder {
     pub id: String,
     #[sqlx(rename = "type")]
     pub log_type: String,
@@ -22,5 +23,19 @@ pub struct IcLogWithDriver {
     pub date: NaiveDateTime,
     pub iid: Option<String>,
     pub machine_ip: String,
-    pub name: Option<String>,
+    pub holder_id: Option<i32>,
+}
+
+/// ICログ + 同時刻の画像の結合結果
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct IcLogWithPic {
+    pub id: String,
+    #[sqlx(rename = "type")]
+    pub log_type: String,
+    pub detail: Option<String>,
+    pub date: NaiveDateTime,
+    pub iid: Option<String>,
+    pub machine_ip: String,
+    pub pic: Option<Vec<u8>>,
+    pub pic_key: Option<String>,
 }
@@ -4,7 +4,6 @@ mod ic_log;
 mod ic_non_reg;
 mod pic_data;
 mod tmp_data;
-mod vapid_key;
 
 pub use driver::*;
 pub use finger_log::*;
@@ -12,4 +11,3 @@ pub use ic_log::*;
 pub use ic_non_reg::*;
 pub use pic_data::*;
 pub use tmp_data::*;
-pub use vapid_key::*;
@@ -13,7 +13,7 @@ pub struct TmpData {
 }
 
 /// 一時データ + 画像 + ドライバー名の結合結果
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
 pub struct TmpDataWithPic {
     pub machine_ip: String,
     pub tmp: String,
@@ -21,7 +21,10 @@ pub struct TmpDataWithPic {
     pub dist: String,
     pub date: NaiveDateTime,
     pub driver_id: Option<i32>,
+    #[sqlx(rename = "name")]
     pub driver_name: Option<String>,
-    pub pic_data_1: Option<String>, // base64
-    pub pic_data_2: Option<String>, // base64
+    pub pic_1: Option<Vec<u8>>, // 'tmp inserted' の画像
+    pub pic_2: Option<Vec<u8>>, // 'tmp inserted by ic/fing' の画像
+    pub pic_1_key: Option<String>,
+    pub pic_2_key: Option<String>,
 }
@@ -0,0 +1,703 @@
+// Durable outbox for outbound HTTP fan-out (Cloudflare Worker and webhook sinks)
+// Events are written to event_outbox first and delivered by a background worker with
+// exponential backoff, per-stream ordering, dead-lettering and an HMAC signature header
+
+use crate::clock;
+use crate::db::{Database, InsertedId};
+use crate::with_pool;
+use chrono::NaiveDateTime;
+use futures_util::future::join_all;
+use hmac::{Hmac, Mac};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use sqlx::Row;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+use tracing::{error, info, warn};
+
+/// Cloudflare Worker の送信先名
+pub const CF_WORKER_DESTINATION: &str = "cf_worker";
+
+/// 署名ヘッダー: `sha256=<hex(HMAC-SHA256(secret, "{timestamp}.{body}"))>`
+pub const SIGNATURE_HEADER: &str = "X-Timecard-Signature";
+pub const TIMESTAMP_HEADER: &str = "X-Timecard-Timestamp";
+pub const EVENT_ID_HEADER: &str = "X-Timecard-Event-Id";
+
+const STATUS_PENDING: &str = "pending";
+const STATUS_DELIVERED: &str = "delivered";
+const STATUS_DEAD: &str = "dead";
+
+/// 配信済みレコードの保持期間
+const DELIVERED_RETENTION_HOURS: i64 = 72;
+
+/// Outbox の動作設定
+#[derive(Debug, Clone)]
+pub struct OutboxSettings {
+    pub max_attempts: i32,
+    pub base_backoff: Duration,
+    pub max_backoff: Duration,
+    pub poll_interval: Duration,
+    pub request_timeout: Duration,
+    pub batch_size: i64,
+}
+
+/// 送信先（URL と署名用シークレット）
+#[derive(Debug, Clone)]
+pub struct Destination {
+    pub url: String,
+    pub secret: Option<String>,
+}
+
+/// 配信統計
+#[derive(Debug, Clone, Default)]
+pub struct OutboxStats {
+    pub pending: i64,
+    pub delivered: i64,
+    pub dead: i64,
+    pub oldest_pending_at: Option<NaiveDateTime>,
+    pub last_error: Option<String>,
+    pub delivered_since_start: u64,
+    pub failed_attempts_since_start: u64,
+}
+
+/// デッドレターの1件
+#[derive(Debug, Clone)]
+pub struct DeadLetter {
+    pub id: i64,
+    pub destination: String,
+    pub stream: String,
+    pub payload: String,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+struct OutboxEntry {
+    id: i64,
+    destination: String,
+    payload: String,
+    attempts: i32,
+}
+
+#[derive(Default)]
+struct Counters {
+    delivered: AtomicU64,
+    failed_attempts: AtomicU64,
+}
+
+#[derive(Clone)]
+pub struct Outbox {
+    db: Database,
+    settings: OutboxSettings,
+    destinations: Arc<HashMap<String, Destination>>,
+    http_client: reqwest::Client,
+    wakeup: Arc<Notify>,
+    counters: Arc<Counters>,
+}
+
+impl Outbox {
+    pub fn new(
+        db: Database,
+        settings: OutboxSettings,
+        destinations: HashMap<String, Destination>,
+    ) -> Self {
+        Self {
+            db,
+            settings,
+            destinations: Arc::new(destinations),
+            http_client: reqwest::Client::new(),
+            wakeup: Arc::new(Notify::new()),
+            counters: Arc::new(Counters::default()),
+        }
+    }
+
+    /// イベントを Outbox に書き込み、ワーカーを起こす
+    pub async fn enqueue(
+        &self,
+        destination: &str,
+        stream: &str,
+        payload: &Value,
+    ) -> Result<i64, sqlx::Error> {
+        let now = clock::now();
+        let id = with_pool!(self.db, pool => {
+            sqlx::query(
+                "INSERT INTO event_outbox
+                    (destination, stream, payload, status, attempts, next_attempt_at, created_at)
+                 VALUES (?, ?, ?, ?, 0, ?, ?)",
+            )
+            .bind(destination)
+            .bind(stream)
+            .bind(payload.to_string())
+            .bind(STATUS_PENDING)
+            .bind(now)
+            .bind(now)
+            .execute(pool)
+            .await?
+            .inserted_id()
+        });
+
+        self.wakeup.notify_one();
+        Ok(id)
+    }
+
+    /// hello イベントを送信先向けに登録（ストリームは端末IP単位）
+    pub async fn enqueue_hello(&self, destination: &str, data: &Value) -> Result<i64, sqlx::Error> {
+        let stream = data
+            .get("ip")
+            .and_then(|v| v.as_str())
+            .filter(|ip| !ip.is_empty())
+            .unwrap_or("global")
+            .to_string();
+
+        // フロントエンド向けに hello イベント形式でラップ
+        let payload = json!({
+            "type": "hello",
+            "data": data,
+            "timestamp": chrono::Utc::now().to_rfc3339()
+        });
+
+        self.enqueue(destination, &stream, &payload).await
+    }
+
+    /// バックグラウンド配信ワーカーを起動
+    pub fn spawn_worker(&self) {
+        let outbox = self.clone();
+        tokio::spawn(async move {
+            info!("Outbox worker started");
+            let mut last_purge = std::time::Instant::now();
+            loop {
+                match outbox.deliver_due().await {
+                    // 配信できた場合は同じストリームの次のイベントをすぐに処理
+                    Ok(delivered) if delivered > 0 => continue,
+                    Ok(_) => {}
+                    Err(e) => error!("Outbox delivery error: {}", e),
+                }
+
+                if last_purge.elapsed() > Duration::from_secs(60 * 60) {
+                    if let Err(e) = outbox.purge_delivered().await {
+                        warn!("Failed to purge delivered outbox entries: {}", e);
+                    }
+                    last_purge = std::time::Instant::now();
+                }
+
+                tokio::select! {
+                    _ = outbox.wakeup.notified() => {}
+                    _ = tokio::time::sleep(outbox.settings.poll_interval) => {}
+                }
+            }
+        });
+    }
+
+    /// 各ストリームの先頭イベントのうち配信時刻に達したものを送信
+    async fn deliver_due(&self) -> Result<usize, sqlx::Error> {
+        let now = clock::now();
+        let entries: Vec<OutboxEntry> = with_pool!(self.db, pool => {
+            sqlx::query(
+                "SELECT o.id, o.destination, o.payload, o.attempts
+                 FROM event_outbox o
+                 INNER JOIN (
+                     SELECT MIN(id) AS id
+                     FROM event_outbox
+                     WHERE status = ?
+                     GROUP BY destination, stream
+                 ) h ON o.id = h.id
+                 WHERE o.next_attempt_at <= ?
+                 ORDER BY o.id
+                 LIMIT ?",
+            )
+            .bind(STATUS_PENDING)
+            .bind(now)
+            .bind(self.settings.batch_size)
+            .fetch_all(pool)
+            .await?
+            .iter()
+            .map(|row| OutboxEntry {
+                id: row.get("id"),
+                destination: row.get("destination"),
+                payload: row.get("payload"),
+                attempts: row.get("attempts"),
+            })
+            .collect()
+        });
+
+        // ストリーム間は並行、ストリーム内は先頭1件ずつ
+        let results = join_all(entries.iter().map(|entry| self.deliver(entry))).await;
+
+        let mut delivered = 0;
+        for (entry, result) in entries.iter().zip(results) {
+            match result {
+                Ok(()) => {
+                    self.mark_delivered(entry.id).await?;
+                    self.counters.delivered.fetch_add(1, Ordering::Relaxed);
+                    delivered += 1;
+                }
+                Err(e) => {
+                    self.counters
+                        .failed_attempts
+                        .fetch_add(1, Ordering::Relaxed);
+                    self.mark_failed(entry, &e).await?;
+                }
+            }
+        }
+
+        Ok(delivered)
+    }
+
+    /// 1件送信（署名ヘッダー付き）
+    async fn deliver(&self, entry: &OutboxEntry) -> Result<(), String> {
+        let destination = self
+            .destinations
+            .get(&entry.destination)
+            .ok_or_else(|| format!("unknown destination '{}'", entry.destination))?;
+
+        let timestamp = chrono::Utc::now().timestamp().to_string();
+        let mut request = self
+            .http_client
+            .post(&destination.url)
+            .header("Content-Type", "application/json")
+            .header(TIMESTAMP_HEADER, &timestamp)
+            .header(EVENT_ID_HEADER, entry.id.to_string())
+            .timeout(self.settings.request_timeout);
+
+        if let Some(ref secret) = destination.secret {
+            request = request.header(
+                SIGNATURE_HEADER,
+                sign_payload(secret, &timestamp, &entry.payload),
+            );
+        }
+
+        let resp = request
+            .body(entry.payload.clone())
+            .send()
+            .await
+            .map_err(|e| format!("request failed: {}", e))?;
+
+        if resp.status().is_success() {
+            Ok(())
+        } else {
+            Err(format!("status {}", resp.status()))
+        }
+    }
+
+    async fn mark_delivered(&self, id: i64) -> Result<(), sqlx::Error> {
+        with_pool!(self.db, pool => {
+            sqlx::query(
+                "UPDATE event_outbox
+                 SET status = ?, attempts = attempts + 1, delivered_at = ?, last_error = NULL
+                 WHERE id = ?",
+            )
+            .bind(STATUS_DELIVERED)
+            .bind(clock::now())
+            .bind(id)
+            .execute(pool)
+            .await?;
+        });
+        Ok(())
+    }
+
+    async fn mark_failed(&self, entry: &OutboxEntry, error: &str) -> Result<(), sqlx::Error> {
+        let attempts = entry.attempts + 1;
+
+        if attempts >= self.settings.max_attempts {
+            warn!(
+                "Outbox event {} to {} dead-lettered after {} attempts: {}",
+                entry.id, entry.destination, attempts, error
+            );
+            with_pool!(self.db, pool => {
+                sqlx::query(
+                    "UPDATE event_outbox SET status = ?, attempts = ?, last_error = ? WHERE id = ?",
+                )
+                .bind(STATUS_DEAD)
+                .bind(attempts)
+                .bind(error)
+                .bind(entry.id)
+                .execute(pool)
+                .await?;
+            });
+            return Ok(());
+        }
+
+        let delay = backoff(&self.settings, attempts);
+        warn!(
+            "Outbox event {} to {} failed (attempt {}), retrying in {:?}: {}",
+            entry.id, entry.destination, attempts, delay, error
+        );
+        let next_attempt_at = clock::now()
+            + chrono::Duration::from_std(delay).unwrap_or_else(|_| chrono::Duration::seconds(60));
+        with_pool!(self.db, pool => {
+            sqlx::query(
+                "UPDATE event_outbox
+                 SET attempts = ?, last_error = ?, next_attempt_at = ?
+                 WHERE id = ?",
+            )
+            .bind(attempts)
+            .bind(error)
+            .bind(next_attempt_at)
+            .bind(entry.id)
+            .execute(pool)
+            .await?;
+        });
+        Ok(())
+    }
+
+    async fn purge_delivered(&self) -> Result<u64, sqlx::Error> {
+        let cutoff = clock::now() - chrono::Duration::hours(DELIVERED_RETENTION_HOURS);
+        let purged = with_pool!(self.db, pool => {
+            sqlx::query("DELETE FROM event_outbox WHERE status = ? AND delivered_at < ?")
+                .bind(STATUS_DELIVERED)
+                .bind(cutoff)
+                .execute(pool)
+                .await?
+                .rows_affected()
+        });
+        Ok(purged)
+    }
+
+    /// 配信統計を取得
+    pub async fn stats(&self) -> Result<OutboxStats, sqlx::Error> {
+        let counts: Vec<(String, i64, Option<NaiveDateTime>)> = with_pool!(self.db, pool => {
+            sqlx::query_as(
+                "SELECT status, COUNT(*) AS cnt, MIN(created_at) AS oldest
+                 FROM event_outbox
+                 GROUP BY status",
+            )
+            .fetch_all(pool)
+            .await?
+        });
+
+        let mut stats = OutboxStats {
+            delivered_since_start: self.counters.delivered.load(Ordering::Relaxed),
+            failed_attempts_since_start: self.counters.failed_attempts.load(Ordering::Relaxed),
+            ..Default::default()
+        };
+        for (status, count, oldest) in counts {
+            match status.as_str() {
+                STATUS_PENDING => {
+                    stats.pending = count;
+                    stats.oldest_pending_at = oldest;
+                }
+                STATUS_DELIVERED => stats.delivered = count,
+                STATUS_DEAD => stats.dead = count,
+                _ => {}
+            }
+        }
+
+        stats.last_error = with_pool!(self.db, pool => {
+            sqlx::query_scalar(
+                "SELECT last_error FROM event_outbox
+                 WHERE last_error IS NOT NULL
+                 ORDER BY next_attempt_at DESC
+                 LIMIT 1",
+            )
+            .fetch_optional(pool)
+            .await?
+        });
+
+        Ok(stats)
+    }
+
+    /// デッドレター一覧を取得
+    pub async fn dead_letters(&self, limit: i32) -> Result<Vec<DeadLetter>, sqlx::Error> {
+        let letters = with_pool!(self.db, pool => {
+            sqlx::query(
+                "SELECT id, destination, stream, payload, attempts, last_error, created_at
+                 FROM event_outbox
+                 WHERE status = ?
+                 ORDER BY id DESC
+                 LIMIT ?",
+            )
+            .bind(STATUS_DEAD)
+            .bind(limit)
+            .fetch_all(pool)
+            .await?
+            .iter()
+            .map(|row| DeadLetter {
+                id: row.get("id"),
+                destination: row.get("destination"),
+                stream: row.get("stream"),
+                payload: row.get("payload"),
+                attempts: row.get("attempts"),
+                last_error: row.get("last_error"),
+                created_at: row.get("created_at"),
+            })
+            .collect()
+        });
+
+        Ok(letters)
+    }
+
+    /// デッドレターを再送キューに戻す（ids が空の場合は全件）
+    pub async fn retry_dead_letters(&self, ids: &[i64]) -> Result<u64, sqlx::Error> {
+        let now = clock::now();
+        let affected = with_pool!(self.db, pool => {
+            if ids.is_empty() {
+                sqlx::query(
+                    "UPDATE event_outbox SET status = ?, attempts = 0, next_attempt_at = ?
+                     WHERE status = ?",
+                )
+                .bind(STATUS_PENDING)
+                .bind(now)
+                .bind(STATUS_DEAD)
+                .execute(pool)
+                .await?
+                .rows_affected()
+            } else {
+                let mut affected = 0;
+                for id in ids {
+                    affected += sqlx::query(
+                        "UPDATE event_outbox SET status = ?, attempts = 0, next_attempt_at = ?
+                         WHERE status = ? AND id = ?",
+                    )
+                    .bind(STATUS_PENDING)
+                    .bind(now)
+                    .bind(STATUS_DEAD)
+                    .bind(id)
+                    .execute(pool)
+                    .await?
+                    .rows_affected();
+                }
+                affected
+            }
+        });
+
+        self.wakeup.notify_one();
+        Ok(affected)
+    }
+}
+
+/// 指数バックオフ（base * 2^(attempts-1)、上限 max_backoff）
+fn backoff(settings: &OutboxSettings, attempts: i32) -> Duration {
+    let exponent = (attempts - 1).clamp(0, 16) as u32;
+    settings
+        .base_backoff
+        .saturating_mul(2u32.saturating_pow(exponent))
+        .min(settings.max_backoff)
+}
+
+/// HMAC-SHA256 署名を生成
+pub fn sign_payload(secret: &str, timestamp: &str, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::State;
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::Router;
+    use std::sync::Mutex;
+
+    /// 受信したリクエスト
+    struct Received {
+        headers: HeaderMap,
+        body: String,
+    }
+
+    /// 送信先のスタンドイン（reject を含むボディには 500 を返す）
+    #[derive(Clone, Default)]
+    struct Receiver {
+        received: Arc<Mutex<Vec<Received>>>,
+        reject: Arc<Mutex<Option<String>>>,
+    }
+
+    impl Receiver {
+        fn reject(&self, pattern: Option<&str>) {
+            *self.reject.lock().unwrap() = pattern.map(str::to_string);
+        }
+
+        fn bodies(&self) -> Vec<String> {
+            let received = self.received.lock().unwrap();
+            received.iter().map(|r| r.body.clone()).collect()
+        }
+    }
+
+    async fn receive(
+        State(receiver): State<Receiver>,
+        headers: HeaderMap,
+        body: String,
+    ) -> StatusCode {
+        let rejected =
+            matches!(*receiver.reject.lock().unwrap(), Some(ref p) if body.contains(p.as_str()));
+        receiver
+            .received
+            .lock()
+            .unwrap()
+            .push(Received { headers, body });
+        if rejected {
+            StatusCode::INTERNAL_SERVER_ERROR
+        } else {
+            StatusCode::OK
+        }
+    }
+
+    fn settings(max_attempts: i32, base_backoff: Duration) -> OutboxSettings {
+        OutboxSettings {
+            max_attempts,
+            base_backoff,
+            max_backoff: Duration::from_secs(600),
+            poll_interval: Duration::from_secs(1),
+            request_timeout: Duration::from_secs(5),
+            batch_size: 100,
+        }
+    }
+
+    /// 送信先を起動して Outbox を作成
+    async fn start(settings: OutboxSettings) -> (Outbox, Receiver) {
+        let receiver = Receiver::default();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/events", listener.local_addr().unwrap());
+        let app = Router::new().fallback(receive).with_state(receiver.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let destinations = HashMap::from([(
+            "webhook".to_string(),
+            Destination {
+                url,
+                secret: Some("s3cret".to_string()),
+            },
+        )]);
+        let outbox = Outbox::new(Database::memory().await, settings, destinations);
+        (outbox, receiver)
+    }
+
+    /// (status, attempts, next_attempt_at, last_error)
+    async fn entry(outbox: &Outbox, id: i64) -> (String, i32, NaiveDateTime, Option<String>) {
+        with_pool!(outbox.db, pool => {
+            sqlx::query_as(
+                "SELECT status, attempts, next_attempt_at, last_error FROM event_outbox WHERE id = ?",
+            )
+            .bind(id)
+            .fetch_one(pool)
+            .await
+            .unwrap()
+        })
+    }
+
+    #[tokio::test]
+    async fn delivery_is_signed_with_hmac() {
+        let (outbox, receiver) = start(settings(5, Duration::from_secs(2))).await;
+        let id = outbox
+            .enqueue("webhook", "10.0.0.1", &json!({ "type": "hello" }))
+            .await
+            .unwrap();
+
+        assert_eq!(outbox.deliver_due().await.unwrap(), 1);
+
+        {
+            let received = receiver.received.lock().unwrap();
+            let request = &received[0];
+            let header = |name: &str| request.headers.get(name).unwrap().to_str().unwrap();
+            assert_eq!(request.body, r#"{"type":"hello"}"#);
+            assert_eq!(header(EVENT_ID_HEADER), id.to_string());
+            assert_eq!(header("content-type"), "application/json");
+
+            // 受信側の検証: HMAC-SHA256(secret, "{timestamp}.{body}")
+            let timestamp = header(TIMESTAMP_HEADER);
+            let mut mac = Hmac::<Sha256>::new_from_slice(b"s3cret").unwrap();
+            mac.update(format!("{}.{}", timestamp, request.body).as_bytes());
+            assert_eq!(
+                header(SIGNATURE_HEADER),
+                format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+            );
+        }
+
+        assert_eq!(entry(&outbox, id).await.0, STATUS_DELIVERED);
+    }
+
+    #[tokio::test]
+    async fn failed_delivery_is_retried_after_backoff() {
+        let (outbox, receiver) = start(settings(5, Duration::from_secs(60))).await;
+        receiver.reject(Some(""));
+        let id = outbox
+            .enqueue("webhook", "10.0.0.1", &json!({ "n": 1 }))
+            .await
+            .unwrap();
+
+        let before = clock::now();
+        assert_eq!(outbox.deliver_due().await.unwrap(), 0);
+        let (status, attempts, next_attempt_at, last_error) = entry(&outbox, id).await;
+        assert_eq!(status, STATUS_PENDING);
+        assert_eq!(attempts, 1);
+        assert!(last_error.unwrap().contains("500"));
+        assert!(next_attempt_at >= before + chrono::Duration::seconds(59));
+
+        // バックオフ中は送信しない
+        assert_eq!(outbox.deliver_due().await.unwrap(), 0);
+        assert_eq!(receiver.bodies().len(), 1);
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_maximum() {
+        let settings = settings(10, Duration::from_secs(2));
+        assert_eq!(backoff(&settings, 1), Duration::from_secs(2));
+        assert_eq!(backoff(&settings, 2), Duration::from_secs(4));
+        assert_eq!(backoff(&settings, 5), Duration::from_secs(32));
+        assert_eq!(backoff(&settings, 20), Duration::from_secs(600));
+    }
+
+    #[tokio::test]
+    async fn exhausted_event_is_dead_lettered_and_can_be_retried() {
+        let (outbox, receiver) = start(settings(2, Duration::ZERO)).await;
+        receiver.reject(Some(""));
+        let id = outbox
+            .enqueue("webhook", "10.0.0.1", &json!({ "n": 1 }))
+            .await
+            .unwrap();
+
+        outbox.deliver_due().await.unwrap();
+        assert_eq!(entry(&outbox, id).await.0, STATUS_PENDING);
+        outbox.deliver_due().await.unwrap();
+        assert_eq!(entry(&outbox, id).await.0, STATUS_DEAD);
+
+        // デッドレターは送信しない
+        outbox.deliver_due().await.unwrap();
+        assert_eq!(receiver.bodies().len(), 2);
+        let dead = outbox.dead_letters(10).await.unwrap();
+        assert_eq!(dead.len(), 1);
+        assert_eq!(dead[0].id, id);
+        assert_eq!(dead[0].attempts, 2);
+        assert_eq!(outbox.stats().await.unwrap().dead, 1);
+
+        receiver.reject(None);
+        assert_eq!(outbox.retry_dead_letters(&[id]).await.unwrap(), 1);
+        assert_eq!(outbox.deliver_due().await.unwrap(), 1);
+        assert_eq!(entry(&outbox, id).await.0, STATUS_DELIVERED);
+    }
+
+    #[tokio::test]
+    async fn failing_head_blocks_only_its_own_stream() {
+        let (outbox, receiver) = start(settings(10, Duration::ZERO)).await;
+        receiver.reject(Some("a1"));
+        for (stream, event) in [("a", "a1"), ("a", "a2"), ("b", "b1")] {
+            outbox
+                .enqueue("webhook", stream, &json!({ "event": event }))
+                .await
+                .unwrap();
+        }
+
+        // a は先頭の a1 が失敗している間 a2 を送らない。b は影響を受けない
+        assert_eq!(outbox.deliver_due().await.unwrap(), 1);
+        let mut first = receiver.bodies();
+        first.sort();
+        assert_eq!(first, [r#"{"event":"a1"}"#, r#"{"event":"b1"}"#]);
+        assert_eq!(outbox.deliver_due().await.unwrap(), 0);
+
+        receiver.reject(None);
+        assert_eq!(outbox.deliver_due().await.unwrap(), 1);
+        assert_eq!(outbox.deliver_due().await.unwrap(), 1);
+        let bodies = receiver.bodies();
+        assert_eq!(
+            bodies[bodies.len() - 2..],
+            [r#"{"event":"a1"}"#, r#"{"event":"a2"}"#]
+        );
+        assert_eq!(outbox.stats().await.unwrap().pending, 0);
+    }
+}
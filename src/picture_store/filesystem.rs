@@ -0,0 +1,103 @@
+// Local filesystem store: one file per object under its root directory (PICTURE_DIR for
+// pictures)
+
+use super::{ObjectStream, PictureStore, PictureStoreError, StoredPicture};
+use bytes::BytesMut;
+use futures_util::stream::{self, StreamExt};
+use std::io::ErrorKind;
+use std::path::{Component, Path, PathBuf};
+use tokio::io::AsyncReadExt;
+
+/// get_stream で1回に読み込むサイズ
+const CHUNK_SIZE: usize = 64 * 1024;
+
+pub struct FilesystemPictureStore {
+    root: PathBuf,
+}
+
+impl FilesystemPictureStore {
+    /// ディレクトリがなければ作成
+    pub async fn open(root: PathBuf) -> Result<Self, PictureStoreError> {
+        tokio::fs::create_dir_all(&root)
+            .await
+            .map_err(|e| PictureStoreError(format!("{}: {}", root.display(), e)))?;
+        Ok(Self { root })
+    }
+
+    /// キーのファイルパス（ルートの外を指すキーは拒否）
+    fn path(&self, key: &str) -> Result<PathBuf, PictureStoreError> {
+        let relative = Path::new(key);
+        if relative
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_)))
+        {
+            return Err(PictureStoreError(format!("invalid object key '{}'", key)));
+        }
+        Ok(self.root.join(relative))
+    }
+}
+
+fn io_error(path: &Path, e: std::io::Error) -> PictureStoreError {
+    PictureStoreError(format!("{}: {}", path.display(), e))
+}
+
+#[tonic::async_trait]
+impl PictureStore for FilesystemPictureStore {
+    fn name(&self) -> &'static str {
+        "filesystem"
+    }
+
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<StoredPicture, PictureStoreError> {
+        let path = self.path(key)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err(|e| io_error(parent, e))?;
+        }
+        // 書き込み途中のファイルを読まれないよう一時ファイルからリネーム
+        let tmp = path.with_extension("part");
+        tokio::fs::write(&tmp, &data)
+            .await
+            .map_err(|e| io_error(&tmp, e))?;
+        tokio::fs::rename(&tmp, &path)
+            .await
+            .map_err(|e| io_error(&path, e))?;
+        Ok(StoredPicture::External(key.to_string()))
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, PictureStoreError> {
+        let path = self.path(key)?;
+        match tokio::fs::read(&path).await {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(io_error(&path, e)),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), PictureStoreError> {
+        let path = self.path(key)?;
+        match tokio::fs::remove_file(&path).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(io_error(&path, e)),
+        }
+    }
+
+    async fn get_stream(&self, key: &str) -> Result<Option<ObjectStream>, PictureStoreError> {
+        let path = self.path(key)?;
+        let file = match tokio::fs::File::open(&path).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(io_error(&path, e)),
+        };
+        let chunks = stream::try_unfold((file, path), |(mut file, path)| async move {
+            let mut buffer = BytesMut::with_capacity(CHUNK_SIZE);
+            match file.read_buf(&mut buffer).await {
+                Ok(0) => Ok(None),
+                Ok(_) => Ok(Some((buffer.freeze(), (file, path)))),
+                Err(e) => Err(io_error(&path, e)),
+            }
+        });
+        Ok(Some(chunks.boxed()))
+    }
+}
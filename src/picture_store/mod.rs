@@ -0,0 +1,253 @@
+// Picture storage backends
+// Camera pictures are stored through PictureStore, selected by PICTURE_STORE. The database
+// store keeps the legacy layout (the image inline in pic_data.pic); the filesystem and
+// S3-compatible stores write the image elsewhere and pic_data only keeps the metadata and
+// the object key (pic_key). Readers resolve either form with `load`, so the services
+// behave the same whichever backend wrote a row. Other objects (release artifacts) use the
+// same backends through the ObjectStore names
+
+mod filesystem;
+mod s3;
+
+pub use filesystem::FilesystemPictureStore;
+pub use s3::{S3PictureStore, S3Settings};
+
+use crate::db::Database;
+use crate::with_pool;
+use bytes::Bytes;
+use chrono::NaiveDateTime;
+use futures_util::stream::{self, BoxStream, StreamExt};
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+/// migrate-pictures で1回に移行する行数
+const MIGRATE_BATCH_SIZE: i64 = 100;
+
+#[derive(Debug)]
+pub struct PictureStoreError(pub String);
+
+impl fmt::Display for PictureStoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for PictureStoreError {}
+
+/// pic_data に記録する画像の保存先
+#[derive(Debug, Clone)]
+pub enum StoredPicture {
+    /// pic_data.pic に画像をそのまま保存
+    Inline(Vec<u8>),
+    /// 外部ストアに保存済み（pic_data.pic_key に記録するキー）
+    External(String),
+}
+
+impl StoredPicture {
+    /// pic_data.pic と pic_data.pic_key に書き込む値
+    pub fn columns(&self) -> (Option<&[u8]>, Option<&str>) {
+        match self {
+            StoredPicture::Inline(data) => (Some(data.as_slice()), None),
+            StoredPicture::External(key) => (None, Some(key.as_str())),
+        }
+    }
+}
+
+/// 外部ストアのオブジェクトを分割して読み込むストリーム
+pub type ObjectStream = BoxStream<'static, Result<Bytes, PictureStoreError>>;
+
+#[tonic::async_trait]
+pub trait PictureStore: Send + Sync {
+    /// ログ・設定表示用の名前
+    fn name(&self) -> &'static str;
+
+    /// 画像を保存し、pic_data に記録する値を返す
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<StoredPicture, PictureStoreError>;
+
+    /// キーの画像を取得（存在しない場合は None）
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, PictureStoreError>;
+
+    /// キーの画像を削除（存在しない場合も成功）
+    async fn delete(&self, key: &str) -> Result<(), PictureStoreError>;
+
+    /// キーのオブジェクトを全体を読み込まずに取得（存在しない場合は None）
+    async fn get_stream(&self, key: &str) -> Result<Option<ObjectStream>, PictureStoreError> {
+        Ok(self
+            .get(key)
+            .await?
+            .map(|data| stream::once(async move { Ok(Bytes::from(data)) }).boxed()))
+    }
+}
+
+/// 画像以外のオブジェクトを保存する際のストアと型の名前
+pub use self::{
+    FilesystemPictureStore as FilesystemObjectStore, PictureStore as ObjectStore,
+    PictureStoreError as ObjectStoreError, StoredPicture as StoredObject,
+};
+
+/// PICTURE_STORE の設定
+#[derive(Debug, Clone)]
+pub enum PictureStoreSettings {
+    Database,
+    Filesystem(PathBuf),
+    S3(S3Settings),
+}
+
+impl PictureStoreSettings {
+    pub fn describe(&self) -> String {
+        match self {
+            PictureStoreSettings::Database => "database (pic_data.pic)".to_string(),
+            PictureStoreSettings::Filesystem(dir) => format!("filesystem ({})", dir.display()),
+            PictureStoreSettings::S3(s3) => format!("s3 ({}/{})", s3.endpoint, s3.bucket),
+        }
+    }
+}
+
+/// 設定からストアを生成
+pub async fn open(
+    settings: &PictureStoreSettings,
+) -> Result<Arc<dyn PictureStore>, PictureStoreError> {
+    Ok(match settings {
+        PictureStoreSettings::Database => Arc::new(DatabasePictureStore),
+        PictureStoreSettings::Filesystem(dir) => {
+            Arc::new(FilesystemPictureStore::open(dir.clone()).await?)
+        }
+        PictureStoreSettings::S3(s3) => Arc::new(S3PictureStore::new(s3.clone())),
+    })
+}
+
+/// 従来どおり pic_data.pic に保存するストア
+pub struct DatabasePictureStore;
+
+#[tonic::async_trait]
+impl PictureStore for DatabasePictureStore {
+    fn name(&self) -> &'static str {
+        "database"
+    }
+
+    async fn put(&self, _key: &str, data: Vec<u8>) -> Result<StoredPicture, PictureStoreError> {
+        Ok(StoredPicture::Inline(data))
+    }
+
+    async fn get(&self, _key: &str) -> Result<Option<Vec<u8>>, PictureStoreError> {
+        Ok(None)
+    }
+
+    async fn delete(&self, _key: &str) -> Result<(), PictureStoreError> {
+        Ok(())
+    }
+}
+
+/// 画像のキー（{日付}/{端末IP}/{時刻}-cam{カメラ}-{乱数}）
+pub fn picture_key(machine_ip: &str, date: NaiveDateTime, cam: i32) -> String {
+    let ip: String = machine_ip
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!(
+        "{}/{}/{}-cam{}-{}.jpg",
+        date.format("%Y%m%d"),
+        ip,
+        date.format("%H%M%S"),
+        cam,
+        uuid::Uuid::new_v4().simple()
+    )
+}
+
+/// pic_data の行から画像を取得（インラインの画像を優先し、なければ pic_key から読み込む）
+///
+/// 外部ストアの読み込みに失敗した場合は警告を出して None を返す
+pub async fn load(
+    store: &dyn PictureStore,
+    pic: Option<Vec<u8>>,
+    key: Option<String>,
+) -> Option<Vec<u8>> {
+    if pic.is_some() {
+        return pic;
+    }
+    let key = key?;
+    match store.get(&key).await {
+        Ok(Some(data)) => Some(data),
+        Ok(None) => {
+            warn!("Picture {} not found in {} store", key, store.name());
+            None
+        }
+        Err(e) => {
+            warn!("Failed to load picture {}: {}", key, e);
+            None
+        }
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct InlinePicture {
+    id: i64,
+    date: NaiveDateTime,
+    cam: i32,
+    machine_ip: String,
+    pic: Vec<u8>,
+}
+
+/// pic_data.pic に残っている画像を外部ストアへ移行（移行した件数を返す）
+///
+/// 1行ずつ保存してから pic_key を設定し pic を NULL にするため、中断しても再実行で続きから移行できる
+pub async fn migrate_inline_pictures(
+    db: &Database,
+    store: &dyn PictureStore,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    let mut migrated = 0u64;
+    let mut last_id = 0i64;
+    loop {
+        let rows: Vec<InlinePicture> = with_pool!(db, pool => {
+            sqlx::query_as(
+                "SELECT id, date, cam, machine_ip, pic
+                 FROM pic_data
+                 WHERE id > ? AND pic IS NOT NULL AND pic_key IS NULL
+                 ORDER BY id
+                 LIMIT ?",
+            )
+            .bind(last_id)
+            .bind(MIGRATE_BATCH_SIZE)
+            .fetch_all(pool)
+            .await?
+        });
+        if rows.is_empty() {
+            break;
+        }
+
+        for row in rows {
+            last_id = row.id;
+            let key = picture_key(&row.machine_ip, row.date, row.cam);
+            let StoredPicture::External(key) = store.put(&key, row.pic).await? else {
+                return Err(format!("{} store keeps pictures in pic_data", store.name()).into());
+            };
+            let updated = with_pool!(db, pool => {
+                sqlx::query(
+                    "UPDATE pic_data SET pic_key = ?, pic = NULL
+                     WHERE id = ? AND pic_key IS NULL",
+                )
+                .bind(&key)
+                .bind(row.id)
+                .execute(pool)
+                .await?
+                .rows_affected()
+            });
+            if updated == 0 {
+                // 並行して移行された行
+                let _ = store.delete(&key).await;
+                continue;
+            }
+            migrated += 1;
+        }
+        info!("Migrated {} pictures to {} store", migrated, store.name());
+    }
+    Ok(migrated)
+}
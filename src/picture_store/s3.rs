@@ -0,0 +1,365 @@
+// S3-compatible picture store (AWS S3, MinIO, ...)
+// Objects are addressed path-style (<endpoint>/<bucket>/<key>) so self-hosted servers work
+// without wildcard DNS. Requests are signed with AWS Signature Version 4
+
+use super::{ObjectStream, PictureStore, PictureStoreError, StoredPicture};
+use chrono::Utc;
+use futures_util::StreamExt;
+use hmac::{Hmac, Mac};
+use reqwest::{Method, StatusCode, Url};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::time::Duration;
+
+/// 1リクエストのタイムアウト
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Clone)]
+pub struct S3Settings {
+    /// 例: https://s3.ap-northeast-1.amazonaws.com / http://localhost:9000
+    pub endpoint: String,
+    pub bucket: String,
+    pub region: String,
+    pub access_key: String,
+    pub secret_key: String,
+    /// キーの前に付けるプレフィックス（例: "pictures/"）
+    pub prefix: String,
+}
+
+impl fmt::Debug for S3Settings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("S3Settings")
+            .field("endpoint", &self.endpoint)
+            .field("bucket", &self.bucket)
+            .field("region", &self.region)
+            .field("access_key", &self.access_key)
+            .field("prefix", &self.prefix)
+            .finish_non_exhaustive()
+    }
+}
+
+pub struct S3PictureStore {
+    settings: S3Settings,
+    client: reqwest::Client,
+}
+
+impl S3PictureStore {
+    pub fn new(settings: S3Settings) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .unwrap_or_default();
+        Self { settings, client }
+    }
+
+    /// 署名付きリクエストを送信
+    async fn send(
+        &self,
+        method: Method,
+        key: &str,
+        body: Vec<u8>,
+    ) -> Result<reqwest::Response, PictureStoreError> {
+        let s3 = &self.settings;
+        let mut url = Url::parse(&s3.endpoint)
+            .map_err(|e| PictureStoreError(format!("invalid S3 endpoint: {}", e)))?;
+        // エンドポイントのパス（リバースプロキシ配下の /minio など）も署名対象のパスに含める
+        let path = format!(
+            "{}/{}/{}",
+            url.path().trim_end_matches('/'),
+            uri_encode(&s3.bucket),
+            uri_encode(&format!("{}{}", s3.prefix, key))
+        );
+        url.set_path(&path);
+        let host = match (url.host_str(), url.port()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            (Some(host), None) => host.to_string(),
+            (None, _) => return Err(PictureStoreError("S3 endpoint has no host".to_string())),
+        };
+
+        let amz_date = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+        let payload_hash = hex::encode(Sha256::digest(&body));
+        let authorization = authorization(
+            s3,
+            method.as_str(),
+            url.path(),
+            &host,
+            &payload_hash,
+            &amz_date,
+        );
+
+        self.client
+            .request(method, url)
+            .header("x-amz-date", amz_date)
+            .header("x-amz-content-sha256", payload_hash)
+            .header("authorization", authorization)
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| PictureStoreError(format!("S3 request failed: {}", e)))
+    }
+}
+
+/// SigV4 の Authorization ヘッダー（path は URI エンコード済み、amz_date は yyyymmddThhmmssZ）
+fn authorization(
+    s3: &S3Settings,
+    method: &str,
+    path: &str,
+    host: &str,
+    payload_hash: &str,
+    amz_date: &str,
+) -> String {
+    let date = &amz_date[..8];
+    let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+    let canonical_request = format!(
+        "{}\n{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+        method, path, host, payload_hash, amz_date, signed_headers, payload_hash
+    );
+    let scope = format!("{}/{}/s3/aws4_request", date, s3.region);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope,
+        hex::encode(Sha256::digest(canonical_request.as_bytes()))
+    );
+
+    let key_date = hmac_sha256(format!("AWS4{}", s3.secret_key).as_bytes(), date);
+    let key_region = hmac_sha256(&key_date, &s3.region);
+    let key_service = hmac_sha256(&key_region, "s3");
+    let signing_key = hmac_sha256(&key_service, "aws4_request");
+    let signature = hex::encode(hmac_sha256(&signing_key, &string_to_sign));
+
+    format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        s3.access_key, scope, signed_headers, signature
+    )
+}
+
+fn hmac_sha256(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// SigV4 の URI エンコード（"/" はパス区切りとして残す）
+fn uri_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// エラー応答をエラーに変換
+async fn status_error(action: &str, key: &str, response: reqwest::Response) -> PictureStoreError {
+    let status = response.status();
+    let body = response.text().await.unwrap_or_default();
+    PictureStoreError(format!(
+        "S3 {} {} failed: {} {}",
+        action,
+        key,
+        status,
+        body.chars().take(200).collect::<String>()
+    ))
+}
+
+#[tonic::async_trait]
+impl PictureStore for S3PictureStore {
+    fn name(&self) -> &'static str {
+        "s3"
+    }
+
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<StoredPicture, PictureStoreError> {
+        let response = self.send(Method::PUT, key, data).await?;
+        if !response.status().is_success() {
+            return Err(status_error("PUT", key, response).await);
+        }
+        Ok(StoredPicture::External(key.to_string()))
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, PictureStoreError> {
+        let response = self.send(Method::GET, key, Vec::new()).await?;
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => response
+                .bytes()
+                .await
+                .map(|body| Some(body.to_vec()))
+                .map_err(|e| PictureStoreError(format!("S3 GET {} failed: {}", key, e))),
+            _ => Err(status_error("GET", key, response).await),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), PictureStoreError> {
+        let response = self.send(Method::DELETE, key, Vec::new()).await?;
+        // S3 は存在しないキーの削除にも 204 を返すが、互換サーバーの 404 も成功として扱う
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(()),
+            status if status.is_success() => Ok(()),
+            _ => Err(status_error("DELETE", key, response).await),
+        }
+    }
+
+    async fn get_stream(&self, key: &str) -> Result<Option<ObjectStream>, PictureStoreError> {
+        let response = self.send(Method::GET, key, Vec::new()).await?;
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => {
+                let key = key.to_string();
+                Ok(Some(
+                    response
+                        .bytes_stream()
+                        .map(move |chunk| {
+                            chunk.map_err(|e| {
+                                PictureStoreError(format!("S3 GET {} failed: {}", key, e))
+                            })
+                        })
+                        .boxed(),
+                ))
+            }
+            _ => Err(status_error("GET", key, response).await),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Bytes;
+    use axum::extract::State;
+    use axum::http::{HeaderMap, Uri};
+    use axum::Router;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    /// パス → オブジェクトを保持する S3 のスタンドイン（署名が合わないリクエストは 403）
+    #[derive(Clone)]
+    struct StubS3 {
+        settings: S3Settings,
+        objects: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+    }
+
+    async fn handle(
+        State(stub): State<StubS3>,
+        method: Method,
+        uri: Uri,
+        headers: HeaderMap,
+        body: Bytes,
+    ) -> (StatusCode, Vec<u8>) {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or_default()
+                .to_string()
+        };
+        let payload_hash = hex::encode(Sha256::digest(&body));
+        let expected = authorization(
+            &stub.settings,
+            method.as_str(),
+            uri.path(),
+            &header("host"),
+            &payload_hash,
+            &header("x-amz-date"),
+        );
+        if header("authorization") != expected || header("x-amz-content-sha256") != payload_hash {
+            return (StatusCode::FORBIDDEN, b"SignatureDoesNotMatch".to_vec());
+        }
+
+        let mut objects = stub.objects.lock().unwrap();
+        let path = uri.path().to_string();
+        match method {
+            Method::PUT => {
+                objects.insert(path, body.to_vec());
+                (StatusCode::OK, Vec::new())
+            }
+            Method::GET => match objects.get(&path) {
+                Some(data) => (StatusCode::OK, data.clone()),
+                None => (StatusCode::NOT_FOUND, Vec::new()),
+            },
+            Method::DELETE => {
+                objects.remove(&path);
+                (StatusCode::NO_CONTENT, Vec::new())
+            }
+            _ => (StatusCode::METHOD_NOT_ALLOWED, Vec::new()),
+        }
+    }
+
+    fn settings(endpoint: String) -> S3Settings {
+        S3Settings {
+            endpoint,
+            bucket: "timecard".to_string(),
+            region: "us-east-1".to_string(),
+            access_key: "minio".to_string(),
+            secret_key: "minio-secret".to_string(),
+            prefix: "pictures/".to_string(),
+        }
+    }
+
+    /// スタンドインを起動してストアとオブジェクト一覧を返す
+    async fn start(endpoint_path: &str) -> (S3PictureStore, Arc<Mutex<HashMap<String, Vec<u8>>>>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}{}", listener.local_addr().unwrap(), endpoint_path);
+        let stub = StubS3 {
+            settings: settings(endpoint.clone()),
+            objects: Arc::default(),
+        };
+        let objects = stub.objects.clone();
+        let app = Router::new().fallback(handle).with_state(stub);
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (S3PictureStore::new(settings(endpoint)), objects)
+    }
+
+    #[tokio::test]
+    async fn put_get_delete_round_trip() {
+        let (store, objects) = start("").await;
+
+        let stored = store
+            .put("2024/01/02 cam1.jpg", vec![1, 2, 3])
+            .await
+            .unwrap();
+        assert!(matches!(stored, StoredPicture::External(ref key) if key == "2024/01/02 cam1.jpg"));
+        assert!(objects
+            .lock()
+            .unwrap()
+            .contains_key("/timecard/pictures/2024/01/02%20cam1.jpg"));
+
+        assert_eq!(
+            store.get("2024/01/02 cam1.jpg").await.unwrap(),
+            Some(vec![1, 2, 3])
+        );
+        store.delete("2024/01/02 cam1.jpg").await.unwrap();
+        assert_eq!(store.get("2024/01/02 cam1.jpg").await.unwrap(), None);
+        // 存在しないキーの削除も成功
+        store.delete("2024/01/02 cam1.jpg").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn endpoint_path_is_signed() {
+        let (store, objects) = start("/minio/").await;
+
+        store.put("a.jpg", vec![9]).await.unwrap();
+        assert!(objects
+            .lock()
+            .unwrap()
+            .contains_key("/minio/timecard/pictures/a.jpg"));
+        assert_eq!(store.get("a.jpg").await.unwrap(), Some(vec![9]));
+    }
+
+    #[tokio::test]
+    async fn rejected_signature_is_an_error() {
+        let (valid, objects) = start("").await;
+        // 別のシークレットで署名したリクエストは 403 になる
+        let mut wrong = valid.settings.clone();
+        wrong.secret_key = "wrong".to_string();
+        let store = S3PictureStore::new(wrong);
+
+        let err = store.put("a.jpg", vec![1]).await.unwrap_err();
+        assert!(err.0.contains("403"), "{}", err);
+        assert!(objects.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,219 @@
+// Socket.IO payload protocol negotiation
+// Clients announce their protocol in the handshake auth payload ({"protocol": 2}).
+// Sockets that announce nothing are the legacy Python client, which expects "hello"
+// payloads as JSON strings (delete_ic even double-encoded because the client checks
+// type(data) is str after json.loads). Each socket joins one room per protocol so a
+// broadcast is encoded once per protocol instead of once per socket
+
+use serde_json::Value;
+use socketioxide::{extract::SocketRef, BroadcastError, SocketIo};
+
+/// クライアントが扱えるペイロード形式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientProtocol {
+    /// v1: JSON 文字列（既存の Python クライアント）
+    Legacy,
+    /// v2: JSON オブジェクトをそのまま送信
+    Json,
+}
+
+/// 旧クライアント向けのエンコード方法
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LegacyEncoding {
+    /// JSON 文字列
+    String,
+    /// JSON 文字列をさらに JSON 文字列化（delete_ic）
+    DoubleString,
+}
+
+impl ClientProtocol {
+    pub const ALL: [ClientProtocol; 2] = [ClientProtocol::Legacy, ClientProtocol::Json];
+
+    pub fn version(&self) -> u32 {
+        match self {
+            Self::Legacy => 1,
+            Self::Json => 2,
+        }
+    }
+
+    /// 接続時の auth ペイロードからプロトコルを判定（未指定・不明は旧形式）
+    pub fn from_auth(auth: &Value) -> Self {
+        let version = match auth.get("protocol") {
+            Some(Value::Number(n)) => n.as_u64(),
+            Some(Value::String(s)) => s.trim().parse().ok(),
+            _ => None,
+        };
+        match version {
+            Some(v) if v >= 2 => Self::Json,
+            _ => Self::Legacy,
+        }
+    }
+
+    fn room(&self) -> &'static str {
+        match self {
+            Self::Legacy => "protocol:1",
+            Self::Json => "protocol:2",
+        }
+    }
+
+    /// ソケットをプロトコル別のルームに参加させる
+    pub fn join(&self, socket: &SocketRef) {
+        // The local adapter cannot fail
+        let _ = socket.join(self.room());
+    }
+
+    /// ペイロードをこのプロトコル向けにエンコード
+    pub fn encode(&self, data: &Value, legacy: LegacyEncoding) -> Value {
+        match (self, legacy) {
+            (Self::Json, _) => data.clone(),
+            (Self::Legacy, LegacyEncoding::String) => Value::String(data.to_string()),
+            (Self::Legacy, LegacyEncoding::DoubleString) => {
+                Value::String(Value::String(data.to_string()).to_string())
+            }
+        }
+    }
+}
+
+/// 全クライアントへプロトコル別にエンコードして送信
+pub fn broadcast(
+    io: &SocketIo,
+    event: &'static str,
+    data: &Value,
+    legacy: LegacyEncoding,
+) -> Result<(), BroadcastError> {
+    for protocol in ClientProtocol::ALL {
+        io.to(protocol.room())
+            .emit(event, protocol.encode(data, legacy))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn from_auth_defaults_to_legacy() {
+        // 未指定（旧 Python クライアントは auth を送らない）
+        assert_eq!(
+            ClientProtocol::from_auth(&Value::Null),
+            ClientProtocol::Legacy
+        );
+        assert_eq!(
+            ClientProtocol::from_auth(&json!({})),
+            ClientProtocol::Legacy
+        );
+        assert_eq!(
+            ClientProtocol::from_auth(&json!({ "protocol": null })),
+            ClientProtocol::Legacy
+        );
+    }
+
+    #[test]
+    fn from_auth_accepts_numbers() {
+        assert_eq!(
+            ClientProtocol::from_auth(&json!({ "protocol": 1 })),
+            ClientProtocol::Legacy
+        );
+        assert_eq!(
+            ClientProtocol::from_auth(&json!({ "protocol": 2 })),
+            ClientProtocol::Json
+        );
+        // 将来のバージョンは JSON として扱う
+        assert_eq!(
+            ClientProtocol::from_auth(&json!({ "protocol": 3 })),
+            ClientProtocol::Json
+        );
+    }
+
+    #[test]
+    fn from_auth_accepts_numeric_strings() {
+        assert_eq!(
+            ClientProtocol::from_auth(&json!({ "protocol": "2" })),
+            ClientProtocol::Json
+        );
+        assert_eq!(
+            ClientProtocol::from_auth(&json!({ "protocol": " 2 " })),
+            ClientProtocol::Json
+        );
+        assert_eq!(
+            ClientProtocol::from_auth(&json!({ "protocol": "1" })),
+            ClientProtocol::Legacy
+        );
+    }
+
+    #[test]
+    fn from_auth_treats_garbage_as_legacy() {
+        for auth in [
+            json!({ "protocol": "v2" }),
+            json!({ "protocol": "" }),
+            json!({ "protocol": -2 }),
+            json!({ "protocol": 2.5 }),
+            json!({ "protocol": true }),
+            json!({ "protocol": [2] }),
+            json!({ "protocol": { "version": 2 } }),
+            json!("protocol=2"),
+        ] {
+            assert_eq!(
+                ClientProtocol::from_auth(&auth),
+                ClientProtocol::Legacy,
+                "{}",
+                auth
+            );
+        }
+    }
+
+    fn payload() -> Value {
+        json!({ "driver_id": 1, "ic_id": "0123ABCD", "name": "山田" })
+    }
+
+    /// Socket.IO パケットの引数として送られる JSON テキスト
+    fn wire(value: &Value) -> String {
+        serde_json::to_string(value).unwrap()
+    }
+
+    #[test]
+    fn legacy_string_is_a_json_string_of_the_payload() {
+        let encoded = ClientProtocol::Legacy.encode(&payload(), LegacyEncoding::String);
+        assert_eq!(
+            wire(&encoded),
+            r#""{\"driver_id\":1,\"ic_id\":\"0123ABCD\",\"name\":\"山田\"}""#
+        );
+
+        // Python クライアント: json.loads(data) で dict に戻る
+        let Value::String(inner) = encoded else {
+            panic!("legacy payload must be a string");
+        };
+        assert_eq!(serde_json::from_str::<Value>(&inner).unwrap(), payload());
+    }
+
+    #[test]
+    fn legacy_double_string_survives_one_extra_decode() {
+        let encoded = ClientProtocol::Legacy.encode(&payload(), LegacyEncoding::DoubleString);
+        assert_eq!(
+            wire(&encoded),
+            r#""\"{\\\"driver_id\\\":1,\\\"ic_id\\\":\\\"0123ABCD\\\",\\\"name\\\":\\\"山田\\\"}\"""#
+        );
+
+        // Python クライアント: json.loads(data) の結果が str であることを確認してから再度 json.loads
+        let Value::String(first) = encoded else {
+            panic!("legacy payload must be a string");
+        };
+        let Value::String(second) = serde_json::from_str::<Value>(&first).unwrap() else {
+            panic!("delete_ic must decode to a string first");
+        };
+        assert_eq!(serde_json::from_str::<Value>(&second).unwrap(), payload());
+    }
+
+    #[test]
+    fn json_protocol_sends_the_object_unchanged() {
+        for legacy in [LegacyEncoding::String, LegacyEncoding::DoubleString] {
+            let encoded = ClientProtocol::Json.encode(&payload(), legacy);
+            assert_eq!(
+                wire(&encoded),
+                r#"{"driver_id":1,"ic_id":"0123ABCD","name":"山田"}"#
+            );
+        }
+    }
+}
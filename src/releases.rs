@@ -0,0 +1,874 @@
+// Terminal software release distribution
+// Releases (artifact + SHA-256 checksum) are registered per channel ("stable", "beta", ...)
+// and downloaded over HTTP. Artifacts are kept in an object store (the external picture
+// backend, or RELEASE_DIR when pictures stay in the database) and streamed to the
+// terminals. Terminals whose reported software_version is behind the latest release of
+// their channel receive an "update_available" push, limited to a stable percentage of
+// terminals while the rollout is staged. Progress reported by the terminals is recorded in
+// release_rollout
+
+use crate::client_state::{ClientEventKind, ClientState};
+use crate::clock;
+use crate::db::{Database, InsertedId};
+use crate::picture_store::{
+    self, FilesystemObjectStore, ObjectStore, ObjectStoreError, ObjectStream, PictureStoreSettings,
+    StoredObject,
+};
+use crate::telemetry;
+use crate::terminal_config;
+use crate::terminal_registry;
+use crate::with_pool;
+use bytes::Bytes;
+use chrono::NaiveDateTime;
+use futures_util::stream::{self, StreamExt};
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use socketioxide::{extract::SocketRef, socket::Sid, SocketIo};
+use sqlx::{FromRow, Row};
+use std::cmp::Ordering;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use tracing::{error, info, warn};
+
+/// Socket.IO イベント名（サーバー → 端末）
+pub const UPDATE_AVAILABLE_EVENT: &str = "update_available";
+
+/// Socket.IO イベント名（端末 → サーバー）
+pub const UPDATE_STATUS_EVENT: &str = "update_status";
+
+/// 端末の配信チャネルを指定する端末設定のキー
+pub const CHANNEL_CONFIG_KEY: &str = "update_channel";
+
+/// 配信チャネル未設定の端末のチャネル
+pub const DEFAULT_CHANNEL: &str = "stable";
+
+#[derive(Debug)]
+pub enum ReleaseError {
+    Invalid(String),
+    Database(sqlx::Error),
+    Store(ObjectStoreError),
+}
+
+impl fmt::Display for ReleaseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReleaseError::Invalid(msg) => write!(f, "Invalid release: {}", msg),
+            ReleaseError::Database(e) => write!(f, "Database error: {}", e),
+            ReleaseError::Store(e) => write!(f, "Artifact store error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ReleaseError {}
+
+impl From<sqlx::Error> for ReleaseError {
+    fn from(e: sqlx::Error) -> Self {
+        ReleaseError::Database(e)
+    }
+}
+
+impl From<ObjectStoreError> for ReleaseError {
+    fn from(e: ObjectStoreError) -> Self {
+        ReleaseError::Store(e)
+    }
+}
+
+fn invalid(msg: impl Into<String>) -> ReleaseError {
+    ReleaseError::Invalid(msg.into())
+}
+
+/// 端末から報告される更新の進捗
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RolloutState {
+    Notified,
+    Downloaded,
+    Installing,
+    Installed,
+    Failed,
+}
+
+impl RolloutState {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "notified" => Some(Self::Notified),
+            "downloaded" => Some(Self::Downloaded),
+            "installing" => Some(Self::Installing),
+            "installed" => Some(Self::Installed),
+            "failed" => Some(Self::Failed),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Notified => "notified",
+            Self::Downloaded => "downloaded",
+            Self::Installing => "installing",
+            Self::Installed => "installed",
+            Self::Failed => "failed",
+        }
+    }
+}
+
+/// software_releases の1行（成果物本体を除く）
+#[derive(Debug, Clone, FromRow)]
+pub struct Release {
+    pub id: i64,
+    pub version: String,
+    pub channel: String,
+    pub filename: String,
+    pub sha256: String,
+    pub size_bytes: i64,
+    pub rollout_percent: i32,
+    pub notes: Option<String>,
+    pub created_by: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+/// 登録するリリース
+#[derive(Debug, Clone)]
+pub struct NewRelease {
+    pub version: String,
+    pub channel: String,
+    pub filename: String,
+    pub artifact: Vec<u8>,
+    /// 指定された場合はアップロード内容と照合
+    pub expected_sha256: Option<String>,
+    pub rollout_percent: i32,
+    pub notes: Option<String>,
+    pub created_by: Option<String>,
+}
+
+/// release_rollout の1行
+#[derive(Debug, Clone, FromRow)]
+pub struct RolloutRecord {
+    pub machine_ip: String,
+    pub state: String,
+    pub message: Option<String>,
+    pub updated_at: NaiveDateTime,
+}
+
+/// 端末からの進捗報告（update_status イベント）
+#[derive(Debug, Clone, Deserialize)]
+pub struct UpdateStatusReport {
+    pub release_id: i64,
+    pub state: String,
+    pub message: Option<String>,
+}
+
+/// バージョン文字列を比較（"v" 接頭辞と "+" 以降のビルドメタデータは無視）
+///
+/// "." 区切りの数値部分は数値として比較する。"-" 以降はプレリリースとして SemVer と同じく
+/// 同じバージョンの正式リリースより前に並べる（1.0.0-beta < 1.0.0-beta.2 < 1.0.0-rc.1 < 1.0.0）
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let split = |v: &str| -> (Vec<String>, Option<Vec<String>>) {
+        let v = v.trim().trim_start_matches(['v', 'V']);
+        let v = v.split_once('+').map_or(v, |(v, _)| v);
+        let (core, pre) = match v.split_once('-') {
+            Some((core, pre)) => (core, Some(pre)),
+            None => (v, None),
+        };
+        let parts = |s: &str| s.split('.').map(|p| p.to_string()).collect::<Vec<_>>();
+        (parts(core), pre.map(parts))
+    };
+    let (a_core, a_pre) = split(a);
+    let (b_core, b_pre) = split(b);
+
+    compare_parts(&a_core, &b_core).then_with(|| match (a_pre, b_pre) {
+        (None, None) => Ordering::Equal,
+        (None, Some(_)) => Ordering::Greater,
+        (Some(_), None) => Ordering::Less,
+        (Some(a), Some(b)) => compare_parts(&a, &b),
+    })
+}
+
+/// "." 区切りの部分を先頭から比較（数値同士は数値、数値は英字より前、部分が多い方が後）
+fn compare_parts(a: &[String], b: &[String]) -> Ordering {
+    for i in 0..a.len().max(b.len()) {
+        let ordering = match (a.get(i), b.get(i)) {
+            (Some(x), Some(y)) => match (x.parse::<u64>(), y.parse::<u64>()) {
+                (Ok(x), Ok(y)) => x.cmp(&y),
+                (Ok(_), Err(_)) => Ordering::Less,
+                (Err(_), Ok(_)) => Ordering::Greater,
+                (Err(_), Err(_)) => x.cmp(y),
+            },
+            (Some(_), None) => Ordering::Greater,
+            (None, Some(_)) => Ordering::Less,
+            (None, None) => Ordering::Equal,
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    Ordering::Equal
+}
+
+impl Release {
+    /// 段階的配信の対象か（端末ごとに固定の 0-99 のバケットで判定）
+    pub fn includes(&self, machine_ip: &str) -> bool {
+        let digest = Sha256::digest(format!("{}:{}", self.version, machine_ip).as_bytes());
+        let bucket = u16::from_be_bytes([digest[0], digest[1]]) % 100;
+        i32::from(bucket) < self.rollout_percent
+    }
+
+    /// 現在のバージョンからこのリリースへの更新が必要か
+    pub fn is_newer_than(&self, current_version: &str) -> bool {
+        compare_versions(&self.version, current_version) == Ordering::Greater
+    }
+
+    /// HTTP ダウンロードのパス
+    pub fn download_path(&self) -> String {
+        format!("/api/releases/{}/download", self.id)
+    }
+}
+
+fn validate_rollout_percent(percent: i32) -> Result<(), ReleaseError> {
+    if !(0..=100).contains(&percent) {
+        return Err(invalid(format!("invalid rollout_percent {}", percent)));
+    }
+    Ok(())
+}
+
+const RELEASE_COLUMNS: &str = "id, version, channel, filename, sha256, size_bytes, \
+                               rollout_percent, notes, created_by, created_at";
+
+/// 成果物の保存先を開く（画像を外部ストアに保存する設定ならそのストア、それ以外は RELEASE_DIR）
+pub async fn open_store(
+    pictures: &PictureStoreSettings,
+    release_dir: PathBuf,
+) -> Result<Arc<dyn ObjectStore>, ObjectStoreError> {
+    match pictures {
+        PictureStoreSettings::Database => {
+            Ok(Arc::new(FilesystemObjectStore::open(release_dir).await?))
+        }
+        external => picture_store::open(external).await,
+    }
+}
+
+/// 成果物のキー（releases/{channel}/{version}-{乱数}/{filename}）
+fn artifact_key(release: &NewRelease) -> String {
+    let safe = |value: &str| -> String {
+        value
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect()
+    };
+    format!(
+        "releases/{}/{}-{}/{}",
+        safe(&release.channel),
+        safe(&release.version),
+        uuid::Uuid::new_v4().simple(),
+        safe(&release.filename)
+    )
+}
+
+/// リリースを登録（成果物はストアに保存し、software_releases にはキーを記録）
+pub async fn create(
+    db: &Database,
+    store: &dyn ObjectStore,
+    release: NewRelease,
+) -> Result<Release, ReleaseError> {
+    if release.version.trim().is_empty() {
+        return Err(invalid("version is required"));
+    }
+    if release.channel.trim().is_empty() {
+        return Err(invalid("channel is required"));
+    }
+    if release.filename.trim().is_empty() || release.filename.contains(['/', '\\', '"']) {
+        return Err(invalid(format!("invalid filename '{}'", release.filename)));
+    }
+    if release.artifact.is_empty() {
+        return Err(invalid("artifact is empty"));
+    }
+    validate_rollout_percent(release.rollout_percent)?;
+
+    let sha256 = hex::encode(Sha256::digest(&release.artifact));
+    if let Some(ref expected) = release.expected_sha256 {
+        if !expected.trim().eq_ignore_ascii_case(&sha256) {
+            return Err(invalid(format!(
+                "checksum mismatch (expected {}, got {})",
+                expected, sha256
+            )));
+        }
+    }
+
+    let exists: Option<i64> = with_pool!(db, pool => {
+        sqlx::query_scalar("SELECT id FROM software_releases WHERE channel = ? AND version = ?")
+            .bind(&release.channel)
+            .bind(&release.version)
+            .fetch_optional(pool)
+            .await?
+    });
+    if exists.is_some() {
+        return Err(invalid(format!(
+            "version {} already exists in channel {}",
+            release.version, release.channel
+        )));
+    }
+
+    let size_bytes = release.artifact.len() as i64;
+    let key = artifact_key(&release);
+    let NewRelease { artifact, .. } = release;
+    let StoredObject::External(key) = store.put(&key, artifact).await? else {
+        return Err(ReleaseError::Store(ObjectStoreError(format!(
+            "{} store cannot hold release artifacts",
+            store.name()
+        ))));
+    };
+
+    let created_at = clock::now();
+    let inserted: Result<i64, sqlx::Error> = with_pool!(db, pool => {
+        sqlx::query(
+            "INSERT INTO software_releases
+                (version, channel, filename, sha256, size_bytes, artifact_key, rollout_percent,
+                 notes, created_by, created_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(&release.version)
+        .bind(&release.channel)
+        .bind(&release.filename)
+        .bind(&sha256)
+        .bind(size_bytes)
+        .bind(&key)
+        .bind(release.rollout_percent)
+        .bind(&release.notes)
+        .bind(&release.created_by)
+        .bind(created_at)
+        .execute(pool)
+        .await
+        .map(|result| result.inserted_id())
+    });
+    let id = match inserted {
+        Ok(id) => id,
+        Err(e) => {
+            // 登録できなかったリリースの成果物は残さない
+            if let Err(e) = store.delete(&key).await {
+                warn!("Failed to delete orphaned release artifact {}: {}", key, e);
+            }
+            return Err(e.into());
+        }
+    };
+
+    Ok(Release {
+        id,
+        version: release.version,
+        channel: release.channel,
+        filename: release.filename,
+        sha256,
+        size_bytes,
+        rollout_percent: release.rollout_percent,
+        notes: release.notes,
+        created_by: release.created_by,
+        created_at,
+    })
+}
+
+/// リリース一覧を新しい順に取得（channel 未指定の場合は全チャネル）
+pub async fn list(db: &Database, channel: Option<&str>) -> Result<Vec<Release>, sqlx::Error> {
+    let sql = format!(
+        "SELECT {} FROM software_releases
+         WHERE (? IS NULL OR channel = ?)
+         ORDER BY id DESC",
+        RELEASE_COLUMNS
+    );
+    with_pool!(db, pool => {
+        sqlx::query_as(&sql)
+            .bind(channel)
+            .bind(channel)
+            .fetch_all(pool)
+            .await
+    })
+}
+
+/// リリースを取得
+pub async fn get(db: &Database, id: i64) -> Result<Option<Release>, sqlx::Error> {
+    let sql = format!(
+        "SELECT {} FROM software_releases WHERE id = ?",
+        RELEASE_COLUMNS
+    );
+    with_pool!(db, pool => {
+        sqlx::query_as(&sql)
+            .bind(id)
+            .fetch_optional(pool)
+            .await
+    })
+}
+
+/// リリースの成果物をストリームで取得
+///
+/// 外部ストア導入前に登録されたリリースは software_releases.artifact から読み込む
+pub async fn open_artifact(
+    db: &Database,
+    store: &dyn ObjectStore,
+    id: i64,
+) -> Result<Option<(Release, ObjectStream)>, ReleaseError> {
+    let sql = format!(
+        "SELECT {}, artifact_key FROM software_releases WHERE id = ?",
+        RELEASE_COLUMNS
+    );
+    let found: Option<(Release, Option<String>)> = with_pool!(db, pool => {
+        match sqlx::query(&sql).bind(id).fetch_optional(pool).await? {
+            Some(row) => Some((Release::from_row(&row)?, row.try_get("artifact_key")?)),
+            None => None,
+        }
+    });
+    let Some((release, key)) = found else {
+        return Ok(None);
+    };
+
+    let artifact = match key {
+        Some(key) => store.get_stream(&key).await?.ok_or_else(|| {
+            ObjectStoreError(format!(
+                "artifact {} not found in {} store",
+                key,
+                store.name()
+            ))
+        })?,
+        None => {
+            let inline: Option<Vec<u8>> = with_pool!(db, pool => {
+                sqlx::query_scalar("SELECT artifact FROM software_releases WHERE id = ?")
+                    .bind(id)
+                    .fetch_one(pool)
+                    .await?
+            });
+            let inline = inline
+                .ok_or_else(|| ObjectStoreError(format!("release {} has no artifact", id)))?;
+            stream::once(async move { Ok(Bytes::from(inline)) }).boxed()
+        }
+    };
+    Ok(Some((release, artifact)))
+}
+
+/// チャネルの最新リリース（バージョン順）
+pub async fn latest(db: &Database, channel: &str) -> Result<Option<Release>, sqlx::Error> {
+    Ok(list(db, Some(channel))
+        .await?
+        .into_iter()
+        .max_by(|a, b| compare_versions(&a.version, &b.version)))
+}
+
+/// 段階的配信の割合を変更
+pub async fn set_rollout(
+    db: &Database,
+    id: i64,
+    rollout_percent: i32,
+) -> Result<Option<Release>, ReleaseError> {
+    validate_rollout_percent(rollout_percent)?;
+    let updated = with_pool!(db, pool => {
+        sqlx::query("UPDATE software_releases SET rollout_percent = ? WHERE id = ?")
+            .bind(rollout_percent)
+            .bind(id)
+            .execute(pool)
+            .await?
+            .rows_affected()
+    });
+    if updated == 0 {
+        return Ok(None);
+    }
+    Ok(get(db, id).await?)
+}
+
+/// 端末の進捗を記録
+pub async fn record_state(
+    db: &Database,
+    release_id: i64,
+    machine_ip: &str,
+    state: RolloutState,
+    message: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    let sql = format!(
+        "INSERT INTO release_rollout (release_id, machine_ip, state, message, updated_at)
+         VALUES (?, ?, ?, ?, ?)
+         {}",
+        db.dialect().upsert(
+            "release_id, machine_ip",
+            &["state", "message", "updated_at"]
+        )
+    );
+    with_pool!(db, pool => {
+        sqlx::query(&sql)
+            .bind(release_id)
+            .bind(machine_ip)
+            .bind(state.as_str())
+            .bind(message)
+            .bind(clock::now())
+            .execute(pool)
+            .await?;
+    });
+    Ok(())
+}
+
+/// HTTP ダウンロードを配信状況に記録（登録済み端末の IP の場合のみ。記録したかを返す）
+pub async fn record_download(
+    db: &Database,
+    release_id: i64,
+    machine_ip: &str,
+) -> Result<bool, sqlx::Error> {
+    if !terminal_registry::exists(db, machine_ip).await? {
+        return Ok(false);
+    }
+    record_state(db, release_id, machine_ip, RolloutState::Downloaded, None).await?;
+    Ok(true)
+}
+
+/// 端末からの進捗報告を記録
+pub async fn record_report(
+    db: &Database,
+    machine_ip: &str,
+    report: &UpdateStatusReport,
+) -> Result<(), ReleaseError> {
+    let state = RolloutState::parse(&report.state)
+        .ok_or_else(|| invalid(format!("unknown state '{}'", report.state)))?;
+    if get(db, report.release_id).await?.is_none() {
+        return Err(invalid(format!("unknown release {}", report.release_id)));
+    }
+    record_state(
+        db,
+        report.release_id,
+        machine_ip,
+        state,
+        report.message.as_deref(),
+    )
+    .await?;
+    Ok(())
+}
+
+/// リリースの端末ごとの進捗
+pub async fn rollout(db: &Database, release_id: i64) -> Result<Vec<RolloutRecord>, sqlx::Error> {
+    with_pool!(db, pool => {
+        sqlx::query_as(
+            "SELECT machine_ip, state, message, updated_at
+             FROM release_rollout
+             WHERE release_id = ?
+             ORDER BY machine_ip",
+        )
+        .bind(release_id)
+        .fetch_all(pool)
+        .await
+    })
+}
+
+/// 端末の配信チャネル（端末設定 update_channel、未設定は stable）
+pub async fn terminal_channel(db: &Database, machine_ip: &str) -> Result<String, sqlx::Error> {
+    let config = terminal_config::effective(db, machine_ip).await?;
+    Ok(config
+        .values
+        .get(CHANNEL_CONFIG_KEY)
+        .and_then(|v| v.as_str())
+        .filter(|s| !s.is_empty())
+        .unwrap_or(DEFAULT_CHANNEL)
+        .to_string())
+}
+
+/// 更新案内を Socket.IO で端末に配信
+#[derive(Clone)]
+pub struct UpdateNotifier {
+    db: Database,
+    clients: ClientState,
+    socketio: Option<Arc<SocketIo>>,
+}
+
+impl UpdateNotifier {
+    pub fn new(db: Database, clients: ClientState, socketio: Option<Arc<SocketIo>>) -> Self {
+        Self {
+            db,
+            clients,
+            socketio,
+        }
+    }
+
+    /// 接続中の全端末に必要な更新を案内し、案内したソケット数を返す
+    pub async fn notify_all(&self) -> Result<usize, sqlx::Error> {
+        let Some(ref io) = self.socketio else {
+            return Ok(0);
+        };
+        let versions = telemetry::latest_by_ip(&self.db).await?;
+
+        let mut notified = 0;
+        for client in self.clients.get_all_clients() {
+            if !client.has_ip() {
+                continue;
+            }
+            let Some(socket) = client
+                .socket_id
+                .parse::<Sid>()
+                .ok()
+                .and_then(|sid| io.get_socket(sid))
+            else {
+                continue;
+            };
+            let current_version = versions
+                .get(&client.ip_address)
+                .map(|t| t.report.software_version.as_str());
+            if self
+                .offer(&socket, &client.ip_address, current_version)
+                .await?
+            {
+                notified += 1;
+            }
+        }
+        Ok(notified)
+    }
+
+    /// 端末のチャネルの最新リリースが対象であれば案内する
+    async fn offer(
+        &self,
+        socket: &SocketRef,
+        machine_ip: &str,
+        current_version: Option<&str>,
+    ) -> Result<bool, sqlx::Error> {
+        // バージョン未報告の端末は比較できないため対象外
+        let Some(current_version) = current_version.filter(|v| !v.is_empty()) else {
+            return Ok(false);
+        };
+        let channel = terminal_channel(&self.db, machine_ip).await?;
+        let Some(release) = latest(&self.db, &channel).await? else {
+            return Ok(false);
+        };
+        if !release.is_newer_than(current_version) || !release.includes(machine_ip) {
+            return Ok(false);
+        }
+
+        let payload = json!({
+            "release_id": release.id,
+            "version": release.version,
+            "channel": release.channel,
+            "filename": release.filename,
+            "sha256": release.sha256,
+            "size": release.size_bytes,
+            "url": release.download_path(),
+            "notes": release.notes,
+        });
+        if let Err(e) = socket.emit(UPDATE_AVAILABLE_EVENT, &payload) {
+            warn!("Failed to offer update to {}: {}", machine_ip, e);
+            return Ok(false);
+        }
+        info!(
+            "Offered {} {} to {} (current {})",
+            release.channel, release.version, machine_ip, current_version
+        );
+
+        // 既に進捗が報告されている場合は上書きしない
+        let sql = format!(
+            "{} INTO release_rollout (release_id, machine_ip, state, message, updated_at)
+             VALUES (?, ?, ?, NULL, ?)",
+            self.db.dialect().insert_ignore()
+        );
+        with_pool!(self.db, pool => {
+            sqlx::query(&sql)
+                .bind(release.id)
+                .bind(machine_ip)
+                .bind(RolloutState::Notified.as_str())
+                .bind(clock::now())
+                .execute(pool)
+                .await?;
+        });
+        Ok(true)
+    }
+
+    /// 端末が IP を報告した時点（接続直後）で更新を案内
+    pub fn spawn_on_connect(&self) {
+        let notifier = self.clone();
+        let mut receiver = self.clients.subscribe();
+        tokio::spawn(async move {
+            loop {
+                let event = match receiver.recv().await {
+                    Ok(event) => event,
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("Update notifier lagged, skipped {} events", skipped);
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                if event.kind != ClientEventKind::IpChanged || !event.client.has_ip() {
+                    continue;
+                }
+                let socket = notifier.socketio.as_ref().and_then(|io| {
+                    let sid = event.client.socket_id.parse::<Sid>().ok()?;
+                    io.get_socket(sid)
+                });
+                let Some(socket) = socket else {
+                    continue;
+                };
+                let notifier = notifier.clone();
+                tokio::spawn(async move {
+                    let machine_ip = &event.client.ip_address;
+                    let result = match telemetry::latest_by_ip(&notifier.db).await {
+                        Ok(mut versions) => {
+                            let current = versions
+                                .remove(machine_ip)
+                                .map(|t| t.report.software_version);
+                            notifier
+                                .offer(&socket, machine_ip, current.as_deref())
+                                .await
+                        }
+                        Err(e) => Err(e),
+                    };
+                    if let Err(e) = result {
+                        error!("Failed to check updates for {}: {}", machine_ip, e);
+                    }
+                });
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::TryStreamExt;
+
+    #[test]
+    fn pre_releases_rank_below_the_release() {
+        let ordered = [
+            "0.9.9",
+            "1.0.0-alpha",
+            "1.0.0-alpha.1",
+            "1.0.0-beta",
+            "1.0.0-beta.2",
+            "1.0.0-beta.11",
+            "1.0.0-rc.1",
+            "1.0.0",
+            "1.0.1",
+            "1.10.0",
+        ];
+        for pair in ordered.windows(2) {
+            assert_eq!(
+                compare_versions(pair[0], pair[1]),
+                Ordering::Less,
+                "{:?}",
+                pair
+            );
+            assert_eq!(
+                compare_versions(pair[1], pair[0]),
+                Ordering::Greater,
+                "{:?}",
+                pair
+            );
+        }
+    }
+
+    #[test]
+    fn prefix_and_build_metadata_are_ignored() {
+        assert_eq!(compare_versions("v1.2.3", "1.2.3"), Ordering::Equal);
+        assert_eq!(compare_versions("1.2.3+build.7", "1.2.3"), Ordering::Equal);
+        assert_eq!(
+            compare_versions("1.2.3-rc.1+build.7", "1.2.3-rc.1"),
+            Ordering::Equal
+        );
+    }
+
+    fn new_release(version: &str, artifact: &[u8]) -> NewRelease {
+        NewRelease {
+            version: version.to_string(),
+            channel: DEFAULT_CHANNEL.to_string(),
+            filename: "timecard-terminal.tar.gz".to_string(),
+            artifact: artifact.to_vec(),
+            expected_sha256: None,
+            rollout_percent: 100,
+            notes: None,
+            created_by: None,
+        }
+    }
+
+    async fn store() -> FilesystemObjectStore {
+        let dir = std::env::temp_dir().join(format!("releases-{}", uuid::Uuid::new_v4().simple()));
+        FilesystemObjectStore::open(dir).await.unwrap()
+    }
+
+    async fn read_all(artifact: ObjectStream) -> Vec<u8> {
+        artifact
+            .try_fold(Vec::new(), |mut data, chunk| async move {
+                data.extend_from_slice(&chunk);
+                Ok(data)
+            })
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn artifact_is_kept_out_of_the_database() {
+        let db = Database::memory().await;
+        let store = store().await;
+        let artifact: Vec<u8> = (0..200_000u32).map(|i| i as u8).collect();
+
+        let release = create(&db, &store, new_release("1.0.0", &artifact))
+            .await
+            .unwrap();
+        assert_eq!(release.size_bytes, artifact.len() as i64);
+
+        let (inline, key): (Option<Vec<u8>>, Option<String>) = with_pool!(db, pool => {
+            sqlx::query_as("SELECT artifact, artifact_key FROM software_releases WHERE id = ?")
+                .bind(release.id)
+                .fetch_one(pool)
+                .await
+                .unwrap()
+        });
+        assert_eq!(inline, None);
+        assert!(key.unwrap().starts_with("releases/stable/1.0.0-"));
+
+        let (opened, stream) = open_artifact(&db, &store, release.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(opened.sha256, release.sha256);
+        assert_eq!(read_all(stream).await, artifact);
+        assert!(open_artifact(&db, &store, release.id + 1)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn releases_stored_inline_are_still_served() {
+        let db = Database::memory().await;
+        let store = store().await;
+        with_pool!(db, pool => {
+            sqlx::query(
+                "INSERT INTO software_releases
+                    (id, version, channel, filename, sha256, size_bytes, artifact, rollout_percent,
+                     created_at)
+                 VALUES (7, '0.9.0', 'stable', 'old.tar.gz', '00', 3, ?, 100, ?)",
+            )
+            .bind(vec![1u8, 2, 3])
+            .bind(clock::now())
+            .execute(pool)
+            .await
+            .unwrap();
+        });
+
+        let (_, stream) = open_artifact(&db, &store, 7).await.unwrap().unwrap();
+        assert_eq!(read_all(stream).await, vec![1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn downloads_are_recorded_only_for_registered_terminals() {
+        let db = Database::memory().await;
+        let store = store().await;
+        let release = create(&db, &store, new_release("1.0.0", b"bin"))
+            .await
+            .unwrap();
+        terminal_registry::touch(&db, "10.0.0.5", clock::now())
+            .await
+            .unwrap();
+
+        assert!(record_download(&db, release.id, "10.0.0.5").await.unwrap());
+        assert!(!record_download(&db, release.id, "203.0.113.9")
+            .await
+            .unwrap());
+
+        let records = rollout(&db, release.id).await.unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].machine_ip, "10.0.0.5");
+        assert_eq!(records[0].state, RolloutState::Downloaded.as_str());
+    }
+}
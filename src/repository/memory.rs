@@ -0,0 +1,584 @@
+// In-memory implementation of the repositories
+// Mirrors the joins of the MySQL queries over plain vectors so handlers can run
+// without a database. One-to-many joins keep the first match
+
+use super::{
+    CardRepository, DriverRepository, IcLogRepository, PictureRepository, ReadingRepository,
+    SortOrder,
+};
+use crate::models::{
+    CardAssignment, CardAssignmentPeriod, Driver, FingerLog, IcLog, IcLogWithHolder, IcLogWithPic,
+    IcNonReg, PicData, TmpData, TmpDataWithPic,
+};
+use chrono::NaiveDateTime;
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// ic_id の1行（ICカードとドライバーの紐付け）
+#[derive(Debug, Clone)]
+pub struct CardRow {
+    pub ic_id: String,
+    pub emp_id: i32,
+    pub date: NaiveDateTime,
+    pub deleted: bool,
+    /// deleted になった時刻（不明なら次の紐付けの日時、次の紐付けもなければ履歴に含めない）
+    pub revoked_at: Option<NaiveDateTime>,
+}
+
+#[derive(Default)]
+struct Tables {
+    drivers: Vec<Driver>,
+    ic_log: Vec<IcLog>,
+    ic_id: Vec<CardRow>,
+    ic_non_reged: Vec<IcNonReg>,
+    tmp_data: Vec<TmpData>,
+    pic_data: Vec<PicData>,
+    finger_log: Vec<FingerLog>,
+}
+
+impl Tables {
+    fn driver_name(&self, id: i32) -> Option<String> {
+        self.drivers
+            .iter()
+            .find(|d| d.id == id)
+            .map(|d| d.name.clone())
+    }
+
+    /// ICカードの現在の保持者（削除されていない最新の紐付け）
+    fn current_holder(&self, ic_id: &str) -> Option<i32> {
+        self.ic_id
+            .iter()
+            .filter(|c| !c.deleted && !c.ic_id.is_empty() && c.ic_id == ic_id)
+            .max_by_key(|c| c.date)
+            .map(|c| c.emp_id)
+    }
+
+    /// ICカードの紐付け履歴（ic_card_history 相当）
+    fn card_history(&self, ic_id: &str) -> Vec<CardAssignmentPeriod> {
+        let rows: Vec<&CardRow> = self
+            .ic_id
+            .iter()
+            .filter(|c| !c.ic_id.is_empty() && c.ic_id == ic_id)
+            .collect();
+        rows.iter()
+            .filter_map(|c| {
+                let revoked_at = match (c.deleted, c.revoked_at) {
+                    (false, _) => None,
+                    (true, Some(revoked)) => Some(revoked),
+                    // 解除日時が不明なものは次の紐付けで閉じる（マイグレーションの移行と同じ）
+                    (true, None) => Some(
+                        rows.iter()
+                            .map(|n| n.date)
+                            .filter(|date| *date > c.date)
+                            .min()?,
+                    ),
+                };
+                Some(CardAssignmentPeriod {
+                    ic_id: c.ic_id.clone(),
+                    emp_id: c.emp_id,
+                    assigned_at: c.date,
+                    revoked_at,
+                })
+            })
+            .collect()
+    }
+
+    /// time の時点の保持者（有効な紐付けのうち最も新しいもの）
+    ///
+    /// 付け替えでは新しい紐付けが優先され、それが解除されると1つ前の紐付けに戻る。
+    /// 同じ日時の紐付けは後に登録された方（SqlRepository と同じ規則）
+    fn holder_at(&self, ic_id: &str, time: NaiveDateTime) -> Option<CardAssignmentPeriod> {
+        self.card_history(ic_id)
+            .into_iter()
+            .filter(|p| p.assigned_at <= time && p.revoked_at.is_none_or(|revoked| revoked > time))
+            .max_by_key(|p| p.assigned_at)
+    }
+
+    fn with_holder(&self, log: &IcLog) -> IcLogWithHolder {
+        IcLogWithHolder {
+            id: log.id.clone(),
+            log_type: log.log_type.clone(),
+            detail: log.detail.clone(),
+            date: log.date,
+            iid: log.iid.clone(),
+            machine_ip: log.machine_ip.clone(),
+            holder_id: self.holder_at(&log.id, log.date).map(|c| c.emp_id),
+        }
+    }
+
+    fn picture(&self, machine_ip: &str, date: NaiveDateTime, details: &[&str]) -> Option<&PicData> {
+        self.pic_data.iter().find(|p| {
+            p.machine_ip == machine_ip && p.date == date && details.contains(&p.detail.as_str())
+        })
+    }
+
+    fn has_picture(&self, machine_ip: &str, date: NaiveDateTime) -> bool {
+        self.pic_data
+            .iter()
+            .any(|p| p.machine_ip == machine_ip && p.date == date)
+    }
+}
+
+fn sorted_desc<T>(mut rows: Vec<T>, date: impl Fn(&T) -> NaiveDateTime) -> Vec<T> {
+    rows.sort_by_key(|r| std::cmp::Reverse(date(r)));
+    rows
+}
+
+fn limited<T>(rows: Vec<T>, limit: i32) -> Vec<T> {
+    rows.into_iter().take(limit.max(0) as usize).collect()
+}
+
+/// テーブルをメモリ上に保持するリポジトリ
+#[derive(Clone, Default)]
+pub struct MemoryRepository {
+    tables: Arc<RwLock<Tables>>,
+}
+
+impl MemoryRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, Tables> {
+        self.tables.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Tables> {
+        self.tables.write().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn insert_driver(&self, driver: Driver) {
+        self.write().drivers.push(driver);
+    }
+
+    pub fn insert_ic_log(&self, log: IcLog) {
+        self.write().ic_log.push(log);
+    }
+
+    pub fn insert_card(&self, card: CardRow) {
+        self.write().ic_id.push(card);
+    }
+
+    pub fn insert_non_reged(&self, card: IcNonReg) {
+        self.write().ic_non_reged.push(card);
+    }
+
+    pub fn insert_tmp(&self, tmp: TmpData) {
+        self.write().tmp_data.push(tmp);
+    }
+
+    pub fn insert_picture(&self, pic: PicData) {
+        self.write().pic_data.push(pic);
+    }
+
+    pub fn insert_finger_log(&self, log: FingerLog) {
+        self.write().finger_log.push(log);
+    }
+
+    /// 未登録カードの現在の状態
+    pub fn non_reged(&self, ic_id: &str) -> Option<IcNonReg> {
+        self.read()
+            .ic_non_reged
+            .iter()
+            .find(|n| n.id == ic_id)
+            .cloned()
+    }
+}
+
+#[tonic::async_trait]
+impl DriverRepository for MemoryRepository {
+    async fn list(&self) -> Result<Vec<Driver>, sqlx::Error> {
+        Ok(self.read().drivers.clone())
+    }
+
+    async fn get(&self, id: i32) -> Result<Option<Driver>, sqlx::Error> {
+        Ok(self.read().drivers.iter().find(|d| d.id == id).cloned())
+    }
+
+    async fn replace_all(&self, drivers: &[Driver]) -> Result<(), sqlx::Error> {
+        self.write().drivers = drivers.to_vec();
+        Ok(())
+    }
+}
+
+#[tonic::async_trait]
+impl IcLogRepository for MemoryRepository {
+    async fn since(
+        &self,
+        start: NaiveDateTime,
+        order: SortOrder,
+    ) -> Result<Vec<IcLog>, sqlx::Error> {
+        let mut logs: Vec<IcLog> = self
+            .read()
+            .ic_log
+            .iter()
+            .filter(|l| l.date >= start)
+            .cloned()
+            .collect();
+        logs.sort_by_key(|l| l.date);
+        if order == SortOrder::Desc {
+            logs.reverse();
+        }
+        Ok(logs)
+    }
+
+    async fn with_holder_since(
+        &self,
+        start: NaiveDateTime,
+    ) -> Result<Vec<IcLogWithHolder>, sqlx::Error> {
+        let tables = self.read();
+        let logs = tables
+            .ic_log
+            .iter()
+            .filter(|l| l.date >= start)
+            .map(|l| tables.with_holder(l))
+            .collect();
+        Ok(sorted_desc(logs, |l| l.date))
+    }
+
+    async fn latest_with_holder(&self, limit: i32) -> Result<Vec<IcLogWithHolder>, sqlx::Error> {
+        let tables = self.read();
+        let logs = tables
+            .ic_log
+            .iter()
+            .map(|l| tables.with_holder(l))
+            .collect();
+        Ok(limited(sorted_desc(logs, |l| l.date), limit))
+    }
+
+    async fn holder_at(
+        &self,
+        ic_id: &str,
+        time: NaiveDateTime,
+    ) -> Result<Option<CardAssignmentPeriod>, sqlx::Error> {
+        Ok(self.read().holder_at(ic_id, time))
+    }
+
+    async fn without_tmp(
+        &self,
+        start: NaiveDateTime,
+        limit: i32,
+    ) -> Result<Vec<IcLog>, sqlx::Error> {
+        let tables = self.read();
+        let logs = tables
+            .ic_log
+            .iter()
+            .filter(|l| l.date >= start)
+            .filter(|l| {
+                !tables
+                    .tmp_data
+                    .iter()
+                    .any(|t| t.machine_ip == l.machine_ip && t.date == l.date)
+            })
+            .cloned()
+            .collect();
+        Ok(limited(sorted_desc(logs, |l| l.date), limit))
+    }
+}
+
+#[tonic::async_trait]
+impl CardRepository for MemoryRepository {
+    async fn current_assignments(&self) -> Result<Vec<CardAssignment>, sqlx::Error> {
+        let tables = self.read();
+        let mut ic_ids: Vec<&str> = tables
+            .ic_id
+            .iter()
+            .filter(|c| !c.deleted && !c.ic_id.is_empty())
+            .map(|c| c.ic_id.as_str())
+            .collect();
+        ic_ids.sort_unstable();
+        ic_ids.dedup();
+        Ok(ic_ids
+            .into_iter()
+            .filter_map(|ic_id| {
+                tables.current_holder(ic_id).map(|emp_id| CardAssignment {
+                    ic_id: ic_id.to_string(),
+                    emp_id,
+                })
+            })
+            .collect())
+    }
+
+    async fn rebuild_current_assignments(&self) -> Result<u64, sqlx::Error> {
+        // 紐付けは ic_id から都度求めているため作り直すものはない
+        Ok(self.current_assignments().await?.len() as u64)
+    }
+
+    async fn unregistered_since(&self, start: NaiveDateTime) -> Result<Vec<IcNonReg>, sqlx::Error> {
+        let tables = self.read();
+        let cards = tables
+            .ic_non_reged
+            .iter()
+            .filter(|n| n.datetime >= start && n.deleted.unwrap_or(0) == 0)
+            // 追加後にICカードとして登録されたものは除く
+            .filter(|n| {
+                !tables
+                    .ic_id
+                    .iter()
+                    .any(|c| c.ic_id == n.id && !c.deleted && c.date >= n.datetime)
+            })
+            .cloned()
+            .collect();
+        Ok(sorted_desc(cards, |n| n.datetime))
+    }
+
+    async fn reserve(&self, ic_id: &str, driver_id: i32) -> Result<(), sqlx::Error> {
+        for card in self
+            .write()
+            .ic_non_reged
+            .iter_mut()
+            .filter(|n| n.id == ic_id)
+        {
+            card.registered_id = Some(driver_id);
+        }
+        Ok(())
+    }
+
+    async fn cancel_reservation(&self, ic_id: &str) -> Result<(), sqlx::Error> {
+        for card in self
+            .write()
+            .ic_non_reged
+            .iter_mut()
+            .filter(|n| n.id == ic_id)
+        {
+            card.registered_id = None;
+            card.deleted = Some(0);
+        }
+        Ok(())
+    }
+
+    async fn reserve_direct(
+        &self,
+        ic_id: &str,
+        driver_id: i32,
+        at: NaiveDateTime,
+    ) -> Result<(), sqlx::Error> {
+        let card = IcNonReg {
+            id: ic_id.to_string(),
+            datetime: at,
+            deleted: Some(0),
+            registered_id: Some(driver_id),
+        };
+        let mut tables = self.write();
+        match tables.ic_non_reged.iter_mut().find(|n| n.id == ic_id) {
+            Some(existing) => *existing = card,
+            None => tables.ic_non_reged.push(card),
+        }
+        Ok(())
+    }
+}
+
+#[tonic::async_trait]
+impl PictureRepository for MemoryRepository {
+    async fn list(&self) -> Result<Vec<PicData>, sqlx::Error> {
+        Ok(sorted_desc(self.read().pic_data.clone(), |p| p.date))
+    }
+
+    async fn tmp_with_pictures(
+        &self,
+        start: NaiveDateTime,
+        limit: i32,
+    ) -> Result<Vec<TmpDataWithPic>, sqlx::Error> {
+        let tables = self.read();
+        let data = tables
+            .tmp_data
+            .iter()
+            .filter(|t| t.id == 0 && t.date >= start)
+            .map(|t| {
+                let driver_id = tables
+                    .tmp_data
+                    .iter()
+                    .find(|m| m.id > 0 && m.machine_ip == t.machine_ip && m.date == t.date)
+                    .map(|m| m.id);
+                let pic_1 = tables.picture(&t.machine_ip, t.date, &["tmp inserted"]);
+                let pic_2 = tables.picture(
+                    &t.machine_ip,
+                    t.date,
+                    &["tmp inserted by ic", "tmp inserted by fing"],
+                );
+                TmpDataWithPic {
+                    machine_ip: t.machine_ip.clone(),
+                    tmp: t.tmp.clone(),
+                    amb: t.amb.clone(),
+                    dist: t.dist.clone(),
+                    date: t.date,
+                    driver_id,
+                    driver_name: driver_id.and_then(|id| tables.driver_name(id)),
+                    pic_1: pic_1.and_then(|p| p.pic.clone()),
+                    pic_2: pic_2.and_then(|p| p.pic.clone()),
+                    pic_1_key: pic_1.and_then(|p| p.pic_key.clone()),
+                    pic_2_key: pic_2.and_then(|p| p.pic_key.clone()),
+                }
+            })
+            .collect();
+        Ok(limited(sorted_desc(data, |t| t.date), limit))
+    }
+
+    async fn ic_with_pictures(
+        &self,
+        start: NaiveDateTime,
+        limit: i32,
+    ) -> Result<Vec<IcLogWithPic>, sqlx::Error> {
+        let tables = self.read();
+        let data = tables
+            .ic_log
+            .iter()
+            .filter(|l| l.date >= start)
+            .map(|l| {
+                let picture = tables
+                    .pic_data
+                    .iter()
+                    .find(|p| p.machine_ip == l.machine_ip && p.date == l.date);
+                IcLogWithPic {
+                    id: l.id.clone(),
+                    log_type: l.log_type.clone(),
+                    detail: l.detail.clone(),
+                    date: l.date,
+                    iid: l.iid.clone(),
+                    machine_ip: l.machine_ip.clone(),
+                    pic: picture.and_then(|p| p.pic.clone()),
+                    pic_key: picture.and_then(|p| p.pic_key.clone()),
+                }
+            })
+            .collect();
+        Ok(limited(sorted_desc(data, |l| l.date), limit))
+    }
+}
+
+#[tonic::async_trait]
+impl ReadingRepository for MemoryRepository {
+    async fn unmatched_tmp(&self, limit: i32) -> Result<Vec<TmpData>, sqlx::Error> {
+        let data = self
+            .read()
+            .tmp_data
+            .iter()
+            .filter(|t| t.id == 0)
+            .cloned()
+            .collect();
+        Ok(limited(sorted_desc(data, |t| t.date), limit))
+    }
+
+    async fn tmp_without_picture(
+        &self,
+        start: NaiveDateTime,
+        limit: i32,
+    ) -> Result<Vec<TmpData>, sqlx::Error> {
+        let tables = self.read();
+        let data = tables
+            .tmp_data
+            .iter()
+            .filter(|t| t.date >= start && !tables.has_picture(&t.machine_ip, t.date))
+            .cloned()
+            .collect();
+        Ok(limited(sorted_desc(data, |t| t.date), limit))
+    }
+
+    async fn finger_logs_since(&self, start: NaiveDateTime) -> Result<Vec<FingerLog>, sqlx::Error> {
+        let logs = self
+            .read()
+            .finger_log
+            .iter()
+            .filter(|l| l.date >= start)
+            .cloned()
+            .collect();
+        Ok(sorted_desc(logs, |l| l.date))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn at(hour: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2026, 4, 1)
+            .unwrap()
+            .and_hms_opt(hour, 0, 0)
+            .unwrap()
+    }
+
+    fn store(cards: &[(i32, u32, Option<u32>)]) -> MemoryRepository {
+        let store = MemoryRepository::new();
+        for &(emp_id, assigned, revoked) in cards {
+            store.insert_card(CardRow {
+                ic_id: "CARD".to_string(),
+                emp_id,
+                date: at(assigned),
+                deleted: revoked.is_some(),
+                revoked_at: revoked.map(at),
+            });
+        }
+        store
+    }
+
+    async fn holder(store: &MemoryRepository, hour: u32) -> Option<i32> {
+        store
+            .holder_at("CARD", at(hour))
+            .await
+            .unwrap()
+            .map(|p| p.emp_id)
+    }
+
+    #[tokio::test]
+    async fn reassignment_keeps_earlier_logs_with_previous_holder() {
+        let store = store(&[(1, 8, None), (2, 12, None)]);
+
+        assert_eq!(holder(&store, 7).await, None);
+        assert_eq!(holder(&store, 8).await, Some(1));
+        assert_eq!(holder(&store, 11).await, Some(1));
+        assert_eq!(holder(&store, 12).await, Some(2));
+    }
+
+    #[tokio::test]
+    async fn deletion_ends_the_assignment_at_revoke_time() {
+        let store = store(&[(1, 8, Some(10))]);
+
+        assert_eq!(holder(&store, 9).await, Some(1));
+        assert_eq!(holder(&store, 10).await, None);
+    }
+
+    #[tokio::test]
+    async fn deleting_the_newer_assignment_falls_back_to_the_older_one() {
+        let store = store(&[(1, 8, None), (2, 12, Some(14))]);
+
+        assert_eq!(holder(&store, 13).await, Some(2));
+        assert_eq!(holder(&store, 14).await, Some(1));
+    }
+
+    #[tokio::test]
+    async fn re_registration_after_deletion_starts_a_new_period() {
+        let store = store(&[(1, 8, Some(10)), (3, 15, None)]);
+
+        assert_eq!(holder(&store, 9).await, Some(1));
+        assert_eq!(holder(&store, 12).await, None);
+        assert_eq!(holder(&store, 16).await, Some(3));
+    }
+
+    #[tokio::test]
+    async fn deletion_without_a_recorded_time_closes_at_the_next_assignment() {
+        let store = MemoryRepository::new();
+        store.insert_card(CardRow {
+            ic_id: "CARD".to_string(),
+            emp_id: 1,
+            date: at(8),
+            deleted: true,
+            revoked_at: None,
+        });
+        store.insert_card(CardRow {
+            ic_id: "CARD".to_string(),
+            emp_id: 2,
+            date: at(12),
+            deleted: false,
+            revoked_at: None,
+        });
+
+        assert_eq!(holder(&store, 10).await, Some(1));
+        assert_eq!(holder(&store, 13).await, Some(2));
+    }
+
+    #[tokio::test]
+    async fn same_assigned_at_prefers_the_later_row() {
+        let store = store(&[(1, 8, None), (2, 8, None)]);
+
+        assert_eq!(holder(&store, 9).await, Some(2));
+    }
+}
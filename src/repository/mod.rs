@@ -0,0 +1,214 @@
+// Repositories for the tables shared with the Python side
+// The gRPC handlers depend only on these traits. `SqlRepository` runs the queries
+// on MySQL or SQLite and `MemoryRepository` keeps the same tables in memory so
+// the handlers can be unit-tested without a database
+
+// MemoryRepository はハンドラーの単体テスト用
+#[cfg(test)]
+mod memory;
+mod routing;
+mod sql;
+
+#[cfg(test)]
+pub use memory::{CardRow, MemoryRepository};
+pub use routing::{ReadOrigin, ReadRoute};
+pub use sql::SqlRepository;
+
+use crate::db::replica::ReplicaMonitor;
+use crate::db::Database;
+use crate::models::{
+    CardAssignment, CardAssignmentPeriod, Driver, FingerLog, IcLog, IcLogWithHolder, IcLogWithPic,
+    IcNonReg, PicData, TmpData, TmpDataWithPic,
+};
+use chrono::NaiveDateTime;
+use std::sync::Arc;
+
+/// 取得順
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+/// ドライバー（drivers）
+#[tonic::async_trait]
+pub trait DriverRepository: Send + Sync {
+    async fn list(&self) -> Result<Vec<Driver>, sqlx::Error>;
+
+    async fn get(&self, id: i32) -> Result<Option<Driver>, sqlx::Error>;
+
+    /// 全件を入れ替え（外部APIからの再読み込み）
+    async fn replace_all(&self, drivers: &[Driver]) -> Result<(), sqlx::Error>;
+}
+
+/// ICカード・免許証の読み取りログ（ic_log）
+#[tonic::async_trait]
+pub trait IcLogRepository: Send + Sync {
+    async fn since(
+        &self,
+        start: NaiveDateTime,
+        order: SortOrder,
+    ) -> Result<Vec<IcLog>, sqlx::Error>;
+
+    /// ログの時刻にICカードを保持していたドライバー付き（新しい順）
+    async fn with_holder_since(
+        &self,
+        start: NaiveDateTime,
+    ) -> Result<Vec<IcLogWithHolder>, sqlx::Error>;
+
+    /// 最新N件（ログの時刻にICカードを保持していたドライバー付き）
+    async fn latest_with_holder(&self, limit: i32) -> Result<Vec<IcLogWithHolder>, sqlx::Error>;
+
+    /// time の時点で有効だったICカードの紐付け
+    async fn holder_at(
+        &self,
+        ic_id: &str,
+        time: NaiveDateTime,
+    ) -> Result<Option<CardAssignmentPeriod>, sqlx::Error>;
+
+    /// 同時刻の体温データがないログ
+    async fn without_tmp(
+        &self,
+        start: NaiveDateTime,
+        limit: i32,
+    ) -> Result<Vec<IcLog>, sqlx::Error>;
+}
+
+/// ICカードの登録（ic_id / ic_non_reged）
+#[tonic::async_trait]
+pub trait CardRepository: Send + Sync {
+    /// ICカードの現在の保持者（カードごとに解除されていない最新の紐付け）
+    async fn current_assignments(&self) -> Result<Vec<CardAssignment>, sqlx::Error>;
+
+    /// 紐付け履歴から現在の紐付け（ic_card_current）を作り直し、件数を返す
+    async fn rebuild_current_assignments(&self) -> Result<u64, sqlx::Error>;
+
+    /// 未登録のまま残っているICカード
+    async fn unregistered_since(&self, start: NaiveDateTime) -> Result<Vec<IcNonReg>, sqlx::Error>;
+
+    /// 登録予約するドライバーを設定
+    async fn reserve(&self, ic_id: &str, driver_id: i32) -> Result<(), sqlx::Error>;
+
+    /// 登録予約を取り消して一覧に戻す
+    async fn cancel_reservation(&self, ic_id: &str) -> Result<(), sqlx::Error>;
+
+    /// 未登録カードとして追加（既存なら予約し直す）。at は業務タイムゾーンの受付時刻
+    async fn reserve_direct(
+        &self,
+        ic_id: &str,
+        driver_id: i32,
+        at: NaiveDateTime,
+    ) -> Result<(), sqlx::Error>;
+}
+
+/// カメラ画像（pic_data）
+#[tonic::async_trait]
+pub trait PictureRepository: Send + Sync {
+    async fn list(&self) -> Result<Vec<PicData>, sqlx::Error>;
+
+    /// 体温データ + 画像 + ドライバー名
+    async fn tmp_with_pictures(
+        &self,
+        start: NaiveDateTime,
+        limit: i32,
+    ) -> Result<Vec<TmpDataWithPic>, sqlx::Error>;
+
+    /// ICログ + 画像
+    async fn ic_with_pictures(
+        &self,
+        start: NaiveDateTime,
+        limit: i32,
+    ) -> Result<Vec<IcLogWithPic>, sqlx::Error>;
+}
+
+/// 体温・指紋の測定データ（tmp_data / finger_log）
+#[tonic::async_trait]
+pub trait ReadingRepository: Send + Sync {
+    /// ドライバー未照合の体温データ（id = 0）
+    async fn unmatched_tmp(&self, limit: i32) -> Result<Vec<TmpData>, sqlx::Error>;
+
+    /// 同時刻の画像がない体温データ
+    async fn tmp_without_picture(
+        &self,
+        start: NaiveDateTime,
+        limit: i32,
+    ) -> Result<Vec<TmpData>, sqlx::Error>;
+
+    async fn finger_logs_since(&self, start: NaiveDateTime) -> Result<Vec<FingerLog>, sqlx::Error>;
+}
+
+/// サービスに渡すリポジトリ一式
+#[derive(Clone)]
+pub struct Repositories {
+    pub drivers: Arc<dyn DriverRepository>,
+    pub ic_logs: Arc<dyn IcLogRepository>,
+    pub cards: Arc<dyn CardRepository>,
+    pub pictures: Arc<dyn PictureRepository>,
+    pub readings: Arc<dyn ReadingRepository>,
+}
+
+impl Repositories {
+    pub fn sql(db: Database) -> Self {
+        Self::from_shared(Arc::new(SqlRepository::new(db)))
+    }
+
+    #[cfg(test)]
+    pub fn memory(store: MemoryRepository) -> Self {
+        Self::from_shared(Arc::new(store))
+    }
+
+    fn from_shared<R>(repo: Arc<R>) -> Self
+    where
+        R: DriverRepository
+            + IcLogRepository
+            + CardRepository
+            + PictureRepository
+            + ReadingRepository
+            + 'static,
+    {
+        Self {
+            drivers: repo.clone(),
+            ic_logs: repo.clone(),
+            cards: repo.clone(),
+            pictures: repo.clone(),
+            readings: repo,
+        }
+    }
+}
+
+/// 読み取り専用の履歴クエリ（読み取りレプリカ設定時はレプリカを優先）
+#[derive(Clone)]
+pub struct ReadRepositories {
+    pub ic_logs: ReadRoute<dyn IcLogRepository>,
+    pub pictures: ReadRoute<dyn PictureRepository>,
+    pub readings: ReadRoute<dyn ReadingRepository>,
+}
+
+impl ReadRepositories {
+    pub fn new(primary: &Repositories, replica: Option<(&Repositories, &ReplicaMonitor)>) -> Self {
+        match replica {
+            None => Self {
+                ic_logs: ReadRoute::primary(primary.ic_logs.clone()),
+                pictures: ReadRoute::primary(primary.pictures.clone()),
+                readings: ReadRoute::primary(primary.readings.clone()),
+            },
+            Some((replica, monitor)) => Self {
+                ic_logs: ReadRoute::with_replica(
+                    primary.ic_logs.clone(),
+                    replica.ic_logs.clone(),
+                    monitor.clone(),
+                ),
+                pictures: ReadRoute::with_replica(
+                    primary.pictures.clone(),
+                    replica.pictures.clone(),
+                    monitor.clone(),
+                ),
+                readings: ReadRoute::with_replica(
+                    primary.readings.clone(),
+                    replica.readings.clone(),
+                    monitor.clone(),
+                ),
+            },
+        }
+    }
+}
@@ -0,0 +1,91 @@
+// Read routing between the primary and the read replica
+// ReadRoute runs a read-only repository call on the replica while ReplicaMonitor reports
+// it usable, and on the primary otherwise. A failed replica query is retried on the
+// primary and keeps the replica out of rotation until its next health check
+
+use crate::db::replica::{FallbackReason, ReadSource, ReplicaMonitor};
+use std::future::Future;
+use std::sync::Arc;
+
+/// 読み取り先
+#[derive(Debug, Clone)]
+pub struct ReadOrigin {
+    pub source: ReadSource,
+    /// レプリカを使わなかった理由（レプリカ未設定時は None）
+    pub fallback: Option<FallbackReason>,
+}
+
+/// 読み取り結果と読み取り先
+pub struct Routed<T> {
+    pub value: T,
+    pub origin: ReadOrigin,
+}
+
+pub struct ReadRoute<R: ?Sized> {
+    primary: Arc<R>,
+    replica: Option<(Arc<R>, ReplicaMonitor)>,
+}
+
+impl<R: ?Sized> Clone for ReadRoute<R> {
+    fn clone(&self) -> Self {
+        Self {
+            primary: self.primary.clone(),
+            replica: self.replica.clone(),
+        }
+    }
+}
+
+impl<R: ?Sized> ReadRoute<R> {
+    /// 常にプライマリから読む
+    pub fn primary(primary: Arc<R>) -> Self {
+        Self {
+            primary,
+            replica: None,
+        }
+    }
+
+    pub fn with_replica(primary: Arc<R>, replica: Arc<R>, monitor: ReplicaMonitor) -> Self {
+        Self {
+            primary,
+            replica: Some((replica, monitor)),
+        }
+    }
+
+    /// 読み取り先を選んで query を実行
+    pub async fn read<T, F, Fut>(&self, query: F) -> Result<Routed<T>, sqlx::Error>
+    where
+        F: Fn(Arc<R>) -> Fut,
+        Fut: Future<Output = Result<T, sqlx::Error>>,
+    {
+        let fallback = match self.replica {
+            None => None,
+            Some((ref replica, ref monitor)) => match monitor.unavailable_reason() {
+                Some(reason) => Some(reason),
+                None => match query(replica.clone()).await {
+                    Ok(value) => {
+                        return Ok(Routed {
+                            value,
+                            origin: ReadOrigin {
+                                source: ReadSource::Replica,
+                                fallback: None,
+                            },
+                        })
+                    }
+                    Err(e) => {
+                        tracing::warn!("Replica read failed, retrying on primary: {}", e);
+                        monitor.mark_failed(&e);
+                        Some(FallbackReason::QueryFailed)
+                    }
+                },
+            },
+        };
+
+        Ok(Routed {
+            value: query(self.primary.clone()).await?,
+            origin: ReadOrigin {
+                source: ReadSource::Primary,
+                fallback,
+            },
+        })
+    }
+}
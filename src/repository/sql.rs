@@ -0,0 +1,683 @@
+// SQL implementation of the repositories (MySQL / SQLite)
+
+use super::{
+    CardRepository, DriverRepository, IcLogRepository, PictureRepository, ReadingRepository,
+    SortOrder,
+};
+use crate::db::Database;
+use crate::models::{
+    CardAssignment, CardAssignmentPeriod, Driver, FingerLog, IcLog, IcLogWithHolder, IcLogWithPic,
+    IcNonReg, PicData, TmpData, TmpDataWithPic,
+};
+use crate::with_pool;
+use chrono::NaiveDateTime;
+
+/// ICログ + ログの時刻にICカードを保持していたドライバー
+/// 現在の紐付け（ic_card_current）が holder_since 以降のログに有効なのでそれを結合し、
+/// それより前のログだけ紐付け履歴から引く（有効な紐付けが複数ある場合は新しい方、
+/// 同じ日時なら後に登録された方。MemoryRepository と同じ規則）
+/// 免許証（iid）のドライバーは呼び出し側で解決する
+const IC_LOG_WITH_HOLDER: &str = "
+    SELECT ic.id, ic.type, ic.detail, ic.date, ic.iid, ic.machine_ip,
+           CASE WHEN c.ic_id IS NOT NULL THEN c.emp_id ELSE (
+               SELECT h.emp_id FROM ic_card_history h
+               WHERE h.ic_id = ic.id AND h.assigned_at <= ic.date
+                 AND (h.revoked_at IS NULL OR h.revoked_at > ic.date)
+               ORDER BY h.assigned_at DESC, h.id DESC
+               LIMIT 1
+           ) END as holder_id
+    FROM ic_log ic
+    LEFT JOIN ic_card_current c ON c.ic_id = ic.id
+        AND c.date <= ic.date AND c.holder_since <= ic.date";
+
+/// 紐付け履歴から求めた現在の紐付け（ic_card_current の再構築用、トリガーと同じ規則）
+/// holder_since は後から登録されて解除された紐付けが最後に閉じた時刻
+const CURRENT_ASSIGNMENTS_FROM_HISTORY: &str = "
+    SELECT h.ic_id, h.emp_id, h.assigned_at, COALESCE(
+            (SELECT MAX(l.revoked_at) FROM ic_card_history l
+             WHERE l.ic_id = h.ic_id AND l.revoked_at IS NOT NULL
+               AND (l.assigned_at > h.assigned_at
+                    OR (l.assigned_at = h.assigned_at AND l.id > h.id))),
+            h.assigned_at
+        )
+    FROM ic_card_history h
+    WHERE h.revoked_at IS NULL
+      AND NOT EXISTS (
+          SELECT 1 FROM ic_card_history h2
+          WHERE h2.ic_id = h.ic_id AND h2.revoked_at IS NULL
+            AND (h2.assigned_at > h.assigned_at
+                 OR (h2.assigned_at = h.assigned_at AND h2.id > h.id))
+      )";
+
+/// 体温データ + pic_data + drivers
+const TMP_WITH_PICTURES: &str = r#"
+    SELECT
+        s9.*,
+        s8.name
+    FROM (
+        SELECT
+            s7.*,
+            s6.pic as pic_2,
+            s6.pic_key as pic_2_key,
+            s6.detail as detail_2
+        FROM (
+            SELECT
+                s5.*,
+                s4.pic as pic_1,
+                s4.pic_key as pic_1_key
+            FROM (
+                SELECT
+                    s3.*,
+                    s2.id as driver_id
+                FROM (
+                    SELECT tmp, amb, dist, date, machine_ip
+                    FROM tmp_data
+                    WHERE id = 0
+                ) s3
+                LEFT JOIN (SELECT * FROM tmp_data WHERE id > 0) s2
+                    ON s3.machine_ip = s2.machine_ip AND s3.date = s2.date
+            ) s5
+            LEFT JOIN (SELECT * FROM pic_data WHERE detail = 'tmp inserted') s4
+                ON s5.machine_ip = s4.machine_ip AND s5.date = s4.date
+        ) s7
+        LEFT JOIN (
+            SELECT * FROM pic_data
+            WHERE detail = 'tmp inserted by ic' OR detail = 'tmp inserted by fing'
+        ) s6
+            ON s7.machine_ip = s6.machine_ip AND s7.date = s6.date
+    ) s9
+    LEFT JOIN drivers s8 ON s9.driver_id = s8.id
+    WHERE s9.date >= ?
+    ORDER BY s9.date DESC
+    LIMIT ?
+"#;
+
+#[derive(Clone)]
+pub struct SqlRepository {
+    db: Database,
+}
+
+impl SqlRepository {
+    pub fn new(db: Database) -> Self {
+        Self { db }
+    }
+}
+
+#[tonic::async_trait]
+impl DriverRepository for SqlRepository {
+    async fn list(&self) -> Result<Vec<Driver>, sqlx::Error> {
+        with_pool!(self.db, pool => {
+            sqlx::query_as("SELECT id, name FROM drivers")
+                .fetch_all(pool)
+                .await
+        })
+    }
+
+    async fn get(&self, id: i32) -> Result<Option<Driver>, sqlx::Error> {
+        with_pool!(self.db, pool => {
+            sqlx::query_as("SELECT id, name FROM drivers WHERE id = ? LIMIT 1")
+                .bind(id)
+                .fetch_optional(pool)
+                .await
+        })
+    }
+
+    async fn replace_all(&self, drivers: &[Driver]) -> Result<(), sqlx::Error> {
+        // トランザクションで既存データを削除して新しいデータを挿入
+        with_pool!(self.db, pool => {
+            let mut tx = pool.begin().await?;
+
+            sqlx::query("DELETE FROM drivers").execute(&mut *tx).await?;
+            for driver in drivers {
+                sqlx::query("INSERT INTO drivers (id, name) VALUES (?, ?)")
+                    .bind(driver.id)
+                    .bind(&driver.name)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+
+            tx.commit().await
+        })
+    }
+}
+
+#[tonic::async_trait]
+impl IcLogRepository for SqlRepository {
+    async fn since(
+        &self,
+        start: NaiveDateTime,
+        order: SortOrder,
+    ) -> Result<Vec<IcLog>, sqlx::Error> {
+        let query = match order {
+            SortOrder::Asc => {
+                "SELECT id, type, detail, date, iid, machine_ip
+                 FROM ic_log
+                 WHERE date >= ?
+                 ORDER BY date ASC"
+            }
+            SortOrder::Desc => {
+                "SELECT id, type, detail, date, iid, machine_ip
+                 FROM ic_log
+                 WHERE date >= ?
+                 ORDER BY date DESC"
+            }
+        };
+
+        with_pool!(self.db, pool => {
+            sqlx::query_as(query)
+                .bind(start)
+                .fetch_all(pool)
+                .await
+        })
+    }
+
+    async fn with_holder_since(
+        &self,
+        start: NaiveDateTime,
+    ) -> Result<Vec<IcLogWithHolder>, sqlx::Error> {
+        let query = format!(
+            "{} WHERE ic.date >= ? ORDER BY ic.date DESC",
+            IC_LOG_WITH_HOLDER
+        );
+
+        with_pool!(self.db, pool => {
+            sqlx::query_as(&query)
+                .bind(start)
+                .fetch_all(pool)
+                .await
+        })
+    }
+
+    async fn latest_with_holder(&self, limit: i32) -> Result<Vec<IcLogWithHolder>, sqlx::Error> {
+        let query = format!("{} ORDER BY ic.date DESC LIMIT ?", IC_LOG_WITH_HOLDER);
+
+        with_pool!(self.db, pool => {
+            sqlx::query_as(&query)
+                .bind(limit)
+                .fetch_all(pool)
+                .await
+        })
+    }
+
+    async fn holder_at(
+        &self,
+        ic_id: &str,
+        time: NaiveDateTime,
+    ) -> Result<Option<CardAssignmentPeriod>, sqlx::Error> {
+        with_pool!(self.db, pool => {
+            sqlx::query_as(
+                "SELECT ic_id, emp_id, assigned_at, revoked_at
+                 FROM ic_card_history
+                 WHERE ic_id = ? AND assigned_at <= ? AND (revoked_at IS NULL OR revoked_at > ?)
+                 ORDER BY assigned_at DESC, id DESC
+                 LIMIT 1",
+            )
+            .bind(ic_id)
+            .bind(time)
+            .bind(time)
+            .fetch_optional(pool)
+            .await
+        })
+    }
+
+    async fn without_tmp(
+        &self,
+        start: NaiveDateTime,
+        limit: i32,
+    ) -> Result<Vec<IcLog>, sqlx::Error> {
+        with_pool!(self.db, pool => {
+            sqlx::query_as(
+                "SELECT ic.id, ic.type, ic.detail, ic.date, ic.iid, ic.machine_ip
+                 FROM ic_log ic
+                 LEFT JOIN tmp_data t ON ic.machine_ip = t.machine_ip AND ic.date = t.date
+                 WHERE t.machine_ip IS NULL AND ic.date >= ?
+                 ORDER BY ic.date DESC
+                 LIMIT ?",
+            )
+            .bind(start)
+            .bind(limit)
+            .fetch_all(pool)
+            .await
+        })
+    }
+}
+
+#[tonic::async_trait]
+impl CardRepository for SqlRepository {
+    async fn current_assignments(&self) -> Result<Vec<CardAssignment>, sqlx::Error> {
+        with_pool!(self.db, pool => {
+            sqlx::query_as("SELECT ic_id, emp_id FROM ic_card_current")
+                .fetch_all(pool)
+                .await
+        })
+    }
+
+    async fn rebuild_current_assignments(&self) -> Result<u64, sqlx::Error> {
+        let query = format!(
+            "INSERT INTO ic_card_current (ic_id, emp_id, date, holder_since) {}",
+            CURRENT_ASSIGNMENTS_FROM_HISTORY
+        );
+
+        with_pool!(self.db, pool => {
+            let mut tx = pool.begin().await?;
+
+            sqlx::query("DELETE FROM ic_card_current")
+                .execute(&mut *tx)
+                .await?;
+            let rebuilt = sqlx::query(&query).execute(&mut *tx).await?.rows_affected();
+
+            tx.commit().await?;
+            Ok(rebuilt)
+        })
+    }
+
+    async fn unregistered_since(&self, start: NaiveDateTime) -> Result<Vec<IcNonReg>, sqlx::Error> {
+        with_pool!(self.db, pool => {
+            sqlx::query_as(
+                "SELECT n.id, n.datetime, n.deleted, n.registered_id
+                 FROM ic_non_reged n
+                 LEFT JOIN ic_id i ON n.id = i.ic_id
+                   AND (i.deleted = 0 OR i.deleted IS NULL)
+                   AND i.date >= n.datetime
+                 WHERE n.datetime >= ? AND (n.deleted = 0 OR n.deleted IS NULL)
+                   AND i.ic_id IS NULL
+                 ORDER BY n.datetime DESC",
+            )
+            .bind(start)
+            .fetch_all(pool)
+            .await
+        })
+    }
+
+    async fn reserve(&self, ic_id: &str, driver_id: i32) -> Result<(), sqlx::Error> {
+        // deleted=0のまま、Pythonクライアントが処理後にdeleted=1にする
+        with_pool!(self.db, pool => {
+            sqlx::query(
+                "UPDATE ic_non_reged
+                 SET registered_id = ?
+                 WHERE id = ?",
+            )
+            .bind(driver_id)
+            .bind(ic_id)
+            .execute(pool)
+            .await?;
+        });
+        Ok(())
+    }
+
+    async fn cancel_reservation(&self, ic_id: &str) -> Result<(), sqlx::Error> {
+        with_pool!(self.db, pool => {
+            sqlx::query(
+                "UPDATE ic_non_reged
+                 SET registered_id = NULL, deleted = 0
+                 WHERE id = ?",
+            )
+            .bind(ic_id)
+            .execute(pool)
+            .await?;
+        });
+        Ok(())
+    }
+
+    async fn reserve_direct(
+        &self,
+        ic_id: &str,
+        driver_id: i32,
+        at: NaiveDateTime,
+    ) -> Result<(), sqlx::Error> {
+        let query = format!(
+            "INSERT INTO ic_non_reged (id, registered_id, datetime, deleted)
+             VALUES (?, ?, ?, 0)
+             {}",
+            self.db
+                .dialect()
+                .upsert("id", &["registered_id", "datetime", "deleted"])
+        );
+
+        with_pool!(self.db, pool => {
+            sqlx::query(&query)
+                .bind(ic_id)
+                .bind(driver_id)
+                .bind(at)
+                .execute(pool)
+                .await?;
+        });
+        Ok(())
+    }
+}
+
+#[tonic::async_trait]
+impl PictureRepository for SqlRepository {
+    async fn list(&self) -> Result<Vec<PicData>, sqlx::Error> {
+        with_pool!(self.db, pool => {
+            sqlx::query_as(
+                "SELECT date, cam, pic, pic_key, detail, machine_ip
+                 FROM pic_data
+                 ORDER BY date DESC",
+            )
+            .fetch_all(pool)
+            .await
+        })
+    }
+
+    async fn tmp_with_pictures(
+        &self,
+        start: NaiveDateTime,
+        limit: i32,
+    ) -> Result<Vec<TmpDataWithPic>, sqlx::Error> {
+        with_pool!(self.db, pool => {
+            sqlx::query_as(TMP_WITH_PICTURES)
+                .bind(start)
+                .bind(limit)
+                .fetch_all(pool)
+                .await
+        })
+    }
+
+    async fn ic_with_pictures(
+        &self,
+        start: NaiveDateTime,
+        limit: i32,
+    ) -> Result<Vec<IcLogWithPic>, sqlx::Error> {
+        with_pool!(self.db, pool => {
+            sqlx::query_as(
+                r#"
+                SELECT
+                    ic.id, ic.type, ic.detail, ic.date, ic.iid, ic.machine_ip,
+                    p.pic, p.pic_key
+                FROM ic_log ic
+                LEFT JOIN pic_data p ON ic.machine_ip = p.machine_ip AND ic.date = p.date
+                WHERE ic.date >= ?
+                ORDER BY ic.date DESC
+                LIMIT ?
+            "#,
+            )
+            .bind(start)
+            .bind(limit)
+            .fetch_all(pool)
+            .await
+        })
+    }
+}
+
+#[tonic::async_trait]
+impl ReadingRepository for SqlRepository {
+    async fn unmatched_tmp(&self, limit: i32) -> Result<Vec<TmpData>, sqlx::Error> {
+        with_pool!(self.db, pool => {
+            sqlx::query_as(
+                "SELECT machine_ip, tmp, amb, dist, date, id
+                 FROM tmp_data
+                 WHERE id = 0
+                 ORDER BY date DESC
+                 LIMIT ?",
+            )
+            .bind(limit)
+            .fetch_all(pool)
+            .await
+        })
+    }
+
+    async fn tmp_without_picture(
+        &self,
+        start: NaiveDateTime,
+        limit: i32,
+    ) -> Result<Vec<TmpData>, sqlx::Error> {
+        with_pool!(self.db, pool => {
+            sqlx::query_as(
+                "SELECT t.machine_ip, t.tmp, t.amb, t.dist, t.date, t.id
+                 FROM tmp_data t
+                 LEFT JOIN pic_data p ON t.machine_ip = p.machine_ip AND t.date = p.date
+                 WHERE p.machine_ip IS NULL AND t.date >= ?
+                 ORDER BY t.date DESC
+                 LIMIT ?",
+            )
+            .bind(start)
+            .bind(limit)
+            .fetch_all(pool)
+            .await
+        })
+    }
+
+    async fn finger_logs_since(&self, start: NaiveDateTime) -> Result<Vec<FingerLog>, sqlx::Error> {
+        with_pool!(self.db, pool => {
+            sqlx::query_as(
+                "SELECT date, machine_ip, id, message
+                 FROM finger_log
+                 WHERE date >= ?
+                 ORDER BY date DESC",
+            )
+            .bind(start)
+            .fetch_all(pool)
+            .await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock;
+    use chrono::{Duration, Timelike};
+
+    /// 解除日時はDBの現在時刻で記録されるため、テストの時刻は現在時刻を基準にする
+    fn base() -> NaiveDateTime {
+        clock::now().with_nanosecond(0).unwrap()
+    }
+
+    async fn assign(db: &Database, ic_id: &str, emp_id: i32, date: NaiveDateTime) {
+        with_pool!(db, pool => {
+            sqlx::query("INSERT INTO ic_id (ic_id, emp_id, date, deleted) VALUES (?, ?, ?, 0)")
+                .bind(ic_id)
+                .bind(emp_id)
+                .bind(date)
+                .execute(pool)
+                .await
+                .unwrap();
+        });
+    }
+
+    async fn mark_deleted(db: &Database, ic_id: &str, emp_id: i32) {
+        with_pool!(db, pool => {
+            sqlx::query("UPDATE ic_id SET deleted = 1 WHERE ic_id = ? AND emp_id = ?")
+                .bind(ic_id)
+                .bind(emp_id)
+                .execute(pool)
+                .await
+                .unwrap();
+        });
+    }
+
+    async fn delete_row(db: &Database, ic_id: &str, emp_id: i32) {
+        with_pool!(db, pool => {
+            sqlx::query("DELETE FROM ic_id WHERE ic_id = ? AND emp_id = ?")
+                .bind(ic_id)
+                .bind(emp_id)
+                .execute(pool)
+                .await
+                .unwrap();
+        });
+    }
+
+    async fn read_card(db: &Database, ic_id: &str, date: NaiveDateTime) {
+        with_pool!(db, pool => {
+            sqlx::query(
+                "INSERT INTO ic_log (id, type, detail, date, iid, machine_ip)
+                 VALUES (?, 'ic', NULL, ?, NULL, '10.0.0.1')",
+            )
+            .bind(ic_id)
+            .bind(date)
+            .execute(pool)
+            .await
+            .unwrap();
+        });
+    }
+
+    async fn holder(repo: &SqlRepository, ic_id: &str, time: NaiveDateTime) -> Option<i32> {
+        repo.holder_at(ic_id, time)
+            .await
+            .unwrap()
+            .map(|period| period.emp_id)
+    }
+
+    async fn log_holders(repo: &SqlRepository, start: NaiveDateTime) -> Vec<Option<i32>> {
+        repo.with_holder_since(start)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|log| log.holder_id)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn reassignment_keeps_history_with_the_previous_holder() {
+        let db = Database::memory().await;
+        let repo = SqlRepository::new(db.clone());
+        let base = base();
+
+        assign(&db, "CARD", 1, base - Duration::hours(10)).await;
+        read_card(&db, "CARD", base - Duration::hours(7)).await;
+        assign(&db, "CARD", 2, base - Duration::hours(5)).await;
+        read_card(&db, "CARD", base - Duration::hours(2)).await;
+
+        assert_eq!(
+            holder(&repo, "CARD", base - Duration::hours(7)).await,
+            Some(1)
+        );
+        assert_eq!(
+            holder(&repo, "CARD", base - Duration::hours(2)).await,
+            Some(2)
+        );
+        assert_eq!(
+            log_holders(&repo, base - Duration::days(1)).await,
+            vec![Some(2), Some(1)]
+        );
+
+        let current = repo.current_assignments().await.unwrap();
+        assert_eq!(current.len(), 1);
+        assert_eq!(current[0].emp_id, 2);
+    }
+
+    #[tokio::test]
+    async fn deletion_closes_the_assignment_in_business_time() {
+        let db = Database::memory().await;
+        let repo = SqlRepository::new(db.clone());
+        let base = base();
+
+        assign(&db, "CARD", 1, base - Duration::hours(10)).await;
+        read_card(&db, "CARD", base - Duration::hours(9)).await;
+        mark_deleted(&db, "CARD", 1).await;
+
+        let period = repo
+            .holder_at("CARD", base - Duration::hours(9))
+            .await
+            .unwrap()
+            .expect("assignment before deletion");
+        let revoked_at = period.revoked_at.expect("revoked");
+        assert!((revoked_at - base).num_minutes().abs() <= 1);
+
+        assert_eq!(holder(&repo, "CARD", base + Duration::hours(1)).await, None);
+        assert_eq!(
+            log_holders(&repo, base - Duration::days(1)).await,
+            vec![Some(1)]
+        );
+        assert!(repo.current_assignments().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn deleting_the_newer_row_falls_back_to_the_previous_assignment() {
+        let db = Database::memory().await;
+        let repo = SqlRepository::new(db.clone());
+        let base = base();
+
+        assign(&db, "CARD", 1, base - Duration::hours(10)).await;
+        read_card(&db, "CARD", base - Duration::hours(8)).await;
+        assign(&db, "CARD", 2, base - Duration::hours(5)).await;
+        read_card(&db, "CARD", base - Duration::hours(2)).await;
+        delete_row(&db, "CARD", 2).await;
+        read_card(&db, "CARD", base + Duration::hours(1)).await;
+
+        assert_eq!(
+            holder(&repo, "CARD", base - Duration::hours(2)).await,
+            Some(2)
+        );
+        assert_eq!(
+            holder(&repo, "CARD", base + Duration::hours(1)).await,
+            Some(1)
+        );
+        // 現在の紐付けは削除より後のログにだけ使われる
+        assert_eq!(
+            log_holders(&repo, base - Duration::days(1)).await,
+            vec![Some(1), Some(2), Some(1)]
+        );
+        let current = repo.current_assignments().await.unwrap();
+        assert_eq!(current.len(), 1);
+        assert_eq!(current[0].emp_id, 1);
+    }
+
+    #[tokio::test]
+    async fn rebuild_restores_current_assignments_from_history() {
+        let db = Database::memory().await;
+        let repo = SqlRepository::new(db.clone());
+        let base = base();
+
+        assign(&db, "A", 1, base - Duration::hours(10)).await;
+        assign(&db, "A", 2, base - Duration::hours(5)).await;
+        assign(&db, "B", 3, base - Duration::hours(4)).await;
+        mark_deleted(&db, "B", 3).await;
+        assign(&db, "C", 4, base - Duration::hours(3)).await;
+        read_card(&db, "A", base - Duration::hours(1)).await;
+
+        with_pool!(db, pool => {
+            sqlx::query("DELETE FROM ic_card_current")
+                .execute(pool)
+                .await
+                .unwrap();
+        });
+        assert!(repo.current_assignments().await.unwrap().is_empty());
+
+        assert_eq!(repo.rebuild_current_assignments().await.unwrap(), 2);
+        let mut current: Vec<(String, i32)> = repo
+            .current_assignments()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|c| (c.ic_id, c.emp_id))
+            .collect();
+        current.sort();
+        assert_eq!(current, vec![("A".to_string(), 2), ("C".to_string(), 4)]);
+        assert_eq!(
+            log_holders(&repo, base - Duration::days(1)).await,
+            vec![Some(2)]
+        );
+    }
+
+    #[tokio::test]
+    async fn re_registration_starts_a_new_assignment() {
+        let db = Database::memory().await;
+        let repo = SqlRepository::new(db.clone());
+        let base = base();
+
+        assign(&db, "CARD", 1, base - Duration::hours(10)).await;
+        read_card(&db, "CARD", base - Duration::hours(9)).await;
+        mark_deleted(&db, "CARD", 1).await;
+        assign(&db, "CARD", 3, base + Duration::hours(1)).await;
+        read_card(&db, "CARD", base + Duration::hours(2)).await;
+
+        assert_eq!(
+            holder(&repo, "CARD", base - Duration::hours(9)).await,
+            Some(1)
+        );
+        assert_eq!(
+            holder(&repo, "CARD", base + Duration::minutes(30)).await,
+            None
+        );
+        assert_eq!(
+            holder(&repo, "CARD", base + Duration::hours(2)).await,
+            Some(3)
+        );
+        assert_eq!(
+            log_holders(&repo, base - Duration::days(1)).await,
+            vec![Some(3), Some(1)]
+        );
+    }
+}
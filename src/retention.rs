@@ -0,0 +1,658 @@
+// Data retention for the punch and picture tables
+// Rows older than the per-table retention period (RETENTION_<TABLE>) are written to
+// gzip-compressed NDJSON files under RETENTION_ARCHIVE_DIR and then deleted, one batch
+// per transaction. A batch is deleted only if exactly the archived rows are removed, so a
+// row is never deleted without being in an archive file. pic_data batches are read and
+// archived (with pictures from an external store embedded) before the delete transaction
+// opens, and deleted by id so rows backfilled meanwhile wait for the next batch; the
+// archived pictures are removed from the store after the delete commits
+
+use crate::clock;
+use crate::db::Database;
+use crate::models::{FingerLog, IcLog, PicData, TmpData};
+use crate::picture_store::PictureStore;
+use crate::with_pool;
+use base64::Engine;
+use chrono::{Months, NaiveDateTime};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::Serialize;
+use serde_json::json;
+use std::fmt;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tracing::{error, info, warn};
+
+/// pic_data の1バッチの上限（画像を含むため他のテーブルより小さくする）
+const PICTURE_BATCH_SIZE: i64 = 100;
+
+/// 保持期間を設定できるテーブル
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetainedTable {
+    PicData,
+    TmpData,
+    IcLog,
+    FingerLog,
+}
+
+impl RetainedTable {
+    pub const ALL: [RetainedTable; 4] = [
+        RetainedTable::PicData,
+        RetainedTable::TmpData,
+        RetainedTable::IcLog,
+        RetainedTable::FingerLog,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::PicData => "pic_data",
+            Self::TmpData => "tmp_data",
+            Self::IcLog => "ic_log",
+            Self::FingerLog => "finger_log",
+        }
+    }
+
+    /// 保持期間を指定する環境変数
+    pub fn env_name(&self) -> &'static str {
+        match self {
+            Self::PicData => "RETENTION_PIC_DATA",
+            Self::TmpData => "RETENTION_TMP_DATA",
+            Self::IcLog => "RETENTION_IC_LOG",
+            Self::FingerLog => "RETENTION_FINGER_LOG",
+        }
+    }
+
+    fn select_sql(&self) -> &'static str {
+        match self {
+            Self::PicData => {
+                "SELECT id, date, cam, pic, pic_key, detail, machine_ip
+                 FROM pic_data WHERE date <= ? ORDER BY date, id"
+            }
+            Self::TmpData => {
+                "SELECT machine_ip, tmp, amb, dist, date, id
+                 FROM tmp_data WHERE date <= ? ORDER BY date"
+            }
+            Self::IcLog => {
+                "SELECT id, type, detail, date, iid, machine_ip
+                 FROM ic_log WHERE date <= ? ORDER BY date"
+            }
+            Self::FingerLog => {
+                "SELECT date, machine_ip, id, message
+                 FROM finger_log WHERE date <= ? ORDER BY date"
+            }
+        }
+    }
+}
+
+/// 保持期間（"90d" / "26w" / "18m" / "5y"、単位なしは日数）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetentionPeriod {
+    Days(u32),
+    Months(u32),
+}
+
+impl RetentionPeriod {
+    /// この日時より前の行が期限切れ
+    pub fn cutoff(&self, now: NaiveDateTime) -> NaiveDateTime {
+        match *self {
+            Self::Days(days) => now - chrono::Duration::days(days as i64),
+            Self::Months(months) => now
+                .checked_sub_months(Months::new(months))
+                .unwrap_or(NaiveDateTime::MIN),
+        }
+    }
+}
+
+impl FromStr for RetentionPeriod {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim().to_ascii_lowercase();
+        let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+            Some(index) => value.split_at(index),
+            None => (value.as_str(), "d"),
+        };
+        let n: u32 = number
+            .parse()
+            .map_err(|_| "expected a number followed by d, w, m or y".to_string())?;
+        if n == 0 {
+            return Err("must be greater than 0".to_string());
+        }
+        let overflow = || "period is too long".to_string();
+        match unit {
+            "d" => Ok(Self::Days(n)),
+            "w" => n.checked_mul(7).map(Self::Days).ok_or_else(overflow),
+            "m" => Ok(Self::Months(n)),
+            "y" => n.checked_mul(12).map(Self::Months).ok_or_else(overflow),
+            _ => Err(format!("unknown unit '{}' (expected d, w, m or y)", unit)),
+        }
+    }
+}
+
+impl fmt::Display for RetentionPeriod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Days(days) => write!(f, "{}d", days),
+            Self::Months(months) if months % 12 == 0 => write!(f, "{}y", months / 12),
+            Self::Months(months) => write!(f, "{}m", months),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RetentionPolicy {
+    pub table: RetainedTable,
+    pub keep: RetentionPeriod,
+}
+
+/// 保持ジョブの設定
+#[derive(Debug, Clone)]
+pub struct RetentionSettings {
+    /// 設定されたテーブルのみ（未設定のテーブルは無期限に保持）
+    pub policies: Vec<RetentionPolicy>,
+    pub archive_dir: PathBuf,
+    pub interval: Duration,
+    pub batch_size: i64,
+}
+
+/// テーブルごとの結果（dry-run では archived_rows は 0）
+#[derive(Debug, Clone)]
+pub struct TableReport {
+    pub table: RetainedTable,
+    pub keep: RetentionPeriod,
+    pub cutoff: NaiveDateTime,
+    pub expired_rows: i64,
+    pub oldest_date: Option<NaiveDateTime>,
+    pub archived_rows: i64,
+    pub archive_files: Vec<PathBuf>,
+}
+
+#[derive(Debug)]
+pub enum RetentionError {
+    Database(sqlx::Error),
+    Archive(String),
+}
+
+impl fmt::Display for RetentionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RetentionError::Database(e) => write!(f, "Database error: {}", e),
+            RetentionError::Archive(msg) => write!(f, "Archive error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for RetentionError {}
+
+impl From<sqlx::Error> for RetentionError {
+    fn from(e: sqlx::Error) -> Self {
+        RetentionError::Database(e)
+    }
+}
+
+/// アーカイブする pic_data の行
+#[derive(sqlx::FromRow)]
+struct ExpiredPicture {
+    id: i64,
+    #[sqlx(flatten)]
+    row: PicData,
+}
+
+/// 1バッチのアーカイブ結果
+struct ArchivedBatch {
+    rows: i64,
+    file: PathBuf,
+    picture_keys: Vec<String>,
+}
+
+#[derive(Clone)]
+pub struct RetentionJob {
+    db: Database,
+    pictures: Arc<dyn PictureStore>,
+    settings: Arc<RetentionSettings>,
+}
+
+impl RetentionJob {
+    pub fn new(db: Database, pictures: Arc<dyn PictureStore>, settings: RetentionSettings) -> Self {
+        Self {
+            db,
+            pictures,
+            settings: Arc::new(settings),
+        }
+    }
+
+    pub fn settings(&self) -> &RetentionSettings {
+        &self.settings
+    }
+
+    /// 期限切れの行数を集計（何も変更しない）
+    pub async fn report(&self) -> Result<Vec<TableReport>, sqlx::Error> {
+        let now = clock::now();
+        let mut reports = Vec::with_capacity(self.settings.policies.len());
+        for policy in &self.settings.policies {
+            let cutoff = policy.keep.cutoff(now);
+            let sql = format!(
+                "SELECT COUNT(*), MIN(date) FROM {} WHERE date < ?",
+                policy.table.as_str()
+            );
+            let (expired_rows, oldest_date): (i64, Option<NaiveDateTime>) = with_pool!(self.db, pool => {
+                sqlx::query_as(&sql).bind(cutoff).fetch_one(pool).await?
+            });
+            reports.push(TableReport {
+                table: policy.table,
+                keep: policy.keep,
+                cutoff,
+                expired_rows,
+                oldest_date,
+                archived_rows: 0,
+                archive_files: Vec::new(),
+            });
+        }
+        Ok(reports)
+    }
+
+    /// 期限切れの行をアーカイブして削除
+    pub async fn run(&self) -> Result<Vec<TableReport>, RetentionError> {
+        let mut reports = self.report().await?;
+        for report in reports.iter_mut().filter(|r| r.expired_rows > 0) {
+            loop {
+                let Some(batch) = self.archive_batch(report.table, report.cutoff).await? else {
+                    break;
+                };
+                info!(
+                    "Archived {} {} rows to {}",
+                    batch.rows,
+                    report.table.as_str(),
+                    batch.file.display()
+                );
+                report.archived_rows += batch.rows;
+                report.archive_files.push(batch.file);
+                for key in batch.picture_keys {
+                    if let Err(e) = self.pictures.delete(&key).await {
+                        warn!("Failed to delete archived picture {}: {}", key, e);
+                    }
+                }
+            }
+        }
+        Ok(reports)
+    }
+
+    /// 定期実行を開始（起動時に1回実行）
+    pub fn spawn(&self) {
+        let job = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(job.settings.interval);
+            loop {
+                interval.tick().await;
+                match job.run().await {
+                    Ok(reports) => {
+                        for report in reports.iter().filter(|r| r.archived_rows > 0) {
+                            info!(
+                                "Retention: archived and deleted {} {} rows older than {}",
+                                report.archived_rows,
+                                report.table.as_str(),
+                                report.cutoff.format("%Y-%m-%d %H:%M:%S")
+                            );
+                        }
+                    }
+                    Err(e) => error!("Retention job failed: {}", e),
+                }
+            }
+        });
+    }
+
+    /// cutoff より前の最も古い行から1バッチ分をアーカイブして削除（対象がなければ None）
+    async fn archive_batch(
+        &self,
+        table: RetainedTable,
+        cutoff: NaiveDateTime,
+    ) -> Result<Option<ArchivedBatch>, RetentionError> {
+        let batch_size = match table {
+            RetainedTable::PicData => self.settings.batch_size.min(PICTURE_BATCH_SIZE),
+            _ => self.settings.batch_size,
+        };
+        // 主キーのないテーブルがあるため日時で区切る（同じ日時の行は同じバッチに含める）
+        let upper_sql = format!(
+            "SELECT MAX(date) FROM (
+                SELECT date FROM {} WHERE date < ? ORDER BY date LIMIT ?
+             ) oldest",
+            table.as_str()
+        );
+        let delete_sql = format!("DELETE FROM {} WHERE date <= ?", table.as_str());
+
+        let upper: Option<NaiveDateTime> = with_pool!(self.db, pool => {
+            sqlx::query_scalar(&upper_sql)
+                .bind(cutoff)
+                .bind(batch_size)
+                .fetch_one(pool)
+                .await?
+        });
+        let Some(upper) = upper else {
+            return Ok(None);
+        };
+        if table == RetainedTable::PicData {
+            return self.archive_pictures(upper).await.map(Some);
+        }
+
+        with_pool!(self.db, pool => {
+            let mut tx = pool.begin().await?;
+            let (lines, picture_keys) = match table {
+                RetainedTable::PicData => unreachable!("pic_data is archived by id"),
+                RetainedTable::TmpData => {
+                    let rows: Vec<TmpData> = sqlx::query_as(table.select_sql())
+                        .bind(upper)
+                        .fetch_all(&mut *tx)
+                        .await?;
+                    (to_lines(&rows)?, Vec::new())
+                }
+                RetainedTable::IcLog => {
+                    let rows: Vec<IcLog> = sqlx::query_as(table.select_sql())
+                        .bind(upper)
+                        .fetch_all(&mut *tx)
+                        .await?;
+                    (to_lines(&rows)?, Vec::new())
+                }
+                RetainedTable::FingerLog => {
+                    let rows: Vec<FingerLog> = sqlx::query_as(table.select_sql())
+                        .bind(upper)
+                        .fetch_all(&mut *tx)
+                        .await?;
+                    (to_lines(&rows)?, Vec::new())
+                }
+            };
+
+            let rows = lines.len() as i64;
+            let file = write_archive(&self.settings.archive_dir, table, upper, &lines).await?;
+            let deleted = sqlx::query(&delete_sql)
+                .bind(upper)
+                .execute(&mut *tx)
+                .await?
+                .rows_affected();
+            if deleted != rows as u64 {
+                // アーカイブ後に行が追加・削除された場合はこのバッチを取り消す
+                tx.rollback().await?;
+                let _ = tokio::fs::remove_file(&file).await;
+                return Err(RetentionError::Archive(format!(
+                    "{} changed during archival ({} archived, {} matched for deletion)",
+                    table.as_str(),
+                    rows,
+                    deleted
+                )));
+            }
+            tx.commit().await?;
+
+            Ok(Some(ArchivedBatch {
+                rows,
+                file,
+                picture_keys,
+            }))
+        })
+    }
+
+    /// upper までの pic_data を読み込んでアーカイブし、読み込んだ行だけを id で削除
+    ///
+    /// 外部ストアからの画像の取得とアーカイブの書き込みはトランザクションの外で行う
+    async fn archive_pictures(
+        &self,
+        upper: NaiveDateTime,
+    ) -> Result<ArchivedBatch, RetentionError> {
+        let table = RetainedTable::PicData;
+        let expired: Vec<ExpiredPicture> = with_pool!(self.db, pool => {
+            sqlx::query_as(table.select_sql())
+                .bind(upper)
+                .fetch_all(pool)
+                .await?
+        });
+        let ids: Vec<i64> = expired.iter().map(|p| p.id).collect();
+        let (lines, picture_keys) = self
+            .picture_lines(expired.into_iter().map(|p| p.row).collect())
+            .await?;
+
+        let rows = lines.len() as i64;
+        let file = write_archive(&self.settings.archive_dir, table, upper, &lines).await?;
+        let delete_sql = format!(
+            "DELETE FROM pic_data WHERE id IN ({})",
+            vec!["?"; ids.len()].join(", ")
+        );
+        let deleted: Result<u64, sqlx::Error> = with_pool!(self.db, pool => {
+            async {
+                let mut tx = pool.begin().await?;
+                let mut query = sqlx::query(&delete_sql);
+                for id in &ids {
+                    query = query.bind(id);
+                }
+                let deleted = query.execute(&mut *tx).await?.rows_affected();
+                if deleted == rows as u64 {
+                    tx.commit().await?;
+                } else {
+                    tx.rollback().await?;
+                }
+                Ok(deleted)
+            }
+            .await
+        });
+        let deleted = match deleted {
+            Ok(deleted) => deleted,
+            Err(e) => {
+                let _ = tokio::fs::remove_file(&file).await;
+                return Err(e.into());
+            }
+        };
+        if deleted != rows as u64 {
+            // アーカイブ中に別の処理が行を削除した場合はこのバッチを取り消す
+            let _ = tokio::fs::remove_file(&file).await;
+            return Err(RetentionError::Archive(format!(
+                "pic_data changed during archival ({} archived, {} deleted)",
+                rows, deleted
+            )));
+        }
+
+        Ok(ArchivedBatch {
+            rows,
+            file,
+            picture_keys,
+        })
+    }
+
+    /// pic_data の行をアーカイブ行に変換（外部ストアの画像も埋め込む）
+    async fn picture_lines(
+        &self,
+        rows: Vec<PicData>,
+    ) -> Result<(Vec<String>, Vec<String>), RetentionError> {
+        let mut lines = Vec::with_capacity(rows.len());
+        let mut keys = Vec::new();
+        for row in rows {
+            let pic = match (row.pic, row.pic_key.as_deref()) {
+                (Some(pic), _) => Some(pic),
+                (None, Some(key)) => {
+                    let pic = self.pictures.get(key).await.map_err(|e| {
+                        RetentionError::Archive(format!("failed to read picture {}: {}", key, e))
+                    })?;
+                    if pic.is_none() {
+                        warn!("Archived picture {} is missing from the store", key);
+                    }
+                    keys.push(key.to_string());
+                    pic
+                }
+                (None, None) => None,
+            };
+            let line = json!({
+                "date": row.date,
+                "cam": row.cam,
+                "detail": row.detail,
+                "machine_ip": row.machine_ip,
+                "pic_key": row.pic_key,
+                "pic_base64": pic.map(|p| base64::engine::general_purpose::STANDARD.encode(p)),
+            });
+            lines.push(line.to_string());
+        }
+        Ok((lines, keys))
+    }
+}
+
+fn to_lines<T: Serialize>(rows: &[T]) -> Result<Vec<String>, RetentionError> {
+    rows.iter()
+        .map(|row| serde_json::to_string(row).map_err(|e| RetentionError::Archive(e.to_string())))
+        .collect()
+}
+
+/// NDJSON を gzip 圧縮して書き込み（{dir}/{table}/{table}-{upper}-{乱数}.ndjson.gz）
+async fn write_archive(
+    dir: &Path,
+    table: RetainedTable,
+    upper: NaiveDateTime,
+    lines: &[String],
+) -> Result<PathBuf, RetentionError> {
+    let archive_error = |path: &Path, e: std::io::Error| {
+        RetentionError::Archive(format!("{}: {}", path.display(), e))
+    };
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    for line in lines {
+        encoder
+            .write_all(line.as_bytes())
+            .and_then(|_| encoder.write_all(b"\n"))
+            .map_err(|e| archive_error(dir, e))?;
+    }
+    let compressed = encoder.finish().map_err(|e| archive_error(dir, e))?;
+
+    let table_dir = dir.join(table.as_str());
+    tokio::fs::create_dir_all(&table_dir)
+        .await
+        .map_err(|e| archive_error(&table_dir, e))?;
+    let path = table_dir.join(format!(
+        "{}-{}-{}.ndjson.gz",
+        table.as_str(),
+        upper.format("%Y%m%dT%H%M%S"),
+        &uuid::Uuid::new_v4().simple().to_string()[..8]
+    ));
+    // 削除をコミットする前にディスクへ書き出す
+    let tmp = path.with_extension("part");
+    let mut file = tokio::fs::File::create(&tmp)
+        .await
+        .map_err(|e| archive_error(&tmp, e))?;
+    file.write_all(&compressed)
+        .await
+        .map_err(|e| archive_error(&tmp, e))?;
+    file.sync_all().await.map_err(|e| archive_error(&tmp, e))?;
+    tokio::fs::rename(&tmp, &path)
+        .await
+        .map_err(|e| archive_error(&path, e))?;
+    Ok(path)
+}
+
+/// ログ・CLI 出力用のレポート
+pub fn describe(report: &TableReport) -> String {
+    let oldest = report
+        .oldest_date
+        .map(|d| d.format("%Y-%m-%d %H:%M:%S").to_string())
+        .unwrap_or_else(|| "-".to_string());
+    format!(
+        "{}: keep {}, cutoff {}, {} expired rows (oldest {})",
+        report.table.as_str(),
+        report.keep,
+        report.cutoff.format("%Y-%m-%d %H:%M:%S"),
+        report.expired_rows,
+        oldest
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::picture_store::{PictureStoreError, StoredPicture};
+    use chrono::NaiveDate;
+
+    /// 画像の取得中に SubmitBatch のバックフィルで古い行が追加されるストア
+    struct BackfillingStore {
+        db: Database,
+        backfill_date: NaiveDateTime,
+    }
+
+    #[tonic::async_trait]
+    impl PictureStore for BackfillingStore {
+        fn name(&self) -> &'static str {
+            "backfilling"
+        }
+
+        async fn put(&self, key: &str, _data: Vec<u8>) -> Result<StoredPicture, PictureStoreError> {
+            Ok(StoredPicture::External(key.to_string()))
+        }
+
+        async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, PictureStoreError> {
+            insert_picture(&self.db, self.backfill_date, "backfilled").await;
+            Ok(Some(key.as_bytes().to_vec()))
+        }
+
+        async fn delete(&self, _key: &str) -> Result<(), PictureStoreError> {
+            Ok(())
+        }
+    }
+
+    async fn insert_picture(db: &Database, date: NaiveDateTime, key: &str) {
+        with_pool!(db, pool => {
+            sqlx::query(
+                "INSERT INTO pic_data (date, cam, pic, pic_key, detail, machine_ip)
+                 VALUES (?, 1, NULL, ?, '', '10.0.0.1')",
+            )
+            .bind(date)
+            .bind(key)
+            .execute(pool)
+            .await
+            .unwrap();
+        });
+    }
+
+    async fn picture_keys(db: &Database) -> Vec<String> {
+        with_pool!(db, pool => {
+            sqlx::query_scalar("SELECT pic_key FROM pic_data ORDER BY id")
+                .fetch_all(pool)
+                .await
+                .unwrap()
+        })
+    }
+
+    #[tokio::test]
+    async fn pictures_backfilled_during_archival_are_kept_for_the_next_batch() {
+        let db = Database::memory().await;
+        let date = NaiveDate::from_ymd_opt(2020, 1, 1)
+            .unwrap()
+            .and_hms_opt(8, 0, 0)
+            .unwrap();
+        insert_picture(&db, date, "old").await;
+
+        let dir = std::env::temp_dir().join(format!("retention-{}", uuid::Uuid::new_v4()));
+        let store = Arc::new(BackfillingStore {
+            db: db.clone(),
+            backfill_date: date,
+        });
+        let job = RetentionJob::new(
+            db.clone(),
+            store,
+            RetentionSettings {
+                policies: Vec::new(),
+                archive_dir: dir.clone(),
+                interval: Duration::from_secs(3600),
+                batch_size: 100,
+            },
+        );
+
+        let batch = job
+            .archive_batch(RetainedTable::PicData, date + chrono::Duration::days(1))
+            .await
+            .unwrap()
+            .unwrap();
+
+        // 選択した行だけが削除され、同じ日付で追加された行は残る
+        assert_eq!(batch.rows, 1);
+        assert_eq!(batch.picture_keys, vec!["old".to_string()]);
+        assert!(batch.file.exists());
+        assert_eq!(picture_keys(&db).await, vec!["backfilled".to_string()]);
+
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+    }
+}
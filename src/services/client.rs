@@ -1,39 +1,412 @@
 // gRPC ClientService implementation
-// Returns connected Socket.IO client information
+// Returns connected Socket.IO client information and the persisted terminal registry
 
-use crate::client_state::ClientState;
+use super::parse_time;
+use crate::client_admin::{self, ClientAdmin, ClientTarget};
+use crate::client_state::{ClientInfo, ClientState};
+use crate::clock;
+use crate::clock_skew;
+use crate::db::Database;
 use crate::proto::timecard::{
-    client_service_server::ClientService, ClientList, ConnectedClient,
+    client_service_server::ClientService, disconnect_request, quarantine_request, AdminAction,
+    AdminActionList, AdminActionRequest, CameraTelemetry, ClientActionResponse, ClientChange,
+    ClientList, ConnectedClient, DeleteTerminalRequest, DeleteTerminalResponse, DisconnectRequest,
+    QuarantineRequest, TelemetryHistory, TelemetryHistoryRequest, TelemetryReport, TerminalInfo,
+    TerminalList, TerminalState,
 };
+use crate::telemetry::{self, TelemetrySnapshot};
+use crate::terminal_registry::{self, RegistryError, Terminal, TerminalStatus};
+use chrono::{DateTime, Utc};
+use futures_util::Stream;
+use std::collections::VecDeque;
+use std::pin::Pin;
+use tokio::sync::broadcast;
 use tonic::{Request, Response, Status};
 
 pub struct ClientServiceImpl {
     clients: ClientState,
+    db: Database,
+    admin: ClientAdmin,
 }
 
 impl ClientServiceImpl {
-    pub fn new(clients: ClientState) -> Self {
-        Self { clients }
+    pub fn new(clients: ClientState, db: Database, admin: ClientAdmin) -> Self {
+        Self { clients, db, admin }
+    }
+}
+
+fn to_connected_client(
+    c: ClientInfo,
+    now: DateTime<Utc>,
+    clients: &ClientState,
+) -> ConnectedClient {
+    ConnectedClient {
+        quarantined: clients.is_quarantined(&c.socket_id),
+        clock_skew_secs: c.clock_skew_secs,
+        idle_seconds: (now - c.last_activity).num_seconds().max(0),
+        health: c.health.as_str().to_string(),
+        socket_id: c.socket_id,
+        ip_address: c.ip_address,
+        connected_at: c.connected_at.to_rfc3339(),
+        last_activity: c.last_activity.to_rfc3339(),
+    }
+}
+
+/// 現在の接続一覧（snapshot × N + synced）
+fn snapshot(clients: &ClientState) -> VecDeque<ClientChange> {
+    let now = Utc::now();
+    let mut changes: VecDeque<ClientChange> = clients
+        .get_all_clients()
+        .into_iter()
+        .map(|c| ClientChange {
+            kind: "snapshot".to_string(),
+            client: Some(to_connected_client(c, now, clients)),
+            previous_ip: None,
+        })
+        .collect();
+    changes.push_back(ClientChange {
+        kind: "synced".to_string(),
+        client: None,
+        previous_ip: None,
+    });
+    changes
+}
+
+fn to_info(terminal: &Terminal) -> TerminalInfo {
+    TerminalInfo {
+        machine_ip: terminal.machine_ip.clone(),
+        display_name: terminal.display_name.clone(),
+        location: terminal.location.clone(),
+        camera_count: terminal.camera_count,
+        expected_online_from: terminal
+            .expected_online_from
+            .map(|t| t.format("%H:%M").to_string()),
+        expected_online_to: terminal
+            .expected_online_to
+            .map(|t| t.format("%H:%M").to_string()),
+    }
+}
+
+fn to_telemetry(snapshot: TelemetrySnapshot) -> TelemetryReport {
+    let report = snapshot.report;
+    TelemetryReport {
+        machine_ip: report.machine_ip,
+        software_version: report.software_version,
+        uptime_secs: report.uptime_secs,
+        disk_total_bytes: report.disk_total_bytes,
+        disk_free_bytes: report.disk_free_bytes,
+        cameras: report
+            .cameras
+            .into_iter()
+            .map(|c| CameraTelemetry {
+                cam: c.cam,
+                ok: c.ok,
+                last_image_age_secs: c.last_image_age_secs,
+                error: c.error,
+            })
+            .collect(),
+        sensor_errors: report.sensor_errors,
+        reported_at: Some(snapshot.reported_at.format("%Y-%m-%d %H:%M:%S").to_string()),
+        alerts: snapshot.alerts,
+        reported_at_timestamp: Some(clock::to_timestamp(snapshot.reported_at)),
+    }
+}
+
+/// oneof の対象指定を ClientTarget に変換
+fn to_target(socket_id: Option<String>, ip_address: Option<String>) -> Option<ClientTarget> {
+    match (socket_id, ip_address) {
+        (Some(id), _) if !id.trim().is_empty() => Some(ClientTarget::Socket(id)),
+        (_, Some(ip)) if !ip.trim().is_empty() => Some(ClientTarget::Ip(ip)),
+        _ => None,
+    }
+}
+
+fn target_required() -> Status {
+    Status::invalid_argument("socket_id or ip_address is required")
+}
+
+fn to_status(e: RegistryError) -> Status {
+    match e {
+        RegistryError::Invalid(msg) => Status::invalid_argument(msg),
+        RegistryError::Database(e) => Status::internal(format!("Database error: {}", e)),
     }
 }
 
 #[tonic::async_trait]
 impl ClientService for ClientServiceImpl {
+    type WatchClientsStream = Pin<Box<dyn Stream<Item = Result<ClientChange, Status>> + Send>>;
+
     async fn get_all(&self, _request: Request<()>) -> Result<Response<ClientList>, Status> {
+        let now = Utc::now();
         let clients: Vec<ConnectedClient> = self
             .clients
             .get_all_clients()
             .into_iter()
-            .map(|c| ConnectedClient {
-                socket_id: c.socket_id,
-                ip_address: c.ip_address,
-                connected_at: c.connected_at.to_rfc3339(),
-                last_activity: c.last_activity.to_rfc3339(),
-            })
+            .map(|c| to_connected_client(c, now, &self.clients))
             .collect();
 
         let total = clients.len() as i32;
 
         Ok(Response::new(ClientList { clients, total }))
     }
+
+    async fn watch_clients(
+        &self,
+        _request: Request<()>,
+    ) -> Result<Response<Self::WatchClientsStream>, Status> {
+        // 取りこぼしを防ぐため一覧取得より先に購読を開始
+        let receiver = self.clients.subscribe();
+        let pending = snapshot(&self.clients);
+        let clients = self.clients.clone();
+
+        let stream = futures_util::stream::unfold(
+            (receiver, pending),
+            move |(mut receiver, mut pending)| {
+                let clients = clients.clone();
+                async move {
+                    loop {
+                        if let Some(change) = pending.pop_front() {
+                            return Some((Ok(change), (receiver, pending)));
+                        }
+                        match receiver.recv().await {
+                            Ok(event) => {
+                                let change = ClientChange {
+                                    kind: event.kind.as_str().to_string(),
+                                    client: Some(to_connected_client(
+                                        event.client,
+                                        Utc::now(),
+                                        &clients,
+                                    )),
+                                    previous_ip: event.previous_ip,
+                                };
+                                return Some((Ok(change), (receiver, pending)));
+                            }
+                            // 取りこぼした場合は一覧を送り直す
+                            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                                tracing::warn!(
+                                    "WatchClients lagged, skipped {} events; resending snapshot",
+                                    skipped
+                                );
+                                pending = snapshot(&clients);
+                            }
+                            Err(broadcast::error::RecvError::Closed) => return None,
+                        }
+                    }
+                }
+            },
+        );
+
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    async fn list_terminals(
+        &self,
+        _request: Request<()>,
+    ) -> Result<Response<TerminalList>, Status> {
+        let registered = terminal_registry::list(&self.db)
+            .await
+            .map_err(|e| Status::internal(format!("Database error: {}", e)))?;
+        let mut latest_telemetry = telemetry::latest_by_ip(&self.db)
+            .await
+            .map_err(|e| Status::internal(format!("Database error: {}", e)))?;
+        let mut clock_skews = clock_skew::latest_by_ip(&self.db)
+            .await
+            .map_err(|e| Status::internal(format!("Database error: {}", e)))?;
+        let now = Utc::now();
+
+        let terminals: Vec<TerminalState> =
+            terminal_registry::overview(registered, &self.clients.get_all_clients())
+                .into_iter()
+                .map(|o| {
+                    let skew = clock_skews.remove(&o.terminal.machine_ip);
+                    TerminalState {
+                        info: Some(to_info(&o.terminal)),
+                        status: o.status.as_str().to_string(),
+                        first_seen_at: o
+                            .terminal
+                            .first_seen_at
+                            .map(|d| d.format("%Y-%m-%d %H:%M:%S").to_string()),
+                        last_seen_at: o
+                            .terminal
+                            .last_seen_at
+                            .map(|d| d.format("%Y-%m-%d %H:%M:%S").to_string()),
+                        uptime_seconds: o
+                            .connected_since
+                            .map(|since| (now - since).num_seconds().max(0))
+                            .unwrap_or(0),
+                        connections: o.connections as i32,
+                        telemetry: latest_telemetry
+                            .remove(&o.terminal.machine_ip)
+                            .map(to_telemetry),
+                        clock_skew_secs: skew.as_ref().map(|s| s.skew_secs),
+                        clock_skew_measured_at: skew
+                            .as_ref()
+                            .map(|s| s.measured_at.format("%Y-%m-%d %H:%M:%S").to_string()),
+                        first_seen_at_timestamp: o.terminal.first_seen_at.map(clock::to_timestamp),
+                        last_seen_at_timestamp: o.terminal.last_seen_at.map(clock::to_timestamp),
+                        clock_skew_measured_at_timestamp: skew
+                            .map(|s| clock::to_timestamp(s.measured_at)),
+                    }
+                })
+                .collect();
+
+        let total = terminals.len() as i32;
+        let missing = terminals
+            .iter()
+            .filter(|t| t.status == TerminalStatus::Missing.as_str())
+            .count() as i32;
+
+        Ok(Response::new(TerminalList {
+            terminals,
+            total,
+            missing,
+        }))
+    }
+
+    async fn upsert_terminal(
+        &self,
+        request: Request<TerminalInfo>,
+    ) -> Result<Response<TerminalInfo>, Status> {
+        let req = request.into_inner();
+        let terminal = Terminal {
+            machine_ip: req.machine_ip.trim().to_string(),
+            display_name: req.display_name.filter(|s| !s.is_empty()),
+            location: req.location.filter(|s| !s.is_empty()),
+            camera_count: req.camera_count,
+            expected_online_from: terminal_registry::parse_expected_time(
+                req.expected_online_from.as_deref().unwrap_or(""),
+            )
+            .map_err(to_status)?,
+            expected_online_to: terminal_registry::parse_expected_time(
+                req.expected_online_to.as_deref().unwrap_or(""),
+            )
+            .map_err(to_status)?,
+            first_seen_at: None,
+            last_seen_at: None,
+        };
+
+        terminal_registry::upsert(&self.db, &terminal)
+            .await
+            .map_err(to_status)?;
+
+        Ok(Response::new(to_info(&terminal)))
+    }
+
+    async fn delete_terminal(
+        &self,
+        request: Request<DeleteTerminalRequest>,
+    ) -> Result<Response<DeleteTerminalResponse>, Status> {
+        let req = request.into_inner();
+        let success = terminal_registry::delete(&self.db, &req.machine_ip)
+            .await
+            .map_err(|e| Status::internal(format!("Database error: {}", e)))?;
+
+        Ok(Response::new(DeleteTerminalResponse { success }))
+    }
+
+    async fn get_telemetry_history(
+        &self,
+        request: Request<TelemetryHistoryRequest>,
+    ) -> Result<Response<TelemetryHistory>, Status> {
+        let req = request.into_inner();
+        if req.machine_ip.trim().is_empty() {
+            return Err(Status::invalid_argument("machine_ip is required"));
+        }
+        let since = req
+            .since
+            .filter(|s| !s.trim().is_empty())
+            .map(|s| parse_time("since", Some(s), clock::now))
+            .transpose()
+            .map_err(Status::invalid_argument)?;
+        let limit = if req.limit > 0 { req.limit } else { 100 };
+
+        let reports = telemetry::history(&self.db, &req.machine_ip, since, limit)
+            .await
+            .map_err(|e| Status::internal(format!("Database error: {}", e)))?
+            .into_iter()
+            .map(to_telemetry)
+            .collect();
+
+        Ok(Response::new(TelemetryHistory { reports }))
+    }
+
+    async fn disconnect(
+        &self,
+        request: Request<DisconnectRequest>,
+    ) -> Result<Response<ClientActionResponse>, Status> {
+        let req = request.into_inner();
+        let target = match req.target {
+            Some(disconnect_request::Target::SocketId(id)) => to_target(Some(id), None),
+            Some(disconnect_request::Target::IpAddress(ip)) => to_target(None, Some(ip)),
+            None => None,
+        }
+        .ok_or_else(target_required)?;
+
+        let affected = self
+            .admin
+            .disconnect(&target, req.reason.as_deref(), req.requested_by.as_deref())
+            .await
+            .map_err(|e| Status::internal(format!("Database error: {}", e)))?;
+
+        Ok(Response::new(ClientActionResponse {
+            success: affected > 0,
+            affected,
+        }))
+    }
+
+    async fn quarantine(
+        &self,
+        request: Request<QuarantineRequest>,
+    ) -> Result<Response<ClientActionResponse>, Status> {
+        let req = request.into_inner();
+        let target = match req.target {
+            Some(quarantine_request::Target::SocketId(id)) => to_target(Some(id), None),
+            Some(quarantine_request::Target::IpAddress(ip)) => to_target(None, Some(ip)),
+            None => None,
+        }
+        .ok_or_else(target_required)?;
+
+        let affected = self
+            .admin
+            .quarantine(
+                &target,
+                req.release,
+                req.reason.as_deref(),
+                req.requested_by.as_deref(),
+            )
+            .await
+            .map_err(|e| Status::internal(format!("Database error: {}", e)))?;
+
+        Ok(Response::new(ClientActionResponse {
+            success: true,
+            affected,
+        }))
+    }
+
+    async fn list_admin_actions(
+        &self,
+        request: Request<AdminActionRequest>,
+    ) -> Result<Response<AdminActionList>, Status> {
+        let req = request.into_inner();
+        let limit = if req.limit > 0 { req.limit } else { 100 };
+
+        let actions = client_admin::history(&self.db, limit)
+            .await
+            .map_err(|e| Status::internal(format!("Database error: {}", e)))?
+            .into_iter()
+            .map(|a| AdminAction {
+                id: a.id,
+                action: a.action,
+                socket_id: a.socket_id,
+                ip_address: a.ip_address,
+                affected: a.affected,
+                reason: a.reason,
+                requested_by: a.requested_by,
+                created_at: a.created_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+                created_at_timestamp: Some(clock::to_timestamp(a.created_at)),
+            })
+            .collect();
+
+        Ok(Response::new(AdminActionList { actions }))
+    }
 }
@@ -1,17 +1,29 @@
-use crate::db::Database;
+use super::db_error;
+use crate::clock;
+use crate::directory::DriverDirectory;
+use crate::models;
 use crate::proto::timecard::{
-    driver_service_server::DriverService, Driver, DriverIdRequest, DriverList,
+    driver_service_server::DriverService, DirectoryStats, Driver, DriverIdRequest, DriverList,
 };
-use sqlx::Row;
+use crate::repository::DriverRepository;
+use std::sync::Arc;
 use tonic::{Request, Response, Status};
 
 pub struct DriverServiceImpl {
-    db: Database,
+    drivers: Arc<dyn DriverRepository>,
+    directory: DriverDirectory,
 }
 
 impl DriverServiceImpl {
-    pub fn new(db: Database) -> Self {
-        Self { db }
+    pub fn new(drivers: Arc<dyn DriverRepository>, directory: DriverDirectory) -> Self {
+        Self { drivers, directory }
+    }
+}
+
+fn to_driver(d: models::Driver) -> Driver {
+    Driver {
+        id: d.id,
+        name: d.name,
     }
 }
 
@@ -21,17 +33,13 @@ impl DriverService for DriverServiceImpl {
         &self,
         _request: Request<()>,
     ) -> Result<Response<DriverList>, Status> {
-        let rows = sqlx::query("SELECT id, name FROM drivers")
-            .fetch_all(self.db.pool())
+        let drivers: Vec<Driver> = self
+            .drivers
+            .list()
             .await
-            .map_err(|e| Status::internal(format!("Database error: {}", e)))?;
-
-        let drivers: Vec<Driver> = rows
-            .iter()
-            .map(|row| Driver {
-                id: row.get("id"),
-                name: row.get("name"),
-            })
+            .map_err(db_error)?
+            .into_iter()
+            .map(to_driver)
             .collect();
 
         Ok(Response::new(DriverList { drivers }))
@@ -43,17 +51,10 @@ impl DriverService for DriverServiceImpl {
     ) -> Result<Response<Driver>, Status> {
         let driver_id = request.into_inner().driver_id;
 
-        let row = sqlx::query("SELECT id, name FROM drivers WHERE id = ? LIMIT 1")
-            .bind(driver_id)
-            .fetch_optional(self.db.pool())
-            .await
-            .map_err(|e| Status::internal(format!("Database error: {}", e)))?;
+        let driver = self.drivers.get(driver_id).await.map_err(db_error)?;
 
-        match row {
-            Some(row) => Ok(Response::new(Driver {
-                id: row.get("id"),
-                name: row.get("name"),
-            })),
+        match driver {
+            Some(driver) => Ok(Response::new(to_driver(driver))),
             None => Err(Status::not_found(format!(
                 "Driver with id {} not found",
                 driver_id
@@ -96,42 +97,48 @@ impl DriverService for DriverServiceImpl {
             .await
             .map_err(|e| Status::internal(format!("Failed to parse external API response: {}", e)))?;
 
-        let external_drivers = api_response.ret;
-
-        // トランザクションで既存データを削除して新しいデータを挿入
-        let mut tx = self
-            .db
-            .pool()
-            .begin()
-            .await
-            .map_err(|e| Status::internal(format!("Transaction error: {}", e)))?;
-
-        sqlx::query("DELETE FROM drivers")
-            .execute(&mut *tx)
-            .await
-            .map_err(|e| Status::internal(format!("Delete error: {}", e)))?;
-
-        for driver in &external_drivers {
-            sqlx::query("INSERT INTO drivers (id, name) VALUES (?, ?)")
-                .bind(driver.id)
-                .bind(&driver.name)
-                .execute(&mut *tx)
-                .await
-                .map_err(|e| Status::internal(format!("Insert error: {}", e)))?;
-        }
-
-        tx.commit()
-            .await
-            .map_err(|e| Status::internal(format!("Commit error: {}", e)))?;
-
-        let drivers: Vec<Driver> = external_drivers
+        let external_drivers: Vec<models::Driver> = api_response
+            .ret
             .into_iter()
-            .map(|d| Driver {
+            .map(|d| models::Driver {
                 id: d.id,
                 name: d.name,
             })
             .collect();
 
+        // 既存データを削除して新しいデータを挿入
+        self.drivers
+            .replace_all(&external_drivers)
+            .await
+            .map_err(db_error)?;
+        self.directory.refresh().await.map_err(db_error)?;
+
+        let drivers: Vec<Driver> = external_drivers.into_iter().map(to_driver).collect();
+
         Ok(Response::new(DriverList { drivers }))
     }
+
+    async fn get_directory_stats(
+        &self,
+        _request: Request<()>,
+    ) -> Result<Response<DirectoryStats>, Status> {
+        let stats = self.directory.stats();
+        let loaded_at = stats.loaded_at.map(clock::from_utc);
+
+        Ok(Response::new(DirectoryStats {
+            drivers: stats.drivers as u64,
+            cards: stats.cards as u64,
+            driver_hits: stats.driver_hits,
+            driver_misses: stats.driver_misses,
+            card_hits: stats.card_hits,
+            card_misses: stats.card_misses,
+            refreshes: stats.refreshes,
+            loaded_at: loaded_at.map(|at| at.format("%Y-%m-%d %H:%M:%S").to_string()),
+            age_seconds: stats.age_secs,
+            stale: stats.stale,
+            last_changes: stats.last_changes as u64,
+            last_error: stats.last_error,
+            loaded_at_timestamp: loaded_at.map(clock::to_timestamp),
+        }))
+    }
 }
@@ -1,23 +1,23 @@
-use crate::db::Database;
+use super::{db_error, routed_response, start_date};
+use crate::clock;
 use crate::proto::timecard::{
     finger_log_service_server::FingerLogService, FingerLog, FingerLogList, TimeRangeRequest,
 };
-use chrono::{Duration, Local};
-use sqlx::Row;
+use crate::repository::{ReadRoute, ReadingRepository};
+use chrono::{Duration, NaiveDateTime};
 use tonic::{Request, Response, Status};
 
 pub struct FingerLogServiceImpl {
-    db: Database,
+    readings: ReadRoute<dyn ReadingRepository>,
 }
 
 impl FingerLogServiceImpl {
-    pub fn new(db: Database) -> Self {
-        Self { db }
+    pub fn new(readings: ReadRoute<dyn ReadingRepository>) -> Self {
+        Self { readings }
     }
 
-    fn get_default_start_date() -> String {
-        let two_days_ago = Local::now() - Duration::days(2);
-        two_days_ago.format("%Y-%m-%d %H:%M:%S").to_string()
+    fn get_default_start_date() -> NaiveDateTime {
+        clock::now() - Duration::days(2)
     }
 }
 
@@ -28,34 +28,66 @@ impl FingerLogService for FingerLogServiceImpl {
         request: Request<TimeRangeRequest>,
     ) -> Result<Response<FingerLogList>, Status> {
         let req = request.into_inner();
-        let start_date = req
-            .start_date
-            .unwrap_or_else(Self::get_default_start_date);
-
-        let rows = sqlx::query(
-            "SELECT date, machine_ip, id, message
-             FROM finger_log
-             WHERE date >= ?
-             ORDER BY date DESC",
-        )
-        .bind(&start_date)
-        .fetch_all(self.db.pool())
-        .await
-        .map_err(|e| Status::internal(format!("Database error: {}", e)))?;
-
-        let logs: Vec<FingerLog> = rows
-            .iter()
-            .map(|row| {
-                let date: chrono::NaiveDateTime = row.get("date");
-                FingerLog {
-                    date: date.format("%Y-%m-%d %H:%M:%S").to_string(),
-                    machine_ip: row.get("machine_ip"),
-                    id: row.get("id"),
-                    message: row.get("message"),
-                }
+        let start_date = start_date(req.start_date, Self::get_default_start_date)
+            .map_err(Status::invalid_argument)?;
+
+        let routed = self
+            .readings
+            .read(|readings| async move { readings.finger_logs_since(start_date).await })
+            .await
+            .map_err(db_error)?;
+        let logs: Vec<FingerLog> = routed
+            .value
+            .into_iter()
+            .map(|log| FingerLog {
+                date: log.date.format("%Y-%m-%d %H:%M:%S").to_string(),
+                timestamp: Some(clock::to_timestamp(log.date)),
+                machine_ip: log.machine_ip,
+                id: log.id,
+                message: log.message,
             })
             .collect();
 
-        Ok(Response::new(FingerLogList { logs }))
+        Ok(routed_response(FingerLogList { logs }, routed.origin))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models;
+    use crate::repository::{MemoryRepository, Repositories};
+
+    #[tokio::test]
+    async fn get_recent_returns_logs_since_the_start_date() {
+        let store = MemoryRepository::new();
+        let now = clock::now();
+        for (hours, id) in [(1, 3), (30, 2), (72, 1)] {
+            store.insert_finger_log(models::FingerLog {
+                date: now - Duration::hours(hours),
+                machine_ip: "10.0.0.1".to_string(),
+                id,
+                message: "ok".to_string(),
+            });
+        }
+        let service =
+            FingerLogServiceImpl::new(ReadRoute::primary(Repositories::memory(store).readings));
+
+        let recent = service
+            .get_recent(Request::new(TimeRangeRequest::default()))
+            .await
+            .unwrap();
+        assert_eq!(recent.metadata().get("x-read-source").unwrap(), "primary");
+        let ids: Vec<i32> = recent.into_inner().logs.iter().map(|l| l.id).collect();
+        assert_eq!(ids, vec![3, 2]);
+
+        let invalid = service
+            .get_recent(Request::new(TimeRangeRequest {
+                start_date: Some("not a date".to_string()),
+                ..Default::default()
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(invalid.code(), tonic::Code::InvalidArgument);
     }
 }
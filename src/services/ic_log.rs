@@ -1,24 +1,87 @@
-use crate::db::Database;
+use super::{db_error, parse_time, routed_response, start_date};
+use crate::clock;
+use crate::directory::DriverDirectory;
+use crate::models;
 use crate::proto::timecard::{
-    ic_log_service_server::IcLogService, IcLog, IcLogList, IcLogWithDriver, IcLogWithDriverList,
-    PaginationRequest, TimeRangeRequest,
+    ic_log_service_server::IcLogService, CardHolder, CardHolderAtRequest, IcLog, IcLogList,
+    IcLogWithDriver, IcLogWithDriverList, PaginationRequest, TimeRangeRequest,
 };
-use chrono::{Duration, Local};
-use sqlx::Row;
+use crate::repository::{IcLogRepository, ReadRoute, SortOrder};
+use chrono::{Duration, NaiveDateTime};
 use tonic::{Request, Response, Status};
 
 pub struct ICLogServiceImpl {
-    db: Database,
+    ic_logs: ReadRoute<dyn IcLogRepository>,
+    directory: DriverDirectory,
 }
 
 impl ICLogServiceImpl {
-    pub fn new(db: Database) -> Self {
-        Self { db }
+    pub fn new(ic_logs: ReadRoute<dyn IcLogRepository>, directory: DriverDirectory) -> Self {
+        Self { ic_logs, directory }
     }
 
-    fn get_default_start_date() -> String {
-        let two_days_ago = Local::now() - Duration::days(2);
-        two_days_ago.format("%Y-%m-%d %H:%M:%S").to_string()
+    fn get_default_start_date() -> NaiveDateTime {
+        clock::now() - Duration::days(2)
+    }
+
+    async fn recent(
+        &self,
+        request: Request<TimeRangeRequest>,
+        order: SortOrder,
+    ) -> Result<Response<IcLogList>, Status> {
+        let req = request.into_inner();
+        let start_date = start_date(req.start_date, Self::get_default_start_date)
+            .map_err(Status::invalid_argument)?;
+
+        let routed = self
+            .ic_logs
+            .read(|ic_logs| async move { ic_logs.since(start_date, order).await })
+            .await
+            .map_err(db_error)?;
+        let logs: Vec<IcLog> = routed.value.into_iter().map(to_ic_log).collect();
+
+        Ok(routed_response(IcLogList { logs }, routed.origin))
+    }
+
+    /// ドライバー名を付与（ICカードはログの時刻の紐付け、免許証は iid で解決）
+    async fn with_driver(&self, logs: Vec<models::IcLogWithHolder>) -> Vec<IcLogWithDriver> {
+        let mut with_driver = Vec::with_capacity(logs.len());
+        for log in logs {
+            let driver_name = self
+                .directory
+                .ic_log_driver_name(log.holder_id, log.iid.as_deref())
+                .await;
+            with_driver.push(to_ic_log_with_driver(log, driver_name));
+        }
+        with_driver
+    }
+}
+
+fn to_ic_log(log: models::IcLog) -> IcLog {
+    IcLog {
+        id: log.id,
+        r#type: log.log_type,
+        detail: log.detail,
+        date: log.date.format("%Y-%m-%d %H:%M:%S").to_string(),
+        timestamp: Some(clock::to_timestamp(log.date)),
+        iid: log.iid,
+        machine_ip: log.machine_ip,
+    }
+}
+
+fn to_ic_log_with_driver(
+    log: models::IcLogWithHolder,
+    driver_name: Option<String>,
+) -> IcLogWithDriver {
+    IcLogWithDriver {
+        id: log.id,
+        r#type: log.log_type,
+        detail: log.detail,
+        date: log.date.format("%Y-%m-%d %H:%M:%S").to_string(),
+        timestamp: Some(clock::to_timestamp(log.date)),
+        iid: log.iid,
+        machine_ip: log.machine_ip,
+        driver_name,
     }
 }
 
@@ -28,76 +91,14 @@ impl IcLogService for ICLogServiceImpl {
         &self,
         request: Request<TimeRangeRequest>,
     ) -> Result<Response<IcLogList>, Status> {
-        let req = request.into_inner();
-        let start_date = req
-            .start_date
-            .unwrap_or_else(Self::get_default_start_date);
-
-        let rows = sqlx::query(
-            "SELECT id, type, detail, date, iid, machine_ip
-             FROM ic_log
-             WHERE date >= ?
-             ORDER BY date ASC",
-        )
-        .bind(&start_date)
-        .fetch_all(self.db.pool())
-        .await
-        .map_err(|e| Status::internal(format!("Database error: {}", e)))?;
-
-        let logs: Vec<IcLog> = rows
-            .iter()
-            .map(|row| {
-                let date: chrono::NaiveDateTime = row.get("date");
-                IcLog {
-                    id: row.get("id"),
-                    r#type: row.get("type"),
-                    detail: row.get("detail"),
-                    date: date.format("%Y-%m-%d %H:%M:%S").to_string(),
-                    iid: row.get("iid"),
-                    machine_ip: row.get("machine_ip"),
-                }
-            })
-            .collect();
-
-        Ok(Response::new(IcLogList { logs }))
+        self.recent(request, SortOrder::Asc).await
     }
 
     async fn get_recent_desc(
         &self,
         request: Request<TimeRangeRequest>,
     ) -> Result<Response<IcLogList>, Status> {
-        let req = request.into_inner();
-        let start_date = req
-            .start_date
-            .unwrap_or_else(Self::get_default_start_date);
-
-        let rows = sqlx::query(
-            "SELECT id, type, detail, date, iid, machine_ip
-             FROM ic_log
-             WHERE date >= ?
-             ORDER BY date DESC",
-        )
-        .bind(&start_date)
-        .fetch_all(self.db.pool())
-        .await
-        .map_err(|e| Status::internal(format!("Database error: {}", e)))?;
-
-        let logs: Vec<IcLog> = rows
-            .iter()
-            .map(|row| {
-                let date: chrono::NaiveDateTime = row.get("date");
-                IcLog {
-                    id: row.get("id"),
-                    r#type: row.get("type"),
-                    detail: row.get("detail"),
-                    date: date.format("%Y-%m-%d %H:%M:%S").to_string(),
-                    iid: row.get("iid"),
-                    machine_ip: row.get("machine_ip"),
-                }
-            })
-            .collect();
-
-        Ok(Response::new(IcLogList { logs }))
+        self.recent(request, SortOrder::Desc).await
     }
 
     async fn get_with_driver(
@@ -105,54 +106,17 @@ impl IcLogService for ICLogServiceImpl {
         request: Request<TimeRangeRequest>,
     ) -> Result<Response<IcLogWithDriverList>, Status> {
         let req = request.into_inner();
-        let start_date = req
-            .start_date
-            .unwrap_or_else(Self::get_default_start_date);
-
-        // ドライバー名取得: ic_id経由またはic_log.iid直接参照（免許証の場合）
-        // 同一ICカードに複数レコードがある場合は最新のみを使用
-        let rows = sqlx::query(
-            "SELECT ic.id, ic.type, ic.detail, ic.date, ic.iid, ic.machine_ip,
-                    COALESCE(d1.name, d2.name) as name
-             FROM ic_log ic
-             LEFT JOIN (
-                 SELECT i1.ic_id, i1.emp_id
-                 FROM ic_id i1
-                 INNER JOIN (
-                     SELECT ic_id, MAX(date) as max_date
-                     FROM ic_id
-                     WHERE deleted = 0 AND ic_id != ''
-                     GROUP BY ic_id
-                 ) i2 ON i1.ic_id = i2.ic_id AND i1.date = i2.max_date
-                 WHERE i1.deleted = 0
-             ) i ON ic.id = i.ic_id
-             LEFT JOIN drivers d1 ON i.emp_id = d1.id
-             LEFT JOIN drivers d2 ON ic.iid = d2.id
-             WHERE ic.date >= ?
-             ORDER BY ic.date DESC",
-        )
-        .bind(&start_date)
-        .fetch_all(self.db.pool())
-        .await
-        .map_err(|e| Status::internal(format!("Database error: {}", e)))?;
-
-        let logs: Vec<IcLogWithDriver> = rows
-            .iter()
-            .map(|row| {
-                let date: chrono::NaiveDateTime = row.get("date");
-                IcLogWithDriver {
-                    id: row.get("id"),
-                    r#type: row.get("type"),
-                    detail: row.get("detail"),
-                    date: date.format("%Y-%m-%d %H:%M:%S").to_string(),
-                    iid: row.get("iid"),
-                    machine_ip: row.get("machine_ip"),
-                    driver_name: row.get("name"),
-                }
-            })
-            .collect();
+        let start_date = start_date(req.start_date, Self::get_default_start_date)
+            .map_err(Status::invalid_argument)?;
+
+        let routed = self
+            .ic_logs
+            .read(|ic_logs| async move { ic_logs.with_holder_since(start_date).await })
+            .await
+            .map_err(db_error)?;
+        let logs = self.with_driver(routed.value).await;
 
-        Ok(Response::new(IcLogWithDriverList { logs }))
+        Ok(routed_response(IcLogWithDriverList { logs }, routed.origin))
     }
 
     async fn get_latest_with_driver(
@@ -163,50 +127,14 @@ impl IcLogService for ICLogServiceImpl {
         let limit = req.limit.unwrap_or(100);
 
         // 最新N件をドライバー名付きで取得
-        // ドライバー名取得: ic_id経由またはic_log.iid直接参照（免許証の場合）
-        // 同一ICカードに複数レコードがある場合は最新のみを使用
-        let rows = sqlx::query(
-            "SELECT ic.id, ic.type, ic.detail, ic.date, ic.iid, ic.machine_ip,
-                    COALESCE(d1.name, d2.name) as name
-             FROM ic_log ic
-             LEFT JOIN (
-                 SELECT i1.ic_id, i1.emp_id
-                 FROM ic_id i1
-                 INNER JOIN (
-                     SELECT ic_id, MAX(date) as max_date
-                     FROM ic_id
-                     WHERE deleted = 0 AND ic_id != ''
-                     GROUP BY ic_id
-                 ) i2 ON i1.ic_id = i2.ic_id AND i1.date = i2.max_date
-                 WHERE i1.deleted = 0
-             ) i ON ic.id = i.ic_id
-             LEFT JOIN drivers d1 ON i.emp_id = d1.id
-             LEFT JOIN drivers d2 ON ic.iid = d2.id
-             ORDER BY ic.date DESC
-             LIMIT ?",
-        )
-        .bind(limit)
-        .fetch_all(self.db.pool())
-        .await
-        .map_err(|e| Status::internal(format!("Database error: {}", e)))?;
-
-        let logs: Vec<IcLogWithDriver> = rows
-            .iter()
-            .map(|row| {
-                let date: chrono::NaiveDateTime = row.get("date");
-                IcLogWithDriver {
-                    id: row.get("id"),
-                    r#type: row.get("type"),
-                    detail: row.get("detail"),
-                    date: date.format("%Y-%m-%d %H:%M:%S").to_string(),
-                    iid: row.get("iid"),
-                    machine_ip: row.get("machine_ip"),
-                    driver_name: row.get("name"),
-                }
-            })
-            .collect();
+        let routed = self
+            .ic_logs
+            .read(|ic_logs| async move { ic_logs.latest_with_holder(limit).await })
+            .await
+            .map_err(db_error)?;
+        let logs = self.with_driver(routed.value).await;
 
-        Ok(Response::new(IcLogWithDriverList { logs }))
+        Ok(routed_response(IcLogWithDriverList { logs }, routed.origin))
     }
 
     async fn get_without_tmp(
@@ -215,39 +143,245 @@ impl IcLogService for ICLogServiceImpl {
     ) -> Result<Response<IcLogList>, Status> {
         let req = request.into_inner();
         let limit = req.limit.unwrap_or(500);
-        let start_date = req
-            .start_date
-            .unwrap_or_else(Self::get_default_start_date);
-
-        let rows = sqlx::query(
-            "SELECT ic.id, ic.type, ic.detail, ic.date, ic.iid, ic.machine_ip
-             FROM ic_log ic
-             LEFT JOIN tmp_data t ON ic.machine_ip = t.machine_ip AND ic.date = t.date
-             WHERE t.machine_ip IS NULL AND ic.date >= ?
-             ORDER BY ic.date DESC
-             LIMIT ?",
-        )
-        .bind(&start_date)
-        .bind(limit)
-        .fetch_all(self.db.pool())
-        .await
-        .map_err(|e| Status::internal(format!("Database error: {}", e)))?;
-
-        let logs: Vec<IcLog> = rows
-            .iter()
-            .map(|row| {
-                let date: chrono::NaiveDateTime = row.get("date");
-                IcLog {
-                    id: row.get("id"),
-                    r#type: row.get("type"),
-                    detail: row.get("detail"),
-                    date: date.format("%Y-%m-%d %H:%M:%S").to_string(),
-                    iid: row.get("iid"),
-                    machine_ip: row.get("machine_ip"),
-                }
+        let start_date = start_date(req.start_date, Self::get_default_start_date)
+            .map_err(Status::invalid_argument)?;
+
+        let routed = self
+            .ic_logs
+            .read(|ic_logs| async move { ic_logs.without_tmp(start_date, limit).await })
+            .await
+            .map_err(db_error)?;
+        let logs: Vec<IcLog> = routed.value.into_iter().map(to_ic_log).collect();
+
+        Ok(routed_response(IcLogList { logs }, routed.origin))
+    }
+
+    async fn card_holder_at(
+        &self,
+        request: Request<CardHolderAtRequest>,
+    ) -> Result<Response<CardHolder>, Status> {
+        let req = request.into_inner();
+        let ic_id = req.ic_id.trim().to_string();
+        if ic_id.is_empty() {
+            return Err(Status::invalid_argument("ic_id is required"));
+        }
+        let time = parse_time("time", req.time, clock::now).map_err(Status::invalid_argument)?;
+
+        let lookup_id = ic_id.clone();
+        let routed = self
+            .ic_logs
+            .read(|ic_logs| {
+                let ic_id = lookup_id.clone();
+                async move { ic_logs.holder_at(&ic_id, time).await }
             })
+            .await
+            .map_err(db_error)?;
+
+        let mut holder = CardHolder {
+            ic_id,
+            time: time.format("%Y-%m-%d %H:%M:%S").to_string(),
+            time_timestamp: Some(clock::to_timestamp(time)),
+            ..Default::default()
+        };
+        if let Some(period) = routed.value {
+            holder.driver_id = Some(period.emp_id);
+            holder.driver_name = self
+                .directory
+                .driver_name(period.emp_id)
+                .await
+                .map_err(db_error)?;
+            holder.assigned_at = Some(period.assigned_at.format("%Y-%m-%d %H:%M:%S").to_string());
+            holder.assigned_timestamp = Some(clock::to_timestamp(period.assigned_at));
+            holder.revoked_at = period
+                .revoked_at
+                .map(|revoked| revoked.format("%Y-%m-%d %H:%M:%S").to_string());
+            holder.revoked_timestamp = period.revoked_at.map(clock::to_timestamp);
+        }
+
+        Ok(routed_response(holder, routed.origin))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Driver;
+    use crate::repository::{CardRow, MemoryRepository, Repositories};
+    use chrono::NaiveDate;
+
+    fn at(hour: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2026, 4, 1)
+            .unwrap()
+            .and_hms_opt(hour, 0, 0)
+            .unwrap()
+    }
+
+    fn card(emp_id: i32, assigned: u32, revoked: Option<u32>) -> CardRow {
+        CardRow {
+            ic_id: "CARD".to_string(),
+            emp_id,
+            date: at(assigned),
+            deleted: revoked.is_some(),
+            revoked_at: revoked.map(at),
+        }
+    }
+
+    fn service(cards: Vec<CardRow>) -> ICLogServiceImpl {
+        let store = MemoryRepository::new();
+        for (id, name) in [(1, "山田"), (2, "佐藤"), (3, "鈴木")] {
+            store.insert_driver(Driver {
+                id,
+                name: name.to_string(),
+            });
+        }
+        for card in cards {
+            store.insert_card(card);
+        }
+        let repositories = Repositories::memory(store);
+        let directory = DriverDirectory::new(
+            repositories.drivers.clone(),
+            repositories.cards.clone(),
+            std::time::Duration::from_secs(300),
+        );
+        ICLogServiceImpl::new(ReadRoute::primary(repositories.ic_logs), directory)
+    }
+
+    async fn holder_at(service: &ICLogServiceImpl, hour: u32) -> CardHolder {
+        service
+            .card_holder_at(Request::new(CardHolderAtRequest {
+                ic_id: " CARD ".to_string(),
+                time: Some(at(hour).format("%Y-%m-%d %H:%M:%S").to_string()),
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+    }
+
+    #[tokio::test]
+    async fn reassigned_card_resolves_the_holder_at_the_requested_time() {
+        let service = service(vec![card(1, 8, None), card(2, 12, None)]);
+
+        let before = holder_at(&service, 10).await;
+        assert_eq!(before.ic_id, "CARD");
+        assert_eq!(before.driver_id, Some(1));
+        assert_eq!(before.driver_name.as_deref(), Some("山田"));
+        assert_eq!(before.assigned_at.as_deref(), Some("2026-04-01 08:00:00"));
+        assert_eq!(before.revoked_at, None);
+
+        let after = holder_at(&service, 13).await;
+        assert_eq!(after.driver_id, Some(2));
+        assert_eq!(after.driver_name.as_deref(), Some("佐藤"));
+    }
+
+    #[tokio::test]
+    async fn deleted_card_has_no_holder_after_revocation() {
+        let service = service(vec![card(1, 8, Some(10))]);
+
+        let during = holder_at(&service, 9).await;
+        assert_eq!(during.driver_id, Some(1));
+        assert_eq!(during.revoked_at.as_deref(), Some("2026-04-01 10:00:00"));
+        assert!(during.revoked_timestamp.is_some());
+
+        let after = holder_at(&service, 11).await;
+        assert_eq!(after.time, "2026-04-01 11:00:00");
+        assert_eq!(after.driver_id, None);
+        assert_eq!(after.driver_name, None);
+        assert_eq!(after.assigned_at, None);
+    }
+
+    #[tokio::test]
+    async fn re_registered_card_resolves_the_new_holder() {
+        let service = service(vec![card(1, 8, Some(10)), card(3, 14, None)]);
+
+        assert_eq!(holder_at(&service, 9).await.driver_id, Some(1));
+        assert_eq!(holder_at(&service, 12).await.driver_id, None);
+        let renewed = holder_at(&service, 15).await;
+        assert_eq!(renewed.driver_id, Some(3));
+        assert_eq!(renewed.driver_name.as_deref(), Some("鈴木"));
+    }
+
+    #[tokio::test]
+    async fn get_with_driver_names_card_holders_and_license_readers() {
+        let now = clock::now();
+        let store = MemoryRepository::new();
+        store.insert_driver(Driver {
+            id: 1,
+            name: "山田".to_string(),
+        });
+        store.insert_driver(Driver {
+            id: 2,
+            name: "佐藤".to_string(),
+        });
+        store.insert_card(CardRow {
+            ic_id: "CARD".to_string(),
+            emp_id: 1,
+            date: now - Duration::hours(5),
+            deleted: false,
+            revoked_at: None,
+        });
+        for (id, iid, hours) in [
+            ("CARD", None, 1),
+            ("LICENSE", Some(" 2 "), 2),
+            ("NEW", None, 3),
+        ] {
+            store.insert_ic_log(models::IcLog {
+                id: id.to_string(),
+                log_type: "ic".to_string(),
+                detail: None,
+                date: now - Duration::hours(hours),
+                iid: iid.map(str::to_string),
+                machine_ip: "10.0.0.1".to_string(),
+            });
+        }
+        let repositories = Repositories::memory(store);
+        let directory = DriverDirectory::new(
+            repositories.drivers.clone(),
+            repositories.cards.clone(),
+            std::time::Duration::from_secs(300),
+        );
+        let service = ICLogServiceImpl::new(ReadRoute::primary(repositories.ic_logs), directory);
+
+        let logs = service
+            .get_with_driver(Request::new(TimeRangeRequest::default()))
+            .await
+            .unwrap()
+            .into_inner()
+            .logs;
+
+        let names: Vec<(&str, Option<&str>)> = logs
+            .iter()
+            .map(|l| (l.id.as_str(), l.driver_name.as_deref()))
             .collect();
+        assert_eq!(
+            names,
+            vec![
+                ("CARD", Some("山田")),
+                ("LICENSE", Some("佐藤")),
+                ("NEW", None)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn card_holder_at_rejects_invalid_requests() {
+        let service = service(Vec::new());
+
+        let missing_id = service
+            .card_holder_at(Request::new(CardHolderAtRequest {
+                ic_id: "  ".to_string(),
+                time: None,
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(missing_id.code(), tonic::Code::InvalidArgument);
 
-        Ok(Response::new(IcLogList { logs }))
+        let bad_time = service
+            .card_holder_at(Request::new(CardHolderAtRequest {
+                ic_id: "CARD".to_string(),
+                time: Some("yesterday".to_string()),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(bad_time.code(), tonic::Code::InvalidArgument);
     }
 }
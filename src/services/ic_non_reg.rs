@@ -1,36 +1,48 @@
-use crate::db::Database;
+use super::{db_error, start_date};
+use crate::clock;
+use crate::directory::DriverDirectory;
 use crate::proto::timecard::{
     ic_non_reg_service_server::IcNonRegService, CancelIcNonRegRequest, DeleteIcRequest,
     DeleteIcResponse, IcNonReg, IcNonRegList, RegisterDirectRequest, RegisterDirectResponse,
     TimeRangeRequest, UpdateIcNonRegRequest,
 };
-use chrono::{Duration, Local};
+use crate::protocol::{self, LegacyEncoding};
+use crate::repository::CardRepository;
+use chrono::{Duration, NaiveDateTime};
 use serde_json::json;
 use socketioxide::SocketIo;
-use sqlx::Row;
 use std::sync::Arc;
 use tonic::{Request, Response, Status};
 
 pub struct ICNonRegServiceImpl {
-    db: Database,
+    cards: Arc<dyn CardRepository>,
+    directory: DriverDirectory,
     socketio: Option<Arc<SocketIo>>,
 }
 
 impl ICNonRegServiceImpl {
-    pub fn new(db: Database) -> Self {
-        Self { db, socketio: None }
+    pub fn new(cards: Arc<dyn CardRepository>, directory: DriverDirectory) -> Self {
+        Self {
+            cards,
+            directory,
+            socketio: None,
+        }
     }
 
-    pub fn with_socketio(db: Database, socketio: Arc<SocketIo>) -> Self {
+    pub fn with_socketio(
+        cards: Arc<dyn CardRepository>,
+        directory: DriverDirectory,
+        socketio: Arc<SocketIo>,
+    ) -> Self {
         Self {
-            db,
+            cards,
+            directory,
             socketio: Some(socketio),
         }
     }
 
-    fn get_default_start_date() -> String {
-        let one_hour_ago = Local::now() - Duration::hours(1);
-        one_hour_ago.format("%Y-%m-%d %H:%M:%S").to_string()
+    fn get_default_start_date() -> NaiveDateTime {
+        clock::now() - Duration::hours(1)
     }
 }
 
@@ -41,36 +53,21 @@ impl IcNonRegService for ICNonRegServiceImpl {
         request: Request<TimeRangeRequest>,
     ) -> Result<Response<IcNonRegList>, Status> {
         let req = request.into_inner();
-        let start_date = req
-            .start_date
-            .unwrap_or_else(Self::get_default_start_date);
-
-        let rows = sqlx::query(
-            "SELECT n.id, n.datetime, n.deleted, n.registered_id
-             FROM ic_non_reged n
-             LEFT JOIN ic_id i ON n.id = i.ic_id
-               AND (i.deleted = 0 OR i.deleted IS NULL)
-               AND i.date >= n.datetime
-             WHERE n.datetime >= ? AND (n.deleted = 0 OR n.deleted IS NULL)
-               AND i.ic_id IS NULL
-             ORDER BY n.datetime DESC",
-        )
-        .bind(&start_date)
-        .fetch_all(self.db.pool())
-        .await
-        .map_err(|e| Status::internal(format!("Database error: {}", e)))?;
-
-        let items: Vec<IcNonReg> = rows
-            .iter()
-            .map(|row| {
-                let datetime: chrono::NaiveDateTime = row.get("datetime");
-                let deleted: Option<i8> = row.try_get("deleted").ok();
-                IcNonReg {
-                    id: row.get("id"),
-                    datetime: datetime.format("%Y-%m-%d %H:%M:%S").to_string(),
-                    deleted: deleted.map(|d| d != 0),
-                    registered_id: row.try_get("registered_id").ok(),
-                }
+        let start_date = start_date(req.start_date, Self::get_default_start_date)
+            .map_err(Status::invalid_argument)?;
+
+        let items: Vec<IcNonReg> = self
+            .cards
+            .unregistered_since(start_date)
+            .await
+            .map_err(db_error)?
+            .into_iter()
+            .map(|card| IcNonReg {
+                id: card.id,
+                datetime: card.datetime.format("%Y-%m-%d %H:%M:%S").to_string(),
+                timestamp: Some(clock::to_timestamp(card.datetime)),
+                deleted: card.deleted.map(|d| d != 0),
+                registered_id: card.registered_id,
             })
             .collect();
 
@@ -84,16 +81,11 @@ impl IcNonRegService for ICNonRegServiceImpl {
         let req = request.into_inner();
 
         // ic_non_regedテーブルを更新（deleted=0のまま、Pythonクライアントが処理後にdeleted=1にする）
-        sqlx::query(
-            "UPDATE ic_non_reged
-             SET registered_id = ?
-             WHERE id = ?",
-        )
-        .bind(req.driver_id)
-        .bind(&req.ic_id)
-        .execute(self.db.pool())
-        .await
-        .map_err(|e| Status::internal(format!("Database error: {}", e)))?;
+        self.cards
+            .reserve(&req.ic_id, req.driver_id)
+            .await
+            .map_err(db_error)?;
+        self.directory.invalidate();
 
         Ok(Response::new(()))
     }
@@ -106,15 +98,11 @@ impl IcNonRegService for ICNonRegServiceImpl {
 
         // registered_idをNULLに戻し、deletedも0に戻す
         // これにより一覧に再表示される
-        sqlx::query(
-            "UPDATE ic_non_reged
-             SET registered_id = NULL, deleted = 0
-             WHERE id = ?",
-        )
-        .bind(&req.ic_id)
-        .execute(self.db.pool())
-        .await
-        .map_err(|e| Status::internal(format!("Database error: {}", e)))?;
+        self.cards
+            .cancel_reservation(&req.ic_id)
+            .await
+            .map_err(db_error)?;
+        self.directory.invalidate();
 
         Ok(Response::new(()))
     }
@@ -126,31 +114,20 @@ impl IcNonRegService for ICNonRegServiceImpl {
         let req = request.into_inner();
 
         // 1. ドライバー名を取得（存在しない場合は空文字）
-        let driver_row = sqlx::query("SELECT id, name FROM drivers WHERE id = ?")
-            .bind(req.driver_id)
-            .fetch_optional(self.db.pool())
+        let driver_name: String = self
+            .directory
+            .driver_name(req.driver_id)
             .await
-            .map_err(|e| Status::internal(format!("Database error: {}", e)))?;
-
-        let driver_name: String = driver_row
-            .map(|row| row.get("name"))
+            .map_err(db_error)?
             .unwrap_or_else(|| format!("ID:{}", req.driver_id));
 
         // 2. ic_non_regedにregistered_idを設定
         // Pythonクライアントが次回ICタッチ時に登録を完了する
-        sqlx::query(
-            r#"INSERT INTO ic_non_reged (id, registered_id, datetime, deleted)
-               VALUES (?, ?, NOW() + INTERVAL 9 HOUR, 0)
-               ON DUPLICATE KEY UPDATE
-               registered_id = VALUES(registered_id),
-               datetime = NOW() + INTERVAL 9 HOUR,
-               deleted = 0"#,
-        )
-        .bind(&req.ic_id)
-        .bind(req.driver_id)
-        .execute(self.db.pool())
-        .await
-        .map_err(|e| Status::internal(format!("Database error: {}", e)))?;
+        self.cards
+            .reserve_direct(&req.ic_id, req.driver_id, clock::now())
+            .await
+            .map_err(db_error)?;
+        self.directory.invalidate();
 
         Ok(Response::new(RegisterDirectResponse {
             success: true,
@@ -176,29 +153,18 @@ impl IcNonRegService for ICNonRegServiceImpl {
                 "status": "delete_ic",
                 "ic": ic_id
             });
-            let json_str = serde_json::to_string(&data)
-                .map_err(|e| Status::internal(format!("JSON serialization error: {}", e)))?;
-
-            // Pythonクライアントはjson.loads後にtype(data) is strでチェックするため
-            // 二重にJSONエンコードして文字列として送信する必要がある
-            let double_encoded = serde_json::to_string(&json_str)
-                .map_err(|e| Status::internal(format!("JSON serialization error: {}", e)))?;
-
-            if let Some(ns) = io.of("/") {
-                if let Err(e) = ns.emit("hello", &double_encoded) {
-                    tracing::error!("Failed to emit delete_ic event: {}", e);
-                    return Ok(Response::new(DeleteIcResponse {
-                        success: false,
-                        message: format!("Socket.IO emit failed: {}", e),
-                    }));
-                }
-                tracing::info!("Delete IC event broadcasted: {}", ic_id);
-            } else {
+
+            // 旧Pythonクライアントはjson.loads後にtype(data) is strでチェックするため
+            // 二重にJSONエンコードした文字列で送信する（新クライアントにはJSONオブジェクト）
+            if let Err(e) = protocol::broadcast(io, "hello", &data, LegacyEncoding::DoubleString) {
+                tracing::error!("Failed to emit delete_ic event: {}", e);
                 return Ok(Response::new(DeleteIcResponse {
                     success: false,
-                    message: "Socket.IO namespace not found".to_string(),
+                    message: format!("Socket.IO emit failed: {}", e),
                 }));
             }
+            tracing::info!("Delete IC event broadcasted: {}", ic_id);
+            self.directory.invalidate();
         } else {
             return Ok(Response::new(DeleteIcResponse {
                 success: false,
@@ -212,3 +178,128 @@ impl IcNonRegService for ICNonRegServiceImpl {
         }))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{self, Driver};
+    use crate::repository::{CardRow, MemoryRepository, Repositories};
+
+    fn service(store: &MemoryRepository) -> ICNonRegServiceImpl {
+        let repositories = Repositories::memory(store.clone());
+        let directory = DriverDirectory::new(
+            repositories.drivers.clone(),
+            repositories.cards.clone(),
+            std::time::Duration::from_secs(300),
+        );
+        ICNonRegServiceImpl::new(repositories.cards, directory)
+    }
+
+    fn non_reged(id: &str, datetime: NaiveDateTime) -> models::IcNonReg {
+        models::IcNonReg {
+            id: id.to_string(),
+            datetime,
+            deleted: Some(0),
+            registered_id: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn get_all_skips_cards_registered_after_they_were_seen() {
+        let store = MemoryRepository::new();
+        let now = clock::now();
+        store.insert_non_reged(non_reged("NEW", now - Duration::minutes(10)));
+        store.insert_non_reged(non_reged("DONE", now - Duration::minutes(20)));
+        store.insert_non_reged(non_reged("OLD", now - Duration::hours(3)));
+        store.insert_card(CardRow {
+            ic_id: "DONE".to_string(),
+            emp_id: 7,
+            date: now - Duration::minutes(5),
+            deleted: false,
+            revoked_at: None,
+        });
+
+        let items = service(&store)
+            .get_all(Request::new(TimeRangeRequest::default()))
+            .await
+            .unwrap()
+            .into_inner()
+            .items;
+
+        let ids: Vec<&str> = items.iter().map(|i| i.id.as_str()).collect();
+        assert_eq!(ids, vec!["NEW"]);
+        assert_eq!(items[0].deleted, Some(false));
+    }
+
+    #[tokio::test]
+    async fn update_and_cancel_toggle_the_reservation() {
+        let store = MemoryRepository::new();
+        store.insert_non_reged(non_reged("CARD", clock::now()));
+        let service = service(&store);
+
+        service
+            .update(Request::new(UpdateIcNonRegRequest {
+                ic_id: "CARD".to_string(),
+                driver_id: 12,
+            }))
+            .await
+            .unwrap();
+        assert_eq!(store.non_reged("CARD").unwrap().registered_id, Some(12));
+
+        service
+            .cancel_reservation(Request::new(CancelIcNonRegRequest {
+                ic_id: "CARD".to_string(),
+            }))
+            .await
+            .unwrap();
+        let card = store.non_reged("CARD").unwrap();
+        assert_eq!(card.registered_id, None);
+        assert_eq!(card.deleted, Some(0));
+    }
+
+    #[tokio::test]
+    async fn register_direct_reserves_the_card_with_the_driver_name() {
+        let store = MemoryRepository::new();
+        store.insert_driver(Driver {
+            id: 5,
+            name: "田中".to_string(),
+        });
+        let service = service(&store);
+
+        let registered = service
+            .register_direct(Request::new(RegisterDirectRequest {
+                ic_id: "CARD".to_string(),
+                driver_id: 5,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(registered.success);
+        assert_eq!(registered.driver_name.as_deref(), Some("田中"));
+        assert_eq!(store.non_reged("CARD").unwrap().registered_id, Some(5));
+
+        let unknown = service
+            .register_direct(Request::new(RegisterDirectRequest {
+                ic_id: "OTHER".to_string(),
+                driver_id: 99,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(unknown.driver_name.as_deref(), Some("ID:99"));
+    }
+
+    #[tokio::test]
+    async fn delete_ic_without_socketio_reports_failure() {
+        let store = MemoryRepository::new();
+
+        let response = service(&store)
+            .delete_ic(Request::new(DeleteIcRequest {
+                ic_id: "card".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(!response.success);
+    }
+}
@@ -0,0 +1,285 @@
+// gRPC IngestService implementation
+// Accepts punches from terminals, stores them and broadcasts hello events.
+// SubmitBatch replays punches queued while a terminal was offline; they are
+// deduplicated by client_id and broadcast with backfilled = true
+
+use crate::clock_skew::ClockSkewMonitor;
+use crate::db::Database;
+use crate::directory::DriverDirectory;
+use crate::ingest::{
+    decode_photo, parse_punch_date, IcRead, IngestError, Photo, Punch, PunchStatus, Readings,
+    MAX_BATCH_PUNCHES,
+};
+use crate::picture_store::PictureStore;
+use crate::proto::timecard::{
+    ingest_service_server::IngestService, punch_photo, PunchBatchRequest, PunchBatchResponse,
+    PunchBatchResult, PunchRequest, PunchResponse, TelemetryAck, TelemetryReport,
+};
+use crate::sinks::{EventBus, OutboundEvent};
+use crate::telemetry::{self, CameraTelemetry, TelemetryError, TelemetryRecorder};
+use serde_json::Value;
+use std::sync::Arc;
+use tonic::{Request, Response, Status};
+
+pub struct IngestServiceImpl {
+    db: Database,
+    events: EventBus,
+    telemetry: TelemetryRecorder,
+    clock: ClockSkewMonitor,
+    pictures: Arc<dyn PictureStore>,
+    directory: DriverDirectory,
+}
+
+impl IngestServiceImpl {
+    pub fn new(
+        db: Database,
+        events: EventBus,
+        telemetry: TelemetryRecorder,
+        clock: ClockSkewMonitor,
+        pictures: Arc<dyn PictureStore>,
+        directory: DriverDirectory,
+    ) -> Self {
+        Self {
+            db,
+            events,
+            telemetry,
+            clock,
+            pictures,
+            directory,
+        }
+    }
+
+    /// PunchRequest を Punch に変換
+    fn to_punch(req: PunchRequest) -> Result<Punch, IngestError> {
+        let status = PunchStatus::parse(&req.status)
+            .ok_or_else(|| IngestError::Invalid(format!("unknown status '{}'", req.status)))?;
+        let date = parse_punch_date(&req.date)?;
+
+        let photos = req
+            .photos
+            .into_iter()
+            .map(|photo| {
+                let data = match photo.image {
+                    Some(punch_photo::Image::Data(data)) => data,
+                    Some(punch_photo::Image::DataBase64(encoded)) => decode_photo(&encoded)?,
+                    None => {
+                        return Err(IngestError::Invalid(format!(
+                            "photo for cam {} has no data",
+                            photo.cam
+                        )))
+                    }
+                };
+                Ok(Photo {
+                    cam: photo.cam,
+                    detail: photo.detail,
+                    data,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Punch {
+            machine_ip: req.machine_ip,
+            status,
+            date,
+            driver_id: req.driver_id,
+            readings: req.readings.map(|r| Readings {
+                tmp: r.tmp,
+                amb: r.amb,
+                dist: r.dist,
+            }),
+            ic: req.ic.map(|ic| IcRead {
+                id: ic.id,
+                log_type: ic.r#type,
+                detail: ic.detail,
+                iid: ic.iid,
+            }),
+            photos,
+            message: req.message,
+            client_id: req.client_id,
+        })
+    }
+
+    /// 保存済みの打刻を設定済みのシンク（Socket.IO / Cloudflare Worker など）へ配信
+    async fn publish(&self, punch: &Punch, backfilled: bool) {
+        let mut hello = punch.hello_payload(&self.directory).await;
+        if backfilled {
+            if let Value::Object(ref mut map) = hello {
+                map.insert("backfilled".to_string(), Value::Bool(true));
+            }
+        }
+        self.events
+            .publish(OutboundEvent::from_message(hello))
+            .await;
+    }
+}
+
+fn batch_result(client_id: String, outcome: &str, message: String) -> PunchBatchResult {
+    PunchBatchResult {
+        client_id,
+        outcome: outcome.to_string(),
+        message,
+    }
+}
+
+fn to_status(e: IngestError) -> Status {
+    match e {
+        IngestError::Invalid(msg) => Status::invalid_argument(msg),
+        IngestError::Database(e) => Status::internal(format!("Database error: {}", e)),
+        IngestError::Storage(e) => Status::unavailable(format!("Picture store error: {}", e)),
+    }
+}
+
+fn telemetry_status(e: TelemetryError) -> Status {
+    match e {
+        TelemetryError::Invalid(msg) => Status::invalid_argument(msg),
+        TelemetryError::Database(e) => Status::internal(format!("Database error: {}", e)),
+    }
+}
+
+#[tonic::async_trait]
+impl IngestService for IngestServiceImpl {
+    async fn submit_punch(
+        &self,
+        request: Request<PunchRequest>,
+    ) -> Result<Response<PunchResponse>, Status> {
+        let punch = Self::to_punch(request.into_inner()).map_err(to_status)?;
+        punch.validate().map_err(to_status)?;
+        // client_id なしのライブ打刻のみ時刻ずれを計測
+        // （client_id 付きは再送・キュー済み打刻の可能性があり、バッチ登録は過去の時刻のため対象外）
+        if punch.client_id.is_none() {
+            self.clock
+                .observe_terminal(&punch.machine_ip, punch.date)
+                .await;
+        }
+        if !punch
+            .store(&self.db, self.pictures.as_ref(), false)
+            .await
+            .map_err(to_status)?
+        {
+            return Ok(Response::new(PunchResponse {
+                success: true,
+                message: "受信済みの打刻です".to_string(),
+                status: punch.status.as_str().to_string(),
+            }));
+        }
+
+        tracing::info!(
+            "Punch stored via gRPC: {} from {} at {}",
+            punch.status.as_str(),
+            punch.machine_ip,
+            punch.date
+        );
+
+        self.publish(&punch, false).await;
+
+        Ok(Response::new(PunchResponse {
+            success: true,
+            message: "打刻を登録しました".to_string(),
+            status: punch.status.as_str().to_string(),
+        }))
+    }
+
+    async fn submit_telemetry(
+        &self,
+        request: Request<TelemetryReport>,
+    ) -> Result<Response<TelemetryAck>, Status> {
+        let req = request.into_inner();
+        let report = telemetry::TelemetryReport {
+            machine_ip: req.machine_ip,
+            software_version: req.software_version,
+            uptime_secs: req.uptime_secs,
+            disk_total_bytes: req.disk_total_bytes,
+            disk_free_bytes: req.disk_free_bytes,
+            cameras: req
+                .cameras
+                .into_iter()
+                .map(|c| CameraTelemetry {
+                    cam: c.cam,
+                    ok: c.ok,
+                    last_image_age_secs: c.last_image_age_secs,
+                    error: c.error,
+                })
+                .collect(),
+            sensor_errors: req.sensor_errors,
+        };
+
+        let alerts = self
+            .telemetry
+            .record(report)
+            .await
+            .map_err(telemetry_status)?;
+
+        Ok(Response::new(TelemetryAck {
+            success: true,
+            alerts,
+        }))
+    }
+
+    async fn submit_batch(
+        &self,
+        request: Request<PunchBatchRequest>,
+    ) -> Result<Response<PunchBatchResponse>, Status> {
+        let req = request.into_inner();
+        if req.punches.is_empty() {
+            return Err(Status::invalid_argument("punches is empty"));
+        }
+        if req.punches.len() > MAX_BATCH_PUNCHES {
+            return Err(Status::invalid_argument(format!(
+                "too many punches ({} > {})",
+                req.punches.len(),
+                MAX_BATCH_PUNCHES
+            )));
+        }
+
+        // 検証に失敗した打刻は rejected とし、残りは打刻時刻順に登録する
+        let mut results: Vec<Option<PunchBatchResult>> = vec![None; req.punches.len()];
+        let mut punches = Vec::new();
+        for (index, punch_req) in req.punches.into_iter().enumerate() {
+            let client_id = punch_req.client_id.clone().unwrap_or_default();
+            let punch = Self::to_punch(punch_req).and_then(|punch| {
+                if punch.client_id.is_none() {
+                    return Err(IngestError::Invalid("client_id is required".to_string()));
+                }
+                punch.validate()?;
+                Ok(punch)
+            });
+            match punch {
+                Ok(punch) => punches.push((index, punch)),
+                Err(e) => results[index] = Some(batch_result(client_id, "rejected", e.to_string())),
+            }
+        }
+        punches.sort_by_key(|(_, punch)| punch.date);
+
+        // DBエラーの場合はそこで中断（登録済みの分は再送時に重複として扱われる）
+        for (index, punch) in punches {
+            let client_id = punch.client_id.clone().unwrap_or_default();
+            let result = match punch.store(&self.db, self.pictures.as_ref(), true).await {
+                Ok(true) => {
+                    self.publish(&punch, true).await;
+                    batch_result(client_id, "accepted", String::new())
+                }
+                Ok(false) => batch_result(client_id, "duplicate", String::new()),
+                Err(IngestError::Invalid(msg)) => batch_result(client_id, "rejected", msg),
+                Err(e) => return Err(to_status(e)),
+            };
+            results[index] = Some(result);
+        }
+
+        let results: Vec<PunchBatchResult> = results.into_iter().flatten().collect();
+        let count = |outcome: &str| results.iter().filter(|r| r.outcome == outcome).count() as i32;
+        let response = PunchBatchResponse {
+            accepted: count("accepted"),
+            duplicates: count("duplicate"),
+            rejected: count("rejected"),
+            results,
+        };
+        tracing::info!(
+            "Punch batch: {} accepted, {} duplicates, {} rejected",
+            response.accepted,
+            response.duplicates,
+            response.rejected
+        );
+
+        Ok(Response::new(response))
+    }
+}
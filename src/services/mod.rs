@@ -3,8 +3,14 @@ mod driver;
 mod finger_log;
 mod ic_log;
 mod ic_non_reg;
+mod ingest;
 mod notification;
+mod outbox;
 mod pic_data;
+mod release;
+mod retention;
+mod terminal_command;
+mod terminal_config;
 mod test;
 mod tmp_data;
 mod vapid_key;
@@ -15,9 +21,107 @@ pub use driver::DriverServiceImpl;
 pub use finger_log::FingerLogServiceImpl;
 pub use ic_log::ICLogServiceImpl;
 pub use ic_non_reg::ICNonRegServiceImpl;
-pub use notification::NotificationServiceImpl;
+pub use ingest::IngestServiceImpl;
+pub use notification::{EventBroadcaster, NotificationServiceImpl};
+pub use outbox::OutboxServiceImpl;
 pub use pic_data::PicDataServiceImpl;
+pub use release::ReleaseServiceImpl;
+pub use retention::RetentionServiceImpl;
+pub use terminal_command::TerminalCommandServiceImpl;
+pub use terminal_config::TerminalConfigServiceImpl;
 pub use test::TestServiceImpl;
 pub use tmp_data::TmpDataServiceImpl;
 pub use vapid_key::VapidKeyServiceImpl;
 pub use version::VersionServiceImpl;
+
+use crate::clock;
+use crate::repository::ReadOrigin;
+use chrono::{NaiveDate, NaiveDateTime};
+use tonic::metadata::MetadataValue;
+use tonic::{Response, Status};
+
+/// リクエストの開始日時（未指定時は default、不正な形式はエラーメッセージ）
+fn start_date(
+    value: Option<String>,
+    default: impl FnOnce() -> NaiveDateTime,
+) -> Result<NaiveDateTime, String> {
+    parse_time("start_date", value, default)
+}
+
+/// リクエストの日時（未指定時は default、不正な形式は name を含むエラーメッセージ）
+/// "YYYY-MM-DD HH:MM:SS" / "YYYY-MM-DDTHH:MM:SS" / "YYYY-MM-DD" は業務タイムゾーンの時刻、
+/// オフセット付き（RFC 3339）はその時点として受け付ける
+fn parse_time(
+    name: &str,
+    value: Option<String>,
+    default: impl FnOnce() -> NaiveDateTime,
+) -> Result<NaiveDateTime, String> {
+    let Some(value) = value else {
+        return Ok(default());
+    };
+    let value = value.trim();
+    if let Some(time) = clock::parse_rfc3339(value) {
+        return Ok(time);
+    }
+    NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S")
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S"))
+        .or_else(|_| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d").map(|d| d.and_time(Default::default()))
+        })
+        .map_err(|_| format!("invalid {} '{}'", name, value))
+}
+
+fn db_error(e: sqlx::Error) -> Status {
+    Status::internal(format!("Database error: {}", e))
+}
+
+/// 読み取り先を x-read-source（レプリカを使わなかった場合は x-read-fallback に理由コード）で返す
+fn routed_response<T>(message: T, origin: ReadOrigin) -> Response<T> {
+    let mut response = Response::new(message);
+    let metadata = response.metadata_mut();
+    metadata.insert(
+        "x-read-source",
+        MetadataValue::from_static(origin.source.as_str()),
+    );
+    if let Some(fallback) = origin.fallback {
+        metadata.insert(
+            "x-read-fallback",
+            MetadataValue::from_static(fallback.as_str()),
+        );
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(value: &str) -> Result<NaiveDateTime, String> {
+        parse_time("since", Some(value.to_string()), clock::now)
+    }
+
+    #[test]
+    fn parse_time_accepts_business_time_and_rfc3339() {
+        let expected = NaiveDate::from_ymd_opt(2026, 4, 1)
+            .unwrap()
+            .and_hms_opt(9, 0, 0)
+            .unwrap();
+        assert_eq!(parse("2026-04-01 09:00:00"), Ok(expected));
+        assert_eq!(parse("2026-04-01T09:00:00"), Ok(expected));
+        // オフセット付きは業務タイムゾーンの時刻に変換
+        assert_eq!(
+            parse("2026-04-01T00:00:00Z"),
+            Ok(clock::from_utc(
+                expected.and_utc() - chrono::Duration::hours(9)
+            ))
+        );
+        assert_eq!(
+            parse("2026-04-01"),
+            Ok(expected.date().and_hms_opt(0, 0, 0).unwrap())
+        );
+        assert_eq!(
+            parse("yesterday"),
+            Err("invalid since 'yesterday'".to_string())
+        );
+    }
+}
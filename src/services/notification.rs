@@ -1,9 +1,11 @@
-use crate::db::Database;
+use crate::directory::DriverDirectory;
 use crate::proto::timecard::{
-    notification_service_server::NotificationService, EventData, TimeCardEvent,
+    notification_service_server::NotificationService, SubscribeRequest, TimeCardEvent,
 };
 use base64::Engine;
-use sqlx::Row;
+use futures_util::Stream;
+use std::collections::HashSet;
+use std::pin::Pin;
 use std::sync::Arc;
 use tokio::sync::broadcast;
 use tonic::{Request, Response, Status};
@@ -12,18 +14,23 @@ use tonic::{Request, Response, Status};
 pub type EventBroadcaster = broadcast::Sender<TimeCardEvent>;
 
 pub struct NotificationServiceImpl {
-    db: Database,
+    directory: DriverDirectory,
     broadcaster: Arc<EventBroadcaster>,
 }
 
 impl NotificationServiceImpl {
-    pub fn new(db: Database, broadcaster: Arc<EventBroadcaster>) -> Self {
-        Self { db, broadcaster }
+    pub fn new(directory: DriverDirectory, broadcaster: Arc<EventBroadcaster>) -> Self {
+        Self {
+            directory,
+            broadcaster,
+        }
     }
 }
 
 #[tonic::async_trait]
 impl NotificationService for NotificationServiceImpl {
+    type SubscribeStream = Pin<Box<dyn Stream<Item = Result<TimeCardEvent, Status>> + Send>>;
+
     /// Cloudflare DOからのイベントをブロードキャスト
     async fn broadcast_event(
         &self,
@@ -49,15 +56,15 @@ impl NotificationService for NotificationServiceImpl {
         if event.status == "tmp inserted wo pic" {
             if let Some(ref mut data) = event.data {
                 if data.id != 0 && data.name.is_empty() {
-                    // データベースからドライバー名を取得
-                    let row = sqlx::query("SELECT name FROM drivers WHERE id = ? LIMIT 1")
-                        .bind(data.id)
-                        .fetch_optional(self.db.pool())
+                    // ドライバー一覧のキャッシュから取得
+                    let name = self
+                        .directory
+                        .driver_name(data.id)
                         .await
                         .map_err(|e| Status::internal(format!("Database error: {}", e)))?;
 
-                    if let Some(row) = row {
-                        data.name = row.get("name");
+                    if let Some(name) = name {
+                        data.name = name;
                     }
                 }
 
@@ -77,4 +84,42 @@ impl NotificationService for NotificationServiceImpl {
 
         Ok(Response::new(event))
     }
+
+    /// 配信イベントを購読（ステータス・端末IPで絞り込み）
+    async fn subscribe(
+        &self,
+        request: Request<SubscribeRequest>,
+    ) -> Result<Response<Self::SubscribeStream>, Status> {
+        let req = request.into_inner();
+        let statuses: HashSet<String> = req.statuses.into_iter().collect();
+        let ip = req.ip.filter(|ip| !ip.is_empty());
+        let receiver = self.broadcaster.subscribe();
+
+        let stream = futures_util::stream::unfold(receiver, move |mut receiver| {
+            let statuses = statuses.clone();
+            let ip = ip.clone();
+            async move {
+                loop {
+                    match receiver.recv().await {
+                        Ok(event) => {
+                            if !statuses.is_empty() && !statuses.contains(&event.status) {
+                                continue;
+                            }
+                            if ip.as_ref().is_some_and(|ip| *ip != event.ip) {
+                                continue;
+                            }
+                            return Some((Ok(event), receiver));
+                        }
+                        // 遅れた購読者は取りこぼし分をスキップして継続
+                        Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                            tracing::warn!("Subscriber lagged, skipped {} events", skipped);
+                        }
+                        Err(broadcast::error::RecvError::Closed) => return None,
+                    }
+                }
+            }
+        });
+
+        Ok(Response::new(Box::pin(stream)))
+    }
 }
@@ -0,0 +1,96 @@
+// gRPC OutboxService implementation
+// Exposes delivery stats and dead letters of the Cloudflare Worker outbox
+
+use crate::clock;
+use crate::outbox::Outbox;
+use crate::proto::timecard::{
+    outbox_service_server::OutboxService, DeadLetter, DeadLetterList, DeadLetterRequest,
+    OutboxStats, RetryDeadLettersRequest, RetryDeadLettersResponse,
+};
+use tonic::{Request, Response, Status};
+
+pub struct OutboxServiceImpl {
+    outbox: Option<Outbox>,
+}
+
+impl OutboxServiceImpl {
+    pub fn new(outbox: Option<Outbox>) -> Self {
+        Self { outbox }
+    }
+}
+
+fn not_configured() -> Status {
+    Status::failed_precondition("CF_BROADCAST_URL not configured")
+}
+
+#[tonic::async_trait]
+impl OutboxService for OutboxServiceImpl {
+    async fn get_stats(&self, _request: Request<()>) -> Result<Response<OutboxStats>, Status> {
+        let stats = self
+            .outbox
+            .as_ref()
+            .ok_or_else(not_configured)?
+            .stats()
+            .await
+            .map_err(|e| Status::internal(format!("Database error: {}", e)))?;
+
+        Ok(Response::new(OutboxStats {
+            pending: stats.pending,
+            delivered: stats.delivered,
+            dead: stats.dead,
+            oldest_pending_at: stats
+                .oldest_pending_at
+                .map(|d| d.format("%Y-%m-%d %H:%M:%S").to_string()),
+            oldest_pending_at_timestamp: stats.oldest_pending_at.map(clock::to_timestamp),
+            last_error: stats.last_error,
+            delivered_since_start: stats.delivered_since_start,
+            failed_attempts_since_start: stats.failed_attempts_since_start,
+        }))
+    }
+
+    async fn list_dead_letters(
+        &self,
+        request: Request<DeadLetterRequest>,
+    ) -> Result<Response<DeadLetterList>, Status> {
+        let limit = request.into_inner().limit.unwrap_or(100);
+
+        let items = self
+            .outbox
+            .as_ref()
+            .ok_or_else(not_configured)?
+            .dead_letters(limit)
+            .await
+            .map_err(|e| Status::internal(format!("Database error: {}", e)))?
+            .into_iter()
+            .map(|d| DeadLetter {
+                id: d.id,
+                destination: d.destination,
+                stream: d.stream,
+                payload: d.payload,
+                attempts: d.attempts,
+                last_error: d.last_error,
+                created_at: d.created_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+                created_at_timestamp: Some(clock::to_timestamp(d.created_at)),
+            })
+            .collect();
+
+        Ok(Response::new(DeadLetterList { items }))
+    }
+
+    async fn retry_dead_letters(
+        &self,
+        request: Request<RetryDeadLettersRequest>,
+    ) -> Result<Response<RetryDeadLettersResponse>, Status> {
+        let ids = request.into_inner().ids;
+
+        let requeued = self
+            .outbox
+            .as_ref()
+            .ok_or_else(not_configured)?
+            .retry_dead_letters(&ids)
+            .await
+            .map_err(|e| Status::internal(format!("Database error: {}", e)))?;
+
+        Ok(Response::new(RetryDeadLettersResponse { requeued }))
+    }
+}
@@ -1,25 +1,39 @@
-use crate::db::Database;
+use super::{db_error, routed_response, start_date};
+use crate::clock;
+use crate::picture_store::{self, PictureStore};
 use crate::proto::timecard::{
     pic_data_service_server::PicDataService, PaginationRequest, PicData, PicDataList, PicIcData,
     PicIcList, PicTmpData, PicTmpList,
 };
+use crate::repository::{PictureRepository, ReadRoute};
 use base64::Engine;
-use chrono::{Duration, Local};
-use sqlx::Row;
+use chrono::{Duration, NaiveDateTime};
+use futures_util::stream::{self, StreamExt};
+use std::sync::Arc;
 use tonic::{Request, Response, Status};
 
+/// 外部ストア（S3 など）から同時に取得する画像の数
+const FETCH_CONCURRENCY: usize = 8;
+
 pub struct PicDataServiceImpl {
-    db: Database,
+    pictures: ReadRoute<dyn PictureRepository>,
+    store: Arc<dyn PictureStore>,
 }
 
 impl PicDataServiceImpl {
-    pub fn new(db: Database) -> Self {
-        Self { db }
+    pub fn new(pictures: ReadRoute<dyn PictureRepository>, store: Arc<dyn PictureStore>) -> Self {
+        Self { pictures, store }
+    }
+
+    /// 行の画像（pic または pic_key）を base64 で取得
+    async fn encode(&self, pic: Option<Vec<u8>>, key: Option<String>) -> Option<String> {
+        picture_store::load(self.store.as_ref(), pic, key)
+            .await
+            .map(|p| base64::engine::general_purpose::STANDARD.encode(p))
     }
 
-    fn get_default_start_date() -> String {
-        let two_days_ago = Local::now() - Duration::days(2);
-        two_days_ago.format("%Y-%m-%d %H:%M:%S").to_string()
+    fn get_default_start_date() -> NaiveDateTime {
+        clock::now() - Duration::days(2)
     }
 }
 
@@ -29,31 +43,27 @@ impl PicDataService for PicDataServiceImpl {
         &self,
         _request: Request<()>,
     ) -> Result<Response<PicDataList>, Status> {
-        let rows = sqlx::query(
-            "SELECT date, cam, pic, detail, machine_ip
-             FROM pic_data
-             ORDER BY date DESC",
-        )
-        .fetch_all(self.db.pool())
-        .await
-        .map_err(|e| Status::internal(format!("Database error: {}", e)))?;
-
-        let pics: Vec<PicData> = rows
-            .iter()
-            .map(|row| {
-                let date: chrono::NaiveDateTime = row.get("date");
-                let pic: Vec<u8> = row.get("pic");
+        let routed = self
+            .pictures
+            .read(|pictures| async move { pictures.list().await })
+            .await
+            .map_err(db_error)?;
+        let pics: Vec<PicData> = stream::iter(routed.value)
+            .map(|pic| async move {
                 PicData {
-                    date: date.format("%Y-%m-%d %H:%M:%S").to_string(),
-                    cam: row.get("cam"),
-                    pic_base64: base64::engine::general_purpose::STANDARD.encode(&pic),
-                    detail: row.get("detail"),
-                    machine_ip: row.get("machine_ip"),
+                    date: pic.date.format("%Y-%m-%d %H:%M:%S").to_string(),
+                    timestamp: Some(clock::to_timestamp(pic.date)),
+                    cam: pic.cam,
+                    pic_base64: self.encode(pic.pic, pic.pic_key).await.unwrap_or_default(),
+                    detail: pic.detail,
+                    machine_ip: pic.machine_ip,
                 }
             })
-            .collect();
+            .buffered(FETCH_CONCURRENCY)
+            .collect()
+            .await;
 
-        Ok(Response::new(PicDataList { pics }))
+        Ok(routed_response(PicDataList { pics }, routed.origin))
     }
 
     async fn get_tmp(
@@ -62,82 +72,38 @@ impl PicDataService for PicDataServiceImpl {
     ) -> Result<Response<PicTmpList>, Status> {
         let req = request.into_inner();
         let limit = req.limit.unwrap_or(500);
-        let start_date = req
-            .start_date
-            .unwrap_or_else(Self::get_default_start_date);
-
-        // 複雑なJOINクエリ: tmp_data + pic_data + drivers
-        let query = r#"
-            SELECT
-                s9.*,
-                s8.name
-            FROM (
-                SELECT
-                    s7.*,
-                    s6.pic as pic_2,
-                    s6.detail as detail_2
-                FROM (
-                    SELECT
-                        s5.*,
-                        s4.pic as pic_1
-                    FROM (
-                        SELECT
-                            s3.*,
-                            s2.id as driver_id
-                        FROM (
-                            SELECT tmp, amb, dist, date, machine_ip
-                            FROM tmp_data
-                            WHERE id = 0
-                        ) s3
-                        LEFT JOIN (SELECT * FROM tmp_data WHERE id > 0) s2
-                            ON s3.machine_ip = s2.machine_ip AND s3.date = s2.date
-                    ) s5
-                    LEFT JOIN (SELECT * FROM pic_data WHERE detail = 'tmp inserted') s4
-                        ON s5.machine_ip = s4.machine_ip AND s5.date = s4.date
-                ) s7
-                LEFT JOIN (
-                    SELECT * FROM pic_data
-                    WHERE detail = 'tmp inserted by ic' OR detail = 'tmp inserted by fing'
-                ) s6
-                    ON s7.machine_ip = s6.machine_ip AND s7.date = s6.date
-            ) s9
-            LEFT JOIN drivers s8 ON s9.driver_id = s8.id
-            WHERE s9.date >= ?
-            ORDER BY s9.date DESC
-            LIMIT ?
-        "#;
-
-        let rows = sqlx::query(query)
-            .bind(&start_date)
-            .bind(limit)
-            .fetch_all(self.db.pool())
-            .await
-            .map_err(|e| Status::internal(format!("Database error: {}", e)))?;
-
-        let data: Vec<PicTmpData> = rows
-            .iter()
-            .map(|row| {
-                let date: chrono::NaiveDateTime = row.get("date");
-                let pic_1: Option<Vec<u8>> = row.try_get("pic_1").ok();
-                let pic_2: Option<Vec<u8>> = row.try_get("pic_2").ok();
+        let start_date = start_date(req.start_date, Self::get_default_start_date)
+            .map_err(Status::invalid_argument)?;
 
+        let routed = self
+            .pictures
+            .read(|pictures| async move { pictures.tmp_with_pictures(start_date, limit).await })
+            .await
+            .map_err(db_error)?;
+        let data: Vec<PicTmpData> = stream::iter(routed.value)
+            .map(|row| async move {
+                let (pic_data_1, pic_data_2) = tokio::join!(
+                    self.encode(row.pic_1, row.pic_1_key),
+                    self.encode(row.pic_2, row.pic_2_key)
+                );
                 PicTmpData {
-                    machine_ip: row.get("machine_ip"),
-                    tmp: row.get("tmp"),
-                    amb: row.get("amb"),
-                    dist: row.get("dist"),
-                    date: date.format("%Y-%m-%d %H:%M:%S").to_string(),
-                    driver_id: row.try_get("driver_id").ok(),
-                    driver_name: row.try_get("name").ok(),
-                    pic_data_1: pic_1
-                        .map(|p| base64::engine::general_purpose::STANDARD.encode(&p)),
-                    pic_data_2: pic_2
-                        .map(|p| base64::engine::general_purpose::STANDARD.encode(&p)),
+                    machine_ip: row.machine_ip,
+                    tmp: row.tmp,
+                    amb: row.amb,
+                    dist: row.dist,
+                    date: row.date.format("%Y-%m-%d %H:%M:%S").to_string(),
+                    timestamp: Some(clock::to_timestamp(row.date)),
+                    driver_id: row.driver_id,
+                    driver_name: row.driver_name,
+                    pic_data_1,
+                    pic_data_2,
                 }
             })
-            .collect();
+            .buffered(FETCH_CONCURRENCY)
+            .collect()
+            .await;
 
-        Ok(Response::new(PicTmpList { data }))
+        Ok(routed_response(PicTmpList { data }, routed.origin))
     }
 
     async fn get_ic(
@@ -146,47 +112,114 @@ impl PicDataService for PicDataServiceImpl {
     ) -> Result<Response<PicIcList>, Status> {
         let req = request.into_inner();
         let limit = req.limit.unwrap_or(500);
-        let start_date = req
-            .start_date
-            .unwrap_or_else(Self::get_default_start_date);
-
-        let query = r#"
-            SELECT
-                ic.id, ic.type, ic.detail, ic.date, ic.iid, ic.machine_ip,
-                p.pic
-            FROM ic_log ic
-            LEFT JOIN pic_data p ON ic.machine_ip = p.machine_ip AND ic.date = p.date
-            WHERE ic.date >= ?
-            ORDER BY ic.date DESC
-            LIMIT ?
-        "#;
-
-        let rows = sqlx::query(query)
-            .bind(&start_date)
-            .bind(limit)
-            .fetch_all(self.db.pool())
-            .await
-            .map_err(|e| Status::internal(format!("Database error: {}", e)))?;
-
-        let data: Vec<PicIcData> = rows
-            .iter()
-            .map(|row| {
-                let date: chrono::NaiveDateTime = row.get("date");
-                let pic: Option<Vec<u8>> = row.try_get("pic").ok();
+        let start_date = start_date(req.start_date, Self::get_default_start_date)
+            .map_err(Status::invalid_argument)?;
 
+        let routed = self
+            .pictures
+            .read(|pictures| async move { pictures.ic_with_pictures(start_date, limit).await })
+            .await
+            .map_err(db_error)?;
+        let data: Vec<PicIcData> = stream::iter(routed.value)
+            .map(|row| async move {
                 PicIcData {
-                    id: row.get("id"),
-                    r#type: row.get("type"),
-                    detail: row.try_get("detail").ok(),
-                    date: date.format("%Y-%m-%d %H:%M:%S").to_string(),
-                    iid: row.try_get("iid").ok(),
-                    machine_ip: row.get("machine_ip"),
-                    pic_base64: pic
-                        .map(|p| base64::engine::general_purpose::STANDARD.encode(&p)),
+                    pic_base64: self.encode(row.pic, row.pic_key).await,
+                    id: row.id,
+                    r#type: row.log_type,
+                    detail: row.detail,
+                    date: row.date.format("%Y-%m-%d %H:%M:%S").to_string(),
+                    timestamp: Some(clock::to_timestamp(row.date)),
+                    iid: row.iid,
+                    machine_ip: row.machine_ip,
                 }
             })
+            .buffered(FETCH_CONCURRENCY)
+            .collect()
+            .await;
+
+        Ok(routed_response(PicIcList { data }, routed.origin))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::PicData as PicRow;
+    use crate::picture_store::{PictureStoreError, StoredPicture};
+    use crate::repository::{MemoryRepository, Repositories};
+    use chrono::NaiveDate;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// 取得に時間のかかる外部ストア（同時に処理中の取得数の最大値を記録）
+    #[derive(Default)]
+    struct SlowStore {
+        in_flight: AtomicUsize,
+        max_in_flight: AtomicUsize,
+    }
+
+    #[tonic::async_trait]
+    impl PictureStore for SlowStore {
+        fn name(&self) -> &'static str {
+            "slow"
+        }
+
+        async fn put(&self, key: &str, _data: Vec<u8>) -> Result<StoredPicture, PictureStoreError> {
+            Ok(StoredPicture::External(key.to_string()))
+        }
+
+        async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, PictureStoreError> {
+            let now = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_in_flight.fetch_max(now, Ordering::SeqCst);
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            Ok(Some(key.as_bytes().to_vec()))
+        }
+
+        async fn delete(&self, _key: &str) -> Result<(), PictureStoreError> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn external_pictures_are_fetched_concurrently_in_row_order() {
+        let repository = MemoryRepository::new();
+        let base = NaiveDate::from_ymd_opt(2026, 4, 1)
+            .unwrap()
+            .and_hms_opt(8, 0, 0)
+            .unwrap();
+        for i in 0..20 {
+            repository.insert_picture(PicRow {
+                date: base + Duration::minutes(i),
+                cam: 1,
+                pic: None,
+                pic_key: Some(format!("pic-{:02}", i)),
+                detail: String::new(),
+                machine_ip: "10.0.0.1".to_string(),
+            });
+        }
+        let store = Arc::new(SlowStore::default());
+        let service = PicDataServiceImpl::new(
+            ReadRoute::primary(Repositories::memory(repository).pictures),
+            store.clone(),
+        );
+
+        let pics = service
+            .get_all(Request::new(()))
+            .await
+            .unwrap()
+            .into_inner()
+            .pics;
+
+        // 新しい順のまま、各行に自分の画像が入る
+        let expected: Vec<String> = (0..20)
+            .rev()
+            .map(|i| base64::engine::general_purpose::STANDARD.encode(format!("pic-{:02}", i)))
             .collect();
+        let actual: Vec<String> = pics.into_iter().map(|p| p.pic_base64).collect();
+        assert_eq!(actual, expected);
 
-        Ok(Response::new(PicIcList { data }))
+        let max = store.max_in_flight.load(Ordering::SeqCst);
+        assert!(max > 1, "fetched sequentially");
+        assert!(max <= FETCH_CONCURRENCY, "{} concurrent fetches", max);
     }
 }
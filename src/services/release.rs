@@ -0,0 +1,233 @@
+// gRPC ReleaseService implementation
+// Registers terminal software releases and reports staged rollout progress
+
+use crate::clock;
+use crate::db::Database;
+use crate::picture_store::ObjectStore;
+use crate::proto::timecard::{
+    release_service_server::ReleaseService, ListReleasesRequest, Release, ReleaseChange,
+    ReleaseList, RolloutStatus, RolloutStatusRequest, SetRolloutRequest, TerminalRollout,
+    UploadReleaseRequest,
+};
+use crate::releases::{self, NewRelease, ReleaseError, UpdateNotifier};
+use crate::telemetry;
+use crate::terminal_registry;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+use tonic::{Request, Response, Status};
+
+pub struct ReleaseServiceImpl {
+    db: Database,
+    artifacts: Arc<dyn ObjectStore>,
+    notifier: UpdateNotifier,
+}
+
+impl ReleaseServiceImpl {
+    pub fn new(db: Database, artifacts: Arc<dyn ObjectStore>, notifier: UpdateNotifier) -> Self {
+        Self {
+            db,
+            artifacts,
+            notifier,
+        }
+    }
+
+    /// 接続中の端末に更新を案内（失敗してもリリース操作自体は成功扱い）
+    async fn notify(&self) -> i32 {
+        match self.notifier.notify_all().await {
+            Ok(notified) => notified as i32,
+            Err(e) => {
+                tracing::error!("Failed to notify terminals of updates: {}", e);
+                0
+            }
+        }
+    }
+}
+
+fn to_release(r: releases::Release) -> Release {
+    Release {
+        download_path: r.download_path(),
+        id: r.id,
+        version: r.version,
+        channel: r.channel,
+        filename: r.filename,
+        sha256: r.sha256,
+        size_bytes: r.size_bytes,
+        rollout_percent: r.rollout_percent,
+        notes: r.notes,
+        created_by: r.created_by,
+        created_at: r.created_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+        created_at_timestamp: Some(clock::to_timestamp(r.created_at)),
+    }
+}
+
+fn to_status(e: ReleaseError) -> Status {
+    match e {
+        ReleaseError::Invalid(msg) => Status::invalid_argument(msg),
+        ReleaseError::Database(e) => Status::internal(format!("Database error: {}", e)),
+        ReleaseError::Store(e) => Status::unavailable(format!("Artifact store error: {}", e)),
+    }
+}
+
+fn db_error(e: sqlx::Error) -> Status {
+    Status::internal(format!("Database error: {}", e))
+}
+
+#[tonic::async_trait]
+impl ReleaseService for ReleaseServiceImpl {
+    async fn upload_release(
+        &self,
+        request: Request<UploadReleaseRequest>,
+    ) -> Result<Response<ReleaseChange>, Status> {
+        let req = request.into_inner();
+        let release = releases::create(
+            &self.db,
+            self.artifacts.as_ref(),
+            NewRelease {
+                version: req.version.trim().to_string(),
+                channel: req.channel.trim().to_string(),
+                filename: req.filename.trim().to_string(),
+                artifact: req.artifact,
+                expected_sha256: req.sha256.filter(|s| !s.is_empty()),
+                rollout_percent: req.rollout_percent,
+                notes: req.notes.filter(|s| !s.is_empty()),
+                created_by: req.created_by.filter(|s| !s.is_empty()),
+            },
+        )
+        .await
+        .map_err(to_status)?;
+
+        tracing::info!(
+            "Release {} {} uploaded ({} bytes, sha256 {}, rollout {}%)",
+            release.channel,
+            release.version,
+            release.size_bytes,
+            release.sha256,
+            release.rollout_percent
+        );
+        let notified = self.notify().await;
+
+        Ok(Response::new(ReleaseChange {
+            release: Some(to_release(release)),
+            notified,
+        }))
+    }
+
+    async fn list_releases(
+        &self,
+        request: Request<ListReleasesRequest>,
+    ) -> Result<Response<ReleaseList>, Status> {
+        let req = request.into_inner();
+        let channel = req.channel.filter(|s| !s.is_empty());
+
+        let releases = releases::list(&self.db, channel.as_deref())
+            .await
+            .map_err(db_error)?
+            .into_iter()
+            .map(to_release)
+            .collect();
+
+        Ok(Response::new(ReleaseList { releases }))
+    }
+
+    async fn set_rollout(
+        &self,
+        request: Request<SetRolloutRequest>,
+    ) -> Result<Response<ReleaseChange>, Status> {
+        let req = request.into_inner();
+        let release = releases::set_rollout(&self.db, req.release_id, req.rollout_percent)
+            .await
+            .map_err(to_status)?
+            .ok_or_else(|| Status::not_found(format!("Release {} not found", req.release_id)))?;
+
+        tracing::info!(
+            "Release {} {} rollout set to {}%",
+            release.channel,
+            release.version,
+            release.rollout_percent
+        );
+        let notified = self.notify().await;
+
+        Ok(Response::new(ReleaseChange {
+            release: Some(to_release(release)),
+            notified,
+        }))
+    }
+
+    async fn get_rollout_status(
+        &self,
+        request: Request<RolloutStatusRequest>,
+    ) -> Result<Response<RolloutStatus>, Status> {
+        let req = request.into_inner();
+        let release = releases::get(&self.db, req.release_id)
+            .await
+            .map_err(db_error)?
+            .ok_or_else(|| Status::not_found(format!("Release {} not found", req.release_id)))?;
+
+        let mut records: HashMap<String, releases::RolloutRecord> =
+            releases::rollout(&self.db, release.id)
+                .await
+                .map_err(db_error)?
+                .into_iter()
+                .map(|r| (r.machine_ip.clone(), r))
+                .collect();
+        let mut versions = telemetry::latest_by_ip(&self.db).await.map_err(db_error)?;
+
+        // 同じチャネルの登録済み端末と、進捗を報告した端末
+        let mut machine_ips: BTreeSet<String> = records.keys().cloned().collect();
+        for terminal in terminal_registry::list(&self.db).await.map_err(db_error)? {
+            let channel = releases::terminal_channel(&self.db, &terminal.machine_ip)
+                .await
+                .map_err(db_error)?;
+            if channel == release.channel {
+                machine_ips.insert(terminal.machine_ip);
+            }
+        }
+
+        let terminals: Vec<TerminalRollout> = machine_ips
+            .into_iter()
+            .map(|machine_ip| {
+                let current_version = versions
+                    .remove(&machine_ip)
+                    .map(|t| t.report.software_version)
+                    .filter(|v| !v.is_empty());
+                let record = records.remove(&machine_ip);
+                // 報告されたバージョンが追いついていればインストール済み
+                let installed = current_version
+                    .as_deref()
+                    .is_some_and(|v| !release.is_newer_than(v));
+                let state = if installed {
+                    releases::RolloutState::Installed.as_str().to_string()
+                } else {
+                    record
+                        .as_ref()
+                        .map(|r| r.state.clone())
+                        .unwrap_or_else(|| "pending".to_string())
+                };
+                TerminalRollout {
+                    in_rollout: release.includes(&machine_ip),
+                    machine_ip,
+                    current_version,
+                    state,
+                    message: record.as_ref().and_then(|r| r.message.clone()),
+                    updated_at: record
+                        .as_ref()
+                        .map(|r| r.updated_at.format("%Y-%m-%d %H:%M:%S").to_string()),
+                    updated_at_timestamp: record.map(|r| clock::to_timestamp(r.updated_at)),
+                }
+            })
+            .collect();
+
+        let count = |state: &str| terminals.iter().filter(|t| t.state == state).count() as i32;
+        let in_rollout = terminals.iter().filter(|t| t.in_rollout).count() as i32;
+        let installed = count(releases::RolloutState::Installed.as_str());
+        let failed = count(releases::RolloutState::Failed.as_str());
+
+        Ok(Response::new(RolloutStatus {
+            release: Some(to_release(release)),
+            terminals,
+            in_rollout,
+            installed,
+            failed,
+        }))
+    }
+}
@@ -0,0 +1,54 @@
+// gRPC RetentionService implementation
+// Reports how many rows the retention job would archive and delete (dry run)
+
+use crate::clock;
+use crate::proto::timecard::{
+    retention_service_server::RetentionService, RetentionReport, RetentionTableReport,
+};
+use crate::retention::RetentionJob;
+use tonic::{Request, Response, Status};
+
+pub struct RetentionServiceImpl {
+    job: RetentionJob,
+}
+
+impl RetentionServiceImpl {
+    pub fn new(job: RetentionJob) -> Self {
+        Self { job }
+    }
+}
+
+#[tonic::async_trait]
+impl RetentionService for RetentionServiceImpl {
+    async fn get_report(&self, _request: Request<()>) -> Result<Response<RetentionReport>, Status> {
+        if self.job.settings().policies.is_empty() {
+            return Err(Status::failed_precondition(
+                "No retention policies configured (RETENTION_*)",
+            ));
+        }
+
+        let tables = self
+            .job
+            .report()
+            .await
+            .map_err(|e| Status::internal(format!("Database error: {}", e)))?
+            .into_iter()
+            .map(|r| RetentionTableReport {
+                table: r.table.as_str().to_string(),
+                keep: r.keep.to_string(),
+                cutoff: r.cutoff.format("%Y-%m-%d %H:%M:%S").to_string(),
+                expired_rows: r.expired_rows,
+                oldest_date: r
+                    .oldest_date
+                    .map(|d| d.format("%Y-%m-%d %H:%M:%S").to_string()),
+                cutoff_timestamp: Some(clock::to_timestamp(r.cutoff)),
+                oldest_date_timestamp: r.oldest_date.map(clock::to_timestamp),
+            })
+            .collect();
+
+        Ok(Response::new(RetentionReport {
+            tables,
+            archive_dir: self.job.settings().archive_dir.display().to_string(),
+        }))
+    }
+}
@@ -0,0 +1,155 @@
+// gRPC TerminalCommandService implementation
+// Sends commands to terminals over Socket.IO and returns the stored results
+
+use crate::clock;
+use crate::db::Database;
+use crate::proto::timecard::{
+    terminal_command_service_server::TerminalCommandService, CommandHistoryRequest, CommandRecord,
+    CommandRecordList, SendCommandRequest,
+};
+use crate::terminal_command::{self, CommandDispatcher, CommandError, TerminalCommand};
+use std::time::Duration;
+use tonic::{Request, Response, Status};
+
+/// 履歴取得件数の既定値と上限
+const DEFAULT_HISTORY_LIMIT: i32 = 100;
+const MAX_HISTORY_LIMIT: i32 = 1000;
+
+pub struct TerminalCommandServiceImpl {
+    db: Database,
+    dispatcher: CommandDispatcher,
+}
+
+impl TerminalCommandServiceImpl {
+    pub fn new(db: Database, dispatcher: CommandDispatcher) -> Self {
+        Self { db, dispatcher }
+    }
+}
+
+fn to_proto(record: terminal_command::CommandRecord) -> CommandRecord {
+    CommandRecord {
+        id: record.id,
+        machine_ip: record.machine_ip,
+        socket_id: record.socket_id,
+        command: record.command,
+        args: record.args,
+        status: record.status,
+        result: record.result,
+        requested_by: record.requested_by,
+        created_at: record.created_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+        completed_at: record
+            .completed_at
+            .map(|d| d.format("%Y-%m-%d %H:%M:%S").to_string()),
+        created_at_timestamp: Some(clock::to_timestamp(record.created_at)),
+        completed_at_timestamp: record.completed_at.map(clock::to_timestamp),
+    }
+}
+
+/// 履歴の取得件数を検証（0以下はエラー、上限を超える値は上限に丸める）
+fn history_limit(limit: Option<i32>) -> Result<i32, String> {
+    match limit {
+        None => Ok(DEFAULT_HISTORY_LIMIT),
+        Some(limit) if limit <= 0 => Err("limit must be positive".to_string()),
+        Some(limit) => Ok(limit.min(MAX_HISTORY_LIMIT)),
+    }
+}
+
+fn to_status(e: CommandError) -> Status {
+    match e {
+        CommandError::Invalid(msg) => Status::invalid_argument(msg),
+        CommandError::Database(e) => Status::internal(format!("Database error: {}", e)),
+    }
+}
+
+#[tonic::async_trait]
+impl TerminalCommandService for TerminalCommandServiceImpl {
+    async fn send(
+        &self,
+        request: Request<SendCommandRequest>,
+    ) -> Result<Response<CommandRecord>, Status> {
+        let req = request.into_inner();
+
+        if req.machine_ip.trim().is_empty() {
+            return Err(Status::invalid_argument("machine_ip is required"));
+        }
+        let command = TerminalCommand::parse(&req.command)
+            .ok_or_else(|| Status::invalid_argument(format!("Unknown command: {}", req.command)))?;
+        let timeout = req
+            .timeout_secs
+            .filter(|secs| *secs > 0)
+            .map(|secs| Duration::from_secs(secs as u64));
+
+        let record = self
+            .dispatcher
+            .send(
+                &req.machine_ip,
+                command,
+                req.args,
+                req.requested_by,
+                timeout,
+            )
+            .await
+            .map_err(to_status)?;
+
+        Ok(Response::new(to_proto(record)))
+    }
+
+    async fn get_history(
+        &self,
+        request: Request<CommandHistoryRequest>,
+    ) -> Result<Response<CommandRecordList>, Status> {
+        let req = request.into_inner();
+        let limit = history_limit(req.limit).map_err(Status::invalid_argument)?;
+
+        let records = terminal_command::history(&self.db, req.machine_ip.as_deref(), limit)
+            .await
+            .map_err(to_status)?
+            .into_iter()
+            .map(to_proto)
+            .collect();
+
+        Ok(Response::new(CommandRecordList { records }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn records_carry_offset_aware_timestamps() {
+        let created_at = NaiveDate::from_ymd_opt(2026, 4, 1)
+            .unwrap()
+            .and_hms_opt(9, 0, 0)
+            .unwrap();
+        let record = to_proto(terminal_command::CommandRecord {
+            id: "cmd-1".to_string(),
+            machine_ip: "10.0.0.1".to_string(),
+            socket_id: None,
+            command: "reload_drivers".to_string(),
+            args: Default::default(),
+            status: "sent".to_string(),
+            result: None,
+            requested_by: None,
+            created_at,
+            completed_at: None,
+        });
+
+        assert_eq!(record.created_at, "2026-04-01 09:00:00");
+        assert_eq!(
+            record.created_at_timestamp,
+            Some(clock::to_timestamp(created_at))
+        );
+        assert_eq!(record.completed_at_timestamp, None);
+    }
+
+    #[test]
+    fn history_limit_rejects_non_positive_and_caps_large_values() {
+        assert_eq!(history_limit(None).unwrap(), DEFAULT_HISTORY_LIMIT);
+        assert_eq!(history_limit(Some(20)).unwrap(), 20);
+        assert_eq!(history_limit(Some(i32::MAX)).unwrap(), MAX_HISTORY_LIMIT);
+        assert!(history_limit(Some(0)).is_err());
+        assert!(history_limit(Some(-1)).is_err());
+    }
+}
@@ -0,0 +1,181 @@
+// gRPC TerminalConfigService implementation
+// Edits the versioned terminal config store and pushes changes to connected terminals
+
+use crate::clock;
+use crate::db::Database;
+use crate::proto::timecard::{
+    terminal_config_service_server::TerminalConfigService, ConfigApplied, ConfigAppliedList,
+    ConfigChange, ConfigEntry, ConfigEntryList, DeleteConfigRequest, EffectiveConfig,
+    EffectiveConfigRequest, ListConfigRequest, SetConfigRequest,
+};
+use crate::terminal_config::{self, ConfigError, ConfigPusher};
+use serde_json::Value;
+use tonic::{Request, Response, Status};
+
+pub struct TerminalConfigServiceImpl {
+    db: Database,
+    pusher: ConfigPusher,
+}
+
+impl TerminalConfigServiceImpl {
+    pub fn new(db: Database, pusher: ConfigPusher) -> Self {
+        Self { db, pusher }
+    }
+}
+
+fn to_status(e: ConfigError) -> Status {
+    match e {
+        ConfigError::Invalid(msg) => Status::invalid_argument(msg),
+        ConfigError::Database(e) => Status::internal(format!("Database error: {}", e)),
+    }
+}
+
+fn db_error(e: sqlx::Error) -> Status {
+    Status::internal(format!("Database error: {}", e))
+}
+
+#[tonic::async_trait]
+impl TerminalConfigService for TerminalConfigServiceImpl {
+    async fn list_config(
+        &self,
+        request: Request<ListConfigRequest>,
+    ) -> Result<Response<ConfigEntryList>, Status> {
+        let req = request.into_inner();
+        let scope = req.scope.filter(|s| !s.is_empty());
+
+        let entries = terminal_config::list(&self.db, scope.as_deref())
+            .await
+            .map_err(db_error)?
+            .into_iter()
+            .map(|e| ConfigEntry {
+                scope: e.scope,
+                key: e.key,
+                value_json: e.value.to_string(),
+                revision: e.revision,
+                updated_by: e.updated_by,
+                updated_at: e.updated_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+                updated_at_timestamp: Some(clock::to_timestamp(e.updated_at)),
+            })
+            .collect();
+        let current_revision = terminal_config::current_revision(&self.db)
+            .await
+            .map_err(db_error)?;
+
+        Ok(Response::new(ConfigEntryList {
+            entries,
+            current_revision,
+        }))
+    }
+
+    async fn set_config(
+        &self,
+        request: Request<SetConfigRequest>,
+    ) -> Result<Response<ConfigChange>, Status> {
+        let req = request.into_inner();
+        let value: Value = serde_json::from_str(&req.value_json)
+            .map_err(|e| Status::invalid_argument(format!("Invalid value_json: {}", e)))?;
+
+        let revision = terminal_config::set(
+            &self.db,
+            &req.scope,
+            &req.key,
+            &value,
+            req.updated_by.as_deref(),
+        )
+        .await
+        .map_err(to_status)?;
+        let pushed = self.pusher.push_scope(&req.scope);
+
+        tracing::info!(
+            "Config {}:{} set (revision {}), pushed to {} sockets",
+            req.scope,
+            req.key,
+            revision,
+            pushed
+        );
+
+        Ok(Response::new(ConfigChange {
+            success: true,
+            revision,
+            pushed: pushed as i32,
+        }))
+    }
+
+    async fn delete_config(
+        &self,
+        request: Request<DeleteConfigRequest>,
+    ) -> Result<Response<ConfigChange>, Status> {
+        let req = request.into_inner();
+
+        let revision =
+            terminal_config::delete(&self.db, &req.scope, &req.key, req.updated_by.as_deref())
+                .await
+                .map_err(to_status)?;
+
+        let Some(revision) = revision else {
+            let revision = terminal_config::current_revision(&self.db)
+                .await
+                .map_err(db_error)?;
+            return Ok(Response::new(ConfigChange {
+                success: false,
+                revision,
+                pushed: 0,
+            }));
+        };
+        let pushed = self.pusher.push_scope(&req.scope);
+
+        Ok(Response::new(ConfigChange {
+            success: true,
+            revision,
+            pushed: pushed as i32,
+        }))
+    }
+
+    async fn get_effective_config(
+        &self,
+        request: Request<EffectiveConfigRequest>,
+    ) -> Result<Response<EffectiveConfig>, Status> {
+        let req = request.into_inner();
+        if req.machine_ip.trim().is_empty() {
+            return Err(Status::invalid_argument("machine_ip is required"));
+        }
+
+        let config = terminal_config::effective(&self.db, &req.machine_ip)
+            .await
+            .map_err(db_error)?;
+
+        Ok(Response::new(EffectiveConfig {
+            machine_ip: config.machine_ip,
+            revision: config.revision,
+            values_json: Value::Object(config.values).to_string(),
+        }))
+    }
+
+    async fn list_applied(
+        &self,
+        _request: Request<()>,
+    ) -> Result<Response<ConfigAppliedList>, Status> {
+        let current_revision = terminal_config::current_revision(&self.db)
+            .await
+            .map_err(db_error)?;
+        let items = terminal_config::applied(&self.db)
+            .await
+            .map_err(db_error)?
+            .into_iter()
+            .map(|a| ConfigApplied {
+                up_to_date: a.success && a.revision >= current_revision,
+                machine_ip: a.machine_ip,
+                revision: a.revision,
+                success: a.success,
+                message: a.message,
+                applied_at: a.applied_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+                applied_at_timestamp: Some(clock::to_timestamp(a.applied_at)),
+            })
+            .collect();
+
+        Ok(Response::new(ConfigAppliedList {
+            items,
+            current_revision,
+        }))
+    }
+}
@@ -1,5 +1,6 @@
 use crate::db::Database;
 use crate::proto::timecard::{test_service_server::TestService, TestData, TestDataList};
+use crate::with_pool;
 use sqlx::Row;
 use tonic::{Request, Response, Status};
 
@@ -19,18 +20,20 @@ impl TestService for TestServiceImpl {
         &self,
         _request: Request<()>,
     ) -> Result<Response<TestDataList>, Status> {
-        let rows = sqlx::query("SELECT id, datettime FROM test")
-            .fetch_all(self.db.pool())
-            .await
-            .map_err(|e| Status::internal(format!("Database error: {}", e)))?;
-
-        let data: Vec<TestData> = rows
-            .iter()
-            .map(|row| TestData {
-                id: row.get("id"),
-                datetime: row.get("datettime"),
-            })
-            .collect();
+        let data: Vec<TestData> = with_pool!(self.db, pool => {
+            sqlx::query("SELECT id, datettime FROM test")
+                .fetch_all(pool)
+                .await
+                .map(|rows| {
+                    rows.iter()
+                        .map(|row| TestData {
+                            id: row.get("id"),
+                            datetime: row.get("datettime"),
+                        })
+                        .collect()
+                })
+        })
+        .map_err(|e| Status::internal(format!("Database error: {}", e)))?;
 
         Ok(Response::new(TestDataList { data }))
     }
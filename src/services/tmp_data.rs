@@ -1,23 +1,36 @@
-use crate::db::Database;
+use super::{db_error, routed_response, start_date};
+use crate::clock;
+use crate::models;
 use crate::proto::timecard::{
     tmp_data_service_server::TmpDataService, PaginationRequest, TmpData, TmpDataList,
 };
-use chrono::{Duration, Local};
-use sqlx::Row;
+use crate::repository::{ReadRoute, ReadingRepository};
+use chrono::{Duration, NaiveDateTime};
 use tonic::{Request, Response, Status};
 
 pub struct TmpDataServiceImpl {
-    db: Database,
+    readings: ReadRoute<dyn ReadingRepository>,
 }
 
 impl TmpDataServiceImpl {
-    pub fn new(db: Database) -> Self {
-        Self { db }
+    pub fn new(readings: ReadRoute<dyn ReadingRepository>) -> Self {
+        Self { readings }
     }
 
-    fn get_default_start_date() -> String {
-        let two_days_ago = Local::now() - Duration::days(2);
-        two_days_ago.format("%Y-%m-%d %H:%M:%S").to_string()
+    fn get_default_start_date() -> NaiveDateTime {
+        clock::now() - Duration::days(2)
+    }
+}
+
+fn to_tmp_data(t: models::TmpData) -> TmpData {
+    TmpData {
+        machine_ip: t.machine_ip,
+        tmp: t.tmp,
+        amb: t.amb,
+        dist: t.dist,
+        date: t.date.format("%Y-%m-%d %H:%M:%S").to_string(),
+        timestamp: Some(clock::to_timestamp(t.date)),
+        id: t.id,
     }
 }
 
@@ -30,34 +43,14 @@ impl TmpDataService for TmpDataServiceImpl {
         let req = request.into_inner();
         let limit = req.limit.unwrap_or(500);
 
-        let rows = sqlx::query(
-            "SELECT machine_ip, tmp, amb, dist, date, id
-             FROM tmp_data
-             WHERE id = 0
-             ORDER BY date DESC
-             LIMIT ?",
-        )
-        .bind(limit)
-        .fetch_all(self.db.pool())
-        .await
-        .map_err(|e| Status::internal(format!("Database error: {}", e)))?;
-
-        let data: Vec<TmpData> = rows
-            .iter()
-            .map(|row| {
-                let date: chrono::NaiveDateTime = row.get("date");
-                TmpData {
-                    machine_ip: row.get("machine_ip"),
-                    tmp: row.get("tmp"),
-                    amb: row.get("amb"),
-                    dist: row.get("dist"),
-                    date: date.format("%Y-%m-%d %H:%M:%S").to_string(),
-                    id: row.get("id"),
-                }
-            })
-            .collect();
-
-        Ok(Response::new(TmpDataList { data }))
+        let routed = self
+            .readings
+            .read(|readings| async move { readings.unmatched_tmp(limit).await })
+            .await
+            .map_err(db_error)?;
+        let data: Vec<TmpData> = routed.value.into_iter().map(to_tmp_data).collect();
+
+        Ok(routed_response(TmpDataList { data }, routed.origin))
     }
 
     async fn get_without_pic(
@@ -66,39 +59,93 @@ impl TmpDataService for TmpDataServiceImpl {
     ) -> Result<Response<TmpDataList>, Status> {
         let req = request.into_inner();
         let limit = req.limit.unwrap_or(500);
-        let start_date = req
-            .start_date
-            .unwrap_or_else(Self::get_default_start_date);
-
-        let rows = sqlx::query(
-            "SELECT t.machine_ip, t.tmp, t.amb, t.dist, t.date, t.id
-             FROM tmp_data t
-             LEFT JOIN pic_data p ON t.machine_ip = p.machine_ip AND t.date = p.date
-             WHERE p.machine_ip IS NULL AND t.date >= ?
-             ORDER BY t.date DESC
-             LIMIT ?",
-        )
-        .bind(&start_date)
-        .bind(limit)
-        .fetch_all(self.db.pool())
-        .await
-        .map_err(|e| Status::internal(format!("Database error: {}", e)))?;
-
-        let data: Vec<TmpData> = rows
-            .iter()
-            .map(|row| {
-                let date: chrono::NaiveDateTime = row.get("date");
-                TmpData {
-                    machine_ip: row.get("machine_ip"),
-                    tmp: row.get("tmp"),
-                    amb: row.get("amb"),
-                    dist: row.get("dist"),
-                    date: date.format("%Y-%m-%d %H:%M:%S").to_string(),
-                    id: row.get("id"),
-                }
-            })
-            .collect();
-
-        Ok(Response::new(TmpDataList { data }))
+        let start_date = start_date(req.start_date, Self::get_default_start_date)
+            .map_err(Status::invalid_argument)?;
+
+        let routed = self
+            .readings
+            .read(|readings| async move { readings.tmp_without_picture(start_date, limit).await })
+            .await
+            .map_err(db_error)?;
+        let data: Vec<TmpData> = routed.value.into_iter().map(to_tmp_data).collect();
+
+        Ok(routed_response(TmpDataList { data }, routed.origin))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::{MemoryRepository, Repositories};
+
+    fn tmp(machine_ip: &str, date: NaiveDateTime, id: i32) -> models::TmpData {
+        models::TmpData {
+            machine_ip: machine_ip.to_string(),
+            tmp: "36.5".to_string(),
+            amb: "24.0".to_string(),
+            dist: "30".to_string(),
+            date,
+            id,
+        }
+    }
+
+    fn service(store: MemoryRepository) -> TmpDataServiceImpl {
+        TmpDataServiceImpl::new(ReadRoute::primary(Repositories::memory(store).readings))
+    }
+
+    #[tokio::test]
+    async fn get_all_returns_unmatched_readings_newest_first() {
+        let store = MemoryRepository::new();
+        let now = clock::now();
+        store.insert_tmp(tmp("10.0.0.1", now - Duration::minutes(30), 0));
+        store.insert_tmp(tmp("10.0.0.1", now - Duration::minutes(10), 0));
+        store.insert_tmp(tmp("10.0.0.2", now - Duration::minutes(5), 42));
+
+        let data = service(store)
+            .get_all(Request::new(PaginationRequest::default()))
+            .await
+            .unwrap()
+            .into_inner()
+            .data;
+
+        assert_eq!(data.len(), 2);
+        assert!(data.iter().all(|t| t.id == 0));
+        assert!(data[0].date > data[1].date);
+    }
+
+    #[tokio::test]
+    async fn get_without_pic_skips_readings_with_a_picture() {
+        let store = MemoryRepository::new();
+        let now = clock::now();
+        let with_pic = now - Duration::minutes(20);
+        store.insert_tmp(tmp("10.0.0.1", with_pic, 0));
+        store.insert_tmp(tmp("10.0.0.1", now - Duration::minutes(10), 0));
+        store.insert_tmp(tmp("10.0.0.1", now - Duration::days(5), 0));
+        store.insert_picture(models::PicData {
+            date: with_pic,
+            cam: 1,
+            pic: Some(vec![0xff, 0xd8]),
+            pic_key: None,
+            detail: "tmp inserted".to_string(),
+            machine_ip: "10.0.0.1".to_string(),
+        });
+
+        let data = service(store)
+            .get_without_pic(Request::new(PaginationRequest {
+                limit: Some(10),
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .data;
+
+        assert_eq!(data.len(), 1);
+        assert_eq!(
+            data[0].date,
+            (now - Duration::minutes(10))
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string()
+        );
     }
 }
@@ -1,5 +1,6 @@
 use crate::db::Database;
 use crate::proto::timecard::{vapid_key_service_server::VapidKeyService, VapidKey};
+use crate::with_pool;
 use tonic::{Request, Response, Status};
 use uuid::Uuid;
 
@@ -34,15 +35,17 @@ impl VapidKeyService for VapidKeyServiceImpl {
         );
 
         // データベースに保存
-        sqlx::query(
-            "INSERT INTO vapidkey (publicKey, privateKey, uuid) VALUES (?, ?, ?)",
-        )
-        .bind(&public_key)
-        .bind(&private_key)
-        .bind(&uuid)
-        .execute(self.db.pool())
-        .await
-        .map_err(|e| Status::internal(format!("Database error: {}", e)))?;
+        with_pool!(self.db, pool => {
+            sqlx::query(
+                "INSERT INTO vapidkey (publicKey, privateKey, uuid) VALUES (?, ?, ?)",
+            )
+            .bind(&public_key)
+            .bind(&private_key)
+            .bind(&uuid)
+            .execute(pool)
+            .await
+            .map_err(|e| Status::internal(format!("Database error: {}", e)))?;
+        });
 
         Ok(Response::new(VapidKey {
             public_key,
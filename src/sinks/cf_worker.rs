@@ -0,0 +1,31 @@
+// Cloudflare Worker sink: queues hello events in the durable outbox
+
+use super::{EventSink, OutboundEvent, SinkError};
+use crate::outbox::Outbox;
+
+pub struct CfWorkerSink {
+    name: String,
+    outbox: Outbox,
+}
+
+impl CfWorkerSink {
+    /// `name` は Outbox の送信先名（Destination の登録名）と一致させる
+    pub fn new(name: String, outbox: Outbox) -> Self {
+        Self { name, outbox }
+    }
+}
+
+#[tonic::async_trait]
+impl EventSink for CfWorkerSink {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn deliver(&self, event: &OutboundEvent) -> Result<(), SinkError> {
+        self.outbox
+            .enqueue_hello(&self.name, &event.data)
+            .await
+            .map(|_| ())
+            .map_err(|e| SinkError(format!("Failed to enqueue: {}", e)))
+    }
+}
@@ -0,0 +1,53 @@
+// NDJSON file sink: appends one JSON line per event (audit / offline replay)
+
+use super::{EventSink, OutboundEvent, SinkError};
+use serde_json::json;
+use std::path::PathBuf;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+pub struct FileSink {
+    name: String,
+    path: PathBuf,
+    // 行が混ざらないよう書き込みを直列化
+    lock: Mutex<()>,
+}
+
+impl FileSink {
+    pub fn new(name: String, path: PathBuf) -> Self {
+        Self {
+            name,
+            path,
+            lock: Mutex::new(()),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl EventSink for FileSink {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn deliver(&self, event: &OutboundEvent) -> Result<(), SinkError> {
+        let mut line = json!({
+            "timestamp": event.created_at.to_rfc3339(),
+            "status": event.status,
+            "ip": event.machine_ip,
+            "data": event.data,
+        })
+        .to_string();
+        line.push('\n');
+
+        let _guard = self.lock.lock().await;
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await
+            .map_err(|e| SinkError(format!("{}: {}", self.path.display(), e)))?;
+        file.write_all(line.as_bytes())
+            .await
+            .map_err(|e| SinkError(format!("{}: {}", self.path.display(), e)))
+    }
+}
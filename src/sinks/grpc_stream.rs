@@ -0,0 +1,66 @@
+// gRPC stream sink: forwards events to NotificationService.Subscribe subscribers
+
+use super::{EventSink, OutboundEvent, SinkError};
+use crate::proto::timecard::{EventData, TimeCardEvent};
+use crate::services::EventBroadcaster;
+use serde_json::Value;
+use std::sync::Arc;
+
+pub struct GrpcStreamSink {
+    name: String,
+    broadcaster: Arc<EventBroadcaster>,
+}
+
+impl GrpcStreamSink {
+    pub fn new(name: String, broadcaster: Arc<EventBroadcaster>) -> Self {
+        Self { name, broadcaster }
+    }
+}
+
+/// hello メッセージ JSON を TimeCardEvent に変換
+fn to_timecard_event(event: &OutboundEvent) -> TimeCardEvent {
+    let str_field = |value: &Value, key: &str| {
+        value
+            .get(key)
+            .and_then(|v| v.as_str())
+            .unwrap_or("")
+            .to_string()
+    };
+
+    let data = event.data.get("data").filter(|d| d.is_object()).map(|d| {
+        // 写真はカメラ1を優先（旧形式の pic_data も許容）
+        let pic = ["pic_data_1", "pic_data"]
+            .iter()
+            .filter_map(|key| d.get(*key).and_then(|v| v.as_str()))
+            .find(|s| !s.is_empty())
+            .map(|s| s.to_string());
+        EventData {
+            time: str_field(d, "time"),
+            pic_data: None,
+            pic_data_base64: pic,
+            name: str_field(d, "name"),
+            id: d.get("id").and_then(|v| v.as_i64()).unwrap_or(0) as i32,
+        }
+    });
+
+    TimeCardEvent {
+        status: event.status.clone(),
+        message: str_field(&event.data, "message"),
+        data,
+        ip: event.machine_ip.clone().unwrap_or_default(),
+        backfilled: event.backfilled,
+    }
+}
+
+#[tonic::async_trait]
+impl EventSink for GrpcStreamSink {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn deliver(&self, event: &OutboundEvent) -> Result<(), SinkError> {
+        // 購読者がいない場合の送信エラーは無視
+        let _ = self.broadcaster.send(to_timecard_event(event));
+        Ok(())
+    }
+}
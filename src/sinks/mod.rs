@@ -0,0 +1,350 @@
+// Outbound event fan-out
+// Every event published by the server goes through EventBus, which forwards it to the
+// configured sinks whose status filter matches
+
+mod cf_worker;
+mod file;
+mod grpc_stream;
+mod socketio;
+mod webhook;
+
+pub use cf_worker::CfWorkerSink;
+pub use file::FileSink;
+pub use grpc_stream::GrpcStreamSink;
+pub use socketio::SocketIoSink;
+pub use webhook::WebhookSink;
+
+use crate::client_state::ClientState;
+use crate::outbox::{Destination, Outbox};
+use crate::services::EventBroadcaster;
+use chrono::{DateTime, Utc};
+use futures_util::future::join_all;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use tokio::sync::broadcast;
+use tracing::warn;
+
+/// 端末のオンライン/オフライン遷移イベントのステータス
+pub const TERMINAL_ONLINE_STATUS: &str = "terminal online";
+pub const TERMINAL_OFFLINE_STATUS: &str = "terminal offline";
+
+/// 外部に配信するイベント（Pythonクライアント形式の JSON を保持）
+#[derive(Debug, Clone)]
+pub struct OutboundEvent {
+    pub status: String,
+    pub machine_ip: Option<String>,
+    pub data: Value,
+    /// オフライン中の打刻を後から登録したもの
+    pub backfilled: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+impl OutboundEvent {
+    /// hello メッセージ JSON からイベントを生成
+    pub fn from_message(data: Value) -> Self {
+        let status = data
+            .get("status")
+            .and_then(|v| v.as_str())
+            .unwrap_or("")
+            .to_string();
+        let machine_ip = data
+            .get("ip")
+            .and_then(|v| v.as_str())
+            .filter(|ip| !ip.is_empty())
+            .map(|ip| ip.to_string());
+        let backfilled = data
+            .get("backfilled")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+
+        Self {
+            status,
+            machine_ip,
+            data,
+            backfilled,
+            created_at: Utc::now(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct SinkError(pub String);
+
+impl fmt::Display for SinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for SinkError {}
+
+/// イベント配信先
+#[tonic::async_trait]
+pub trait EventSink: Send + Sync {
+    fn name(&self) -> &str;
+
+    async fn deliver(&self, event: &OutboundEvent) -> Result<(), SinkError>;
+}
+
+/// ステータスによる配信フィルタ
+#[derive(Debug, Clone, Default)]
+pub struct StatusFilter {
+    include: Option<HashSet<String>>,
+    exclude: HashSet<String>,
+}
+
+impl StatusFilter {
+    pub fn new(include: Vec<String>, exclude: Vec<String>) -> Self {
+        Self {
+            include: if include.is_empty() {
+                None
+            } else {
+                Some(include.into_iter().collect())
+            },
+            exclude: exclude.into_iter().collect(),
+        }
+    }
+
+    pub fn matches(&self, status: &str) -> bool {
+        if self.exclude.contains(status) {
+            return false;
+        }
+        self.include
+            .as_ref()
+            .map(|include| include.contains(status))
+            .unwrap_or(true)
+    }
+}
+
+struct RegisteredSink {
+    sink: Arc<dyn EventSink>,
+    filter: StatusFilter,
+}
+
+/// 登録済みシンクへのイベント配信
+#[derive(Clone, Default)]
+pub struct EventBus {
+    sinks: Arc<RwLock<Vec<RegisteredSink>>>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// シンクを登録
+    pub fn register(&self, sink: Arc<dyn EventSink>, filter: StatusFilter) {
+        tracing::info!("Event sink registered: {}", sink.name());
+        self.sinks
+            .write()
+            .expect("event sink lock poisoned")
+            .push(RegisteredSink { sink, filter });
+    }
+
+    /// フィルタに一致する全シンクへ並行して配信
+    pub async fn publish(&self, event: OutboundEvent) {
+        let targets: Vec<Arc<dyn EventSink>> = self
+            .sinks
+            .read()
+            .expect("event sink lock poisoned")
+            .iter()
+            .filter(|s| s.filter.matches(&event.status))
+            .map(|s| s.sink.clone())
+            .collect();
+
+        let results = join_all(targets.iter().map(|sink| sink.deliver(&event))).await;
+        for (sink, result) in targets.iter().zip(results) {
+            if let Err(e) = result {
+                warn!("Event sink {} failed: {}", sink.name(), e);
+            }
+        }
+    }
+}
+
+/// EVENT_SINKS 環境変数（JSON 配列）の1要素
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SinkKind {
+    Socketio,
+    CfWorker {
+        url: Option<String>,
+        secret: Option<String>,
+    },
+    Webhook {
+        url: String,
+        secret: Option<String>,
+    },
+    File {
+        path: String,
+    },
+    GrpcStream,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SinkConfig {
+    pub name: Option<String>,
+    #[serde(flatten)]
+    pub kind: SinkKind,
+    #[serde(default)]
+    pub statuses: Vec<String>,
+    #[serde(default)]
+    pub exclude_statuses: Vec<String>,
+}
+
+impl SinkConfig {
+    /// シンク名（未指定の場合は種別名）
+    pub fn sink_name(&self) -> String {
+        self.name.clone().unwrap_or_else(|| {
+            match self.kind {
+                SinkKind::Socketio => "socketio",
+                SinkKind::CfWorker { .. } => crate::outbox::CF_WORKER_DESTINATION,
+                SinkKind::Webhook { .. } => "webhook",
+                SinkKind::File { .. } => "file",
+                SinkKind::GrpcStream => "grpc_stream",
+            }
+            .to_string()
+        })
+    }
+
+    pub fn filter(&self) -> StatusFilter {
+        StatusFilter::new(self.statuses.clone(), self.exclude_statuses.clone())
+    }
+}
+
+/// シンク設定をパース（未設定の場合は Socket.IO + CF Worker(URL設定時) + gRPC ストリーム）
+pub fn parse_sink_configs(
+    raw: Option<&str>,
+    cf_broadcast_url: Option<&str>,
+) -> Result<Vec<SinkConfig>, String> {
+    if let Some(raw) = raw {
+        return serde_json::from_str(raw).map_err(|e| format!("Invalid EVENT_SINKS: {}", e));
+    }
+
+    // 接続状態・アラートイベントは Socket.IO シンク自体が端末に送らない
+    let mut configs = vec![SinkConfig {
+        name: None,
+        kind: SinkKind::Socketio,
+        statuses: vec![],
+        exclude_statuses: vec![],
+    }];
+    if cf_broadcast_url.is_some() {
+        configs.push(SinkConfig {
+            name: None,
+            kind: SinkKind::CfWorker {
+                url: None,
+                secret: None,
+            },
+            statuses: vec![],
+            exclude_statuses: vec![],
+        });
+    }
+    configs.push(SinkConfig {
+        name: None,
+        kind: SinkKind::GrpcStream,
+        statuses: vec![],
+        exclude_statuses: vec![],
+    });
+    Ok(configs)
+}
+
+/// HTTP 系シンク（CF Worker / Webhook）の Outbox 送信先を抽出
+pub fn outbox_destinations(
+    configs: &[SinkConfig],
+    cf_broadcast_url: Option<&str>,
+    cf_broadcast_secret: Option<&str>,
+) -> Result<HashMap<String, Destination>, String> {
+    let mut destinations = HashMap::new();
+    for config in configs {
+        let destination = match &config.kind {
+            SinkKind::CfWorker { url, secret } => {
+                let url = url
+                    .as_deref()
+                    .or(cf_broadcast_url)
+                    .ok_or("cf_worker sink requires url or CF_BROADCAST_URL")?;
+                Destination {
+                    url: url.to_string(),
+                    secret: secret
+                        .clone()
+                        .or_else(|| cf_broadcast_secret.map(|s| s.to_string())),
+                }
+            }
+            SinkKind::Webhook { url, secret } => Destination {
+                url: url.clone(),
+                secret: secret.clone(),
+            },
+            _ => continue,
+        };
+        let name = config.sink_name();
+        if destinations.insert(name.clone(), destination).is_some() {
+            return Err(format!("Duplicate event sink name: {}", name));
+        }
+    }
+    Ok(destinations)
+}
+
+/// Socket.IO 以外のシンクを登録（Socket.IO は SocketIo 初期化後に登録する）
+pub fn register_sinks(
+    events: &EventBus,
+    configs: &[SinkConfig],
+    outbox: Option<&Outbox>,
+    broadcaster: &Arc<EventBroadcaster>,
+) {
+    for config in configs {
+        let name = config.sink_name();
+        let sink: Arc<dyn EventSink> = match &config.kind {
+            SinkKind::Socketio => continue,
+            SinkKind::CfWorker { .. } | SinkKind::Webhook { .. } => {
+                let Some(outbox) = outbox else { continue };
+                if matches!(config.kind, SinkKind::CfWorker { .. }) {
+                    Arc::new(CfWorkerSink::new(name, outbox.clone()))
+                } else {
+                    Arc::new(WebhookSink::new(name, outbox.clone()))
+                }
+            }
+            SinkKind::File { path } => Arc::new(FileSink::new(name, PathBuf::from(path))),
+            SinkKind::GrpcStream => Arc::new(GrpcStreamSink::new(name, broadcaster.clone())),
+        };
+        events.register(sink, config.filter());
+    }
+}
+
+/// ClientState のオンライン/オフライン遷移をシンクへ配信
+pub fn spawn_liveness_publisher(clients: &ClientState, events: EventBus) {
+    let mut receiver = clients.subscribe();
+    tokio::spawn(async move {
+        loop {
+            let event = match receiver.recv().await {
+                Ok(event) => event,
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Liveness publisher lagged, skipped {} events", skipped);
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            };
+            let Some(online) = event.liveness() else {
+                continue;
+            };
+
+            let (status, message) = if online {
+                (TERMINAL_ONLINE_STATUS, "端末が接続されました")
+            } else {
+                (TERMINAL_OFFLINE_STATUS, "端末からの応答がありません")
+            };
+            let data = json!({
+                "ip": event.client.ip_address,
+                "status": status,
+                "message": message,
+                "data": {
+                    "time": event.client.last_activity.to_rfc3339(),
+                    "socket_id": event.client.socket_id,
+                    "health": event.client.health.as_str(),
+                }
+            });
+            events.publish(OutboundEvent::from_message(data)).await;
+        }
+    });
+}
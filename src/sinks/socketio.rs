@@ -0,0 +1,62 @@
+// Socket.IO sink: broadcasts hello events to every connected client
+// Terminal connection and alert events are never sent, whatever the sink's status filter:
+// the terminals' hello handler does not expect those statuses
+
+use super::{EventSink, OutboundEvent, SinkError, TERMINAL_OFFLINE_STATUS, TERMINAL_ONLINE_STATUS};
+use crate::protocol::{self, LegacyEncoding};
+use crate::telemetry::TERMINAL_ALERT_STATUS;
+use socketioxide::SocketIo;
+use std::sync::Arc;
+
+/// 端末に送らないステータス（接続状態・アラート）
+const WITHHELD_STATUSES: [&str; 3] = [
+    TERMINAL_ONLINE_STATUS,
+    TERMINAL_OFFLINE_STATUS,
+    TERMINAL_ALERT_STATUS,
+];
+
+/// 端末の hello ハンドラに送るイベントか
+fn forwards(status: &str) -> bool {
+    !WITHHELD_STATUSES.contains(&status)
+}
+
+pub struct SocketIoSink {
+    name: String,
+    io: Arc<SocketIo>,
+}
+
+impl SocketIoSink {
+    pub fn new(name: String, io: Arc<SocketIo>) -> Self {
+        Self { name, io }
+    }
+}
+
+#[tonic::async_trait]
+impl EventSink for SocketIoSink {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn deliver(&self, event: &OutboundEvent) -> Result<(), SinkError> {
+        if !forwards(&event.status) {
+            return Ok(());
+        }
+        // 旧Pythonクライアントには JSON 文字列で送信（送信元を含む全クライアント）
+        protocol::broadcast(&self.io, "hello", &event.data, LegacyEncoding::String)
+            .map_err(|e| SinkError(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn terminal_events_are_withheld() {
+        assert!(!forwards(TERMINAL_ONLINE_STATUS));
+        assert!(!forwards(TERMINAL_OFFLINE_STATUS));
+        assert!(!forwards(TERMINAL_ALERT_STATUS));
+        assert!(forwards("出勤"));
+        assert!(forwards(""));
+    }
+}
@@ -0,0 +1,42 @@
+// Generic webhook sink: queues events in the outbox under the sink's own destination
+// Delivery, retries and HMAC signing are handled by the outbox worker
+
+use super::{EventSink, OutboundEvent, SinkError};
+use crate::outbox::Outbox;
+use serde_json::json;
+
+pub struct WebhookSink {
+    name: String,
+    outbox: Outbox,
+}
+
+impl WebhookSink {
+    /// `name` は Outbox の送信先名（Destination の登録名）と一致させる
+    pub fn new(name: String, outbox: Outbox) -> Self {
+        Self { name, outbox }
+    }
+}
+
+#[tonic::async_trait]
+impl EventSink for WebhookSink {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn deliver(&self, event: &OutboundEvent) -> Result<(), SinkError> {
+        let stream = event.machine_ip.as_deref().unwrap_or("global");
+        let payload = json!({
+            "type": "timecard_event",
+            "status": event.status,
+            "ip": event.machine_ip,
+            "data": event.data,
+            "timestamp": event.created_at.to_rfc3339(),
+        });
+
+        self.outbox
+            .enqueue(&self.name, stream, &payload)
+            .await
+            .map(|_| ())
+            .map_err(|e| SinkError(format!("Failed to enqueue: {}", e)))
+    }
+}
@@ -1,16 +1,26 @@
 // Socket.IO Server implementation
 // Replaces Node.js Socket.IO server on port 3050
 
-use crate::client_state::ClientState;
+use crate::client_state::{ClientState, LivenessSettings};
+use crate::clock_skew::{parse_terminal_time, ClockSkewMonitor};
 use crate::db::Database;
+use crate::directory::DriverDirectory;
+use crate::ingest::{IngestError, PunchPayload};
+use crate::picture_store::PictureStore;
+use crate::protocol::{self, ClientProtocol, LegacyEncoding};
+use crate::releases::{self, UpdateStatusReport, UPDATE_STATUS_EVENT};
+use crate::sinks::{EventBus, OutboundEvent};
+use crate::telemetry::{TelemetryRecorder, TelemetryReport, TELEMETRY_EVENT};
+use crate::terminal_command::{self, CommandReply};
+use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use socketioxide::{
-    extract::{Data, SocketRef, State},
+    extract::{AckSender, Bin, Data, SocketRef, State, TryData},
     SocketIo,
 };
-use sqlx::Row;
 use std::sync::Arc;
+use std::time::Duration;
 use tracing::{error, info, warn};
 
 /// Shared state for Socket.IO handlers
@@ -18,8 +28,11 @@ use tracing::{error, info, warn};
 pub struct SocketState {
     pub db: Database,
     pub clients: ClientState,
-    pub cf_broadcast_url: Option<Arc<String>>,
-    pub http_client: reqwest::Client,
+    pub events: EventBus,
+    pub telemetry: TelemetryRecorder,
+    pub clock: ClockSkewMonitor,
+    pub pictures: Arc<dyn PictureStore>,
+    pub directory: DriverDirectory,
 }
 
 /// Message data structure from Python client
@@ -44,20 +57,40 @@ pub struct MessagePayload {
     pub pic_data_2: Option<String>,
 }
 
+/// Socket.IO heartbeat (engine.io ping) settings
+#[derive(Debug, Clone, Copy)]
+pub struct HeartbeatSettings {
+    pub ping_interval: Duration,
+    pub ping_timeout: Duration,
+}
+
 /// Setup Socket.IO server with message handling
+#[allow(clippy::too_many_arguments)]
 pub fn setup_socketio(
     db: Database,
     clients: ClientState,
-    cf_broadcast_url: Option<String>,
+    events: EventBus,
+    telemetry: TelemetryRecorder,
+    clock: ClockSkewMonitor,
+    pictures: Arc<dyn PictureStore>,
+    directory: DriverDirectory,
+    heartbeat: HeartbeatSettings,
 ) -> (socketioxide::layer::SocketIoLayer, SocketIo) {
-    let http_client = reqwest::Client::new();
     let state = SocketState {
         db,
         clients,
-        cf_broadcast_url: cf_broadcast_url.map(|url| Arc::new(url)),
-        http_client,
+        events,
+        telemetry,
+        clock,
+        pictures,
+        directory,
     };
-    let (layer, io) = SocketIo::builder().with_state(state).build_layer();
+    // Half-open sockets are closed when the ping is not answered within the timeout
+    let (layer, io) = SocketIo::builder()
+        .ping_interval(heartbeat.ping_interval)
+        .ping_timeout(heartbeat.ping_timeout)
+        .with_state(state)
+        .build_layer();
 
     io.ns("/", on_connect);
 
@@ -65,13 +98,28 @@ pub fn setup_socketio(
 }
 
 /// Handle new socket connection
-async fn on_connect(socket: SocketRef, state: State<SocketState>) {
+async fn on_connect(
+    socket: SocketRef,
+    TryData::<Value>(auth): TryData<Value>,
+    state: State<SocketState>,
+) {
     let socket_id = socket.id.to_string();
-    info!("Client connected: {}", socket_id);
+    // Clients that send no auth payload are the legacy Python client
+    let auth = auth.unwrap_or(Value::Null);
+    let client_protocol = ClientProtocol::from_auth(&auth);
+    client_protocol.join(&socket);
+    info!(
+        "Client connected: {} (protocol v{})",
+        socket_id,
+        client_protocol.version()
+    );
 
     // Register client immediately on connect (IP will be updated on start_connect)
     state.clients.add_client(socket_id.clone(), "unknown".to_string());
     info!("Client registered on connect: {}", socket_id);
+    if let Some(time) = terminal_time(&auth) {
+        state.clock.observe_socket(&socket_id, time).await;
+    }
 
     // Send initial hello message on connect
     if let Err(e) = socket.emit("hello", "from server") {
@@ -84,8 +132,14 @@ async fn on_connect(socket: SocketRef, state: State<SocketState>) {
         |socket: SocketRef, Data::<Value>(data), state: State<SocketState>| async move {
             let socket_id = socket.id.to_string();
 
-            // Update last activity for this client
-            state.clients.update_activity(&socket_id);
+            // Update last activity for this client (quarantined clients get no credit)
+            if !state.clients.accept_activity(&socket_id) {
+                warn!(
+                    "Dropped message from quarantined client {}: {:?}",
+                    socket_id, data
+                );
+                return;
+            }
 
             // Update client IP from any message that contains ip field
             if let Some(ip) = data.get("ip").and_then(|v| v.as_str()) {
@@ -94,16 +148,153 @@ async fn on_connect(socket: SocketRef, state: State<SocketState>) {
                     info!("Client IP updated: {} -> {}", socket_id, ip);
                 }
             }
+            if let Some(time) = data.get("data").and_then(terminal_time) {
+                state.clock.observe_socket(&socket_id, time).await;
+            }
 
             info!("Received message: {:?}", data);
-            handle_message(
-                socket,
-                data,
-                state.db.clone(),
-                state.cf_broadcast_url.clone(),
-                state.http_client.clone(),
-            )
-            .await;
+            handle_message(data, &state.directory, &state.events).await;
+        },
+    );
+
+    // Handle punch ingestion from terminals (replaces direct MySQL writes)
+    socket.on(
+        "punch",
+        |socket: SocketRef,
+         Data::<Value>(data),
+         ack: AckSender,
+         state: State<SocketState>,
+         Bin(bin)| async move {
+            let socket_id = socket.id.to_string();
+            if !state.clients.accept_activity(&socket_id) {
+                warn!(
+                    "Dropped punch from quarantined client {}: {:?}",
+                    socket_id, data
+                );
+                return;
+            }
+
+            let attachments: Vec<Vec<u8>> = bin.iter().map(|b| b.to_vec()).collect();
+            let result = handle_punch(&socket, data, &attachments, &state).await;
+            let response = match result {
+                Ok(status) => json!({ "success": true, "message": "ok", "status": status }),
+                Err(e) => {
+                    warn!("Rejected punch from {}: {}", socket_id, e);
+                    json!({ "success": false, "message": e.to_string() })
+                }
+            };
+            if let Err(e) = ack.send(response) {
+                warn!("Failed to ack punch: {}", e);
+            }
+        },
+    );
+
+    // Handle application-level heartbeat (keeps idle terminals from going stale)
+    socket.on(
+        "heartbeat",
+        |socket: SocketRef,
+         TryData::<Value>(data),
+         ack: AckSender,
+         state: State<SocketState>| async move {
+            let socket_id = socket.id.to_string();
+            // Quarantined sockets must not keep themselves alive with heartbeats alone
+            if !state.clients.accept_activity(&socket_id) {
+                warn!("Dropped heartbeat from quarantined client {}", socket_id);
+                return;
+            }
+            // Terminals may include their own clock ({"time": ...}) for skew detection
+            if let Some(time) = data.ok().as_ref().and_then(terminal_time) {
+                state.clock.observe_socket(&socket_id, time).await;
+            }
+            // Ack is optional; clients that emit without a callback are ignored
+            let _ = ack.send(json!({ "time": chrono::Utc::now().to_rfc3339() }));
+        },
+    );
+
+    // Handle periodic telemetry reports (version, uptime, disk, cameras, sensors)
+    socket.on(
+        TELEMETRY_EVENT,
+        |socket: SocketRef, Data::<Value>(data), ack: AckSender, state: State<SocketState>| async move {
+            let socket_id = socket.id.to_string();
+            if !state.clients.accept_activity(&socket_id) {
+                warn!("Dropped telemetry from quarantined client {}", socket_id);
+                return;
+            }
+
+            let result = match serde_json::from_value::<TelemetryReport>(data) {
+                Ok(report) => state.telemetry.record(report).await.map_err(|e| e.to_string()),
+                Err(e) => Err(format!("malformed telemetry payload: {}", e)),
+            };
+            let response = match result {
+                Ok(alerts) => json!({ "success": true, "alerts": alerts }),
+                Err(e) => {
+                    warn!("Rejected telemetry from {}: {}", socket_id, e);
+                    json!({ "success": false, "message": e })
+                }
+            };
+            // Ack is optional
+            let _ = ack.send(response);
+        },
+    );
+
+    // Handle asynchronous command results (sent after the ack for long-running commands)
+    socket.on(
+        "command_result",
+        |socket: SocketRef, Data::<CommandReply>(reply), state: State<SocketState>| async move {
+            let socket_id = socket.id.to_string();
+            if !state.clients.accept_activity(&socket_id) {
+                warn!(
+                    "Dropped command_result from quarantined client {}",
+                    socket_id
+                );
+                return;
+            }
+            match terminal_command::record_reply(&state.db, &reply).await {
+                Ok(true) => info!("Command result recorded: {:?}", reply.id),
+                Ok(false) => warn!("Command result for unknown command: {:?}", reply.id),
+                Err(e) => error!("Failed to record command result: {}", e),
+            }
+        },
+    );
+
+    // Handle software update progress (notified -> downloaded -> installing -> installed/failed)
+    socket.on(
+        UPDATE_STATUS_EVENT,
+        |socket: SocketRef,
+         Data::<UpdateStatusReport>(report),
+         ack: AckSender,
+         state: State<SocketState>| async move {
+            let socket_id = socket.id.to_string();
+            if !state.clients.accept_activity(&socket_id) {
+                warn!(
+                    "Dropped update_status from quarantined client {}",
+                    socket_id
+                );
+                return;
+            }
+            let result = match state.clients.get_client(&socket_id) {
+                Some(client) if client.has_ip() => {
+                    releases::record_report(&state.db, &client.ip_address, &report)
+                        .await
+                        .map_err(|e| e.to_string())
+                }
+                _ => Err("terminal IP is not known yet".to_string()),
+            };
+            let response = match result {
+                Ok(()) => {
+                    info!(
+                        "Update status from {}: release {} {}",
+                        socket_id, report.release_id, report.state
+                    );
+                    json!({ "success": true })
+                }
+                Err(e) => {
+                    warn!("Rejected update_status from {}: {}", socket_id, e);
+                    json!({ "success": false, "message": e })
+                }
+            };
+            // Ack is optional; clients that emit without a callback are ignored
+            let _ = ack.send(response);
         },
     );
 
@@ -121,14 +312,8 @@ async fn on_connect(socket: SocketRef, state: State<SocketState>) {
     });
 }
 
-/// Process message and broadcast hello event
-async fn handle_message(
-    socket: SocketRef,
-    mut data: Value,
-    db: Database,
-    cf_broadcast_url: Option<Arc<String>>,
-    http_client: reqwest::Client,
-) {
+/// Process message and publish it as a hello event
+async fn handle_message(mut data: Value, directory: &DriverDirectory, events: &EventBus) {
     let status = data
         .get("status")
         .and_then(|v| v.as_str())
@@ -137,14 +322,14 @@ async fn handle_message(
 
     match status.as_str() {
         "tmp inserted wo pic" => {
-            // Get driver name from database if id is provided but name is missing
+            // Get driver name from the directory if id is provided but name is missing
             if let Some(inner_data) = data.get_mut("data") {
                 let has_id = inner_data.get("id").is_some();
                 let has_name = inner_data.get("name").and_then(|v| v.as_str()).is_some();
 
                 if has_id && !has_name {
                     if let Some(id) = inner_data.get("id").and_then(|v| v.as_i64()) {
-                        match get_driver_name(&db, id as i32).await {
+                        match directory.driver_name(id as i32).await {
                             Ok(Some(name)) => {
                                 inner_data["name"] = json!(name);
                                 info!("Added driver name {} for id {}", name, id);
@@ -170,81 +355,95 @@ async fn handle_message(
         }
         "delete_ic" => {
             info!("Delete IC event received");
+            directory.invalidate();
         }
         _ => {
             info!("Unknown status: {}, broadcasting as-is", status);
         }
     }
 
-    // Broadcast hello event to all clients (including sender)
-    let json_str = serde_json::to_string(&data).unwrap_or_else(|_| "{}".to_string());
-    broadcast_hello(&socket, &json_str).await;
-
-    // Notify Cloudflare Worker asynchronously (fire-and-forget)
-    if let Some(url) = cf_broadcast_url {
-        let json_str_clone = json_str.clone();
-        tokio::spawn(async move {
-            notify_cf_worker(&http_client, &url, &json_str_clone).await;
-        });
-    }
+    // Fan out to the configured sinks (Socket.IO clients, Cloudflare Worker, ...)
+    events.publish(OutboundEvent::from_message(data)).await;
 }
 
-/// Get driver name from database
-async fn get_driver_name(
-    db: &Database,
-    driver_id: i32,
-) -> Result<Option<String>, sqlx::Error> {
-    let row = sqlx::query("SELECT name FROM drivers WHERE id = ?")
-        .bind(driver_id)
-        .fetch_optional(db.pool())
-        .await?;
-
-    Ok(row.map(|r| r.get("name")))
+/// Terminal clock reported in a payload's "time" field
+fn terminal_time(data: &Value) -> Option<NaiveDateTime> {
+    data.get("time")
+        .and_then(|v| v.as_str())
+        .and_then(parse_terminal_time)
 }
 
-/// Broadcast hello event to all connected clients
-async fn broadcast_hello(socket: &SocketRef, data: &str) {
-    // Broadcast to all other clients
-    if let Err(e) = socket.broadcast().emit("hello", data) {
-        error!("Failed to broadcast hello: {}", e);
+/// Validate and store a punch, then publish it as a hello event
+async fn handle_punch(
+    socket: &SocketRef,
+    data: Value,
+    attachments: &[Vec<u8>],
+    state: &SocketState,
+) -> Result<&'static str, IngestError> {
+    let payload: PunchPayload = serde_json::from_value(data)
+        .map_err(|e| IngestError::Invalid(format!("malformed punch payload: {}", e)))?;
+    let punch = payload.into_punch(attachments)?;
+    punch.validate()?;
+
+    let socket_id = socket.id.to_string();
+    state
+        .clients
+        .update_ip(&socket_id, punch.machine_ip.clone());
+    // Punches carrying a client_id may be retries or replays of a queued punch,
+    // so only fresh punches are compared with the server clock
+    if punch.client_id.is_none() {
+        state.clock.observe_socket(&socket_id, punch.date).await;
     }
 
-    // Also send to the sender
-    if let Err(e) = socket.emit("hello", data) {
-        error!("Failed to emit hello to sender: {}", e);
+    if !punch
+        .store(&state.db, state.pictures.as_ref(), false)
+        .await?
+    {
+        info!(
+            "Duplicate punch {:?} from {} ignored",
+            punch.client_id, punch.machine_ip
+        );
+        return Ok(punch.status.as_str());
     }
+    info!(
+        "Punch stored: {} from {} at {}",
+        punch.status.as_str(),
+        punch.machine_ip,
+        punch.date
+    );
 
-    info!("Broadcasted hello event");
-}
+    let hello = punch.hello_payload(&state.directory).await;
+    state
+        .events
+        .publish(OutboundEvent::from_message(hello))
+        .await;
 
-/// Notify Cloudflare Worker to broadcast message to WebSocket clients
-async fn notify_cf_worker(client: &reqwest::Client, url: &str, data: &str) {
-    // Wrap data in hello event format for frontend
-    let payload = json!({
-        "type": "hello",
-        "data": serde_json::from_str::<Value>(data).unwrap_or(Value::Null),
-        "timestamp": chrono::Utc::now().to_rfc3339()
-    });
+    Ok(punch.status.as_str())
+}
 
-    match client
-        .post(url)
-        .header("Content-Type", "application/json")
-        .body(payload.to_string())
-        .timeout(std::time::Duration::from_secs(5))
-        .send()
-        .await
-    {
-        Ok(resp) => {
-            if resp.status().is_success() {
-                info!("CF Worker notified successfully");
-            } else {
-                warn!("CF Worker returned status: {}", resp.status());
+/// Periodically mark idle clients stale and disconnect evicted sockets
+pub fn spawn_client_sweeper(io: Arc<SocketIo>, clients: ClientState, settings: LivenessSettings) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(settings.sweep_interval);
+        loop {
+            interval.tick().await;
+            for client in clients.sweep(&settings) {
+                let socket = client
+                    .socket_id
+                    .parse()
+                    .ok()
+                    .and_then(|sid| io.get_socket(sid));
+                if let Some(socket) = socket {
+                    if let Err(e) = socket.disconnect() {
+                        warn!(
+                            "Failed to disconnect evicted client {}: {}",
+                            client.socket_id, e
+                        );
+                    }
+                }
             }
         }
-        Err(e) => {
-            warn!("Failed to notify CF Worker: {}", e);
-        }
-    }
+    });
 }
 
 /// Get SocketIo instance for external use (e.g., emit from HTTP handlers)
@@ -274,7 +473,7 @@ impl SocketIoHandle {
             "status": "delete_ic",
             "ic": ic_id
         });
-        let json_str = serde_json::to_string(&data).map_err(|e| e.to_string())?;
-        self.emit_hello(&json_str).await
+        protocol::broadcast(&self.io, "hello", &data, LegacyEncoding::DoubleString)
+            .map_err(|e| e.to_string())
     }
 }
@@ -0,0 +1,301 @@
+// Terminal telemetry (software version, uptime, disk, cameras, sensors)
+// Reports arrive over Socket.IO or gRPC, are stored as time-series snapshots in
+// terminal_telemetry and checked against thresholds to raise alerts
+
+use crate::clock;
+use crate::db::Database;
+use crate::sinks::{EventBus, OutboundEvent};
+use crate::with_pool;
+use chrono::NaiveDateTime;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::FromRow;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use tracing::warn;
+
+/// Socket.IO イベント名
+pub const TELEMETRY_EVENT: &str = "telemetry";
+
+/// アラート発生時に配信するイベントのステータス
+pub const TERMINAL_ALERT_STATUS: &str = "terminal alert";
+
+/// カメラ1台の状態
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CameraTelemetry {
+    pub cam: i32,
+    #[serde(default)]
+    pub ok: bool,
+    /// 最後に画像を取得してからの経過秒数
+    pub last_image_age_secs: Option<i64>,
+    pub error: Option<String>,
+}
+
+/// 端末から送られるテレメトリ
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TelemetryReport {
+    #[serde(alias = "ip")]
+    pub machine_ip: String,
+    #[serde(default)]
+    pub software_version: String,
+    #[serde(default)]
+    pub uptime_secs: i64,
+    pub disk_total_bytes: Option<i64>,
+    pub disk_free_bytes: Option<i64>,
+    #[serde(default)]
+    pub cameras: Vec<CameraTelemetry>,
+    #[serde(default)]
+    pub sensor_errors: Vec<String>,
+}
+
+/// terminal_telemetry の1行
+#[derive(Debug, Clone)]
+pub struct TelemetrySnapshot {
+    pub report: TelemetryReport,
+    pub alerts: Vec<String>,
+    /// サーバー受信時刻（端末時刻は信用しない）
+    pub reported_at: NaiveDateTime,
+}
+
+/// アラートのしきい値
+#[derive(Debug, Clone, Copy)]
+pub struct TelemetryThresholds {
+    pub min_disk_free_percent: f64,
+    pub max_camera_image_age_secs: i64,
+}
+
+#[derive(Debug)]
+pub enum TelemetryError {
+    Invalid(String),
+    Database(sqlx::Error),
+}
+
+impl fmt::Display for TelemetryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TelemetryError::Invalid(msg) => write!(f, "Invalid telemetry: {}", msg),
+            TelemetryError::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for TelemetryError {}
+
+impl From<sqlx::Error> for TelemetryError {
+    fn from(e: sqlx::Error) -> Self {
+        TelemetryError::Database(e)
+    }
+}
+
+impl TelemetryReport {
+    pub fn validate(&self) -> Result<(), TelemetryError> {
+        if self.machine_ip.trim().is_empty() {
+            return Err(TelemetryError::Invalid(
+                "machine_ip is required".to_string(),
+            ));
+        }
+        if let (Some(total), Some(free)) = (self.disk_total_bytes, self.disk_free_bytes) {
+            if total < 0 || free < 0 || free > total {
+                return Err(TelemetryError::Invalid(format!(
+                    "invalid disk usage {}/{}",
+                    free, total
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// しきい値を超えた項目をアラートとして返す
+    pub fn evaluate(&self, thresholds: &TelemetryThresholds) -> Vec<String> {
+        let mut alerts = Vec::new();
+
+        if let (Some(total), Some(free)) = (self.disk_total_bytes, self.disk_free_bytes) {
+            if total > 0 {
+                let free_percent = free as f64 * 100.0 / total as f64;
+                if free_percent < thresholds.min_disk_free_percent {
+                    alerts.push(format!("disk free {:.1}% is below threshold", free_percent));
+                }
+            }
+        }
+
+        for camera in &self.cameras {
+            if !camera.ok {
+                alerts.push(format!(
+                    "camera {} error: {}",
+                    camera.cam,
+                    camera.error.as_deref().unwrap_or("not ok")
+                ));
+            } else if let Some(age) = camera.last_image_age_secs {
+                if age > thresholds.max_camera_image_age_secs {
+                    alerts.push(format!(
+                        "camera {} has not produced an image for {}s",
+                        camera.cam, age
+                    ));
+                }
+            }
+        }
+
+        for error in &self.sensor_errors {
+            alerts.push(format!("sensor error: {}", error));
+        }
+
+        alerts
+    }
+}
+
+/// テレメトリの保存とアラート通知
+#[derive(Clone)]
+pub struct TelemetryRecorder {
+    db: Database,
+    thresholds: TelemetryThresholds,
+    events: EventBus,
+    /// 端末ごとの直近のアラート（同じアラートの繰り返し通知を抑止）
+    last_alerts: Arc<DashMap<String, Vec<String>>>,
+}
+
+impl TelemetryRecorder {
+    pub fn new(db: Database, thresholds: TelemetryThresholds, events: EventBus) -> Self {
+        Self {
+            db,
+            thresholds,
+            events,
+            last_alerts: Arc::new(DashMap::new()),
+        }
+    }
+
+    /// テレメトリを保存し、評価したアラートを返す
+    pub async fn record(&self, report: TelemetryReport) -> Result<Vec<String>, TelemetryError> {
+        report.validate()?;
+        let alerts = report.evaluate(&self.thresholds);
+        let reported_at = clock::now();
+
+        with_pool!(self.db, pool => {
+            sqlx::query(
+                "INSERT INTO terminal_telemetry
+                    (machine_ip, reported_at, software_version, uptime_secs, disk_total_bytes,
+                     disk_free_bytes, cameras, sensor_errors, alerts)
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            )
+            .bind(&report.machine_ip)
+            .bind(reported_at)
+            .bind(&report.software_version)
+            .bind(report.uptime_secs)
+            .bind(report.disk_total_bytes)
+            .bind(report.disk_free_bytes)
+            .bind(serde_json::to_string(&report.cameras).unwrap_or_else(|_| "[]".to_string()))
+            .bind(serde_json::to_string(&report.sensor_errors).unwrap_or_else(|_| "[]".to_string()))
+            .bind(serde_json::to_string(&alerts).unwrap_or_else(|_| "[]".to_string()))
+            .execute(pool)
+            .await?;
+        });
+
+        let previous = self
+            .last_alerts
+            .insert(report.machine_ip.clone(), alerts.clone())
+            .unwrap_or_default();
+        if !alerts.is_empty() && alerts != previous {
+            warn!(
+                "Terminal alert from {}: {}",
+                report.machine_ip,
+                alerts.join("; ")
+            );
+            let data = json!({
+                "ip": report.machine_ip,
+                "status": TERMINAL_ALERT_STATUS,
+                "message": alerts.join("\n"),
+                "data": {
+                    "time": reported_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+                    "alerts": alerts,
+                    "software_version": report.software_version,
+                }
+            });
+            self.events.publish(OutboundEvent::from_message(data)).await;
+        }
+
+        Ok(alerts)
+    }
+}
+
+/// terminal_telemetry の行（JSON 列は文字列のまま）
+#[derive(FromRow)]
+struct TelemetryRow {
+    machine_ip: String,
+    reported_at: NaiveDateTime,
+    software_version: String,
+    uptime_secs: i64,
+    disk_total_bytes: Option<i64>,
+    disk_free_bytes: Option<i64>,
+    cameras: String,
+    sensor_errors: String,
+    alerts: String,
+}
+
+impl From<TelemetryRow> for TelemetrySnapshot {
+    fn from(row: TelemetryRow) -> Self {
+        TelemetrySnapshot {
+            report: TelemetryReport {
+                machine_ip: row.machine_ip,
+                software_version: row.software_version,
+                uptime_secs: row.uptime_secs,
+                disk_total_bytes: row.disk_total_bytes,
+                disk_free_bytes: row.disk_free_bytes,
+                cameras: serde_json::from_str(&row.cameras).unwrap_or_default(),
+                sensor_errors: serde_json::from_str(&row.sensor_errors).unwrap_or_default(),
+            },
+            alerts: serde_json::from_str(&row.alerts).unwrap_or_default(),
+            reported_at: row.reported_at,
+        }
+    }
+}
+
+/// 端末ごとの最新テレメトリ
+pub async fn latest_by_ip(
+    db: &Database,
+) -> Result<HashMap<String, TelemetrySnapshot>, sqlx::Error> {
+    let rows: Vec<TelemetryRow> = with_pool!(db, pool => {
+        sqlx::query_as(
+            "SELECT t.id, t.machine_ip, t.reported_at, t.software_version, t.uptime_secs,
+                    t.disk_total_bytes, t.disk_free_bytes, t.cameras, t.sensor_errors, t.alerts
+             FROM terminal_telemetry t
+             JOIN (SELECT machine_ip, MAX(id) AS id FROM terminal_telemetry GROUP BY machine_ip) latest
+               ON t.id = latest.id",
+        )
+        .fetch_all(pool)
+        .await?
+    });
+
+    Ok(rows
+        .into_iter()
+        .map(TelemetrySnapshot::from)
+        .map(|snapshot| (snapshot.report.machine_ip.clone(), snapshot))
+        .collect())
+}
+
+/// 端末のテレメトリ履歴を新しい順に取得
+pub async fn history(
+    db: &Database,
+    machine_ip: &str,
+    since: Option<NaiveDateTime>,
+    limit: i32,
+) -> Result<Vec<TelemetrySnapshot>, sqlx::Error> {
+    let rows: Vec<TelemetryRow> = with_pool!(db, pool => {
+        sqlx::query_as(
+            "SELECT id, machine_ip, reported_at, software_version, uptime_secs, disk_total_bytes,
+                    disk_free_bytes, cameras, sensor_errors, alerts
+             FROM terminal_telemetry
+             WHERE machine_ip = ? AND (? IS NULL OR reported_at >= ?)
+             ORDER BY reported_at DESC, id DESC
+             LIMIT ?",
+        )
+        .bind(machine_ip)
+        .bind(since)
+        .bind(since)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?
+    });
+
+    Ok(rows.into_iter().map(TelemetrySnapshot::from).collect())
+}
@@ -0,0 +1,373 @@
+// Remote command channel from server to terminals
+// Commands are delivered over Socket.IO with acks and every attempt is stored in terminal_commands
+
+use crate::client_state::ClientState;
+use crate::clock;
+use crate::db::Database;
+use crate::with_pool;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use socketioxide::{socket::Sid, SocketIo};
+use sqlx::Row;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Socket.IO イベント名
+pub const COMMAND_EVENT: &str = "command";
+
+/// 端末に送信できるコマンド
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TerminalCommand {
+    ReloadDrivers,
+    ResyncCards,
+    RestartApp,
+    ShowMessage,
+    CaptureTestPhoto,
+}
+
+impl TerminalCommand {
+    pub fn parse(command: &str) -> Option<Self> {
+        match command {
+            "reload_drivers" => Some(Self::ReloadDrivers),
+            "resync_cards" => Some(Self::ResyncCards),
+            "restart_app" => Some(Self::RestartApp),
+            "show_message" => Some(Self::ShowMessage),
+            "capture_test_photo" => Some(Self::CaptureTestPhoto),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::ReloadDrivers => "reload_drivers",
+            Self::ResyncCards => "resync_cards",
+            Self::RestartApp => "restart_app",
+            Self::ShowMessage => "show_message",
+            Self::CaptureTestPhoto => "capture_test_photo",
+        }
+    }
+
+    /// コマンドごとの引数を検証
+    pub fn validate_args(&self, args: &HashMap<String, String>) -> Result<(), CommandError> {
+        match self {
+            Self::ShowMessage => {
+                let text = args.get("text").map(|t| t.trim()).unwrap_or("");
+                if text.is_empty() {
+                    return Err(CommandError::Invalid(
+                        "show_message requires 'text'".to_string(),
+                    ));
+                }
+            }
+            Self::CaptureTestPhoto => {
+                if let Some(cam) = args.get("cam") {
+                    if cam.parse::<i32>().is_err() {
+                        return Err(CommandError::Invalid(format!("invalid cam '{}'", cam)));
+                    }
+                }
+            }
+            Self::ReloadDrivers | Self::ResyncCards | Self::RestartApp => {}
+        }
+        Ok(())
+    }
+}
+
+/// コマンドの実行状態
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandStatus {
+    /// 送信済み・応答待ち
+    Sent,
+    /// 端末が実行成功を応答
+    Succeeded,
+    /// 端末が実行失敗を応答
+    Failed,
+    /// 応答タイムアウト
+    Timeout,
+    /// 対象端末が接続されていない
+    Undelivered,
+}
+
+impl CommandStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Sent => "sent",
+            Self::Succeeded => "succeeded",
+            Self::Failed => "failed",
+            Self::Timeout => "timeout",
+            Self::Undelivered => "undelivered",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CommandError {
+    Invalid(String),
+    Database(sqlx::Error),
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::Invalid(msg) => write!(f, "Invalid command: {}", msg),
+            CommandError::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+impl From<sqlx::Error> for CommandError {
+    fn from(e: sqlx::Error) -> Self {
+        CommandError::Database(e)
+    }
+}
+
+/// terminal_commands の1行
+#[derive(Debug, Clone)]
+pub struct CommandRecord {
+    pub id: String,
+    pub machine_ip: String,
+    pub socket_id: Option<String>,
+    pub command: String,
+    pub args: HashMap<String, String>,
+    pub status: String,
+    pub result: Option<String>,
+    pub requested_by: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub completed_at: Option<NaiveDateTime>,
+}
+
+/// 端末からの応答（ack または command_result イベント）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommandReply {
+    pub id: Option<String>,
+    #[serde(default)]
+    pub success: bool,
+    pub message: Option<String>,
+    pub result: Option<Value>,
+}
+
+impl CommandReply {
+    fn status(&self) -> CommandStatus {
+        if self.success {
+            CommandStatus::Succeeded
+        } else {
+            CommandStatus::Failed
+        }
+    }
+
+    fn result_text(&self) -> String {
+        json!({ "message": self.message, "result": self.result }).to_string()
+    }
+}
+
+/// コマンド送信と履歴管理
+#[derive(Clone)]
+pub struct CommandDispatcher {
+    db: Database,
+    clients: ClientState,
+    socketio: Option<Arc<SocketIo>>,
+    ack_timeout: Duration,
+}
+
+impl CommandDispatcher {
+    pub fn new(
+        db: Database,
+        clients: ClientState,
+        socketio: Option<Arc<SocketIo>>,
+        ack_timeout: Duration,
+    ) -> Self {
+        Self {
+            db,
+            clients,
+            socketio,
+            ack_timeout,
+        }
+    }
+
+    /// 端末にコマンドを送信し、応答（またはタイムアウト）まで待つ
+    pub async fn send(
+        &self,
+        machine_ip: &str,
+        command: TerminalCommand,
+        args: HashMap<String, String>,
+        requested_by: Option<String>,
+        timeout: Option<Duration>,
+    ) -> Result<CommandRecord, CommandError> {
+        command.validate_args(&args)?;
+
+        let mut record = CommandRecord {
+            id: uuid::Uuid::new_v4().to_string(),
+            machine_ip: machine_ip.to_string(),
+            socket_id: None,
+            command: command.as_str().to_string(),
+            args,
+            status: CommandStatus::Sent.as_str().to_string(),
+            result: None,
+            requested_by,
+            created_at: clock::now(),
+            completed_at: None,
+        };
+
+        // 同一IPに複数ソケットがある場合は直近に通信したソケットを使用
+        let socket = self.socketio.as_ref().and_then(|io| {
+            self.clients
+                .find_by_ip(machine_ip)
+                .into_iter()
+                .filter_map(|client| client.socket_id.parse::<Sid>().ok())
+                .find_map(|sid| io.get_socket(sid))
+        });
+
+        let Some(socket) = socket else {
+            record.status = CommandStatus::Undelivered.as_str().to_string();
+            record.result = Some("terminal is not connected".to_string());
+            record.completed_at = Some(clock::now());
+            insert(&self.db, &record).await?;
+            warn!(
+                "Command {} undelivered: {} not connected",
+                record.command, machine_ip
+            );
+            return Ok(record);
+        };
+
+        record.socket_id = Some(socket.id.to_string());
+        insert(&self.db, &record).await?;
+
+        let payload = json!({
+            "id": record.id,
+            "command": record.command,
+            "args": record.args,
+        });
+
+        let ack = socket
+            .timeout(timeout.unwrap_or(self.ack_timeout))
+            .emit_with_ack::<_, CommandReply>(COMMAND_EVENT, payload);
+
+        let (status, result) = match ack {
+            Ok(stream) => match stream.await {
+                Ok(reply) => (reply.data.status(), Some(reply.data.result_text())),
+                Err(socketioxide::AckError::Timeout) => (CommandStatus::Timeout, None),
+                Err(e) => (CommandStatus::Failed, Some(format!("ack error: {}", e))),
+            },
+            Err(e) => (CommandStatus::Failed, Some(format!("emit error: {}", e))),
+        };
+
+        info!(
+            "Command {} ({}) to {}: {}",
+            record.command,
+            record.id,
+            machine_ip,
+            status.as_str()
+        );
+
+        complete(&self.db, &record.id, status, result.as_deref()).await?;
+        record.status = status.as_str().to_string();
+        record.result = result;
+        record.completed_at = Some(clock::now());
+        Ok(record)
+    }
+}
+
+/// ack 後に端末から送られる実行結果（command_result イベント）を記録
+pub async fn record_reply(db: &Database, reply: &CommandReply) -> Result<bool, CommandError> {
+    let Some(ref id) = reply.id else {
+        return Err(CommandError::Invalid(
+            "command_result requires 'id'".to_string(),
+        ));
+    };
+    let updated = complete(db, id, reply.status(), Some(&reply.result_text())).await?;
+    Ok(updated)
+}
+
+/// 実行履歴を新しい順に取得
+pub async fn history(
+    db: &Database,
+    machine_ip: Option<&str>,
+    limit: i32,
+) -> Result<Vec<CommandRecord>, CommandError> {
+    let records = with_pool!(db, pool => {
+        sqlx::query(
+            "SELECT id, machine_ip, socket_id, command, args, status, result, requested_by,
+                    created_at, completed_at
+             FROM terminal_commands
+             WHERE (? IS NULL OR machine_ip = ?)
+             ORDER BY created_at DESC
+             LIMIT ?",
+        )
+        .bind(machine_ip)
+        .bind(machine_ip)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?
+        .iter()
+        .map(|row| {
+            let args: String = row.get("args");
+            CommandRecord {
+                id: row.get("id"),
+                machine_ip: row.get("machine_ip"),
+                socket_id: row.get("socket_id"),
+                command: row.get("command"),
+                args: serde_json::from_str(&args).unwrap_or_default(),
+                status: row.get("status"),
+                result: row.get("result"),
+                requested_by: row.get("requested_by"),
+                created_at: row.get("created_at"),
+                completed_at: row.get("completed_at"),
+            }
+        })
+        .collect()
+    });
+
+    Ok(records)
+}
+
+async fn insert(db: &Database, record: &CommandRecord) -> Result<(), sqlx::Error> {
+    let args = serde_json::to_string(&record.args).unwrap_or_else(|_| "{}".to_string());
+    with_pool!(db, pool => {
+        sqlx::query(
+            "INSERT INTO terminal_commands
+                (id, machine_ip, socket_id, command, args, status, result, requested_by,
+                 created_at, completed_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(&record.id)
+        .bind(&record.machine_ip)
+        .bind(&record.socket_id)
+        .bind(&record.command)
+        .bind(args)
+        .bind(&record.status)
+        .bind(&record.result)
+        .bind(&record.requested_by)
+        .bind(record.created_at)
+        .bind(record.completed_at)
+        .execute(pool)
+        .await?;
+    });
+    Ok(())
+}
+
+async fn complete(
+    db: &Database,
+    id: &str,
+    status: CommandStatus,
+    result: Option<&str>,
+) -> Result<bool, sqlx::Error> {
+    let updated = with_pool!(db, pool => {
+        sqlx::query(
+            "UPDATE terminal_commands
+             SET status = ?, result = COALESCE(?, result), completed_at = ?
+             WHERE id = ?",
+        )
+        .bind(status.as_str())
+        .bind(result)
+        .bind(clock::now())
+        .bind(id)
+        .execute(pool)
+        .await?
+        .rows_affected()
+    });
+    Ok(updated > 0)
+}
@@ -0,0 +1,429 @@
+// Centralized terminal configuration
+// Values are stored per scope ("*" = site defaults, otherwise a machine_ip override) and every
+// change bumps a global revision. Terminals receive the merged config over Socket.IO and
+// the revision they acknowledged is recorded in terminal_config_applied
+
+use crate::client_state::{ClientEventKind, ClientState};
+use crate::clock;
+use crate::db::{Database, InsertedId};
+use crate::with_pool;
+use chrono::NaiveDateTime;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use socketioxide::{extract::SocketRef, socket::Sid, SocketIo};
+use sqlx::Row;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::{error, info, warn};
+
+/// Socket.IO イベント名
+pub const CONFIG_EVENT: &str = "config";
+
+/// サイト共通設定のスコープ
+pub const DEFAULT_SCOPE: &str = "*";
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Invalid(String),
+    Database(sqlx::Error),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Invalid(msg) => write!(f, "Invalid config: {}", msg),
+            ConfigError::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<sqlx::Error> for ConfigError {
+    fn from(e: sqlx::Error) -> Self {
+        ConfigError::Database(e)
+    }
+}
+
+/// terminal_config の1行
+#[derive(Debug, Clone)]
+pub struct ConfigEntry {
+    pub scope: String,
+    pub key: String,
+    pub value: Value,
+    pub revision: i64,
+    pub updated_by: Option<String>,
+    pub updated_at: NaiveDateTime,
+}
+
+/// 端末に適用される設定（共通設定に端末別設定を上書き）
+#[derive(Debug, Clone)]
+pub struct EffectiveConfig {
+    pub machine_ip: String,
+    pub revision: i64,
+    pub values: Map<String, Value>,
+}
+
+/// terminal_config_applied の1行
+#[derive(Debug, Clone)]
+pub struct AppliedRecord {
+    pub machine_ip: String,
+    pub revision: i64,
+    pub success: bool,
+    pub message: Option<String>,
+    pub applied_at: NaiveDateTime,
+}
+
+/// 端末からの適用結果（ack）
+#[derive(Debug, Clone, Deserialize)]
+struct ConfigAck {
+    #[serde(default)]
+    success: bool,
+    message: Option<String>,
+}
+
+fn validate_key(scope: &str, key: &str) -> Result<(), ConfigError> {
+    if scope.trim().is_empty() {
+        return Err(ConfigError::Invalid("scope is required".to_string()));
+    }
+    if key.trim().is_empty() {
+        return Err(ConfigError::Invalid("key is required".to_string()));
+    }
+    Ok(())
+}
+
+/// 設定値を登録・更新し、新しいリビジョンを返す
+pub async fn set(
+    db: &Database,
+    scope: &str,
+    key: &str,
+    value: &Value,
+    updated_by: Option<&str>,
+) -> Result<i64, ConfigError> {
+    validate_key(scope, key)?;
+    let now = clock::now();
+    let upsert = format!(
+        "INSERT INTO terminal_config (scope, config_key, value, revision, updated_by, updated_at)
+         VALUES (?, ?, ?, ?, ?, ?)
+         {}",
+        db.dialect().upsert(
+            "scope, config_key",
+            &["value", "revision", "updated_by", "updated_at"]
+        )
+    );
+
+    let revision = with_pool!(db, pool => {
+        let mut tx = pool.begin().await?;
+
+        let revision = sqlx::query(
+            "INSERT INTO terminal_config_history (scope, config_key, value, updated_by, created_at)
+             VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(scope)
+        .bind(key)
+        .bind(value.to_string())
+        .bind(updated_by)
+        .bind(now)
+        .execute(&mut *tx)
+        .await?
+        .inserted_id();
+
+        sqlx::query(&upsert)
+            .bind(scope)
+            .bind(key)
+            .bind(value.to_string())
+            .bind(revision)
+            .bind(updated_by)
+            .bind(now)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        revision
+    });
+    Ok(revision)
+}
+
+/// 設定値を削除し、新しいリビジョンを返す（存在しない場合は None）
+pub async fn delete(
+    db: &Database,
+    scope: &str,
+    key: &str,
+    updated_by: Option<&str>,
+) -> Result<Option<i64>, ConfigError> {
+    validate_key(scope, key)?;
+    let revision = with_pool!(db, pool => {
+        let mut tx = pool.begin().await?;
+
+        let deleted = sqlx::query("DELETE FROM terminal_config WHERE scope = ? AND config_key = ?")
+            .bind(scope)
+            .bind(key)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        if deleted == 0 {
+            return Ok(None);
+        }
+
+        // 削除も履歴に残す（value = NULL）
+        let revision = sqlx::query(
+            "INSERT INTO terminal_config_history (scope, config_key, value, updated_by, created_at)
+             VALUES (?, ?, NULL, ?, ?)",
+        )
+        .bind(scope)
+        .bind(key)
+        .bind(updated_by)
+        .bind(clock::now())
+        .execute(&mut *tx)
+        .await?
+        .inserted_id();
+
+        tx.commit().await?;
+        revision
+    });
+    Ok(Some(revision))
+}
+
+/// 設定値一覧（scope 未指定の場合は全スコープ）
+pub async fn list(db: &Database, scope: Option<&str>) -> Result<Vec<ConfigEntry>, sqlx::Error> {
+    let entries = with_pool!(db, pool => {
+        sqlx::query(
+            "SELECT scope, config_key, value, revision, updated_by, updated_at
+             FROM terminal_config
+             WHERE (? IS NULL OR scope = ?)
+             ORDER BY scope, config_key",
+        )
+        .bind(scope)
+        .bind(scope)
+        .fetch_all(pool)
+        .await?
+        .iter()
+        .map(|row| {
+            let value: String = row.get("value");
+            ConfigEntry {
+                scope: row.get("scope"),
+                key: row.get("config_key"),
+                value: serde_json::from_str(&value).unwrap_or(Value::String(value)),
+                revision: row.get("revision"),
+                updated_by: row.get("updated_by"),
+                updated_at: row.get("updated_at"),
+            }
+        })
+        .collect()
+    });
+
+    Ok(entries)
+}
+
+/// 現在の設定リビジョン（変更がない場合は0）
+pub async fn current_revision(db: &Database) -> Result<i64, sqlx::Error> {
+    let revision: Option<i64> = with_pool!(db, pool => {
+        sqlx::query_scalar("SELECT MAX(id) FROM terminal_config_history")
+            .fetch_one(pool)
+            .await?
+    });
+    Ok(revision.unwrap_or(0))
+}
+
+/// 端末に適用される設定を取得
+pub async fn effective(db: &Database, machine_ip: &str) -> Result<EffectiveConfig, sqlx::Error> {
+    let revision = current_revision(db).await?;
+    let mut values = Map::new();
+    for entry in list(db, Some(DEFAULT_SCOPE)).await? {
+        values.insert(entry.key, entry.value);
+    }
+    for entry in list(db, Some(machine_ip)).await? {
+        values.insert(entry.key, entry.value);
+    }
+
+    Ok(EffectiveConfig {
+        machine_ip: machine_ip.to_string(),
+        revision,
+        values,
+    })
+}
+
+/// 端末の適用結果を記録
+pub async fn record_applied(
+    db: &Database,
+    machine_ip: &str,
+    revision: i64,
+    success: bool,
+    message: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    let sql = format!(
+        "INSERT INTO terminal_config_applied (machine_ip, revision, success, message, applied_at)
+         VALUES (?, ?, ?, ?, ?)
+         {}",
+        db.dialect().upsert(
+            "machine_ip",
+            &["revision", "success", "message", "applied_at"]
+        )
+    );
+    with_pool!(db, pool => {
+        sqlx::query(&sql)
+            .bind(machine_ip)
+            .bind(revision)
+            .bind(success)
+            .bind(message)
+            .bind(clock::now())
+            .execute(pool)
+            .await?;
+    });
+    Ok(())
+}
+
+/// 端末ごとの適用状況
+pub async fn applied(db: &Database) -> Result<Vec<AppliedRecord>, sqlx::Error> {
+    let records = with_pool!(db, pool => {
+        sqlx::query(
+            "SELECT machine_ip, revision, success, message, applied_at
+             FROM terminal_config_applied
+             ORDER BY machine_ip",
+        )
+        .fetch_all(pool)
+        .await?
+        .iter()
+        .map(|row| AppliedRecord {
+            machine_ip: row.get("machine_ip"),
+            revision: row.get("revision"),
+            success: row.get("success"),
+            message: row.get("message"),
+            applied_at: row.get("applied_at"),
+        })
+        .collect()
+    });
+
+    Ok(records)
+}
+
+/// 設定を Socket.IO で端末に配信
+#[derive(Clone)]
+pub struct ConfigPusher {
+    db: Database,
+    clients: ClientState,
+    socketio: Option<Arc<SocketIo>>,
+    ack_timeout: Duration,
+}
+
+impl ConfigPusher {
+    pub fn new(
+        db: Database,
+        clients: ClientState,
+        socketio: Option<Arc<SocketIo>>,
+        ack_timeout: Duration,
+    ) -> Self {
+        Self {
+            db,
+            clients,
+            socketio,
+            ack_timeout,
+        }
+    }
+
+    /// 変更されたスコープに該当する接続中の端末へ配信（配信対象のソケット数を返す）
+    pub fn push_scope(&self, scope: &str) -> usize {
+        let Some(ref io) = self.socketio else {
+            return 0;
+        };
+        let targets: Vec<(SocketRef, String)> = self
+            .clients
+            .get_all_clients()
+            .into_iter()
+            .filter(|c| c.has_ip() && (scope == DEFAULT_SCOPE || c.ip_address == scope))
+            .filter_map(|c| {
+                let sid = c.socket_id.parse::<Sid>().ok()?;
+                io.get_socket(sid).map(|socket| (socket, c.ip_address))
+            })
+            .collect();
+
+        let count = targets.len();
+        for (socket, machine_ip) in targets {
+            let pusher = self.clone();
+            tokio::spawn(async move { pusher.push(socket, &machine_ip).await });
+        }
+        count
+    }
+
+    /// 1ソケットへ設定を送信し、ack の結果を記録
+    async fn push(&self, socket: SocketRef, machine_ip: &str) {
+        let config = match effective(&self.db, machine_ip).await {
+            Ok(config) => config,
+            Err(e) => {
+                error!("Failed to load config for {}: {}", machine_ip, e);
+                return;
+            }
+        };
+        let payload = json!({
+            "revision": config.revision,
+            "config": config.values,
+        });
+
+        let ack = socket
+            .timeout(self.ack_timeout)
+            .emit_with_ack::<_, ConfigAck>(CONFIG_EVENT, payload);
+        let (success, message) = match ack {
+            Ok(stream) => match stream.await {
+                Ok(reply) => (reply.data.success, reply.data.message),
+                Err(e) => (false, Some(format!("ack error: {}", e))),
+            },
+            Err(e) => (false, Some(format!("emit error: {}", e))),
+        };
+
+        if success {
+            info!(
+                "Config revision {} applied by {}",
+                config.revision, machine_ip
+            );
+        } else {
+            warn!(
+                "Config revision {} not applied by {}: {:?}",
+                config.revision, machine_ip, message
+            );
+        }
+        if let Err(e) = record_applied(
+            &self.db,
+            machine_ip,
+            config.revision,
+            success,
+            message.as_deref(),
+        )
+        .await
+        {
+            error!("Failed to record config status for {}: {}", machine_ip, e);
+        }
+    }
+
+    /// 端末が IP を報告した時点（接続直後）で設定を配信
+    pub fn spawn_on_connect(&self) {
+        let pusher = self.clone();
+        let mut receiver = self.clients.subscribe();
+        tokio::spawn(async move {
+            loop {
+                let event = match receiver.recv().await {
+                    Ok(event) => event,
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("Config pusher lagged, skipped {} events", skipped);
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                if event.kind != ClientEventKind::IpChanged || !event.client.has_ip() {
+                    continue;
+                }
+                let socket = pusher.socketio.as_ref().and_then(|io| {
+                    let sid = event.client.socket_id.parse::<Sid>().ok()?;
+                    io.get_socket(sid)
+                });
+                if let Some(socket) = socket {
+                    let pusher = pusher.clone();
+                    tokio::spawn(
+                        async move { pusher.push(socket, &event.client.ip_address).await },
+                    );
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,330 @@
+// Persisted terminal registry
+// Terminals are registered automatically when they first report an IP and keep their
+// metadata (name, location, cameras, expected hours) after disconnecting
+
+use crate::client_state::{ClientEventKind, ClientHealth, ClientInfo, ClientState};
+use crate::clock;
+use crate::db::{Database, Dialect};
+use crate::with_pool;
+use chrono::{DateTime, NaiveDateTime, NaiveTime, Utc};
+use sqlx::Row;
+use std::fmt;
+use tokio::sync::broadcast;
+use tracing::{error, warn};
+
+/// 端末の稼働状態
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TerminalStatus {
+    /// 正常に接続中
+    Online,
+    /// 接続中だが無通信
+    Stale,
+    /// 未接続（稼働予定時間外）
+    Offline,
+    /// 稼働予定時間内なのに未接続
+    Missing,
+}
+
+impl TerminalStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Online => "online",
+            Self::Stale => "stale",
+            Self::Offline => "offline",
+            Self::Missing => "missing",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RegistryError {
+    Invalid(String),
+    Database(sqlx::Error),
+}
+
+impl fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegistryError::Invalid(msg) => write!(f, "Invalid terminal: {}", msg),
+            RegistryError::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for RegistryError {}
+
+impl From<sqlx::Error> for RegistryError {
+    fn from(e: sqlx::Error) -> Self {
+        RegistryError::Database(e)
+    }
+}
+
+/// terminals の1行
+#[derive(Debug, Clone)]
+pub struct Terminal {
+    pub machine_ip: String,
+    pub display_name: Option<String>,
+    pub location: Option<String>,
+    pub camera_count: i32,
+    pub expected_online_from: Option<NaiveTime>,
+    pub expected_online_to: Option<NaiveTime>,
+    pub first_seen_at: Option<NaiveDateTime>,
+    pub last_seen_at: Option<NaiveDateTime>,
+}
+
+impl Terminal {
+    /// 指定時刻が稼働予定時間内か（from > to の場合は日付をまたぐ）
+    pub fn expected_online_at(&self, time: NaiveTime) -> bool {
+        match (self.expected_online_from, self.expected_online_to) {
+            (Some(from), Some(to)) if from <= to => from <= time && time < to,
+            (Some(from), Some(to)) => time >= from || time < to,
+            _ => false,
+        }
+    }
+}
+
+/// 端末レジストリ情報とライブ接続状態の突き合わせ結果
+#[derive(Debug, Clone)]
+pub struct TerminalOverview {
+    pub terminal: Terminal,
+    pub status: TerminalStatus,
+    pub connections: usize,
+    /// 現在の接続のうち最も古い接続時刻
+    pub connected_since: Option<DateTime<Utc>>,
+}
+
+/// "HH:MM" 形式の時刻をパース
+pub fn parse_expected_time(value: &str) -> Result<Option<NaiveTime>, RegistryError> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+    NaiveTime::parse_from_str(value, "%H:%M")
+        .map(Some)
+        .map_err(|_| RegistryError::Invalid(format!("invalid time '{}', expected HH:MM", value)))
+}
+
+/// 端末の最終確認時刻を記録（未登録の場合は自動登録）
+pub async fn touch(
+    db: &Database,
+    machine_ip: &str,
+    seen_at: NaiveDateTime,
+) -> Result<(), sqlx::Error> {
+    let now = clock::now();
+    let sql = match db.dialect() {
+        Dialect::MySql => {
+            "INSERT INTO terminals (machine_ip, first_seen_at, last_seen_at, created_at, updated_at)
+             VALUES (?, ?, ?, ?, ?)
+             ON DUPLICATE KEY UPDATE
+                last_seen_at = GREATEST(COALESCE(last_seen_at, VALUES(last_seen_at)), VALUES(last_seen_at)),
+                first_seen_at = COALESCE(first_seen_at, VALUES(first_seen_at))"
+        }
+        Dialect::Sqlite => {
+            "INSERT INTO terminals (machine_ip, first_seen_at, last_seen_at, created_at, updated_at)
+             VALUES (?, ?, ?, ?, ?)
+             ON CONFLICT (machine_ip) DO UPDATE SET
+                last_seen_at = MAX(COALESCE(last_seen_at, excluded.last_seen_at), excluded.last_seen_at),
+                first_seen_at = COALESCE(first_seen_at, excluded.first_seen_at)"
+        }
+    };
+    with_pool!(db, pool => {
+        sqlx::query(sql)
+            .bind(machine_ip)
+            .bind(seen_at)
+            .bind(seen_at)
+            .bind(now)
+            .bind(now)
+            .execute(pool)
+            .await?;
+    });
+    Ok(())
+}
+
+/// 登録済み端末を IP 順に取得
+pub async fn list(db: &Database) -> Result<Vec<Terminal>, sqlx::Error> {
+    let terminals = with_pool!(db, pool => {
+        sqlx::query(
+            "SELECT machine_ip, display_name, location, camera_count, expected_online_from,
+                    expected_online_to, first_seen_at, last_seen_at
+             FROM terminals
+             ORDER BY machine_ip",
+        )
+        .fetch_all(pool)
+        .await?
+        .iter()
+        .map(|row| Terminal {
+            machine_ip: row.get("machine_ip"),
+            display_name: row.get("display_name"),
+            location: row.get("location"),
+            camera_count: row.get("camera_count"),
+            expected_online_from: row.get("expected_online_from"),
+            expected_online_to: row.get("expected_online_to"),
+            first_seen_at: row.get("first_seen_at"),
+            last_seen_at: row.get("last_seen_at"),
+        })
+        .collect()
+    });
+
+    Ok(terminals)
+}
+
+/// 登録済みの端末か
+pub async fn exists(db: &Database, machine_ip: &str) -> Result<bool, sqlx::Error> {
+    let found: Option<i64> = with_pool!(db, pool => {
+        sqlx::query_scalar("SELECT 1 FROM terminals WHERE machine_ip = ?")
+            .bind(machine_ip)
+            .fetch_optional(pool)
+            .await?
+    });
+    Ok(found.is_some())
+}
+
+/// 端末情報を登録・更新（接続履歴は保持）
+pub async fn upsert(db: &Database, terminal: &Terminal) -> Result<(), RegistryError> {
+    if terminal.machine_ip.trim().is_empty() {
+        return Err(RegistryError::Invalid("machine_ip is required".to_string()));
+    }
+    if terminal.camera_count < 0 {
+        return Err(RegistryError::Invalid(
+            "camera_count must not be negative".to_string(),
+        ));
+    }
+    if terminal.expected_online_from.is_some() != terminal.expected_online_to.is_some() {
+        return Err(RegistryError::Invalid(
+            "expected_online_from and expected_online_to must be set together".to_string(),
+        ));
+    }
+
+    let now = clock::now();
+    let sql = format!(
+        "INSERT INTO terminals
+            (machine_ip, display_name, location, camera_count, expected_online_from,
+             expected_online_to, created_at, updated_at)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+         {}",
+        db.dialect().upsert(
+            "machine_ip",
+            &[
+                "display_name",
+                "location",
+                "camera_count",
+                "expected_online_from",
+                "expected_online_to",
+                "updated_at",
+            ]
+        )
+    );
+    with_pool!(db, pool => {
+        sqlx::query(&sql)
+            .bind(&terminal.machine_ip)
+            .bind(&terminal.display_name)
+            .bind(&terminal.location)
+            .bind(terminal.camera_count)
+            .bind(terminal.expected_online_from)
+            .bind(terminal.expected_online_to)
+            .bind(now)
+            .bind(now)
+            .execute(pool)
+            .await?;
+    });
+    Ok(())
+}
+
+/// 端末をレジストリから削除
+pub async fn delete(db: &Database, machine_ip: &str) -> Result<bool, sqlx::Error> {
+    let deleted = with_pool!(db, pool => {
+        sqlx::query("DELETE FROM terminals WHERE machine_ip = ?")
+            .bind(machine_ip)
+            .execute(pool)
+            .await?
+            .rows_affected()
+    });
+    Ok(deleted > 0)
+}
+
+/// 登録済み端末と接続中クライアントを突き合わせる
+/// 未登録の接続中端末（IP 報告前の登録待ちなど）も含める
+pub fn overview(terminals: Vec<Terminal>, clients: &[ClientInfo]) -> Vec<TerminalOverview> {
+    let now_time = clock::now().time();
+
+    let mut terminals = terminals;
+    for client in clients.iter().filter(|c| c.has_ip()) {
+        if !terminals.iter().any(|t| t.machine_ip == client.ip_address) {
+            terminals.push(Terminal {
+                machine_ip: client.ip_address.clone(),
+                display_name: None,
+                location: None,
+                camera_count: 0,
+                expected_online_from: None,
+                expected_online_to: None,
+                first_seen_at: None,
+                last_seen_at: None,
+            });
+        }
+    }
+
+    terminals
+        .into_iter()
+        .map(|terminal| {
+            let live: Vec<&ClientInfo> = clients
+                .iter()
+                .filter(|c| c.ip_address == terminal.machine_ip)
+                .collect();
+            let healthy = live.iter().any(|c| c.health == ClientHealth::Healthy);
+
+            let status = if healthy {
+                TerminalStatus::Online
+            } else if !live.is_empty() {
+                TerminalStatus::Stale
+            } else if terminal.expected_online_at(now_time) {
+                TerminalStatus::Missing
+            } else {
+                TerminalStatus::Offline
+            };
+
+            // 接続中の場合は最終通信時刻をライブ情報で補完
+            let mut terminal = terminal;
+            if let Some(latest) = live.iter().map(|c| c.last_activity).max() {
+                let latest = clock::from_utc(latest);
+                if terminal.last_seen_at.is_none_or(|seen| seen < latest) {
+                    terminal.last_seen_at = Some(latest);
+                }
+            }
+
+            TerminalOverview {
+                connections: live.len(),
+                connected_since: live.iter().map(|c| c.connected_at).min(),
+                status,
+                terminal,
+            }
+        })
+        .collect()
+}
+
+/// クライアントの接続状態変化を端末レジストリに記録
+pub fn spawn_presence_recorder(db: Database, clients: &ClientState) {
+    let mut receiver = clients.subscribe();
+    tokio::spawn(async move {
+        loop {
+            let event = match receiver.recv().await {
+                Ok(event) => event,
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Presence recorder lagged, skipped {} events", skipped);
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            };
+            if !event.client.has_ip() || event.kind == ClientEventKind::Connected {
+                continue;
+            }
+
+            let seen_at = clock::from_utc(event.client.last_activity);
+            if let Err(e) = touch(&db, &event.client.ip_address, seen_at).await {
+                error!(
+                    "Failed to record presence for {}: {}",
+                    event.client.ip_address, e
+                );
+            }
+        }
+    });
+}